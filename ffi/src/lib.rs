@@ -0,0 +1,3 @@
+//! C ABI for `parakeet-crypto`; see [`parakeet::ffi`].
+
+pub use parakeet::ffi::*;
@@ -0,0 +1,47 @@
+//! Compile and run the C test harness (`tests/test_ffi.c`) against the static library.
+//!
+//! Requires a C compiler (`$CC`, or `cc`); the test fails if none is available.
+//! Only run on Unix: MSVC names the library differently, and has no `cc`.
+#![cfg(unix)]
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Directory of the library artifacts, i.e. `target/<profile>/deps`.
+fn deps_dir() -> PathBuf {
+    let exe = std::env::current_exe().unwrap();
+    exe.parent().unwrap().to_path_buf()
+}
+
+#[test]
+fn test_c_harness() {
+    let crate_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let root = crate_dir.parent().unwrap();
+    let static_lib = deps_dir().join("libparakeet_crypto.a");
+    assert!(static_lib.exists(), "missing {}", static_lib.display());
+
+    let cc = std::env::var("CC").unwrap_or_else(|_| "cc".into());
+    let harness = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("test_ffi");
+    let compiled = Command::new(&cc)
+        .arg(crate_dir.join("tests/test_ffi.c"))
+        .arg("-I")
+        .arg(root.join("include"))
+        .arg("-o")
+        .arg(&harness)
+        .arg(&static_lib)
+        .args(["-lpthread", "-ldl", "-lm"])
+        .status()
+        .unwrap_or_else(|err| panic!("could not run the C compiler `{cc}`: {err}"));
+    assert!(compiled.success(), "failed to compile the C harness");
+
+    let output = Command::new(&harness)
+        .arg(root.join("sample"))
+        .arg(root.join("src/crypto/tencent/tail/__fixtures__"))
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "C harness failed:\n{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
@@ -0,0 +1,114 @@
+//! Check that `include/parakeet_crypto.h` matches the exported functions and constants.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use parakeet::ErrorCode;
+
+fn read(path: &str) -> String {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR")).parent().unwrap();
+    fs::read_to_string(root.join(path)).unwrap()
+}
+
+/// Remove `/* ... */` comments from the header.
+fn strip_comments(mut source: &str) -> String {
+    let mut result = String::new();
+    while let Some(start) = source.find("/*") {
+        result.push_str(&source[..start]);
+        let end = source[start..].find("*/").unwrap();
+        source = &source[start + end + 2..];
+    }
+    result.push_str(source);
+    result
+}
+
+/// `IoError` -> `IO_ERROR`
+fn screaming_snake_case(name: &str) -> String {
+    let mut result = String::new();
+    for (i, c) in name.chars().enumerate() {
+        if c.is_ascii_uppercase() && i != 0 {
+            result.push('_');
+        }
+        result.push(c.to_ascii_uppercase());
+    }
+    result
+}
+
+/// Number of parameters in `(a, b, c)`; `void` or empty means none.
+fn count_params(params: &str) -> usize {
+    match params.trim() {
+        "" | "void" => 0,
+        params => params.trim_end_matches(',').split(',').count(),
+    }
+}
+
+/// `name -> parameter count` of the functions declared in `source`, after `marker`.
+fn functions(source: &str, marker: &str) -> BTreeMap<String, usize> {
+    source
+        .match_indices(marker)
+        .map(|(pos, _)| {
+            let rest = &source[pos + marker.len()..];
+            let open = rest.find('(').unwrap();
+            let close = rest.find(')').unwrap();
+            let name = rest[..open].trim().trim_start_matches('*');
+            (name.to_string(), count_params(&rest[open + 1..close]))
+        })
+        .collect()
+}
+
+#[test]
+fn test_constants() {
+    let header = strip_comments(&read("include/parakeet_crypto.h"));
+    let defines = header
+        .lines()
+        .filter_map(|line| line.strip_prefix("#define PARAKEET_"))
+        .filter_map(|line| line.split_once(' '))
+        .map(|(name, value)| {
+            let value = value.trim().trim_matches(|c| c == '(' || c == ')');
+            (format!("PARAKEET_{name}"), value.parse::<i32>().unwrap())
+        })
+        .collect::<BTreeMap<_, _>>();
+
+    let source = read("src/ffi/mod.rs");
+    let mut expected = source
+        .lines()
+        .filter_map(|line| line.strip_prefix("pub const "))
+        .filter_map(|line| line.split_once(": i32 = "))
+        .map(|(name, value)| {
+            (
+                name.to_string(),
+                value.trim_end_matches(';').parse().unwrap(),
+            )
+        })
+        .collect::<BTreeMap<_, _>>();
+    for code in ErrorCode::ALL {
+        let name = screaming_snake_case(&format!("{code:?}"));
+        expected.insert(format!("PARAKEET_ERR_{name}"), code as i32);
+    }
+
+    assert_eq!(defines, expected);
+}
+
+#[test]
+fn test_functions() {
+    let header = strip_comments(&read("include/parakeet_crypto.h"));
+    let declared = header
+        .split(';')
+        .filter(|decl| decl.contains("parakeet_") && decl.contains('('))
+        .map(|decl| {
+            let decl = decl.trim();
+            let open = decl.find('(').unwrap();
+            let name = decl[..open].rsplit([' ', '*']).next().unwrap();
+            let close = decl.rfind(')').unwrap();
+            (name.to_string(), count_params(&decl[open + 1..close]))
+        })
+        .collect::<BTreeMap<_, _>>();
+
+    let source = read("src/ffi/mod.rs");
+    let mut exported = functions(&source, "pub unsafe extern \"C\" fn ");
+    exported.extend(functions(&source, "pub extern \"C\" fn "));
+
+    assert!(!exported.is_empty());
+    assert_eq!(declared, exported);
+}
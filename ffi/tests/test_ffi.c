@@ -0,0 +1,165 @@
+/*
+ * C test harness for the FFI, run by `tests/c_harness.rs`:
+ *
+ *     test_ffi <sample dir> <tail fixtures dir>
+ */
+
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+#include "parakeet_crypto.h"
+
+static int failures = 0;
+
+#define CHECK(cond)                                                                                \
+    do {                                                                                           \
+        if (!(cond)) {                                                                             \
+            fprintf(stderr, "%s:%d: check failed: %s\n", __FILE__, __LINE__, #cond);               \
+            failures++;                                                                            \
+        }                                                                                          \
+    } while (0)
+
+#define CHECK_CODE(expr, expected)                                                                 \
+    do {                                                                                           \
+        int32_t code_ = (expr);                                                                    \
+        if (code_ != (expected)) {                                                                 \
+            fprintf(stderr, "%s:%d: %s returned %d (%s), expected %d\n", __FILE__, __LINE__,       \
+                    #expr, code_, parakeet_error_message(code_), (expected));                      \
+            failures++;                                                                            \
+        }                                                                                          \
+    } while (0)
+
+static uint8_t *read_file(const char *dir, const char *name, size_t *len) {
+    char path[4096];
+    snprintf(path, sizeof(path), "%s/%s", dir, name);
+
+    FILE *f = fopen(path, "rb");
+    if (f == NULL) {
+        fprintf(stderr, "could not open %s\n", path);
+        exit(2);
+    }
+
+    fseek(f, 0, SEEK_END);
+    *len = (size_t)ftell(f);
+    fseek(f, 0, SEEK_SET);
+
+    uint8_t *data = malloc(*len);
+    if (fread(data, 1, *len, f) != *len) {
+        fprintf(stderr, "could not read %s\n", path);
+        exit(2);
+    }
+    fclose(f);
+    return data;
+}
+
+static void test_kugou(const char *sample_dir) {
+    size_t file_len, plain_len;
+    uint8_t *file = read_file(sample_dir, "test_kgm_v3.vpr", &file_len);
+    uint8_t *plain = read_file(sample_dir, "test_121529_32kbps.ogg", &plain_len);
+
+    ParakeetDecryptor *handle = NULL;
+    size_t header_len = 0;
+    CHECK_CODE(parakeet_kugou_new(file, file_len, (const uint8_t *)"09AZ", 4, &handle, &header_len),
+               PARAKEET_OK);
+    CHECK(header_len == 1024);
+    CHECK(file_len - header_len == plain_len);
+
+    /* Decrypt in uneven chunks. */
+    uint8_t *audio = file + header_len;
+    size_t offset = 0;
+    while (offset < plain_len) {
+        size_t len = plain_len - offset < 333 ? plain_len - offset : 333;
+        CHECK_CODE(parakeet_decrypt(handle, offset, audio + offset, len), PARAKEET_OK);
+        offset += len;
+    }
+    CHECK(memcmp(audio, plain, plain_len) == 0);
+    parakeet_decryptor_free(handle);
+
+    /* Wrong slot key */
+    handle = NULL;
+    CHECK_CODE(parakeet_kugou_new(file, 1024, (const uint8_t *)"nope", 4, &handle, &header_len),
+               PARAKEET_ERR_KUGOU_CIPHER);
+    CHECK(handle == NULL);
+
+    free(file);
+    free(plain);
+}
+
+static void test_tail(const char *fixture_dir) {
+    size_t tail_len, expected_len;
+    uint8_t *tail = read_file(fixture_dir, "ekey_pc_enc_v1.bin", &tail_len);
+    uint8_t *expected = read_file(fixture_dir, "ekey_pc_enc_v1_result.bin", &expected_len);
+
+    /* Not enough data */
+    size_t parsed_tail_len = 0;
+    CHECK_CODE(parakeet_tail_parse(tail + tail_len - 8, 8, &parsed_tail_len, NULL, NULL),
+               PARAKEET_ERR_NEED_MORE_BYTES);
+    CHECK(parsed_tail_len > 8);
+
+    /* Query the key size first. */
+    size_t key_len = 0;
+    CHECK_CODE(parakeet_tail_parse(tail, tail_len, &parsed_tail_len, NULL, &key_len),
+               PARAKEET_ERR_BUFFER_TOO_SMALL);
+    CHECK(key_len == expected_len);
+
+    uint8_t *key = malloc(key_len);
+    CHECK_CODE(parakeet_tail_parse(tail, tail_len, &parsed_tail_len, key, &key_len), PARAKEET_OK);
+    CHECK(key_len == expected_len && memcmp(key, expected, key_len) == 0);
+
+    /* ekey: `[ekey][u32 ekey_len]` */
+    size_t ekey_len = parsed_tail_len - 4;
+    const uint8_t *ekey = tail + tail_len - parsed_tail_len;
+    memset(key, 0, key_len);
+    CHECK_CODE(parakeet_ekey_decrypt(ekey, ekey_len, key, &key_len), PARAKEET_OK);
+    CHECK(key_len == expected_len && memcmp(key, expected, key_len) == 0);
+    CHECK_CODE(parakeet_ekey_decrypt(ekey, 4, key, &key_len), PARAKEET_ERR_TENCENT_KEY_DECRYPT);
+
+    /* QMCv2 from tail */
+    ParakeetDecryptor *handle = NULL;
+    CHECK_CODE(parakeet_qmc2_new_from_tail(tail, tail_len, &handle, &parsed_tail_len), PARAKEET_OK);
+    CHECK(handle != NULL);
+    parakeet_decryptor_free(handle);
+
+    free(key);
+    free(tail);
+    free(expected);
+}
+
+static void test_qmc1(void) {
+    uint8_t data[16] = {0};
+    ParakeetDecryptor *handle = NULL;
+
+    CHECK_CODE(parakeet_qmc1_new(data, sizeof(data), &handle), PARAKEET_ERR_DECRYPTOR);
+    CHECK_CODE(parakeet_qmc1_new(NULL, 0, &handle), PARAKEET_OK);
+
+    /* QMCv1 is a symmetric XOR cipher. */
+    CHECK_CODE(parakeet_decrypt(handle, 0x7FFF, data, sizeof(data)), PARAKEET_OK);
+    CHECK_CODE(parakeet_decrypt(handle, 0x7FFF, data, sizeof(data)), PARAKEET_OK);
+    for (size_t i = 0; i < sizeof(data); i++) {
+        CHECK(data[i] == 0);
+    }
+
+    CHECK_CODE(parakeet_decrypt(NULL, 0, data, sizeof(data)), PARAKEET_ERR_NULL_POINTER);
+    parakeet_decryptor_free(handle);
+    parakeet_decryptor_free(NULL);
+}
+
+int main(int argc, char **argv) {
+    if (argc != 3) {
+        fprintf(stderr, "usage: %s <sample dir> <tail fixtures dir>\n", argv[0]);
+        return 2;
+    }
+
+    test_kugou(argv[1]);
+    test_tail(argv[2]);
+    test_qmc1();
+
+    if (failures != 0) {
+        fprintf(stderr, "%d check(s) failed\n", failures);
+        return 1;
+    }
+
+    printf("all checks passed\n");
+    return 0;
+}
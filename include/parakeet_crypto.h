@@ -0,0 +1,122 @@
+/*
+ * parakeet-crypto C API.
+ *
+ * Build the library with `cargo build --release -p parakeet-crypto-ffi`, then
+ * link against `libparakeet_crypto.a` (static) or `libparakeet_crypto.so` (shared).
+ *
+ * This header mirrors `src/ffi/mod.rs`; `ffi/tests/header.rs` checks that the
+ * constants and functions of both match.
+ *
+ * All functions return `PARAKEET_OK` (0) on success, a negative value for
+ * errors raised by the FFI layer, or a positive error code raised by the
+ * library (`PARAKEET_ERR_IO`, ...). A panic in the library is reported as
+ * `PARAKEET_ERR_PANIC`.
+ */
+
+#ifndef PARAKEET_CRYPTO_H
+#define PARAKEET_CRYPTO_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* FFI layer errors */
+#define PARAKEET_OK 0
+#define PARAKEET_ERR_NULL_POINTER (-1)
+#define PARAKEET_ERR_BUFFER_TOO_SMALL (-2)
+#define PARAKEET_ERR_NEED_MORE_BYTES (-3)
+#define PARAKEET_ERR_KEY_REQUIRED (-4)
+#define PARAKEET_ERR_PANIC (-5)
+
+/* Library errors, see `parakeet_crypto::ErrorCode` */
+#define PARAKEET_ERR_IO 1
+#define PARAKEET_ERR_DECRYPTOR 2
+#define PARAKEET_ERR_TENCENT_KEY_DECRYPT 100
+#define PARAKEET_ERR_TENCENT_TAIL_PARSE 101
+#define PARAKEET_ERR_TENCENT_INIT_CIPHER 102
+#define PARAKEET_ERR_TENCENT_KEY_ENCRYPT 103
+#define PARAKEET_ERR_KUGOU_HEADER_DESERIALIZE 200
+#define PARAKEET_ERR_KUGOU_HEADER_SERIALIZE 201
+#define PARAKEET_ERR_KUGOU_CIPHER 202
+#define PARAKEET_ERR_KUWO_HEADER_PARSE 300
+#define PARAKEET_ERR_KUWO_INIT_CIPHER 301
+#define PARAKEET_ERR_XIMALAYA_ANDROID 400
+#define PARAKEET_ERR_XIMALAYA_PC 500
+
+/** Opaque decryptor handle. */
+typedef struct ParakeetDecryptor ParakeetDecryptor;
+
+/** Init a QMCv1 decryptor; `table` is optional (`NULL` for the default table). */
+int32_t parakeet_qmc1_new(const uint8_t *table, size_t table_len, ParakeetDecryptor **out);
+
+/** Init a QMCv2 decryptor from a decrypted key. */
+int32_t parakeet_qmc2_new(const uint8_t *key, size_t key_len, ParakeetDecryptor **out);
+
+/**
+ * Init a QMCv2 decryptor from the end of the file.
+ *
+ * `*out_tail_len` receives the size of the tail, or the number of bytes
+ * required if `PARAKEET_ERR_NEED_MORE_BYTES` is returned.
+ */
+int32_t parakeet_qmc2_new_from_tail(const uint8_t *tail, size_t tail_len, ParakeetDecryptor **out,
+                                    size_t *out_tail_len);
+
+/**
+ * Init a Kugou decryptor from the file header.
+ *
+ * `slot_key` is optional (`NULL` to use the key selected by the header).
+ * `*out_header_len` receives the offset of the audio data.
+ * Mode 4 key tables are computed on demand, to keep the memory usage low.
+ */
+int32_t parakeet_kugou_new(const uint8_t *header, size_t header_len, const uint8_t *slot_key,
+                           size_t slot_key_len, ParakeetDecryptor **out, size_t *out_header_len);
+
+/**
+ * Init a Kuwo decryptor from the file header (0x400 bytes).
+ *
+ * `ekey` is optional, and only required for KWMv2 files.
+ */
+int32_t parakeet_kuwo_new(const uint8_t *header, size_t header_len, const uint8_t *ekey,
+                          size_t ekey_len, ParakeetDecryptor **out);
+
+/**
+ * Decrypt `buf` in place; `offset` is relative to the start of the encrypted audio.
+ *
+ * Kugou (mode 4) files can't be decrypted past a fixed length: `PARAKEET_ERR_KUGOU_CIPHER`
+ * is returned, and `buf` is left untouched.
+ */
+int32_t parakeet_decrypt(const ParakeetDecryptor *handle, size_t offset, uint8_t *buf, size_t len);
+
+/** Free a decryptor handle; `NULL` is ignored. */
+void parakeet_decryptor_free(ParakeetDecryptor *handle);
+
+/**
+ * Decrypt a QMC ekey.
+ *
+ * `*out_len` should hold the capacity of `out`, and receives the size of the key.
+ */
+int32_t parakeet_ekey_decrypt(const uint8_t *ekey, size_t ekey_len, uint8_t *out, size_t *out_len);
+
+/**
+ * Parse a QMC tail.
+ *
+ * `*out_tail_len` receives the size of the tail, or the number of bytes
+ * required if `PARAKEET_ERR_NEED_MORE_BYTES` is returned.
+ *
+ * The embedded key is copied to `key` (optional); `*key_len` should hold
+ * its capacity, and receives the size of the key (0 if the tail has no key).
+ */
+int32_t parakeet_tail_parse(const uint8_t *tail, size_t tail_len, size_t *out_tail_len, uint8_t *key,
+                            size_t *key_len);
+
+/** Static description of an error code. */
+const char *parakeet_error_message(int32_t code);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* PARAKEET_CRYPTO_H */
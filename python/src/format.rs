@@ -0,0 +1,66 @@
+//! Argument and error mapping for [`parakeet::decrypt`].
+
+use std::cell::RefCell;
+use std::fs::File;
+use std::path::Path;
+
+use pyo3::prelude::*;
+
+use parakeet::decrypt::{self, DecryptError, KeyStore};
+use parakeet::detect::{self, Format};
+
+use crate::{BinaryContent, ParakeetError};
+
+pub fn to_py_err<E: Into<DecryptError>>(error: E) -> PyErr {
+    match error.into() {
+        DecryptError::Parakeet(parakeet::Error::Io(error)) => error.into(),
+        DecryptError::Parakeet(error) => {
+            ParakeetError::new_err((error.to_string(), Some(error.code() as u32)))
+        }
+        error => ParakeetError::new_err((error.to_string(), None::<u32>)),
+    }
+}
+
+/// Detect the format of an encrypted file, see [`parakeet::detect::detect`].
+pub fn detect(path: &Path) -> PyResult<Option<Format>> {
+    let mut src = File::open(path)?;
+    let extension = path.extension().map(|ext| ext.to_string_lossy());
+    Ok(detect::detect(&mut src, extension.as_deref())?)
+}
+
+/// Decrypt `path_in` to `path_out`, see [`parakeet::decrypt::decrypt_file`].
+///
+/// `keystore` is a Python mapping; errors raised by its `get` method are returned as-is.
+pub fn decrypt(
+    path_in: &Path,
+    path_out: &Path,
+    keystore: Option<Bound<'_, PyAny>>,
+) -> PyResult<Format> {
+    let lookup_error = RefCell::new(None);
+    let key_store = keystore.map(|keystore| {
+        let lookup_error = &lookup_error;
+        move |name: &str| {
+            let value = keystore
+                .call_method1("get", (name,))
+                .and_then(|value| value.extract::<Option<BinaryContent>>());
+            match value {
+                Ok(value) => value.map(BinaryContent::into_bytes),
+                Err(error) => {
+                    lookup_error.borrow_mut().get_or_insert(error);
+                    None
+                }
+            }
+        }
+    });
+
+    let result = decrypt::decrypt_file(
+        path_in,
+        path_out,
+        key_store.as_ref().map(|f| f as &KeyStore),
+        |_| {},
+    );
+    if let Some(error) = lookup_error.take() {
+        return Err(error);
+    }
+    result.map_err(to_py_err)
+}
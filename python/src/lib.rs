@@ -0,0 +1,129 @@
+//! Python bindings, exposed as the `parakeet_crypto` module.
+//!
+//! Build with `maturin develop` (or `maturin build`) from this directory.
+
+use std::path::PathBuf;
+
+use pyo3::create_exception;
+use pyo3::exceptions::PyException;
+use pyo3::prelude::*;
+use pyo3::types::{PyBytes, PyDict};
+
+use parakeet::crypto::tencent::metadata::TailParseResult;
+use parakeet::crypto::tencent::{ekey, parse_tail as parse_tencent_tail};
+use parakeet::utils::audio;
+
+use format::to_py_err;
+
+mod format;
+
+create_exception!(
+    parakeet_crypto,
+    ParakeetError,
+    PyException,
+    "Raised with `(message, code)`, where `code` is the library error code (or `None`)."
+);
+
+/// Keys and ekeys can be given as `bytes` or `str`.
+#[derive(FromPyObject)]
+pub(crate) enum BinaryContent {
+    Bytes(Vec<u8>),
+    Text(String),
+}
+
+impl BinaryContent {
+    pub(crate) fn into_bytes(self) -> Box<[u8]> {
+        match self {
+            BinaryContent::Bytes(value) => value.into(),
+            BinaryContent::Text(value) => value.into_bytes().into(),
+        }
+    }
+}
+
+/// Parse the QMC tail (end of the file) to a `dict`.
+#[pyfunction]
+fn parse_tail<'py>(py: Python<'py>, data: &[u8]) -> PyResult<Bound<'py, PyDict>> {
+    let tail = parse_tencent_tail(data).map_err(to_py_err)?;
+
+    let dict = PyDict::new(py);
+    dict.set_item("tail_len", tail.get_tail_len())?;
+    match &tail {
+        TailParseResult::PcLegacy(m) => {
+            dict.set_item("type", "pc_legacy")?;
+            dict.set_item("key", PyBytes::new(py, &m.key))?;
+        }
+        TailParseResult::PcMusicEx(m) => {
+            dict.set_item("type", "pc_musicex")?;
+            dict.set_item("tag_version", m.tag_version)?;
+            dict.set_item("mid", &m.mid)?;
+            dict.set_item("media_filename", &m.media_filename)?;
+        }
+        TailParseResult::AndroidQTag(m) => {
+            dict.set_item("type", "android_qtag")?;
+            dict.set_item("key", PyBytes::new(py, &m.key))?;
+            dict.set_item("tag_version", m.tag_version)?;
+            dict.set_item("resource_id", m.resource_id)?;
+            dict.set_item("extra_fields", &m.extra_fields)?;
+        }
+        TailParseResult::AndroidSTag(m) => {
+            dict.set_item("type", "android_stag")?;
+            dict.set_item("tag_version", m.tag_version)?;
+            dict.set_item("media_mid", &m.media_mid)?;
+            dict.set_item("media_numeric_id", m.media_numeric_id)?;
+            dict.set_item("extra_fields", &m.extra_fields)?;
+        }
+    }
+
+    Ok(dict)
+}
+
+/// Decrypt a QMC ekey.
+#[pyfunction]
+fn decrypt_ekey<'py>(py: Python<'py>, ekey: BinaryContent) -> PyResult<Bound<'py, PyBytes>> {
+    let key = ekey::decrypt(ekey.into_bytes()).map_err(to_py_err)?;
+    Ok(PyBytes::new(py, &key))
+}
+
+/// Detect the audio container from the beginning of a decrypted file,
+/// e.g. `"flac"`; `None` if unknown.
+#[pyfunction]
+fn detect_audio_type(data: &[u8]) -> Option<&'static str> {
+    audio::detect_audio_type(data).map(|audio_type| audio_type.extension())
+}
+
+/// Detect the format of an encrypted file, e.g. `"qmc2"`; `None` if unknown.
+#[pyfunction]
+fn detect(path: PathBuf) -> PyResult<Option<&'static str>> {
+    let format = format::detect(&path)?;
+    Ok(format.map(|format| format.name()))
+}
+
+/// Decrypt `path_in` to `path_out`, and return the detected format.
+///
+/// `keystore` is a mapping of names to ekeys, used when the file does not
+/// include its key: QMCv2 files are looked up by media file name, `mid` or file
+/// name; KWMv2 files by their mmkv key (`sec_ekey#<rid>-<quality>`).
+///
+/// `path_out` must not be `path_in`, and is removed if the decryption fails.
+#[pyfunction]
+#[pyo3(signature = (path_in, path_out, keystore=None))]
+fn decrypt(
+    path_in: PathBuf,
+    path_out: PathBuf,
+    keystore: Option<Bound<'_, PyAny>>,
+) -> PyResult<&'static str> {
+    let format = format::decrypt(&path_in, &path_out, keystore)?;
+    Ok(format.name())
+}
+
+#[pymodule]
+#[pyo3(name = "parakeet_crypto")]
+fn parakeet_crypto_py(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add("ParakeetError", m.py().get_type::<ParakeetError>())?;
+    m.add_function(wrap_pyfunction!(parse_tail, m)?)?;
+    m.add_function(wrap_pyfunction!(decrypt_ekey, m)?)?;
+    m.add_function(wrap_pyfunction!(detect_audio_type, m)?)?;
+    m.add_function(wrap_pyfunction!(detect, m)?)?;
+    m.add_function(wrap_pyfunction!(decrypt, m)?)?;
+    Ok(())
+}
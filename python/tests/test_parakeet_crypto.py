@@ -0,0 +1,163 @@
+import struct
+from pathlib import Path
+
+import pytest
+
+import parakeet_crypto
+
+ROOT = Path(__file__).resolve().parents[2]
+SAMPLE = (ROOT / "sample" / "test_121529_32kbps.ogg").read_bytes()
+TAIL_FIXTURES = ROOT / "src" / "crypto" / "tencent" / "tail" / "__fixtures__"
+
+
+def map_l(key, offset):
+    if offset > 0x7FFF:
+        offset %= 0x7FFF
+    return key[offset & 0x7F]
+
+
+def qmc_map_encrypt(key128, data):
+    return bytes(b ^ map_l(key128, i) for i, b in enumerate(data))
+
+
+def qmc2_key_to_map_key(key):
+    long_key = bytes(((b << (i % 8 + 4) % 8) | (b >> (i % 8 + 4) % 8)) & 0xFF for i, b in enumerate(key))
+    return bytes(long_key[(i * i + 0x1162E) % len(long_key)] for i in range(128))
+
+
+def ekey_from_pc_tail(tail):
+    (ekey_len,) = struct.unpack("<I", tail[-4:])
+    return tail[-4 - ekey_len : -4].rstrip(b"\0")
+
+
+def make_stag(payload):
+    return payload.encode() + struct.pack(">I", len(payload)) + b"STag"
+
+
+def test_detect_audio_type():
+    assert parakeet_crypto.detect_audio_type(SAMPLE) == "ogg"
+    assert parakeet_crypto.detect_audio_type(b"fLaC\0\0\0\x22") == "flac"
+    assert parakeet_crypto.detect_audio_type(b"not an audio file") is None
+
+
+def test_decrypt_ekey():
+    tail = (TAIL_FIXTURES / "ekey_pc_enc_v2.bin").read_bytes()
+    expected = (TAIL_FIXTURES / "ekey_pc_enc_v2_result.bin").read_bytes()
+
+    ekey = ekey_from_pc_tail(tail)
+    assert parakeet_crypto.decrypt_ekey(ekey) == expected
+    assert parakeet_crypto.decrypt_ekey(ekey.decode()) == expected
+
+    with pytest.raises(parakeet_crypto.ParakeetError) as error:
+        parakeet_crypto.decrypt_ekey(b"not an ekey")
+    assert error.value.args[1] == 100
+
+
+def test_parse_tail():
+    tail = (TAIL_FIXTURES / "ekey_pc_enc_v1.bin").read_bytes()
+    result = parakeet_crypto.parse_tail(tail)
+    assert result["type"] == "pc_legacy"
+    assert result["tail_len"] == 0x2C4
+    assert result["key"] == (TAIL_FIXTURES / "ekey_pc_enc_v1_result.bin").read_bytes()
+
+    result = parakeet_crypto.parse_tail((TAIL_FIXTURES / "ekey_android_stag.bin").read_bytes())
+    assert result == {
+        "type": "android_stag",
+        "tail_len": 0x20,
+        "tag_version": 2,
+        "media_mid": "001y7CaR29k6YP",
+        "media_numeric_id": 5177785,
+        "extra_fields": [],
+    }
+
+
+def test_parse_tail_need_more_bytes():
+    with pytest.raises(parakeet_crypto.ParakeetError) as error:
+        parakeet_crypto.parse_tail(b"STag")
+    message, code = error.value.args
+    assert "need more bytes" in message
+    assert code == 101
+
+
+def test_decrypt_qmc1(tmp_path):
+    table = (ROOT / "src" / "crypto" / "tencent" / "qmc1.bin").read_bytes()
+    path_in = tmp_path / "test.qmcogg"
+    path_in.write_bytes(qmc_map_encrypt(table, SAMPLE))
+
+    assert parakeet_crypto.detect(path_in) == "qmc1"
+    assert parakeet_crypto.decrypt(path_in, tmp_path / "test.ogg") == "qmc1"
+    assert (tmp_path / "test.ogg").read_bytes() == SAMPLE
+
+
+def test_decrypt_qmc2_embedded_key(tmp_path):
+    tail = (TAIL_FIXTURES / "ekey_pc_enc_v2.bin").read_bytes()[-0x229:]
+    key = (TAIL_FIXTURES / "ekey_pc_enc_v2_result.bin").read_bytes()
+    path_in = tmp_path / "test.bin"
+    path_in.write_bytes(qmc_map_encrypt(qmc2_key_to_map_key(key), SAMPLE) + tail)
+
+    assert parakeet_crypto.detect(path_in) == "qmc2"
+    assert parakeet_crypto.decrypt(str(path_in), str(tmp_path / "test.ogg")) == "qmc2"
+    assert (tmp_path / "test.ogg").read_bytes() == SAMPLE
+
+
+def test_decrypt_qmc2_keystore(tmp_path):
+    ekey = ekey_from_pc_tail((TAIL_FIXTURES / "ekey_pc_enc_v2.bin").read_bytes())
+    key = (TAIL_FIXTURES / "ekey_pc_enc_v2_result.bin").read_bytes()
+    path_in = tmp_path / "test.mgg"
+    path_in.write_bytes(
+        qmc_map_encrypt(qmc2_key_to_map_key(key), SAMPLE) + make_stag("5177785,2,001y7CaR29k6YP")
+    )
+
+    with pytest.raises(parakeet_crypto.ParakeetError) as error:
+        parakeet_crypto.decrypt(path_in, tmp_path / "test.ogg")
+    assert "001y7CaR29k6YP" in error.value.args[0]
+    assert error.value.args[1] is None
+    assert not (tmp_path / "test.ogg").exists()
+
+    keystore = {"001y7CaR29k6YP": ekey.decode()}
+    assert parakeet_crypto.decrypt(path_in, tmp_path / "test.ogg", keystore) == "qmc2"
+    assert (tmp_path / "test.ogg").read_bytes() == SAMPLE
+
+
+def test_decrypt_kuwo_v1(tmp_path):
+    resource_id = 12345
+    scramble_key = (ROOT / "src" / "crypto" / "kuwo" / "v1_key.bin").read_bytes()
+    rid = str(resource_id).encode()
+    key = bytes(k ^ rid[i % len(rid)] for i, k in enumerate(scramble_key))
+
+    header = b"yeelion-kuwo-tme" + struct.pack("<III", 1, 0, resource_id)
+    header = header.ljust(0x400, b"\0")
+    encrypted = bytes(b ^ key[i % len(key)] for i, b in enumerate(SAMPLE))
+    path_in = tmp_path / "test.kwm"
+    path_in.write_bytes(header + encrypted)
+
+    assert parakeet_crypto.detect(path_in) == "kuwo"
+    assert parakeet_crypto.decrypt(path_in, tmp_path / "test.ogg") == "kuwo"
+    assert (tmp_path / "test.ogg").read_bytes() == SAMPLE
+
+
+def test_detect_kugou():
+    path = ROOT / "sample" / "test_kgm_v3.kgm"
+    assert parakeet_crypto.detect(path) == "kugou"
+
+
+def test_detect_unsupported(tmp_path):
+    path_in = tmp_path / "test.ogg"
+    path_in.write_bytes(SAMPLE)
+    assert parakeet_crypto.detect(path_in) is None
+
+    with pytest.raises(parakeet_crypto.ParakeetError):
+        parakeet_crypto.decrypt(path_in, tmp_path / "out.ogg")
+
+    with pytest.raises(FileNotFoundError):
+        parakeet_crypto.decrypt(tmp_path / "missing.mgg", tmp_path / "out.ogg")
+
+
+def test_decrypt_same_path(tmp_path):
+    path_in = tmp_path / "test.qmcogg"
+    data = (ROOT / "sample" / "test_qmc1.qmcogg").read_bytes()
+    path_in.write_bytes(data)
+
+    with pytest.raises(parakeet_crypto.ParakeetError):
+        parakeet_crypto.decrypt(path_in, tmp_path / "." / "test.qmcogg")
+    assert path_in.read_bytes() == data
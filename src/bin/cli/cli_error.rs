@@ -1,64 +1,109 @@
 use thiserror::Error;
 
-use parakeet_crypto::crypto::{kugou, kuwo, tencent, ximalaya_pc};
+#[cfg(feature = "kugou")]
+use parakeet_crypto::crypto::kugou;
+#[cfg(feature = "kuwo")]
+use parakeet_crypto::crypto::kuwo;
+#[cfg(feature = "tencent")]
+use parakeet_crypto::crypto::tencent;
+#[cfg(feature = "ximalaya-pc")]
+use parakeet_crypto::crypto::ximalaya_pc;
 
+// Only the I/O variants are left when Tencent and Kuwo are disabled.
+#[allow(clippy::enum_variant_names)]
 #[derive(Debug, Error)]
 pub enum ParakeetCliError {
     #[error("Source io error: {0}")]
     SourceIoError(std::io::Error),
     #[error("Destination io error: {0}")]
     DestinationIoError(std::io::Error),
+    #[cfg(feature = "tencent")]
+    #[error("Input from stdin exceeds the buffer limit ({0} bytes), use a file instead")]
+    StdinBufferLimitExceeded(usize),
+    #[cfg(any(feature = "mmkv", feature = "ximalaya-pc"))]
     #[error("Other I/O Error '{0}': {1}")]
     OtherIoError(std::path::PathBuf, std::io::Error),
 
-    #[error("QMC tail parse error: {0}")]
-    QMCTailParseError(tencent::metadata::TailParseError),
-    #[error("QMC EKey decryption failed: {0}")]
-    QMCKeyDecryptionError(tencent::ekey::KeyDecryptError),
-    #[error("Unable to extract key from QMC tail")]
-    QMCKeyRequired,
+    #[error("Stdin/stdout is not supported, use a file instead")]
+    StdioUnsupported,
 
-    #[error("Unable to parse mmkv file")]
-    MMKVParseError(mmkv_parser::Error),
+    #[error("Output '{0}' is the input file")]
+    OutputIsInput(std::path::PathBuf),
 
-    #[error("Unable to deserialize header: {0}")]
-    KugouHeaderDeserializeError(kugou::HeaderDeserializeError),
-    #[error("Cipher error: {0}")]
-    KugouCipherError(kugou::CipherError),
+    #[error("{0}")]
+    CryptoError(parakeet_crypto::Error),
+    #[error("{0}")]
+    DecryptError(parakeet_crypto::decrypt::DecryptError),
 
-    #[error("Failed to parse header.")]
-    KuwoHeaderParseError(kuwo::header::HeaderParseError),
-    #[error("Failed to init kuwo cipher: {0}")]
-    KuwoCipherInitError(kuwo::InitCipherError),
+    #[cfg(feature = "tencent")]
+    #[error("Unable to extract key from QMC tail")]
+    QMCKeyRequired,
+    #[cfg(feature = "tencent")]
+    #[error("Unable to detect QMCv2 cipher mode - is the key correct?")]
+    QMCModeDetectionError,
 
-    #[error("Cipher error: {0}")]
-    XimalayaPcError(ximalaya_pc::Error),
+    #[cfg(any(feature = "tencent", feature = "kugou"))]
+    #[error("Missing required argument: {0}")]
+    MissingArgument(&'static str),
 
     #[error("Unspecified error (placeholder)")]
     #[allow(dead_code)]
     UnspecifiedError,
 }
 
-impl From<kugou::CipherError> for ParakeetCliError {
-    fn from(error: kugou::CipherError) -> Self {
-        Self::KugouCipherError(error)
+#[cfg(feature = "tencent")]
+impl From<tencent::TailReadError> for ParakeetCliError {
+    fn from(error: tencent::TailReadError) -> Self {
+        match error {
+            tencent::TailReadError::IoError(error) => Self::SourceIoError(error),
+            error => Self::CryptoError(error.into()),
+        }
     }
 }
 
-impl From<kuwo::header::HeaderParseError> for ParakeetCliError {
-    fn from(error: kuwo::header::HeaderParseError) -> Self {
-        Self::KuwoHeaderParseError(error)
+impl From<parakeet_crypto::Error> for ParakeetCliError {
+    fn from(error: parakeet_crypto::Error) -> Self {
+        Self::CryptoError(error)
     }
 }
 
-impl From<kuwo::InitCipherError> for ParakeetCliError {
-    fn from(error: kuwo::InitCipherError) -> Self {
-        Self::KuwoCipherInitError(error)
+impl From<parakeet_crypto::decrypt::DecryptError> for ParakeetCliError {
+    fn from(error: parakeet_crypto::decrypt::DecryptError) -> Self {
+        Self::DecryptError(error)
     }
 }
 
-impl From<ximalaya_pc::Error> for ParakeetCliError {
-    fn from(error: ximalaya_pc::Error) -> Self {
-        Self::XimalayaPcError(error)
-    }
+macro_rules! impl_from_crypto_error {
+    ($($(#[$attr:meta])* $error:ty),+ $(,)?) => {
+        $(
+            $(#[$attr])*
+            impl From<$error> for ParakeetCliError {
+                fn from(error: $error) -> Self {
+                    Self::CryptoError(error.into())
+                }
+            }
+        )+
+    };
 }
+
+impl_from_crypto_error!(
+    parakeet_crypto::interfaces::DecryptorError,
+    #[cfg(feature = "tencent")]
+    tencent::ekey::KeyDecryptError,
+    #[cfg(feature = "tencent")]
+    tencent::ekey::KeyEncryptError,
+    #[cfg(feature = "tencent")]
+    tencent::metadata::TailParseError,
+    #[cfg(feature = "tencent")]
+    tencent::InitCipherError,
+    #[cfg(feature = "kugou")]
+    kugou::HeaderDeserializeError,
+    #[cfg(feature = "kugou")]
+    kugou::CipherError,
+    #[cfg(feature = "kuwo")]
+    kuwo::header::HeaderParseError,
+    #[cfg(feature = "kuwo")]
+    kuwo::InitCipherError,
+    #[cfg(feature = "ximalaya-pc")]
+    ximalaya_pc::Error,
+);
@@ -0,0 +1,49 @@
+use argh::FromArgs;
+
+use parakeet_crypto::decrypt::{decrypt_file, KeyStore};
+
+use crate::cli::cli_error::ParakeetCliError;
+use crate::cli::logger::CliLogger;
+use crate::cli::progress::ProgressBar;
+use crate::cli::utils::{CliBinaryContent, CliFilePath};
+
+/// Detect the format of a file, and decrypt it.
+#[derive(Debug, Eq, PartialEq, FromArgs)]
+#[argh(subcommand, name = "auto")]
+pub struct Options {
+    /// ekey, for files that do not include their key (QMCv2, KWMv2).
+    #[argh(option)]
+    ekey: Option<CliBinaryContent>,
+
+    /// input file name/path
+    #[argh(option, short = 'i')]
+    input_file: CliFilePath,
+
+    /// output file name/path
+    #[argh(option, short = 'o')]
+    output_file: CliFilePath,
+}
+
+pub fn handle(args: Options) -> Result<(), ParakeetCliError> {
+    let log = CliLogger::new("auto");
+
+    // The file is detected and decrypted in place, stdin and stdout are not supported.
+    if args.input_file.is_stdio() || args.output_file.is_stdio() {
+        Err(ParakeetCliError::StdioUnsupported)?;
+    }
+
+    let key_store = args
+        .ekey
+        .map(|ekey| move |_: &str| Some(ekey.content.clone()));
+    let mut progress = ProgressBar::new("decrypt");
+    let format = decrypt_file(
+        &args.input_file.path,
+        &args.output_file.path,
+        key_store.as_ref().map(|f| f as &KeyStore),
+        |p| progress.update(p),
+    )?;
+    progress.finish();
+
+    log.info(format!("decrypted as {}", format.name()));
+    Ok(())
+}
@@ -0,0 +1,462 @@
+use std::io::{Read, Write};
+
+#[cfg(any(feature = "tencent", feature = "kugou"))]
+use argh::FromArgValue;
+use argh::FromArgs;
+
+use parakeet_crypto::crypto::byte_offset_cipher::StreamProgress;
+#[cfg(feature = "kugou")]
+use parakeet_crypto::crypto::kugou;
+#[cfg(feature = "kuwo")]
+use parakeet_crypto::crypto::kuwo::header::{self as kuwo_header, KuwoHeader};
+#[cfg(feature = "tencent")]
+use parakeet_crypto::crypto::tencent::{
+    self, ekey,
+    metadata::{AndroidQTagMetadata, PcLegacyMetadata, TailParseResult},
+};
+use parakeet_crypto::encrypt;
+
+use crate::cli::cli_error::ParakeetCliError;
+#[cfg(feature = "ximalaya-android")]
+use crate::cli::cli_handle_ximalaya_android::CmdKeyType;
+use crate::cli::logger::CliLogger;
+use crate::cli::progress::ProgressBar;
+#[cfg(feature = "kugou")]
+use crate::cli::utils::CliBinaryArray;
+#[cfg(any(feature = "tencent", feature = "kugou"))]
+use crate::cli::utils::CliBinaryContent;
+#[cfg(feature = "tencent")]
+use crate::cli::utils::QMCKeyType;
+use crate::cli::utils::{create_output, ensure_distinct_paths, open_input, CliFilePath};
+
+/// Encrypt audio to the format of a client, e.g. to convert between formats.
+#[derive(Debug, PartialEq, FromArgs)]
+#[argh(subcommand, name = "encrypt")]
+pub struct Options {
+    #[argh(subcommand)]
+    command: SubCommand,
+}
+
+#[derive(Debug, PartialEq, FromArgs)]
+#[argh(subcommand)]
+enum SubCommand {
+    #[cfg(feature = "tencent")]
+    QMCv1(Qmc1Options),
+    #[cfg(feature = "tencent")]
+    QMCv2(Qmc2Options),
+    #[cfg(feature = "kugou")]
+    Kugou(KugouOptions),
+    #[cfg(feature = "kuwo")]
+    Kuwo(KuwoOptions),
+    #[cfg(feature = "ximalaya-android")]
+    XimalayaAndroid(XimalayaAndroidOptions),
+}
+
+/// Encrypt to QMCv1 (e.g. qmcflac).
+#[cfg(feature = "tencent")]
+#[derive(Debug, Eq, PartialEq, FromArgs)]
+#[argh(subcommand, name = "qmc1")]
+struct Qmc1Options {
+    /// input file name/path, "-" for stdin
+    #[argh(option, short = 'i', long = "input")]
+    input_file: CliFilePath,
+
+    /// output file name/path, "-" for stdout
+    #[argh(option, short = 'o', long = "output")]
+    output_file: CliFilePath,
+}
+
+#[cfg(feature = "tencent")]
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+enum CmdTailType {
+    None,
+    Pc,
+    QTag,
+}
+
+#[cfg(feature = "tencent")]
+impl FromArgValue for CmdTailType {
+    fn from_arg_value(value: &str) -> Result<Self, String> {
+        match value.to_lowercase().as_str() {
+            "none" => Ok(Self::None),
+            "pc" => Ok(Self::Pc),
+            "qtag" => Ok(Self::QTag),
+            _ => Err("Invalid tail type".into()),
+        }
+    }
+}
+
+/// Encrypt to QMCv2 (e.g. mflac/mgg).
+#[cfg(feature = "tencent")]
+#[derive(Debug, Eq, PartialEq, FromArgs)]
+#[argh(subcommand, name = "qmc2")]
+struct Qmc2Options {
+    /// encryption key
+    #[argh(option, short = 'k')]
+    key: CliBinaryContent,
+
+    /// key type, default to "ekey".
+    #[argh(option, short = 't', default = "QMCKeyType::EKey")]
+    key_type: QMCKeyType,
+
+    /// tail to append, default to "qtag".
+    /// "qtag" (Android) and "pc" (legacy PC client) embed the key as ekey; "none" omits
+    /// the tail, the key has to be provided to the client in another way.
+    #[argh(option, default = "CmdTailType::QTag")]
+    tail: CmdTailType,
+
+    /// copy the tail of an existing QMCv2 file instead; its key is replaced.
+    #[argh(option)]
+    tail_from: Option<CliFilePath>,
+
+    /// qtag: numeric resource id, default to 0.
+    #[argh(option, long = "rid", default = "0")]
+    resource_id: u64,
+
+    /// input file name/path, "-" for stdin
+    #[argh(option, short = 'i', long = "input")]
+    input_file: CliFilePath,
+
+    /// output file name/path, "-" for stdout
+    #[argh(option, short = 'o', long = "output")]
+    output_file: CliFilePath,
+}
+
+#[cfg(feature = "kugou")]
+#[derive(Debug, PartialEq, Eq, Clone)]
+struct CmdMediaType(kugou::MediaType);
+
+#[cfg(feature = "kugou")]
+impl FromArgValue for CmdMediaType {
+    fn from_arg_value(value: &str) -> Result<Self, String> {
+        match value.to_lowercase().as_str() {
+            "kgm" => Ok(Self(kugou::MediaType::KGM)),
+            "vpr" => Ok(Self(kugou::MediaType::VPR)),
+            _ => Err("Invalid type".into()),
+        }
+    }
+}
+
+/// Encrypt to KGM/VPR.
+#[cfg(feature = "kugou")]
+#[derive(Debug, Eq, PartialEq, FromArgs)]
+#[argh(subcommand, name = "kugou")]
+struct KugouOptions {
+    /// file type, "kgm" or "vpr". Default to "kgm", or the type of `--header-from`.
+    #[argh(option, short = 't', long = "type")]
+    media_type: Option<CmdMediaType>,
+
+    /// reuse the header (cipher version, key slot, file key) of an existing KGM/VPR file
+    #[argh(option)]
+    header_from: Option<CliFilePath>,
+
+    /// cipher version (2, 3 or 4), default to 3 for new headers
+    #[argh(option)]
+    crypto_version: Option<u32>,
+
+    /// key slot, default to 1 for new headers
+    #[argh(option)]
+    key_slot: Option<u32>,
+
+    /// 16-byte file key, required by new headers with cipher version 3 or 4
+    #[argh(option)]
+    file_key: Option<CliBinaryArray<16>>,
+
+    /// custom slot key, overrides the key selected by the header
+    #[argh(option)]
+    slot_key: Option<CliBinaryContent>,
+
+    /// input file name/path, "-" for stdin
+    #[argh(option, short = 'i', long = "input")]
+    input_file: CliFilePath,
+
+    /// output file name/path, "-" for stdout
+    #[argh(option, short = 'o', long = "output")]
+    output_file: CliFilePath,
+}
+
+#[cfg(feature = "kuwo")]
+fn parse_format_name(value: &str) -> Result<[u8; 12], String> {
+    let mut format_name = [0u8; 12];
+    match format_name.get_mut(..value.len()) {
+        Some(prefix) => prefix.copy_from_slice(value.as_bytes()),
+        None => Err("format name is longer than 12 bytes")?,
+    }
+    Ok(format_name)
+}
+
+/// Encrypt to KWM.
+#[cfg(feature = "kuwo")]
+#[derive(Debug, Eq, PartialEq, FromArgs)]
+#[argh(subcommand, name = "kuwo")]
+struct KuwoOptions {
+    /// reuse the header of an existing KWM file
+    #[argh(option)]
+    header_from: Option<CliFilePath>,
+
+    /// header version: 1 (KWMv1, default for new headers) or 2 (KWMv2, requires a key)
+    #[argh(option)]
+    version: Option<u32>,
+
+    /// kwm_v1: resource id, used to derive the key
+    #[argh(option, long = "rid")]
+    resource_id: Option<u32>,
+
+    /// format name with its quality id, e.g. "2000FLAC" or "320kmp3"
+    #[argh(option, from_str_fn(parse_format_name))]
+    format_name: Option<[u8; 12]>,
+
+    /// kwm_v2: encryption key
+    #[argh(option, short = 'k')]
+    key: Option<CliBinaryContent>,
+
+    /// kwm_v2: encryption key type, default to "ekey".
+    #[argh(option, short = 't', default = "QMCKeyType::EKey")]
+    key_type: QMCKeyType,
+
+    /// input file name/path, "-" for stdin
+    #[argh(option, short = 'i', long = "input")]
+    input_file: CliFilePath,
+
+    /// output file name/path, "-" for stdout
+    #[argh(option, short = 'o', long = "output")]
+    output_file: CliFilePath,
+}
+
+/// Encrypt to x2m/x3m.
+#[cfg(feature = "ximalaya-android")]
+#[derive(Debug, PartialEq, FromArgs)]
+#[argh(subcommand, name = "ximalaya-android")]
+struct XimalayaAndroidOptions {
+    /// x2m / x3m key. Accepted values are "x2m" and "x3m".
+    #[argh(option, short = 't', long = "type")]
+    key_type: CmdKeyType,
+
+    /// input file name/path, "-" for stdin
+    #[argh(option, short = 'i', long = "input")]
+    input_file: CliFilePath,
+
+    /// output file name/path, "-" for stdout
+    #[argh(option, short = 'o', long = "output")]
+    output_file: CliFilePath,
+}
+
+/// Read up to `len` bytes from the beginning of a file.
+#[cfg(any(feature = "kugou", feature = "kuwo"))]
+fn read_header(path: &CliFilePath, len: usize) -> Result<Vec<u8>, ParakeetCliError> {
+    let mut header = Vec::with_capacity(len);
+    std::fs::File::open(&path.path)
+        .and_then(|file| file.take(len as u64).read_to_end(&mut header))
+        .map_err(ParakeetCliError::SourceIoError)?;
+    Ok(header)
+}
+
+/// Run `encrypt` from the input to the output, with a progress bar.
+fn encrypt_file<F>(
+    log: &CliLogger,
+    input_file: &CliFilePath,
+    output_file: &CliFilePath,
+    encrypt: F,
+) -> Result<(), ParakeetCliError>
+where
+    F: FnOnce(
+        &mut dyn Read,
+        &mut dyn Write,
+        &mut dyn FnMut(StreamProgress),
+    ) -> parakeet_crypto::Result<usize>,
+{
+    ensure_distinct_paths(input_file, output_file)?;
+    let mut src = open_input(input_file)?;
+    let mut dst = create_output(output_file)?;
+
+    let total = src.len.map(|len| len as usize);
+    let mut progress = ProgressBar::new("encrypt");
+    let bytes_written = encrypt(&mut src.reader, &mut dst, &mut |p| {
+        progress.update(StreamProgress { total, ..p })
+    })?;
+    progress.finish();
+    dst.flush().map_err(ParakeetCliError::DestinationIoError)?;
+
+    log.info(format!("encrypt: done, encrypted {} bytes", bytes_written));
+    Ok(())
+}
+
+#[cfg(feature = "tencent")]
+fn handle_qmc1(args: Qmc1Options) -> Result<(), ParakeetCliError> {
+    let log = CliLogger::new("QMCv1");
+    encrypt_file(&log, &args.input_file, &args.output_file, |src, dst, p| {
+        encrypt::encrypt_qmc1(src, dst, p)
+    })
+}
+
+#[cfg(feature = "tencent")]
+fn handle_qmc2(args: Qmc2Options) -> Result<(), ParakeetCliError> {
+    let log = CliLogger::new("QMCv2");
+
+    let key = match args.key_type {
+        QMCKeyType::Key => args.key.content,
+        QMCKeyType::EKey => ekey::decrypt(args.key.content)?,
+    };
+
+    let tail = match (args.tail_from, args.tail) {
+        (Some(path), _) => {
+            let mut src =
+                std::fs::File::open(&path.path).map_err(ParakeetCliError::SourceIoError)?;
+            let (tail, _) = tencent::parse_tail_from_reader(&mut src)?;
+            match tail {
+                TailParseResult::PcLegacy(m) => Some(TailParseResult::PcLegacy(PcLegacyMetadata {
+                    key: key.clone(),
+                    ..m
+                })),
+                TailParseResult::AndroidQTag(m) => {
+                    Some(TailParseResult::AndroidQTag(AndroidQTagMetadata {
+                        key: key.clone(),
+                        ..m
+                    }))
+                }
+                tail => {
+                    log.warn("the tail does not embed a key: the client has to know it already");
+                    Some(tail)
+                }
+            }
+        }
+        (None, CmdTailType::None) => None,
+        (None, CmdTailType::Pc) => Some(TailParseResult::PcLegacy(PcLegacyMetadata {
+            tail_len: 0,
+            key: key.clone(),
+        })),
+        (None, CmdTailType::QTag) => Some(TailParseResult::AndroidQTag(AndroidQTagMetadata {
+            tail_len: 0,
+            key: key.clone(),
+            tag_version: 2,
+            resource_id: args.resource_id,
+            extra_fields: vec![],
+        })),
+    };
+
+    log.info(format!("key accepted (key_len={})", key.len()));
+    encrypt_file(&log, &args.input_file, &args.output_file, |src, dst, p| {
+        encrypt::encrypt_qmc2(src, dst, &key, tail.as_ref(), p)
+    })
+}
+
+#[cfg(feature = "kugou")]
+fn handle_kugou(args: KugouOptions) -> Result<(), ParakeetCliError> {
+    let log = CliLogger::new("Kugou");
+
+    let mut hdr = match &args.header_from {
+        Some(path) => {
+            let header = read_header(path, kugou::DEFAULT_HEADER_LEN as usize)?;
+            let hdr = kugou::Header::from_bytes(&header)?;
+            // Keep all of `extra` when the header is longer than usual.
+            match hdr.header_len as usize > header.len() {
+                true => kugou::Header::from_bytes(read_header(path, hdr.header_len as usize)?)?,
+                false => hdr,
+            }
+        }
+        None => {
+            let mut hdr = kugou::Header::new(kugou::MediaType::KGM);
+            hdr.crypto_version = 3;
+            hdr.key_slot = 1;
+            hdr
+        }
+    };
+    if let Some(media_type) = args.media_type {
+        hdr.set_file_type(media_type.0);
+    }
+    if let Some(crypto_version) = args.crypto_version {
+        hdr.crypto_version = crypto_version;
+    }
+    if let Some(key_slot) = args.key_slot {
+        hdr.key_slot = key_slot;
+    }
+    match args.file_key {
+        Some(file_key) => hdr.file_key = file_key.content,
+        None if args.header_from.is_none() && hdr.crypto_version >= 3 => {
+            Err(ParakeetCliError::MissingArgument("--file-key"))?
+        }
+        None => {}
+    }
+
+    let cipher = match args.slot_key {
+        Some(slot_key) => kugou::CipherModes::with_slot_key_unchecked(&hdr, slot_key.content)?,
+        None => {
+            let slot_key = kugou::get_slot_key(hdr.key_slot)
+                .ok_or(kugou::CipherError::SlotKeyMissing(hdr.key_slot))?;
+            kugou::CipherModes::with_slot_key_unchecked(&hdr, slot_key)?
+        }
+    };
+    log.info(format!(
+        "header: type={:?}, crypto=v{}, key_slot={}",
+        hdr.get_file_type(),
+        hdr.crypto_version,
+        hdr.key_slot
+    ));
+
+    encrypt_file(&log, &args.input_file, &args.output_file, |src, dst, p| {
+        encrypt::encrypt_kugou(src, dst, &hdr, cipher, p)
+    })
+}
+
+#[cfg(feature = "kuwo")]
+fn handle_kuwo(args: KuwoOptions) -> Result<(), ParakeetCliError> {
+    let log = CliLogger::new("KWM");
+
+    let mut hdr = match &args.header_from {
+        Some(path) => KuwoHeader::from_bytes(read_header(path, kuwo_header::HEADER_FIXED_LEN)?)?,
+        None => KuwoHeader {
+            magic: kuwo_header::MAGIC_1,
+            version: 1,
+            ..Default::default()
+        },
+    };
+    if let Some(version) = args.version {
+        hdr.version = version;
+    }
+    if let Some(resource_id) = args.resource_id {
+        hdr.resource_id = resource_id;
+    }
+    if let Some(format_name) = args.format_name {
+        hdr.format_name = format_name;
+    }
+
+    let key = match (args.key, args.key_type) {
+        (Some(user_key), QMCKeyType::Key) => Some(user_key.content),
+        (Some(user_key), QMCKeyType::EKey) => Some(ekey::decrypt(user_key.content)?),
+        (None, _) => None,
+    };
+    log.info(format!(
+        "kwm(version={}, resource_id={}, quality_id={}, format={})",
+        hdr.version,
+        hdr.resource_id,
+        hdr.get_quality_id(),
+        hdr.get_format_suffix(),
+    ));
+
+    encrypt_file(&log, &args.input_file, &args.output_file, |src, dst, p| {
+        encrypt::encrypt_kuwo(src, dst, &hdr, key.as_deref(), p)
+    })
+}
+
+#[cfg(feature = "ximalaya-android")]
+fn handle_ximalaya_android(args: XimalayaAndroidOptions) -> Result<(), ParakeetCliError> {
+    let log = CliLogger::new("Ximalaya (Android)");
+    encrypt_file(&log, &args.input_file, &args.output_file, |src, dst, p| {
+        encrypt::encrypt_ximalaya_android(src, dst, args.key_type.0, p)
+    })
+}
+
+pub fn handle(args: Options) -> Result<(), ParakeetCliError> {
+    match args.command {
+        #[cfg(feature = "tencent")]
+        SubCommand::QMCv1(options) => handle_qmc1(options),
+        #[cfg(feature = "tencent")]
+        SubCommand::QMCv2(options) => handle_qmc2(options),
+        #[cfg(feature = "kugou")]
+        SubCommand::Kugou(options) => handle_kugou(options),
+        #[cfg(feature = "kuwo")]
+        SubCommand::Kuwo(options) => handle_kuwo(options),
+        #[cfg(feature = "ximalaya-android")]
+        SubCommand::XimalayaAndroid(options) => handle_ximalaya_android(options),
+    }
+}
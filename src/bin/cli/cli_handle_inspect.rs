@@ -0,0 +1,266 @@
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+
+use argh::FromArgs;
+use serde::Serialize;
+use serde_json::Value;
+
+#[cfg(any(feature = "tencent", feature = "kugou", feature = "kuwo"))]
+use parakeet_crypto::crypto::byte_offset_cipher::ByteOffsetDecipher;
+#[cfg(feature = "kugou")]
+use parakeet_crypto::crypto::kugou;
+#[cfg(feature = "kuwo")]
+use parakeet_crypto::crypto::kuwo::{header::KuwoHeader, header::HEADER_FIXED_LEN, KuwoBuilder};
+#[cfg(feature = "tencent")]
+use parakeet_crypto::crypto::tencent;
+#[cfg(feature = "ximalaya-android")]
+use parakeet_crypto::crypto::ximalaya_android::{
+    self, keys::Type as XimalayaType, keys::SCRAMBLED_HEADER_LEN,
+};
+#[cfg(feature = "ximalaya-pc")]
+use parakeet_crypto::crypto::ximalaya_pc;
+use parakeet_crypto::detect::detect;
+#[cfg(any(
+    feature = "tencent",
+    feature = "kugou",
+    feature = "kuwo",
+    feature = "ximalaya-android",
+    feature = "ximalaya-pc"
+))]
+use parakeet_crypto::detect::Format;
+#[cfg(any(feature = "kugou", feature = "ximalaya-pc"))]
+use parakeet_crypto::detect::DETECTION_HEADER_LEN;
+use parakeet_crypto::utils::audio::{detect_audio_type, AUDIO_DETECTION_LEN};
+
+use crate::cli::cli_error::ParakeetCliError;
+use crate::cli::utils::CliFilePath;
+
+/// Detect the format of a file, and print its header, tail and key information as JSON.
+#[derive(Debug, Eq, PartialEq, FromArgs)]
+#[argh(subcommand, name = "inspect")]
+pub struct Options {
+    /// input file name/path
+    #[argh(positional)]
+    input_file: CliFilePath,
+}
+
+#[derive(Debug, Default, Serialize)]
+struct Report {
+    file: String,
+    file_size: u64,
+    /// Name of the `parakeet_cli` subcommand for the format, `None` if unknown.
+    format: Option<&'static str>,
+    /// Parsed file header.
+    header: Option<Value>,
+    /// Parsed tail metadata.
+    tail: Option<Value>,
+    /// Where the key comes from: "static", "tail", "slot_key", "resource_id" or "header".
+    /// `None` if the key has to be provided.
+    key_source: Option<&'static str>,
+    /// Audio container, when the file can be decrypted without an external key.
+    audio_type: Option<&'static str>,
+}
+
+/// Details of a format: everything in [`Report`] that requires parsing the file.
+#[derive(Default)]
+struct Details {
+    header: Option<Value>,
+    tail: Option<Value>,
+    key_source: Option<&'static str>,
+    /// Beginning of the decrypted audio.
+    audio_head: Option<Vec<u8>>,
+}
+
+fn read_at(src: &mut File, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+    let mut buffer = Vec::with_capacity(len);
+    src.seek(SeekFrom::Start(offset))?;
+    src.take(len as u64).read_to_end(&mut buffer)?;
+    Ok(buffer)
+}
+
+#[cfg(any(
+    feature = "tencent",
+    feature = "kugou",
+    feature = "kuwo",
+    feature = "ximalaya-pc"
+))]
+fn to_value<T: Serialize>(value: &T) -> Value {
+    // Headers and tails only contain strings, numbers and lists.
+    serde_json::to_value(value).expect("header should serialize to JSON")
+}
+
+#[cfg(any(feature = "tencent", feature = "kugou", feature = "kuwo"))]
+fn decrypt_head<C: ByteOffsetDecipher>(
+    src: &mut File,
+    cipher: &C,
+    offset: u64,
+) -> io::Result<Vec<u8>> {
+    let mut head = read_at(src, offset, AUDIO_DETECTION_LEN)?;
+    cipher.decipher_buffer(0, &mut head);
+    Ok(head)
+}
+
+#[cfg(feature = "tencent")]
+fn inspect_qmc1(src: &mut File) -> Result<Details, ParakeetCliError> {
+    let head = decrypt_head(src, &tencent::QMCv1::new(), 0);
+    Ok(Details {
+        key_source: Some("static"),
+        audio_head: Some(head.map_err(ParakeetCliError::SourceIoError)?),
+        ..Default::default()
+    })
+}
+
+#[cfg(feature = "tencent")]
+fn inspect_qmc2(src: &mut File) -> Result<Details, ParakeetCliError> {
+    // Files detected by their extension may not have a tail.
+    let tail = match tencent::parse_tail_from_reader(src) {
+        Ok((tail, _)) => tail,
+        Err(tencent::TailReadError::IoError(err)) => Err(ParakeetCliError::SourceIoError(err))?,
+        Err(_) => return Ok(Details::default()),
+    };
+
+    let mut details = Details {
+        tail: Some(to_value(&tail)),
+        ..Default::default()
+    };
+    if let Some(key) = tail.get_key() {
+        let cipher = tencent::QMCv2::try_new(key)?;
+        details.key_source = Some("tail");
+        details.audio_head =
+            Some(decrypt_head(src, &cipher, 0).map_err(ParakeetCliError::SourceIoError)?);
+    }
+    Ok(details)
+}
+
+#[cfg(feature = "kugou")]
+fn inspect_kugou(src: &mut File) -> Result<Details, ParakeetCliError> {
+    let head = read_at(src, 0, DETECTION_HEADER_LEN).map_err(ParakeetCliError::SourceIoError)?;
+    let hdr = kugou::Header::from_bytes(head)?;
+
+    let mut details = Details {
+        header: Some(to_value(&hdr)),
+        ..Default::default()
+    };
+    // Unknown slots require a custom slot key.
+    if let Ok(cipher) = kugou::Kugou::new(&hdr) {
+        let head = decrypt_head(src, &cipher, hdr.header_len.into());
+        details.key_source = Some("slot_key");
+        details.audio_head = Some(head.map_err(ParakeetCliError::SourceIoError)?);
+    }
+    Ok(details)
+}
+
+#[cfg(feature = "kuwo")]
+fn inspect_kuwo(src: &mut File) -> Result<Details, ParakeetCliError> {
+    let head = read_at(src, 0, HEADER_FIXED_LEN).map_err(ParakeetCliError::SourceIoError)?;
+    let hdr = KuwoHeader::from_bytes(&head)?;
+
+    let mut details = Details {
+        header: Some(to_value(&hdr)),
+        ..Default::default()
+    };
+    // KWMv2 requires an ekey.
+    if let Ok((cipher, _)) = KuwoBuilder::new(&head).build() {
+        let head = decrypt_head(src, &cipher, HEADER_FIXED_LEN as u64);
+        details.key_source = Some("resource_id");
+        details.audio_head = Some(head.map_err(ParakeetCliError::SourceIoError)?);
+    }
+    Ok(details)
+}
+
+#[cfg(feature = "ximalaya-android")]
+fn inspect_ximalaya_android(
+    src: &mut File,
+    key_type: XimalayaType,
+) -> Result<Details, ParakeetCliError> {
+    let mut head = [0u8; SCRAMBLED_HEADER_LEN];
+    src.seek(SeekFrom::Start(0))
+        .and_then(|_| src.read_exact(&mut head))
+        .map_err(ParakeetCliError::SourceIoError)?;
+
+    let (content_key, scramble_table) = ximalaya_android::keys::get_key(key_type);
+    let head = ximalaya_android::decrypt_header(&head, content_key, scramble_table);
+    Ok(Details {
+        header: Some(serde_json::json!({ "type": key_type })),
+        key_source: Some("static"),
+        audio_head: Some(head.to_vec()),
+        ..Default::default()
+    })
+}
+
+#[cfg(feature = "ximalaya-pc")]
+fn inspect_ximalaya_pc(src: &mut File) -> Result<Details, ParakeetCliError> {
+    let head = read_at(src, 0, DETECTION_HEADER_LEN).map_err(ParakeetCliError::SourceIoError)?;
+    let hdr = match ximalaya_pc::Header::from_bytes(&head) {
+        Err(ximalaya_pc::Error::InputTooSmall(n, _)) => {
+            let head = read_at(src, 0, n).map_err(ParakeetCliError::SourceIoError)?;
+            ximalaya_pc::Header::from_bytes(head)?
+        }
+        res => res?,
+    };
+
+    let part_2 = read_at(src, hdr.data_start_offset as u64, hdr.encrypted_header_len)
+        .map_err(ParakeetCliError::SourceIoError)?;
+    let mut audio_head = hdr.stolen_header_bytes.to_vec();
+    audio_head.extend(ximalaya_pc::decipher_part_2(&hdr, &part_2)?);
+
+    Ok(Details {
+        header: Some(to_value(&hdr)),
+        key_source: Some("header"),
+        audio_head: Some(audio_head),
+        ..Default::default()
+    })
+}
+
+pub fn handle(args: Options) -> Result<(), ParakeetCliError> {
+    let path = args.input_file.path;
+    let mut src = File::open(&path).map_err(ParakeetCliError::SourceIoError)?;
+    let file_size = src
+        .seek(SeekFrom::End(0))
+        .map_err(ParakeetCliError::SourceIoError)?;
+
+    let extension = path.extension().map(|ext| ext.to_string_lossy());
+    let format = detect(&mut src, extension.as_deref()).map_err(ParakeetCliError::SourceIoError)?;
+
+    let details = match format {
+        #[cfg(feature = "tencent")]
+        Some(Format::QMCv1) => inspect_qmc1(&mut src)?,
+        #[cfg(feature = "tencent")]
+        Some(Format::QMCv2) => inspect_qmc2(&mut src)?,
+        #[cfg(feature = "kugou")]
+        Some(Format::Kugou) => inspect_kugou(&mut src)?,
+        #[cfg(feature = "kuwo")]
+        Some(Format::Kuwo) => inspect_kuwo(&mut src)?,
+        #[cfg(feature = "ximalaya-android")]
+        Some(Format::XimalayaAndroid(key_type)) => inspect_ximalaya_android(&mut src, key_type)?,
+        #[cfg(feature = "ximalaya-pc")]
+        Some(Format::XimalayaPc) => inspect_ximalaya_pc(&mut src)?,
+        // Unknown, or not encrypted.
+        None => Details {
+            audio_head: Some(
+                read_at(&mut src, 0, AUDIO_DETECTION_LEN)
+                    .map_err(ParakeetCliError::SourceIoError)?,
+            ),
+            ..Default::default()
+        },
+    };
+
+    let report = Report {
+        file: path.to_string_lossy().into_owned(),
+        file_size,
+        format: format.map(|format| format.name()),
+        header: details.header,
+        tail: details.tail,
+        key_source: details.key_source,
+        audio_type: details
+            .audio_head
+            .and_then(detect_audio_type)
+            .map(|audio_type| audio_type.extension()),
+    };
+
+    serde_json::to_writer_pretty(io::stdout().lock(), &report)
+        .map_err(|err| ParakeetCliError::DestinationIoError(err.into()))?;
+    println!();
+
+    Ok(())
+}
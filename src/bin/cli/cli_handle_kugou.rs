@@ -1,5 +1,4 @@
-use std::fs::File;
-use std::io::{Read, Seek, SeekFrom};
+use std::io::{self, Cursor, Read};
 
 use argh::FromArgs;
 
@@ -7,17 +6,24 @@ use parakeet_crypto::crypto::kugou;
 
 use crate::cli::cli_error::ParakeetCliError;
 use crate::cli::logger::CliLogger;
-use crate::cli::utils::{decrypt_file_stream, CliFilePath};
+use crate::cli::utils::{
+    create_output, decrypt_file_stream, ensure_distinct_paths, open_input, CliBinaryContent,
+    CliFilePath,
+};
 
 /// Handle Kugou encryption/decryption.
 #[derive(Debug, Eq, PartialEq, FromArgs)]
 #[argh(subcommand, name = "kugou")]
 pub struct Options {
-    /// input file name/path
+    /// custom slot key, overrides the key selected by the header
+    #[argh(option)]
+    slot_key: Option<CliBinaryContent>,
+
+    /// input file name/path, "-" for stdin
     #[argh(option, short = 'i', long = "input")]
     input_file: CliFilePath,
 
-    /// output file name/path
+    /// output file name/path, "-" for stdout
     #[argh(option, short = 'o', long = "output")]
     output_file: CliFilePath,
 }
@@ -25,25 +31,64 @@ pub struct Options {
 pub fn handle(args: Options) -> Result<(), ParakeetCliError> {
     let log = CliLogger::new("Kugou");
 
-    let mut src = File::open(args.input_file.path).map_err(ParakeetCliError::SourceIoError)?;
-    let mut dst =
-        File::create(args.output_file.path).map_err(ParakeetCliError::DestinationIoError)?;
+    ensure_distinct_paths(&args.input_file, &args.output_file)?;
+    let mut src = open_input(&args.input_file)?;
+    let mut dst = create_output(&args.output_file)?;
 
     let mut hdr_bytes = vec![0u8; 1024];
-    src.read_exact(&mut hdr_bytes)
+    src.reader
+        .read_exact(&mut hdr_bytes)
         .map_err(ParakeetCliError::SourceIoError)?;
-    let hdr = kugou::Header::from_bytes(hdr_bytes)
-        .map_err(ParakeetCliError::KugouHeaderDeserializeError)?;
+    let hdr = kugou::Header::from_bytes(&hdr_bytes)?;
     log.debug(format!(
-        "header: crypto=v{}, hdr_len={}, key_slot={}",
-        hdr.crypto_version, hdr.header_len, hdr.key_slot
+        "header: type={:?}, crypto=v{}, hdr_len={}, key_slot={}",
+        hdr.get_file_type(),
+        hdr.crypto_version,
+        hdr.header_len,
+        hdr.key_slot
     ));
-    let cipher = kugou::CipherModes::new(&hdr)?;
+    let cipher = match args.slot_key {
+        Some(slot_key) => kugou::Kugou::with_slot_key(&hdr, slot_key.content)?,
+        None => kugou::Kugou::new(&hdr)?,
+    };
 
-    src.seek(SeekFrom::Start(hdr.header_len.into()))
-        .map_err(ParakeetCliError::SourceIoError)?;
+    let header_len = hdr.header_len as usize;
+    let payload_len = src
+        .len
+        .map(|len| len.saturating_sub(header_len as u64) as usize);
+    if let Some(payload_len) = payload_len {
+        cipher.check_len(payload_len)?;
+    }
+
+    // Skip the rest of the header, or keep the audio that was read along with it.
+    let audio_head = match header_len.checked_sub(hdr_bytes.len()) {
+        Some(skip_len) => {
+            io::copy(
+                &mut (&mut src.reader).take(skip_len as u64),
+                &mut io::sink(),
+            )
+            .map_err(ParakeetCliError::SourceIoError)?;
+            vec![]
+        }
+        None => hdr_bytes.split_off(header_len),
+    };
+    let mut reader = Cursor::new(audio_head).chain(src.reader);
 
-    let bytes_written = decrypt_file_stream(&log, cipher, &mut dst, &mut src, 0, None)?;
+    // Stop at the length supported by the cipher (e.g. stdin, whose size is unknown).
+    let max_len = cipher.max_len();
+    let mut reader = reader
+        .by_ref()
+        .take(max_len.map_or(u64::MAX, |len| len as u64));
+    let bytes_written =
+        decrypt_file_stream(&log, cipher.clone(), &mut dst, &mut reader, 0, payload_len)?;
+    if max_len.is_some() {
+        let mut extra = [0u8; 1];
+        let n = reader
+            .into_inner()
+            .read(&mut extra)
+            .map_err(ParakeetCliError::SourceIoError)?;
+        cipher.check_len(bytes_written + n)?;
+    }
     log.info(format!("decrypt: done, written {} bytes", bytes_written));
 
     Ok(())
@@ -1,24 +1,30 @@
+#[cfg(feature = "mmkv")]
 use std::fs::File;
-use std::io::{Read, Seek, SeekFrom};
+use std::io::{Cursor, Read};
+use std::path::Path;
+#[cfg(feature = "mmkv")]
 use std::path::PathBuf;
 
 use argh::FromArgs;
+#[cfg(feature = "mmkv")]
 use mmkv_parser::mmkv::ParseControl;
 
-use parakeet_crypto::crypto::kuwo::{header, Kuwo};
-use parakeet_crypto::crypto::tencent::ekey;
+#[cfg(feature = "mmkv")]
+use parakeet_crypto::crypto::kuwo::KuwoKeyStore;
+use parakeet_crypto::crypto::kuwo::{header, v1, KuwoBuilder};
+use parakeet_crypto::tagging::{provenance, TagWriter};
 use parakeet_crypto::utils::validate::is_digits_str;
 
 use crate::cli::cli_error::ParakeetCliError;
-use crate::cli::utils::{decrypt_file_stream, QMCKeyType};
+use crate::cli::utils::{
+    create_output, decrypt_file_stream, ensure_distinct_paths, finish_tags, open_input, QMCKeyType,
+};
 
 use super::{
     logger::CliLogger,
     utils::{CliBinaryContent, CliFilePath},
 };
 
-// TODO: Add support to read from mmkv store.
-
 /// Handle Kuwo KWMv1 & KWMv2 files.
 #[derive(Debug, Eq, PartialEq, FromArgs)]
 #[argh(subcommand, name = "kuwo")]
@@ -31,113 +37,169 @@ pub struct Options {
     #[argh(option, short = 't', default = "QMCKeyType::EKey")]
     key_type: QMCKeyType,
 
+    /// kwm_v1: resource id to use instead of the one from the header
+    #[argh(option, long = "rid")]
+    resource_id: Option<u32>,
+
+    /// kwm_v1: verify the resource id against the decrypted audio, and try the
+    /// numeric file name (e.g. `<rid>.kwm`) as a fallback.
+    #[argh(switch)]
+    verify: bool,
+
+    /// write the info from the header (resource id, format name) to the tags of
+    /// the output (FLAC, Ogg, MP3 or M4A).
+    #[argh(switch)]
+    write_tags: bool,
+
     /// path to the mmkv store
+    #[cfg(feature = "mmkv")]
     #[argh(option, short = 'm', long = "mmkv")]
     mmkv_path: Option<PathBuf>,
 
-    /// input file name/path
+    /// input file name/path, "-" for stdin
     #[argh(option, short = 'i', long = "input")]
     input_file: CliFilePath,
 
-    /// output file name/path
+    /// output file name/path, "-" for stdout
     #[argh(option, short = 'o', long = "output")]
     output_file: CliFilePath,
 }
 
-fn find_key(
-    log: &CliLogger,
-    hdr: &header::KuwoHeader,
-    mmkv_path: &PathBuf,
-) -> Result<Option<Box<[u8]>>, ParakeetCliError> {
-    let mut mmkv_data = Vec::with_capacity(4096);
-
-    log.debug("read mmkv file to memory...");
-    File::open(mmkv_path)
-        .map_err(|err| ParakeetCliError::OtherIoError(mmkv_path.clone(), err))?
-        .read_to_end(&mut mmkv_data)
-        .map_err(|err| ParakeetCliError::OtherIoError(mmkv_path.clone(), err))?;
-
-    let needle = format!("sec_ekey#{}-{}", hdr.resource_id, hdr.get_quality_id());
-    log.debug(format!("ekey search needle: {}", needle));
-
-    let mut ekey = None;
-    mmkv_parser::mmkv::parse_callback(&mmkv_data, |k, v| {
-        // It should either key by prefix exactly, or followed by a non-digit character.
-        if let Some(suffix) = k.strip_prefix(needle.as_bytes()) {
-            if suffix.is_empty() || !is_digits_str(&suffix[..1]) {
-                log.debug(format!("pick ekey from: {}", String::from_utf8_lossy(k)));
-                ekey = Some(v);
-            } else {
-                log.debug(format!("ignore [{}]", String::from_utf8_lossy(k)));
+#[cfg(feature = "mmkv")]
+struct MmkvKeyStore<'a> {
+    log: &'a CliLogger,
+    mmkv_data: Vec<u8>,
+}
+
+#[cfg(feature = "mmkv")]
+impl<'a> MmkvKeyStore<'a> {
+    fn open(log: &'a CliLogger, mmkv_path: &PathBuf) -> Result<Self, ParakeetCliError> {
+        let mut mmkv_data = Vec::with_capacity(4096);
+
+        log.debug("read mmkv file to memory...");
+        File::open(mmkv_path)
+            .map_err(|err| ParakeetCliError::OtherIoError(mmkv_path.clone(), err))?
+            .read_to_end(&mut mmkv_data)
+            .map_err(|err| ParakeetCliError::OtherIoError(mmkv_path.clone(), err))?;
+
+        Ok(Self { log, mmkv_data })
+    }
+
+    fn find_ekey(&self, hdr: &header::KuwoHeader) -> Result<Option<Box<[u8]>>, mmkv_parser::Error> {
+        let log = self.log;
+        let needle = format!("sec_ekey#{}-{}", hdr.resource_id, hdr.get_quality_id());
+        log.debug(format!("ekey search needle: {}", needle));
+
+        let mut ekey = None;
+        mmkv_parser::mmkv::parse_callback(&self.mmkv_data, |k, v| {
+            // It should either key by prefix exactly, or followed by a non-digit character.
+            if let Some(suffix) = k.strip_prefix(needle.as_bytes()) {
+                if suffix.is_empty() || !is_digits_str(&suffix[..1]) {
+                    log.debug(format!("pick ekey from: {}", String::from_utf8_lossy(k)));
+                    ekey = Some(v);
+                } else {
+                    log.debug(format!("ignore [{}]", String::from_utf8_lossy(k)));
+                }
             }
-        }
-        ParseControl::Continue
-    })
-    .map_err(ParakeetCliError::MMKVParseError)?;
+            ParseControl::Continue
+        })?;
 
-    let key = match ekey {
-        // No ekey found
-        None => None,
+        match ekey {
+            // No ekey found
+            None => Ok(None),
 
-        // found ekey, decrypt it.
-        Some(mmkv_ekey) => {
-            let (_, mmkv_ekey) = mmkv_parser::mmkv::read_container(mmkv_ekey)
-                .map_err(ParakeetCliError::MMKVParseError)?;
-            Some(ekey::decrypt(mmkv_ekey).map_err(ParakeetCliError::QMCKeyDecryptionError)?)
+            // found ekey, unwrap it from its container.
+            Some(mmkv_ekey) => {
+                let (_, mmkv_ekey) = mmkv_parser::mmkv::read_container(mmkv_ekey)?;
+                Ok(Some(mmkv_ekey.into()))
+            }
         }
-    };
+    }
+}
 
-    Ok(key)
+#[cfg(feature = "mmkv")]
+impl KuwoKeyStore for MmkvKeyStore<'_> {
+    fn get_ekey(&self, hdr: &header::KuwoHeader) -> Option<Box<[u8]>> {
+        self.find_ekey(hdr).unwrap_or_else(|err| {
+            self.log.warn(format!("Unable to parse mmkv file: {}", err));
+            None
+        })
+    }
+}
+
+/// Resource id from a file name like `1234.kwm`.
+fn resource_id_from_path(path: &Path) -> Option<u32> {
+    path.file_stem()
+        .and_then(|stem| stem.to_str())
+        .filter(|stem| is_digits_str(stem))
+        .and_then(|stem| stem.parse().ok())
 }
 
 pub fn handle(args: Options) -> Result<(), ParakeetCliError> {
     let log = CliLogger::new("KWM");
 
-    let mut src = File::open(args.input_file.path).map_err(ParakeetCliError::SourceIoError)?;
-    let mut dst =
-        File::create(args.output_file.path).map_err(ParakeetCliError::DestinationIoError)?;
+    ensure_distinct_paths(&args.input_file, &args.output_file)?;
+    let mut src = open_input(&args.input_file)?;
+    let dst = create_output(&args.output_file)?;
 
-    // Parse header
-    let mut header_buf = [0u8; header::HEADER_PARSE_REQUIRED_LEN];
-    src.read(&mut header_buf)
-        .map_err(ParakeetCliError::SourceIoError)?;
-    src.seek(SeekFrom::Start(header::HEADER_FIXED_LEN as u64))
+    let mut header_buf = [0u8; header::HEADER_FIXED_LEN];
+    src.reader
+        .read_exact(&mut header_buf)
         .map_err(ParakeetCliError::SourceIoError)?;
 
-    let hdr = header::KuwoHeader::from_bytes(header_buf)?;
+    #[cfg(feature = "mmkv")]
+    let key_store = match &args.mmkv_path {
+        Some(mmkv_path) => Some(MmkvKeyStore::open(&log, mmkv_path)?),
+        None => None,
+    };
+
+    // Key from cli has priority, then mmkv.
+    let mut builder = KuwoBuilder::new(header_buf);
+    builder = match (args.key, args.key_type) {
+        (Some(user_key), QMCKeyType::Key) => builder.with_key(user_key.content),
+        (Some(user_key), QMCKeyType::EKey) => builder.with_ekey(user_key.content),
+        (None, _) => builder,
+    };
+    #[cfg(feature = "mmkv")]
+    if let Some(key_store) = &key_store {
+        builder = builder.with_key_store(key_store);
+    }
+    if let Some(resource_id) = args.resource_id {
+        builder = builder.with_resource_id(resource_id);
+    }
+    // Read ahead the audio to verify; it is put back in front of the stream.
+    let mut encrypted_head = vec![];
+    if args.verify {
+        src.reader
+            .by_ref()
+            .take(v1::VERIFY_LEN as u64)
+            .read_to_end(&mut encrypted_head)
+            .map_err(ParakeetCliError::SourceIoError)?;
+
+        let candidates = resource_id_from_path(&args.input_file.path);
+        builder = builder.with_verification(&encrypted_head, candidates);
+    }
+
+    let (cipher, hdr) = builder.build()?;
     log.info(format!(
-        "kwm(version={}, resource_id={}, quality_id={})",
+        "kwm(version={}, resource_id={}, quality_id={}, format={})",
         hdr.version,
         hdr.resource_id,
-        hdr.get_quality_id()
+        hdr.get_quality_id(),
+        hdr.get_format_suffix(),
     ));
 
-    // Key from cli has priority
-    let key = match args.key {
-        // we got a key, let's use it.
-        Some(user_key) => match args.key_type {
-            QMCKeyType::Key => Some(user_key.content),
-            QMCKeyType::EKey => Some(
-                ekey::decrypt(user_key.content).map_err(ParakeetCliError::QMCKeyDecryptionError)?,
-            ),
-        },
-        // no key? try mmkv
-        None => match args.mmkv_path {
-            // no mmkv? ignore
-            None => None,
-            // try to find the ekey and get its decrypted form
-            Some(mmkv_path) => find_key(&log, &hdr, &mmkv_path)?,
-        },
+    let payload_len = src
+        .len
+        .map(|len| (len as usize).saturating_sub(header::HEADER_FIXED_LEN));
+    let mut reader = Cursor::new(encrypted_head).chain(src.reader);
+    let tags = match args.write_tags {
+        true => provenance::from_kuwo_header(&hdr),
+        false => vec![],
     };
-
-    #[cfg(debug_assertions)]
-    if let Some(key_inner) = &key {
-        log.debug(format!("key accepted (key_len={})", key_inner.len()));
-    }
-
-    let cipher = Kuwo::from_header(&hdr, key)?;
-
-    let bytes_written = decrypt_file_stream(&log, cipher, &mut dst, &mut src, 0, None)?;
+    let mut dst = TagWriter::new(dst, tags);
+    let bytes_written = decrypt_file_stream(&log, cipher, &mut dst, &mut reader, 0, payload_len)?;
+    finish_tags(&log, dst)?;
     log.info(format!("decrypt: done, written {} bytes", bytes_written));
 
     Ok(())
@@ -1,34 +1,100 @@
 use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
 
 use argh::FromArgs;
 
-use parakeet_crypto::crypto::tencent::QMCv1;
+use parakeet_crypto::crypto::byte_offset_cipher::ByteOffsetDecipher;
+use parakeet_crypto::crypto::tencent::{variants, QMCv1};
+use parakeet_crypto::utils::audio::{detect_audio_type, AUDIO_DETECTION_LEN};
 
 use crate::cli::cli_error::ParakeetCliError;
-use crate::cli::utils::decrypt_file_stream;
+use crate::cli::utils::{
+    create_output, decrypt_file_stream, ensure_distinct_paths, open_input, CliBinaryContent,
+};
 use crate::cli::{logger::CliLogger, utils::CliFilePath};
 
 /// Handle QMC1 File.
 #[derive(Debug, Eq, PartialEq, FromArgs)]
 #[argh(subcommand, name = "qmc1")]
 pub struct Options {
-    /// input file name/path
+    /// custom 128-byte cipher table, default to the table used by the official client.
+    #[argh(option)]
+    table: Option<CliBinaryContent>,
+
+    /// input file name/path, "-" for stdin
     #[argh(option, short = 'i', long = "input")]
     input_file: CliFilePath,
 
-    /// output file name/path
+    /// output file name/path, "-" for stdout.
+    /// when absent, the input file name with the extension of the decrypted audio is used.
     #[argh(option, short = 'o', long = "output")]
-    output_file: CliFilePath,
+    output_file: Option<CliFilePath>,
+}
+
+fn get_output_path(
+    log: &CliLogger,
+    cipher: &QMCv1,
+    input_path: &Path,
+    src: &mut File,
+) -> Result<PathBuf, ParakeetCliError> {
+    let input_ext = input_path
+        .extension()
+        .map(|ext| ext.to_string_lossy())
+        .unwrap_or_default();
+
+    let audio_type = match variants::find_variant(&input_ext).and_then(|v| v.audio_type) {
+        Some(audio_type) => Some(audio_type),
+        None => {
+            let mut head = [0u8; AUDIO_DETECTION_LEN];
+            src.read_exact(&mut head)
+                .and_then(|_| src.seek(SeekFrom::Start(0)))
+                .map_err(ParakeetCliError::SourceIoError)?;
+            cipher.decipher_buffer(0, &mut head);
+            detect_audio_type(head)
+        }
+    };
+
+    let output_ext = match audio_type {
+        Some(audio_type) => audio_type.extension(),
+        None => {
+            log.warn("could not determine the audio type, fallback to '.bin'");
+            "bin"
+        }
+    };
+
+    Ok(input_path.with_extension(output_ext))
 }
 
 pub fn handle(args: Options) -> Result<(), ParakeetCliError> {
     let log = CliLogger::new("QMCv1");
 
-    let mut src = File::open(args.input_file.path).map_err(ParakeetCliError::SourceIoError)?;
-    let mut dst =
-        File::create(args.output_file.path).map_err(ParakeetCliError::DestinationIoError)?;
+    let cipher = match args.table {
+        Some(table) => QMCv1::with_table(table.content)?,
+        None => QMCv1::new(),
+    };
+
+    let output_file = match args.output_file {
+        Some(output_file) => output_file,
+        None if args.input_file.is_stdio() => Err(ParakeetCliError::MissingArgument("--output"))?,
+        None => {
+            let mut src =
+                File::open(&args.input_file.path).map_err(ParakeetCliError::SourceIoError)?;
+            let output_path = get_output_path(&log, &cipher, &args.input_file.path, &mut src)?;
+            CliFilePath {
+                path: output_path.into_boxed_path(),
+            }
+        }
+    };
+    log.info(format!("output: {}", output_file.path.display()));
+    // e.g. `song.bin` when the audio type is unknown.
+    ensure_distinct_paths(&args.input_file, &output_file)?;
+
+    let mut src = open_input(&args.input_file)?;
+    let mut dst = create_output(&output_file)?;
 
-    let bytes_written = decrypt_file_stream(&log, QMCv1, &mut dst, &mut src, 0, None)?;
+    let file_size = src.len.map(|len| len as usize);
+    let bytes_written = decrypt_file_stream(&log, cipher, &mut dst, &mut src.reader, 0, file_size)?;
     log.info(format!("decrypt: done, written {} bytes", bytes_written));
 
     Ok(())
@@ -1,23 +1,55 @@
-use std::fs::File;
 use std::io::{Read, Seek, SeekFrom};
 
-use argh::FromArgs;
+use argh::{FromArgValue, FromArgs};
 
 use parakeet_crypto::crypto::tencent;
-use parakeet_crypto::crypto::tencent::{ekey, QMCv2};
+use parakeet_crypto::crypto::tencent::{ekey, Mode, QMCv2, DETECT_MODE_LEN};
+use parakeet_crypto::tagging::{provenance, TagWriter};
 
 use crate::cli::cli_error::ParakeetCliError;
-use crate::cli::utils::{decrypt_file_stream, QMCKeyType};
+use crate::cli::cli_handle_qmc2_inspect;
+use crate::cli::utils::{
+    create_output, decrypt_file_stream, ensure_distinct_paths, finish_tags, open_input_seekable,
+    QMCKeyType,
+};
 
 use super::{
     logger::CliLogger,
     utils::{CliBinaryContent, CliFilePath},
 };
 
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+enum CmdCipherMode {
+    Auto,
+    Detect,
+    Fixed(Mode),
+}
+
+impl FromArgValue for CmdCipherMode {
+    fn from_arg_value(value: &str) -> Result<Self, String> {
+        match value.to_lowercase().as_str() {
+            "auto" => Ok(Self::Auto),
+            "detect" => Ok(Self::Detect),
+            "map" => Ok(Self::Fixed(Mode::Map)),
+            "rc4" => Ok(Self::Fixed(Mode::RC4)),
+            _ => Err("Invalid cipher mode".into()),
+        }
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, FromArgs)]
+#[argh(subcommand)]
+enum SubCommand {
+    Inspect(cli_handle_qmc2_inspect::Options),
+}
+
 /// Handle QMCv2 File.
 #[derive(Debug, Eq, PartialEq, FromArgs)]
 #[argh(subcommand, name = "qmc2")]
 pub struct Options {
+    #[argh(subcommand)]
+    command: Option<SubCommand>,
+
     /// encryption key
     #[argh(option, short = 'k')]
     key: Option<CliBinaryContent>,
@@ -33,44 +65,66 @@ pub struct Options {
     #[argh(option)]
     tail_trim: Option<i64>,
 
-    /// input file name/path
+    /// cipher mode, default to "auto".
+    /// "auto" guess the mode from key length; "detect" test both modes against the
+    /// decrypted audio header; "map" and "rc4" forces the given mode.
+    #[argh(option, short = 'm', default = "CmdCipherMode::Auto")]
+    mode: CmdCipherMode,
+
+    /// write the info from the tail (mid, media_mid, resource_id) to the tags of
+    /// the output (FLAC, Ogg, MP3 or M4A).
+    #[argh(switch)]
+    write_tags: bool,
+
+    /// input file name/path, "-" for stdin
     #[argh(option, short = 'i', long = "input")]
-    input_file: CliFilePath,
+    input_file: Option<CliFilePath>,
 
-    /// output file name/path
+    /// output file name/path, "-" for stdout
     #[argh(option, short = 'o', long = "output")]
-    output_file: CliFilePath,
+    output_file: Option<CliFilePath>,
 }
 
-const TAIL_BUF_LEN: usize = 1024;
-
 pub fn handle(args: Options) -> Result<(), ParakeetCliError> {
+    if let Some(SubCommand::Inspect(options)) = args.command {
+        return cli_handle_qmc2_inspect::handle(options);
+    }
+
     let log = CliLogger::new("QMCv2");
 
-    let mut src = File::open(args.input_file.path).map_err(ParakeetCliError::SourceIoError)?;
-    let mut dst =
-        File::create(args.output_file.path).map_err(ParakeetCliError::DestinationIoError)?;
+    let input_file = args
+        .input_file
+        .ok_or(ParakeetCliError::MissingArgument("--input"))?;
+    let output_file = args
+        .output_file
+        .ok_or(ParakeetCliError::MissingArgument("--output"))?;
+
+    ensure_distinct_paths(&input_file, &output_file)?;
+    // The tail is at the end of the file: stdin has to be buffered.
+    let mut src = open_input_seekable(&input_file)?;
+    let dst = create_output(&output_file)?;
 
     // Parse input file tail first
-    let mut tail_buf = vec![0u8; TAIL_BUF_LEN].into_boxed_slice();
-    src.seek(SeekFrom::End(-(TAIL_BUF_LEN as i64)))
-        .map_err(ParakeetCliError::SourceIoError)?;
-    src.read(&mut tail_buf)
-        .map_err(ParakeetCliError::SourceIoError)?;
     let file_size = src
-        .stream_position()
+        .seek(SeekFrom::End(0))
         .map_err(ParakeetCliError::SourceIoError)?;
+    let tail_result = tencent::parse_tail_from_reader(&mut *src).map(|(tail, _)| tail);
     src.seek(SeekFrom::Start(0))
         .map_err(ParakeetCliError::SourceIoError)?;
 
-    let tail_result = tencent::parse_tail(&tail_buf);
+    let tags = match (&tail_result, args.write_tags) {
+        (Ok(tail), true) => provenance::from_tencent_tail(tail),
+        _ => vec![],
+    };
+    if args.write_tags && tags.is_empty() {
+        log.warn("tags: no info found in the tail");
+    }
 
     let (key, tail_len) = match args.key {
         Some(user_key) => {
             let key = match args.key_type {
                 QMCKeyType::Key => user_key.content,
-                QMCKeyType::EKey => ekey::decrypt(user_key.content)
-                    .map_err(ParakeetCliError::QMCKeyDecryptionError)?,
+                QMCKeyType::EKey => ekey::decrypt(user_key.content)?,
             };
             let tail_len = match args.tail_trim {
                 Some(value) => value as usize,
@@ -82,7 +136,7 @@ pub fn handle(args: Options) -> Result<(), ParakeetCliError> {
             (key, tail_len)
         }
         None => {
-            let tail_result = tail_result.map_err(ParakeetCliError::QMCTailParseError)?;
+            let tail_result = tail_result?;
             let tail_key = tail_result
                 .get_key()
                 .ok_or(ParakeetCliError::QMCKeyRequired)?;
@@ -96,9 +150,27 @@ pub fn handle(args: Options) -> Result<(), ParakeetCliError> {
         tail_len
     ));
 
-    let cipher = QMCv2::from_key(key);
-    let file_size = file_size as usize;
-    let bytes_written = decrypt_file_stream(&log, cipher, &mut dst, &mut src, 0, Some(file_size))?;
+    let payload_len = (file_size as usize).saturating_sub(tail_len);
+    let cipher = match args.mode {
+        CmdCipherMode::Auto => QMCv2::try_new(key)?,
+        CmdCipherMode::Fixed(mode) => QMCv2::with_mode(mode, key)?,
+        CmdCipherMode::Detect => {
+            let mut head = vec![];
+            (&mut src)
+                .take(DETECT_MODE_LEN.min(payload_len) as u64)
+                .read_to_end(&mut head)
+                .and_then(|_| src.seek(SeekFrom::Start(0)))
+                .map_err(ParakeetCliError::SourceIoError)?;
+            let mode =
+                QMCv2::detect_mode(&key, &head).ok_or(ParakeetCliError::QMCModeDetectionError)?;
+            log.info(format!("detected cipher mode: {:?}", mode));
+            QMCv2::with_mode(mode, key)?
+        }
+    };
+    let mut dst = TagWriter::new(dst, tags);
+    let bytes_written =
+        decrypt_file_stream(&log, cipher, &mut dst, &mut src, 0, Some(payload_len))?;
+    finish_tags(&log, dst)?;
     log.info(format!("decrypt: done, written {} bytes", bytes_written));
 
     Ok(())
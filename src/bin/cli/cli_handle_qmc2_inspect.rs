@@ -0,0 +1,39 @@
+use std::fs::File;
+use std::io::{Seek, SeekFrom};
+
+use argh::FromArgs;
+
+use parakeet_crypto::crypto::tencent;
+
+use crate::cli::cli_error::ParakeetCliError;
+use crate::cli::logger::CliLogger;
+use crate::cli::utils::CliFilePath;
+
+/// Inspect the tail of a QMCv2 file, and report why each tail parser accepted or rejected it.
+#[derive(Debug, Eq, PartialEq, FromArgs)]
+#[argh(subcommand, name = "inspect")]
+pub struct Options {
+    /// input file name/path
+    #[argh(option, short = 'i', long = "input")]
+    input_file: CliFilePath,
+}
+
+pub fn handle(args: Options) -> Result<(), ParakeetCliError> {
+    let log = CliLogger::new("QMCv2");
+
+    let mut src = File::open(args.input_file.path).map_err(ParakeetCliError::SourceIoError)?;
+    let file_size = src
+        .seek(SeekFrom::End(0))
+        .map_err(ParakeetCliError::SourceIoError)?;
+    let report = tencent::parse_tail_verbose_from_reader(&mut src)?;
+
+    log.info(format!("file size: {} bytes", file_size));
+    for attempt in report.attempts {
+        match attempt.result {
+            Ok(tail) => log.info(format!("{:?}: accepted, {:?}", attempt.parser, tail)),
+            Err(err) => log.info(format!("{:?}: rejected, {}", attempt.parser, err)),
+        }
+    }
+
+    Ok(())
+}
@@ -1,4 +1,3 @@
-use std::fs::File;
 use std::io::{Read, Write};
 
 use argh::{FromArgValue, FromArgs};
@@ -7,10 +6,11 @@ use parakeet_crypto::crypto::ximalaya_android;
 use parakeet_crypto::crypto::ximalaya_android::keys::SCRAMBLED_HEADER_LEN;
 
 use crate::cli::cli_error::ParakeetCliError;
-use crate::cli::{logger::CliLogger, utils::CliFilePath};
+use crate::cli::logger::CliLogger;
+use crate::cli::utils::{create_output, ensure_distinct_paths, open_input, CliFilePath};
 
 #[derive(Debug, PartialEq, Copy, Clone)]
-struct CmdKeyType(ximalaya_android::keys::Type);
+pub struct CmdKeyType(pub ximalaya_android::keys::Type);
 
 impl FromArgValue for CmdKeyType {
     fn from_arg_value(value: &str) -> Result<Self, String> {
@@ -30,11 +30,11 @@ pub struct Options {
     #[argh(option, short = 't', long = "type")]
     key_type: CmdKeyType,
 
-    /// input file name/path
+    /// input file name/path, "-" for stdin
     #[argh(option, short = 'i', long = "input")]
     input: CliFilePath,
 
-    /// output file name/path
+    /// output file name/path, "-" for stdout
     #[argh(option, short = 'o', long = "output")]
     output: CliFilePath,
 }
@@ -42,11 +42,13 @@ pub struct Options {
 pub fn handle(args: Options) -> Result<(), ParakeetCliError> {
     let log = CliLogger::new("Ximalaya (Android)");
 
-    let mut src = File::open(args.input.path).map_err(ParakeetCliError::SourceIoError)?;
-    let mut dst = File::create(args.output.path).map_err(ParakeetCliError::DestinationIoError)?;
+    ensure_distinct_paths(&args.input, &args.output)?;
+    let mut src = open_input(&args.input)?;
+    let mut dst = create_output(&args.output)?;
 
     let mut hdr = [0u8; SCRAMBLED_HEADER_LEN];
-    src.read_exact(&mut hdr)
+    src.reader
+        .read_exact(&mut hdr)
         .map_err(ParakeetCliError::SourceIoError)?;
 
     let (content_key, scramble_table) = ximalaya_android::keys::get_key(args.key_type.0);
@@ -54,7 +56,9 @@ pub fn handle(args: Options) -> Result<(), ParakeetCliError> {
 
     dst.write_all(&hdr)
         .map_err(ParakeetCliError::DestinationIoError)?;
-    std::io::copy(&mut src, &mut dst).map_err(ParakeetCliError::DestinationIoError)?;
+    std::io::copy(&mut src.reader, &mut dst)
+        .and_then(|_| dst.flush())
+        .map_err(ParakeetCliError::DestinationIoError)?;
 
     log.info("Decryption OK.");
     Ok(())
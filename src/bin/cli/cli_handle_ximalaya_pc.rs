@@ -1,45 +1,69 @@
-use std::fs::File;
-use std::io::{Read, Seek, SeekFrom, Write};
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::PathBuf;
 
 use argh::FromArgs;
 
 use parakeet_crypto::crypto::ximalaya_pc;
+use parakeet_crypto::tagging::TagWriter;
 
 use crate::cli::cli_error::ParakeetCliError;
 use crate::cli::logger::CliLogger;
-use crate::cli::utils::CliFilePath;
+use crate::cli::utils::{
+    create_output, ensure_distinct_paths, finish_tags, open_input, CliFilePath,
+};
 
 /// Handle Ximalaya PC encryption/decryption.
 #[derive(Debug, Eq, PartialEq, FromArgs)]
 #[argh(subcommand, name = "ximalaya-pc")]
 pub struct Options {
-    /// input file name/path
+    /// input file name/path, "-" for stdin
     #[argh(option, short = 'i', long = "input")]
     input_file: CliFilePath,
 
-    /// output file name/path
+    /// output file name/path, "-" for stdout
     #[argh(option, short = 'o', long = "output")]
     output_file: CliFilePath,
+
+    /// save the cover embedded in the header to this path
+    #[argh(option)]
+    extract_cover: Option<PathBuf>,
+
+    /// write the title, artist, album and cover from the header to the tags of
+    /// the output (FLAC, Ogg, MP3 or M4A).
+    #[argh(switch)]
+    embed_extras: bool,
+}
+
+/// Read from `reader` until `buffer` has `len` bytes.
+fn fill_buffer<R: Read + ?Sized>(
+    reader: &mut R,
+    buffer: &mut Vec<u8>,
+    len: usize,
+) -> io::Result<()> {
+    if let Some(n) = len.checked_sub(buffer.len()) {
+        let offset = buffer.len();
+        buffer.resize(len, 0);
+        reader.read_exact(&mut buffer[offset..offset + n])?;
+    }
+    Ok(())
 }
 
 pub fn handle(args: Options) -> Result<(), ParakeetCliError> {
-    let log = CliLogger::new("Kugou");
+    let log = CliLogger::new("Ximalaya (PC)");
 
-    let mut src = File::open(args.input_file.path).map_err(ParakeetCliError::SourceIoError)?;
-    let mut dst =
-        File::create(args.output_file.path).map_err(ParakeetCliError::DestinationIoError)?;
+    ensure_distinct_paths(&args.input_file, &args.output_file)?;
+    let mut src = open_input(&args.input_file)?;
+    let dst = create_output(&args.output_file)?;
 
-    let mut buffer = vec![0u8; 1024];
-    src.read_exact(&mut buffer)
-        .map_err(ParakeetCliError::SourceIoError)?;
+    // The header is only read forward, so this also works with stdin.
+    let mut buffer = vec![];
+    fill_buffer(&mut src.reader, &mut buffer, 1024).map_err(ParakeetCliError::SourceIoError)?;
 
     let hdr = match ximalaya_pc::Header::from_bytes(&buffer) {
         // in case our buffer was too small...
         Err(ximalaya_pc::Error::InputTooSmall(n, _)) => {
-            buffer.resize(n, 0);
-
-            src.seek(SeekFrom::Start(0))
-                .and_then(|_| src.read_exact(&mut buffer))
+            fill_buffer(&mut src.reader, &mut buffer, n)
                 .map_err(ParakeetCliError::SourceIoError)?;
             ximalaya_pc::Header::from_bytes(&buffer)?
         }
@@ -52,23 +76,56 @@ pub fn handle(args: Options) -> Result<(), ParakeetCliError> {
         hdr.encrypted_header_len,
         hdr.data_start_offset,
     ));
+    log.debug(format!(
+        "extras: title={:?}, artist={:?}, album={:?}, cover={:?}",
+        hdr.extras.title,
+        hdr.extras.artist,
+        hdr.extras.album,
+        hdr.extras.cover.as_ref().map(|cover| &cover.mime),
+    ));
+
+    if let Some(path) = &args.extract_cover {
+        match &hdr.extras.cover {
+            Some(cover) => {
+                fs::write(path, &cover.data)
+                    .map_err(|err| ParakeetCliError::OtherIoError(path.clone(), err))?;
+                log.info(format!(
+                    "cover: written {} bytes ({})",
+                    cover.data.len(),
+                    cover.mime
+                ));
+            }
+            None => log.warn("cover: not found in the header"),
+        }
+    }
+
+    let extras = match args.embed_extras {
+        true => hdr.extras.clone(),
+        false => Default::default(),
+    };
+    let mut dst = TagWriter::new(dst, vec![]).with_extras(extras);
 
     // read encrypted part 2 data, and decrypt it
-    buffer.resize(hdr.encrypted_header_len, 0);
-    src.seek(SeekFrom::Start(hdr.data_start_offset as u64))
-        .and_then(|_| src.read_exact(&mut buffer))
+    let part_2_end = hdr.data_start_offset + hdr.encrypted_header_len;
+    fill_buffer(&mut src.reader, &mut buffer, part_2_end)
         .map_err(ParakeetCliError::SourceIoError)?;
-    let decrypted_part_2 = ximalaya_pc::decipher_part_2(&hdr, &buffer)?;
+    let decrypted_part_2 =
+        ximalaya_pc::decipher_part_2(&hdr, &buffer[hdr.data_start_offset..part_2_end])?;
 
-    // write all parts to dst.
+    // write all parts to dst, including the audio read along with the header.
     dst.write_all(&hdr.stolen_header_bytes)
         .and_then(|_| dst.write_all(&decrypted_part_2))
-        .and_then(|_| std::io::copy(&mut src, &mut dst))
+        .and_then(|_| dst.write_all(&buffer[part_2_end..]))
         .map_err(ParakeetCliError::DestinationIoError)?;
-
-    let bytes_written = dst
-        .stream_position()
+    let bytes_copied = io::copy(&mut src.reader, &mut dst)
+        .and_then(|n| dst.flush().map(|_| n))
         .map_err(ParakeetCliError::DestinationIoError)?;
+    finish_tags(&log, dst)?;
+
+    let bytes_written = hdr.stolen_header_bytes.len()
+        + decrypted_part_2.len()
+        + (buffer.len() - part_2_end)
+        + bytes_copied as usize;
     log.info(format!("decrypt: done, written {} bytes", bytes_written));
 
     Ok(())
@@ -1,10 +1,27 @@
 use argh::FromArgs;
 
+use crate::cli::logger::LogFormat;
 use crate::cli::*;
 
 /// Test CLI tool for parakeet_crypto.
 #[derive(FromArgs, PartialEq, Debug)]
 pub struct CliOptions {
+    /// show debug messages
+    #[argh(switch, short = 'v')]
+    pub verbose: bool,
+
+    /// only show errors
+    #[argh(switch, short = 'q')]
+    pub quiet: bool,
+
+    /// log format: "text" (default), or "json" for one JSON object per line
+    #[argh(option, default = "LogFormat::Text")]
+    pub log_format: LogFormat,
+
+    /// do not show the progress bar
+    #[argh(switch)]
+    pub no_progress: bool,
+
     #[argh(subcommand)]
     pub command: Command,
 }
@@ -12,10 +29,25 @@ pub struct CliOptions {
 #[derive(FromArgs, PartialEq, Debug)]
 #[argh(subcommand)]
 pub enum Command {
+    Inspect(cli_handle_inspect::Options),
+    Auto(cli_handle_auto::Options),
+    #[cfg(any(
+        feature = "tencent",
+        feature = "kugou",
+        feature = "kuwo",
+        feature = "ximalaya-android"
+    ))]
+    Encrypt(cli_handle_encrypt::Options),
+    #[cfg(feature = "tencent")]
     TencentQMCv1(cli_handle_qmc1::Options),
+    #[cfg(feature = "tencent")]
     TencentQMCv2(cli_handle_qmc2::Options),
+    #[cfg(feature = "kugou")]
     Kugou(cli_handle_kugou::Options),
+    #[cfg(feature = "kuwo")]
     Kuwo(cli_handle_kuwo::Options),
+    #[cfg(feature = "ximalaya-android")]
     XimalayaAndroid(cli_handle_ximalaya_android::Options),
+    #[cfg(feature = "ximalaya-pc")]
     XimalayaPc(cli_handle_ximalaya_pc::Options),
 }
@@ -1,27 +1,89 @@
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+static MAX_LEVEL: AtomicU8 = AtomicU8::new(Level::Info as u8);
+static JSON_FORMAT: AtomicBool = AtomicBool::new(false);
+
 pub struct CliLogger {
     module: String,
 }
 
+/// Log levels, from the least to the most verbose.
 #[allow(dead_code)]
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub enum Level {
-    Error,
-    Info,
-    Warn,
-    Debug,
+    Error = 0,
+    Warn = 1,
+    Info = 2,
+    Debug = 3,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    Text,
+    /// One JSON object per line.
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(String::from("expected \"text\" or \"json\"")),
+        }
+    }
+}
+
+/// Set the most verbose level to print, and the output format, for all loggers.
+pub fn init(max_level: Level, format: LogFormat) {
+    MAX_LEVEL.store(max_level as u8, Ordering::Relaxed);
+    JSON_FORMAT.store(format == LogFormat::Json, Ordering::Relaxed);
+}
+
+pub fn enabled(level: Level) -> bool {
+    level as u8 <= MAX_LEVEL.load(Ordering::Relaxed)
+}
+
+pub fn is_json() -> bool {
+    JSON_FORMAT.load(Ordering::Relaxed)
 }
 
 #[allow(dead_code)]
 impl CliLogger {
     pub fn new<S: AsRef<str>>(module: S) -> Self {
         Self {
-            module: format!("{:>5}", module.as_ref()),
+            module: module.as_ref().to_string(),
         }
     }
 
     pub fn log<S: AsRef<str>>(&self, level: Level, msg: S) {
-        let level = format!("{:?}", level).to_uppercase();
-        eprintln!("[{}][{:>5}] {}", self.module, level, msg.as_ref());
+        if !enabled(level) {
+            return;
+        }
+
+        let level = format!("{:?}", level).to_lowercase();
+        if is_json() {
+            let time = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0.0, |time| time.as_secs_f64());
+            let record = serde_json::json!({
+                "time": time,
+                "level": level,
+                "module": self.module,
+                "message": msg.as_ref(),
+            });
+            eprintln!("{}", record);
+        } else {
+            eprintln!(
+                "[{:>5}][{:>5}] {}",
+                self.module,
+                level.to_uppercase(),
+                msg.as_ref()
+            );
+        }
     }
 
     pub fn info<S: AsRef<str>>(&self, msg: S) {
@@ -36,9 +98,7 @@ impl CliLogger {
         self.log(Level::Warn, msg);
     }
 
-    #[allow(unused_variables)]
     pub fn debug<S: AsRef<str>>(&self, msg: S) {
-        #[cfg(debug_assertions)]
         self.log(Level::Debug, msg);
     }
 }
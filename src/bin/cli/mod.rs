@@ -2,18 +2,44 @@ use crate::cli::commands::Command;
 
 mod commands;
 mod logger;
+mod progress;
 mod utils;
 
 mod cli_error;
+mod cli_handle_auto;
+#[cfg(any(
+    feature = "tencent",
+    feature = "kugou",
+    feature = "kuwo",
+    feature = "ximalaya-android"
+))]
+mod cli_handle_encrypt;
+mod cli_handle_inspect;
+#[cfg(feature = "kugou")]
 mod cli_handle_kugou;
+#[cfg(feature = "kuwo")]
 mod cli_handle_kuwo;
+#[cfg(feature = "tencent")]
 mod cli_handle_qmc1;
+#[cfg(feature = "tencent")]
 mod cli_handle_qmc2;
+#[cfg(feature = "tencent")]
+mod cli_handle_qmc2_inspect;
+#[cfg(feature = "ximalaya-android")]
 mod cli_handle_ximalaya_android;
+#[cfg(feature = "ximalaya-pc")]
 mod cli_handle_ximalaya_pc;
 
 pub fn parakeet_main() {
     let options: commands::CliOptions = argh::from_env();
+    let max_level = match (options.quiet, options.verbose) {
+        (true, _) => logger::Level::Error,
+        (false, true) => logger::Level::Debug,
+        (false, false) => logger::Level::Info,
+    };
+    logger::init(max_level, options.log_format);
+    progress::init(!options.no_progress);
+
     let log = logger::CliLogger::new("main");
 
     #[cfg(debug_assertions)]
@@ -23,18 +49,34 @@ pub fn parakeet_main() {
     }
 
     let cmd_result = match options.command {
+        Command::Inspect(options) => cli_handle_inspect::handle(options),
+        Command::Auto(options) => cli_handle_auto::handle(options),
+        #[cfg(any(
+            feature = "tencent",
+            feature = "kugou",
+            feature = "kuwo",
+            feature = "ximalaya-android"
+        ))]
+        Command::Encrypt(options) => cli_handle_encrypt::handle(options),
+        #[cfg(feature = "tencent")]
         Command::TencentQMCv1(options) => cli_handle_qmc1::handle(options),
+        #[cfg(feature = "tencent")]
         Command::TencentQMCv2(options) => cli_handle_qmc2::handle(options),
+        #[cfg(feature = "kugou")]
         Command::Kugou(options) => cli_handle_kugou::handle(options),
+        #[cfg(feature = "kuwo")]
         Command::Kuwo(options) => cli_handle_kuwo::handle(options),
+        #[cfg(feature = "ximalaya-android")]
         Command::XimalayaAndroid(options) => cli_handle_ximalaya_android::handle(options),
+        #[cfg(feature = "ximalaya-pc")]
         Command::XimalayaPc(options) => cli_handle_ximalaya_pc::handle(options),
     };
 
     match cmd_result {
         Ok(_) => (),
         Err(err) => {
-            log.error(format!("Command failed with error: {}", err).as_str());
+            log.error(format!("Command failed with error: {}", err));
+            std::process::exit(1);
         }
     }
 }
@@ -0,0 +1,118 @@
+use std::io::{self, IsTerminal, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use parakeet_crypto::crypto::byte_offset_cipher::StreamProgress;
+
+use crate::cli::logger::{self, Level};
+
+static ENABLED: AtomicBool = AtomicBool::new(true);
+
+const BAR_WIDTH: usize = 30;
+const REDRAW_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Allow (or hide) the progress bar.
+///
+/// It is only drawn when stderr is a terminal, and text logs at `info` level or more
+/// verbose are enabled.
+pub fn init(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+fn format_bytes(n: f64) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+
+    let mut n = n;
+    let mut unit = 0;
+    while n >= 1024.0 && unit < UNITS.len() - 1 {
+        n /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", n, UNITS[unit])
+}
+
+/// Progress bar with throughput, drawn on stderr.
+pub struct ProgressBar {
+    label: &'static str,
+    enabled: bool,
+    start: Instant,
+    last_draw: Option<Instant>,
+}
+
+impl ProgressBar {
+    pub fn new(label: &'static str) -> Self {
+        let enabled = ENABLED.load(Ordering::Relaxed)
+            && logger::enabled(Level::Info)
+            && !logger::is_json()
+            && io::stderr().is_terminal();
+
+        Self {
+            label,
+            enabled,
+            start: Instant::now(),
+            last_draw: None,
+        }
+    }
+
+    pub fn update(&mut self, progress: StreamProgress) {
+        let now = Instant::now();
+        let done = progress.total == Some(progress.processed);
+        let due = self
+            .last_draw
+            .map_or(true, |last| now.duration_since(last) >= REDRAW_INTERVAL);
+        if !self.enabled || !(due || done) {
+            return;
+        }
+        self.last_draw = Some(now);
+
+        let elapsed = now.duration_since(self.start).as_secs_f64();
+        let throughput = if elapsed > 0.0 {
+            progress.processed as f64 / elapsed
+        } else {
+            0.0
+        };
+        let processed = format_bytes(progress.processed as f64);
+        let line = match progress.total {
+            Some(total) if total > 0 => {
+                let ratio = (progress.processed as f64 / total as f64).min(1.0);
+                let filled = (ratio * BAR_WIDTH as f64) as usize;
+                format!(
+                    "{} [{}{}] {:5.1}% {} / {}, {}/s",
+                    self.label,
+                    "#".repeat(filled),
+                    "-".repeat(BAR_WIDTH - filled),
+                    ratio * 100.0,
+                    processed,
+                    format_bytes(total as f64),
+                    format_bytes(throughput),
+                )
+            }
+            _ => format!(
+                "{} {}, {}/s",
+                self.label,
+                processed,
+                format_bytes(throughput)
+            ),
+        };
+
+        let mut stderr = io::stderr().lock();
+        let _ = write!(stderr, "\r\x1b[2K{}", line);
+        let _ = stderr.flush();
+    }
+
+    /// Clear the progress bar, so it does not mix with the next log line.
+    pub fn finish(&mut self) {
+        if self.enabled && self.last_draw.is_some() {
+            let mut stderr = io::stderr().lock();
+            let _ = write!(stderr, "\r\x1b[2K");
+            let _ = stderr.flush();
+        }
+        self.last_draw = None;
+    }
+}
+
+impl Drop for ProgressBar {
+    fn drop(&mut self) {
+        self.finish();
+    }
+}
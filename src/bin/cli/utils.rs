@@ -1,14 +1,29 @@
-use std::io::{Error, ErrorKind, Read, Write};
-use std::str::FromStr;
-use std::{fs, path::Path};
+// Most helpers are only used by the stream ciphers; Ximalaya only needs `CliFilePath`.
+#![cfg_attr(
+    not(any(feature = "tencent", feature = "kugou")),
+    allow(dead_code, unused_imports)
+)]
+
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::time::Instant;
+#[cfg(feature = "tencent")]
+use std::{
+    io::{Cursor, Error, ErrorKind, Seek},
+    str::FromStr,
+};
 
 use argh::FromArgValue;
 use base64::{engine::general_purpose::STANDARD as Base64, Engine as _};
 
 use parakeet_crypto::crypto::byte_offset_cipher::ByteOffsetDecipher;
+#[cfg(any(feature = "tencent", feature = "ximalaya-pc"))]
+use parakeet_crypto::tagging::TagWriter;
 
 use crate::cli::cli_error::ParakeetCliError;
 use crate::cli::logger::CliLogger;
+use crate::cli::progress::ProgressBar;
 
 pub const DECRYPTION_BUFFER_SIZE: usize = 2 * 1024 * 1024;
 
@@ -43,6 +58,7 @@ impl FromArgValue for CliBinaryContent {
     }
 }
 
+#[allow(dead_code)]
 #[derive(Debug, Eq, PartialEq, Clone)]
 pub struct CliBinaryArray<const SIZE: usize> {
     pub content: [u8; SIZE],
@@ -81,6 +97,105 @@ impl FromArgValue for CliFilePath {
     }
 }
 
+impl CliFilePath {
+    /// `-` stands for stdin (input) or stdout (output).
+    pub fn is_stdio(&self) -> bool {
+        self.path.as_os_str() == "-"
+    }
+}
+
+/// Maximum number of bytes buffered from stdin, for formats that need random access.
+#[cfg(feature = "tencent")]
+pub const STDIN_BUFFER_LIMIT: usize = 1024 * 1024 * 1024;
+
+/// Input file, or stdin.
+pub struct CliInput {
+    pub reader: Box<dyn Read>,
+    /// Size of the input file, `None` for stdin.
+    pub len: Option<u64>,
+}
+
+pub fn open_input(input: &CliFilePath) -> Result<CliInput, ParakeetCliError> {
+    if input.is_stdio() {
+        return Ok(CliInput {
+            reader: Box::new(io::stdin().lock()),
+            len: None,
+        });
+    }
+
+    let file = File::open(&input.path).map_err(ParakeetCliError::SourceIoError)?;
+    let len = file
+        .metadata()
+        .map_err(ParakeetCliError::SourceIoError)?
+        .len();
+    Ok(CliInput {
+        reader: Box::new(file),
+        len: Some(len),
+    })
+}
+
+#[cfg(feature = "tencent")]
+pub trait ReadSeek: Read + Seek {}
+#[cfg(feature = "tencent")]
+impl<T: Read + Seek + ?Sized> ReadSeek for T {}
+
+/// Open the input for random access.
+///
+/// Stdin is read to memory, up to [`STDIN_BUFFER_LIMIT`] bytes.
+#[cfg(feature = "tencent")]
+pub fn open_input_seekable(input: &CliFilePath) -> Result<Box<dyn ReadSeek>, ParakeetCliError> {
+    if !input.is_stdio() {
+        let file = File::open(&input.path).map_err(ParakeetCliError::SourceIoError)?;
+        return Ok(Box::new(file));
+    }
+
+    let mut buffer = vec![];
+    io::stdin()
+        .lock()
+        .take(STDIN_BUFFER_LIMIT as u64 + 1)
+        .read_to_end(&mut buffer)
+        .map_err(ParakeetCliError::SourceIoError)?;
+    if buffer.len() > STDIN_BUFFER_LIMIT {
+        Err(ParakeetCliError::StdinBufferLimitExceeded(
+            STDIN_BUFFER_LIMIT,
+        ))?;
+    }
+    Ok(Box::new(Cursor::new(buffer)))
+}
+
+/// Fail if `output` is an existing file that is also `input`, before it gets truncated.
+pub fn ensure_distinct_paths(
+    input: &CliFilePath,
+    output: &CliFilePath,
+) -> Result<(), ParakeetCliError> {
+    if input.is_stdio() || output.is_stdio() {
+        return Ok(());
+    }
+    // A missing output can't be the input.
+    if let (Ok(input_path), Ok(output_path)) = (
+        fs::canonicalize(&input.path),
+        fs::canonicalize(&output.path),
+    ) {
+        if input_path == output_path {
+            Err(ParakeetCliError::OutputIsInput(output_path))?;
+        }
+    }
+    Ok(())
+}
+
+/// Create the output file, or use stdout.
+pub fn create_output(output: &CliFilePath) -> Result<Box<dyn Write>, ParakeetCliError> {
+    if output.is_stdio() {
+        return Ok(Box::new(io::stdout().lock()));
+    }
+
+    let file = File::create(&output.path).map_err(ParakeetCliError::DestinationIoError)?;
+    Ok(Box::new(file))
+}
+
+/// Decrypt `reader` to `writer`, with a progress bar.
+///
+/// The progress bar shows a percentage if `max_read` is given.
 pub fn decrypt_file_stream<C, R, W>(
     log: &CliLogger,
     cipher: C,
@@ -96,26 +211,73 @@ where
 {
     let mut buffer = vec![0u8; DECRYPTION_BUFFER_SIZE];
     let mut dst_write_error = Ok(());
+    let mut progress = ProgressBar::new("decrypt");
+    let start = Instant::now();
     let bytes_written = cipher
-        .decipher_stream_ex(&mut buffer, offset, reader, max_read, |block| {
-            log.debug(format!("decrypt: process {} bytes", block.len()));
-            dst_write_error = writer
-                .write_all(block)
-                .map_err(ParakeetCliError::DestinationIoError);
+        .decipher_stream_with_progress(
+            &mut buffer,
+            offset,
+            reader,
+            max_read,
+            |block| {
+                dst_write_error = writer
+                    .write_all(block)
+                    .map_err(ParakeetCliError::DestinationIoError);
 
-            dst_write_error.as_ref().into()
-        })
+                dst_write_error.as_ref().into()
+            },
+            |p| progress.update(p),
+        )
         .map_err(ParakeetCliError::SourceIoError)?;
+    progress.finish();
     dst_write_error?;
+    writer
+        .flush()
+        .map_err(ParakeetCliError::DestinationIoError)?;
+
+    let elapsed = start.elapsed();
+    log.debug(format!(
+        "decrypt: {} bytes in {:.2?} ({:.1} MiB/s)",
+        bytes_written,
+        elapsed,
+        bytes_written as f64 / 1024.0 / 1024.0 / elapsed.as_secs_f64().max(f64::EPSILON)
+    ));
     Ok(bytes_written)
 }
 
+/// Write what's left of the tagged output, and log whether the tags were written.
+#[cfg(any(feature = "tencent", feature = "ximalaya-pc"))]
+pub fn finish_tags<W: Write>(
+    log: &CliLogger,
+    writer: TagWriter<W>,
+) -> Result<(), ParakeetCliError> {
+    let is_empty = writer.is_empty();
+    let (_, report) = writer
+        .finish()
+        .map_err(ParakeetCliError::DestinationIoError)?;
+    if is_empty {
+        return Ok(());
+    }
+
+    match (report.tagged, report.audio_type) {
+        (true, Some(audio_type)) => log.info(format!("tags: written ({})", audio_type.extension())),
+        (_, Some(audio_type)) => log.warn(format!(
+            "tags: not written, unsupported or invalid container ({})",
+            audio_type.extension()
+        )),
+        (_, None) => log.warn("tags: not written, unknown container"),
+    }
+    Ok(())
+}
+
+#[cfg(feature = "tencent")]
 #[derive(Debug, Eq, PartialEq)]
 pub enum QMCKeyType {
     EKey = 1,
     Key = 0,
 }
 
+#[cfg(feature = "tencent")]
 impl FromStr for QMCKeyType {
     type Err = Error;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
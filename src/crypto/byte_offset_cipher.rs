@@ -1,4 +1,4 @@
-use std::cmp::min;
+#[cfg(feature = "std")]
 use std::io::Read;
 
 pub enum StreamControlState {
@@ -24,6 +24,16 @@ impl<T, E> From<Result<T, E>> for StreamControlState {
     }
 }
 
+/// Progress of a stream operation, reported after each block is written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StreamProgress {
+    /// Number of bytes processed so far.
+    pub processed: usize,
+    /// Number of bytes to process (`max_read`), if known.
+    pub total: Option<usize>,
+}
+
+#[cfg(feature = "std")]
 const DEFAULT_CIPHER_BUFFER_LEN: usize = 1024 * 1024;
 
 fn handler_buffer<T, P>(offset: usize, buffer: &mut T, transform: P)
@@ -31,23 +41,24 @@ where
     T: AsMut<[u8]> + ?Sized,
     P: Fn(usize, u8) -> u8,
 {
-    let mut offset = offset;
-    for datum in buffer.as_mut() {
+    for (offset, datum) in (offset..).zip(buffer.as_mut().iter_mut()) {
         *datum = transform(offset, *datum);
-        offset += 1;
     }
 }
 
-fn handler_stream_ex<F, R, P>(
+#[cfg(feature = "std")]
+fn handler_stream_ex<F, G, R, P>(
     buffer: &mut [u8],
     offset: usize,
     reader: &mut R,
     max_read: Option<usize>,
     transform: P,
     mut write_callback: F,
+    mut progress_callback: G,
 ) -> Result<usize, std::io::Error>
 where
     F: FnMut(&[u8]) -> StreamControlState,
+    G: FnMut(StreamProgress),
     R: Read + ?Sized,
     P: Fn(usize, &mut [u8]),
 {
@@ -56,7 +67,7 @@ where
     loop {
         let block_len = match max_read {
             None => buffer.len(),
-            Some(max_read) => min(max_read - bytes_processed, buffer.len()),
+            Some(max_read) => (max_read - bytes_processed).min(buffer.len()),
         };
 
         if block_len == 0 {
@@ -76,11 +87,16 @@ where
 
         offset += read_len;
         bytes_processed += read_len;
+        progress_callback(StreamProgress {
+            processed: bytes_processed,
+            total: max_read,
+        });
     }
 
     Ok(bytes_processed)
 }
 
+#[cfg(feature = "std")]
 fn handler_stream<R, P>(
     offset: usize,
     reader: &mut R,
@@ -93,15 +109,24 @@ where
 {
     let mut result = vec![];
     let mut buffer = vec![0u8; DEFAULT_CIPHER_BUFFER_LEN];
-    let n = handler_stream_ex(&mut buffer, offset, reader, max_read, transform, |buf| {
+    let write_callback = |buf: &[u8]| {
         result.extend(buf);
         StreamControlState::Continue
-    })?;
+    };
+    let n = handler_stream_ex(
+        &mut buffer,
+        offset,
+        reader,
+        max_read,
+        transform,
+        write_callback,
+        |_| {},
+    )?;
     Ok((result, n))
 }
 
 macro_rules! impl_byte_offset_cipher {
-    ($name:ident, $byte_method:ident, $buffer_method:ident, $stream_method:ident, $stream_ex_method:ident) => {
+    ($name:ident, $byte_method:ident, $buffer_method:ident, $stream_method:ident, $stream_ex_method:ident, $stream_progress_method:ident) => {
         pub trait $name {
             fn $byte_method(&self, offset: usize, datum: u8) -> u8;
 
@@ -111,6 +136,7 @@ macro_rules! impl_byte_offset_cipher {
                 })
             }
 
+            #[cfg(feature = "std")]
             fn $stream_method<R>(
                 &self,
                 offset: usize,
@@ -125,6 +151,7 @@ macro_rules! impl_byte_offset_cipher {
                 })
             }
 
+            #[cfg(feature = "std")]
             fn $stream_ex_method<F, R>(
                 &self,
                 buffer: &mut [u8],
@@ -136,6 +163,33 @@ macro_rules! impl_byte_offset_cipher {
             where
                 F: FnMut(&[u8]) -> StreamControlState,
                 R: Read + ?Sized,
+            {
+                self.$stream_progress_method(
+                    buffer,
+                    offset,
+                    reader,
+                    max_read,
+                    write_callback,
+                    |_| {},
+                )
+            }
+
+            /// Same as the `_stream_ex` variant, with `progress_callback` called after
+            /// each block is written.
+            #[cfg(feature = "std")]
+            fn $stream_progress_method<F, G, R>(
+                &self,
+                buffer: &mut [u8],
+                offset: usize,
+                reader: &mut R,
+                max_read: Option<usize>,
+                write_callback: F,
+                progress_callback: G,
+            ) -> Result<usize, std::io::Error>
+            where
+                F: FnMut(&[u8]) -> StreamControlState,
+                G: FnMut(StreamProgress),
+                R: Read + ?Sized,
             {
                 handler_stream_ex(
                     buffer,
@@ -144,6 +198,7 @@ macro_rules! impl_byte_offset_cipher {
                     max_read,
                     |offset, buffer| self.$buffer_method(offset, buffer),
                     write_callback,
+                    progress_callback,
                 )
             }
         }
@@ -155,12 +210,53 @@ impl_byte_offset_cipher!(
     encipher_byte,
     encipher_buffer,
     encipher_stream,
-    encipher_stream_ex
+    encipher_stream_ex,
+    encipher_stream_with_progress
 );
 impl_byte_offset_cipher!(
     ByteOffsetDecipher,
     decipher_byte,
     decipher_buffer,
     decipher_stream,
-    decipher_stream_ex
+    decipher_stream_ex,
+    decipher_stream_with_progress
 );
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+
+    struct XorCipher;
+
+    impl ByteOffsetDecipher for XorCipher {
+        fn decipher_byte(&self, offset: usize, datum: u8) -> u8 {
+            datum ^ offset as u8
+        }
+    }
+
+    #[test]
+    fn test_stream_progress() {
+        let input = [0u8; 10];
+        let mut buffer = [0u8; 4];
+        let mut output = vec![];
+        let mut reports = vec![];
+
+        let n = XorCipher
+            .decipher_stream_with_progress(
+                &mut buffer,
+                0,
+                &mut &input[..],
+                Some(9),
+                |block| {
+                    output.extend_from_slice(block);
+                    StreamControlState::Continue
+                },
+                |progress| reports.push(progress.processed),
+            )
+            .unwrap();
+
+        assert_eq!(n, 9);
+        assert_eq!(output, [0, 1, 2, 3, 4, 5, 6, 7, 8]);
+        assert_eq!(reports, [4, 8, 9]);
+    }
+}
@@ -0,0 +1,273 @@
+use crate::crypto::byte_offset_cipher::{ByteOffsetDecipher, ByteOffsetEncipher};
+use crate::crypto::kugou::{CipherError, CipherModes, Header, MediaType};
+
+/// Extra mask applied by the Kugou client to the decrypted audio of VPR files.
+pub const VPR_MASK_DIFF: [u8; 17] = [
+    0x25, 0xDF, 0xE8, 0xA6, 0x75, 0x1E, 0x75, 0x0E, //
+    0x2F, 0x80, 0xF3, 0x2D, 0xB8, 0xB6, 0xE3, 0x11, //
+    0x00,
+];
+
+/// Apply (or remove) the VPR mask, `offset` being relative to the start of the audio.
+pub fn apply_vpr_mask<T: AsMut<[u8]> + ?Sized>(offset: usize, buffer: &mut T) {
+    for (i, datum) in (offset..).zip(buffer.as_mut().iter_mut()) {
+        *datum ^= VPR_MASK_DIFF[i % VPR_MASK_DIFF.len()];
+    }
+}
+
+/// Kugou cipher, with the post-processing required by the media type.
+#[derive(Debug, Clone, PartialOrd, Ord, PartialEq, Eq)]
+pub struct Kugou {
+    media_type: MediaType,
+    cipher: CipherModes,
+}
+
+impl Kugou {
+    /// Init the cipher with the slot key from [`crate::crypto::kugou::SLOT_KEYS`].
+    pub fn new(hdr: &Header) -> Result<Self, CipherError> {
+        Self::with_cipher(hdr, CipherModes::new(hdr)?)
+    }
+
+    /// Same as [`Kugou::new`], with the key tables computed on demand (mode 4);
+    /// slower, but uses much less memory.
+    pub fn new_lazy(hdr: &Header) -> Result<Self, CipherError> {
+        Self::with_cipher(hdr, CipherModes::new_lazy(hdr)?)
+    }
+
+    /// Init the cipher with a custom slot key, ignoring `hdr.key_slot`.
+    pub fn with_slot_key<T: AsRef<[u8]>>(hdr: &Header, slot_key: T) -> Result<Self, CipherError> {
+        Self::with_cipher(hdr, CipherModes::with_slot_key(hdr, slot_key)?)
+    }
+
+    /// Same as [`Kugou::with_slot_key`], with the key tables computed on demand (mode 4).
+    pub fn with_slot_key_lazy<T: AsRef<[u8]>>(
+        hdr: &Header,
+        slot_key: T,
+    ) -> Result<Self, CipherError> {
+        Self::with_cipher(hdr, CipherModes::with_slot_key_lazy(hdr, slot_key)?)
+    }
+
+    /// Wrap an existing cipher; its challenge is verified against the header.
+    pub fn with_cipher(hdr: &Header, cipher: CipherModes) -> Result<Self, CipherError> {
+        let media_type = hdr
+            .get_file_type()
+            .ok_or(CipherError::CouldNotGenerateChallenge)?;
+        cipher.verify_challenge(hdr)?;
+
+        Ok(Self { media_type, cipher })
+    }
+
+    /// Largest amount of audio the cipher can process, `None` if unlimited.
+    ///
+    /// Bytes beyond it are left as-is by [`ByteOffsetDecipher`]; use
+    /// [`Kugou::try_decipher_buffer`] to detect them.
+    pub fn max_len(&self) -> Option<usize> {
+        self.cipher.max_len()
+    }
+
+    /// Check that `len` bytes of audio can be processed by the cipher.
+    pub fn check_len(&self, len: usize) -> Result<(), CipherError> {
+        match self.cipher.max_len() {
+            Some(max_len) if len > max_len => Err(CipherError::OffsetOutOfRange(len, max_len)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Decrypt the buffer, or return an error if it goes beyond [`Kugou::max_len`].
+    pub fn try_decipher_buffer<T: AsMut<[u8]> + ?Sized>(
+        &self,
+        offset: usize,
+        buffer: &mut T,
+    ) -> Result<(), CipherError> {
+        let buffer = buffer.as_mut();
+        self.cipher.try_decipher_buffer(offset, buffer)?;
+        if self.media_type == MediaType::VPR {
+            apply_vpr_mask(offset, buffer);
+        }
+        Ok(())
+    }
+
+    pub fn get_media_type(&self) -> MediaType {
+        self.media_type.clone()
+    }
+}
+
+impl ByteOffsetDecipher for Kugou {
+    fn decipher_byte(&self, offset: usize, datum: u8) -> u8 {
+        let datum = self.cipher.decipher_byte(offset, datum);
+        match self.media_type {
+            MediaType::KGM => datum,
+            MediaType::VPR => datum ^ VPR_MASK_DIFF[offset % VPR_MASK_DIFF.len()],
+        }
+    }
+
+    fn decipher_buffer<T: AsMut<[u8]> + ?Sized>(&self, offset: usize, buffer: &mut T) {
+        self.cipher.decipher_buffer(offset, buffer);
+        if self.media_type == MediaType::VPR {
+            apply_vpr_mask(offset, buffer);
+        }
+    }
+}
+
+impl ByteOffsetEncipher for Kugou {
+    fn encipher_byte(&self, offset: usize, datum: u8) -> u8 {
+        let datum = match self.media_type {
+            MediaType::KGM => datum,
+            MediaType::VPR => datum ^ VPR_MASK_DIFF[offset % VPR_MASK_DIFF.len()],
+        };
+        self.cipher.encipher_byte(offset, datum)
+    }
+
+    fn encipher_buffer<T: AsMut<[u8]> + ?Sized>(&self, offset: usize, buffer: &mut T) {
+        if self.media_type == MediaType::VPR {
+            apply_vpr_mask(offset, buffer);
+        }
+        self.cipher.encipher_buffer(offset, buffer);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::crypto::kugou::Mode4;
+
+    use super::*;
+
+    const SAMPLE: &[u8] = include_bytes!("../../../sample/test_121529_32kbps.ogg");
+    const TEST_SLOT_KEY: &[u8] = b"09AZ";
+
+    fn decrypt_sample(cipher: &Kugou, hdr: &Header, file: &[u8]) -> Vec<u8> {
+        let mut audio = file[hdr.header_len as usize..].to_vec();
+        cipher.decipher_buffer(0, &mut audio);
+        audio
+    }
+
+    fn test_sample(file: &[u8], media_type: MediaType) {
+        let hdr = Header::from_bytes(file).unwrap();
+        let cipher = Kugou::with_slot_key(&hdr, TEST_SLOT_KEY).unwrap();
+        assert_eq!(cipher.get_media_type(), media_type);
+        assert_eq!(decrypt_sample(&cipher, &hdr, file), SAMPLE);
+    }
+
+    #[test]
+    fn test_kgm_v2() {
+        test_sample(
+            include_bytes!("../../../sample/test_kgm_v2.kgm"),
+            MediaType::KGM,
+        );
+    }
+
+    #[test]
+    fn test_kgm_v3() {
+        test_sample(
+            include_bytes!("../../../sample/test_kgm_v3.kgm"),
+            MediaType::KGM,
+        );
+    }
+
+    #[test]
+    fn test_kgm_v3_vpr() {
+        test_sample(
+            include_bytes!("../../../sample/test_kgm_v3.vpr"),
+            MediaType::VPR,
+        );
+    }
+
+    #[test]
+    fn test_kgm_v4() {
+        let file = include_bytes!("../../../sample/test_kgm_v4.kgm");
+        let slot_key_salt = include_bytes!("../../../sample/test_kgm_v4_slotkey_table.bin");
+        let file_key_salt = include_bytes!("../../../sample/test_kgm_v4_filekey_table.bin");
+
+        let hdr = Header::from_bytes(file).unwrap();
+        let mode4 = Mode4::with_salts(TEST_SLOT_KEY, hdr.file_key, slot_key_salt, file_key_salt);
+        let cipher = Kugou::with_cipher(&hdr, CipherModes::Mode4(mode4)).unwrap();
+        assert_eq!(decrypt_sample(&cipher, &hdr, file), SAMPLE);
+
+        let max_len = cipher.max_len().unwrap();
+        assert!(cipher.try_decipher_buffer(max_len - 1, &mut [0u8]).is_ok());
+        assert!(matches!(
+            cipher.try_decipher_buffer(max_len - 1, &mut [0u8; 2]),
+            Err(CipherError::OffsetOutOfRange(len, _)) if len == max_len + 1
+        ));
+    }
+
+    #[test]
+    fn test_vpr_round_trip() {
+        let file = include_bytes!("../../../sample/test_kgm_v3.vpr");
+        let hdr = Header::from_bytes(file).unwrap();
+        let cipher = Kugou::with_slot_key(&hdr, TEST_SLOT_KEY).unwrap();
+
+        let mut data = SAMPLE[..100].to_vec();
+        cipher.encipher_buffer(0, &mut data);
+        assert_eq!(data, file[1024..1124]);
+        assert_eq!(cipher.decipher_byte(5, data[5]), SAMPLE[5]);
+    }
+
+    #[test]
+    fn test_kgm_to_vpr() {
+        let file = include_bytes!("../../../sample/test_kgm_v3.kgm");
+        let hdr = Header::from_bytes(file).unwrap();
+        let cipher = CipherModes::with_slot_key(&hdr, TEST_SLOT_KEY).unwrap();
+
+        let mut vpr_hdr = hdr.clone();
+        vpr_hdr.set_file_type(MediaType::VPR);
+        assert!(Kugou::with_cipher(&vpr_hdr, cipher.clone()).is_err());
+        cipher.sign_challenge(&mut vpr_hdr).unwrap();
+        let encipher = Kugou::with_cipher(&vpr_hdr, cipher).unwrap();
+
+        let mut audio = SAMPLE.to_vec();
+        encipher.encipher_buffer(0, &mut audio);
+        let mut vpr = vpr_hdr.to_bytes().unwrap();
+        vpr.extend(audio);
+        test_sample(&vpr, MediaType::VPR);
+    }
+
+    #[test]
+    fn test_new_header() {
+        let mut hdr = Header::new(MediaType::KGM);
+        hdr.crypto_version = 3;
+        hdr.key_slot = 1;
+        hdr.file_key = *b"0123456789abcdef";
+        let cipher = CipherModes::with_slot_key_unchecked(&hdr, TEST_SLOT_KEY).unwrap();
+        cipher.sign_challenge(&mut hdr).unwrap();
+
+        let hdr = Header::from_bytes(hdr.to_bytes().unwrap()).unwrap();
+        assert_eq!(hdr.header_len, 1024);
+        assert!(Kugou::with_slot_key(&hdr, TEST_SLOT_KEY).is_ok());
+    }
+
+    #[test]
+    fn test_lazy() {
+        let mut hdr = Header::new(MediaType::VPR);
+        hdr.crypto_version = 4;
+        hdr.file_key = *b"0123456789abcdef";
+        let eager = CipherModes::with_slot_key_unchecked(&hdr, TEST_SLOT_KEY).unwrap();
+        eager.sign_challenge(&mut hdr).unwrap();
+
+        let eager = Kugou::with_slot_key(&hdr, TEST_SLOT_KEY).unwrap();
+        let lazy = Kugou::with_slot_key_lazy(&hdr, TEST_SLOT_KEY).unwrap();
+        assert_eq!(eager.max_len(), lazy.max_len());
+
+        let mut data_eager = SAMPLE[..100].to_vec();
+        let mut data_lazy = data_eager.clone();
+        eager.decipher_buffer(0x1234, &mut data_eager);
+        lazy.try_decipher_buffer(0x1234, &mut data_lazy).unwrap();
+        assert_eq!(data_eager, data_lazy);
+
+        let max_len = lazy.max_len().unwrap();
+        assert!(matches!(
+            lazy.try_decipher_buffer(max_len, &mut [0u8]),
+            Err(CipherError::OffsetOutOfRange(len, _)) if len == max_len + 1
+        ));
+    }
+
+    #[test]
+    fn test_wrong_slot_key() {
+        let file = include_bytes!("../../../sample/test_kgm_v2.kgm");
+        let hdr = Header::from_bytes(file).unwrap();
+        assert!(matches!(
+            Kugou::with_slot_key(&hdr, b"wrong key"),
+            Err(CipherError::ChallengeValidationFail(_, _))
+        ));
+    }
+}
@@ -1,9 +1,11 @@
-use std::io::{BufReader, Error, Read, Write};
+use alloc::boxed::Box;
+use alloc::vec::Vec;
 
-use byteorder::{ReadBytesExt, WriteBytesExt, LE};
+use byteorder::{ByteOrder, LE};
 use thiserror::Error;
 
-#[derive(Debug, Clone, PartialEq, Eq, Default)]
+#[derive(Debug, Clone, PartialOrd, Ord, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub enum MediaType {
     #[default]
     KGM,
@@ -15,49 +17,106 @@ const KGM_CHALLENGE: [u8; 16] = *include_bytes!("data/test_vector_kgm.bin");
 const VPR_HEADER_MAGIC: [u8; 16] = *include_bytes!("data/header_vpr.bin");
 const VPR_CHALLENGE: [u8; 16] = *include_bytes!("data/test_vector_vpr.bin");
 
+/// Kugou file header.
+///
+/// | Offset | Size  | Field                 |
+/// |--------|-------|-----------------------|
+/// | `0x00` | 16    | `magic`               |
+/// | `0x10` | 4     | `header_len`          |
+/// | `0x14` | 4     | `crypto_version`      |
+/// | `0x18` | 4     | `key_slot`            |
+/// | `0x1C` | 16    | `encrypted_test_data` |
+/// | `0x2C` | 16    | `file_key`            |
+/// | `0x3C` | rest  | `extra`               |
 #[derive(Debug, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct Header {
+    #[cfg_attr(feature = "serde", serde(with = "crate::utils::serde_hex"))]
     pub magic: [u8; 16],
     pub header_len: u32,
     pub crypto_version: u32,
     pub key_slot: u32,
+    #[cfg_attr(feature = "serde", serde(with = "crate::utils::serde_hex"))]
     pub encrypted_test_data: [u8; 16],
+    #[cfg_attr(feature = "serde", serde(with = "crate::utils::serde_hex"))]
     pub file_key: [u8; 16],
+
+    /// Rest of the header (up to `header_len`), kept as-is.
+    /// Truncated if fewer bytes were given to the parser.
+    #[cfg_attr(feature = "serde", serde(with = "crate::utils::serde_hex"))]
+    pub extra: Box<[u8]>,
 }
 
 pub const MIN_HEADER_LEN: usize = 16 * 3 + 4 * 3;
+/// Header length used by the clients.
+pub const DEFAULT_HEADER_LEN: u32 = 0x400;
+/// Largest `header_len` accepted; clients use [`DEFAULT_HEADER_LEN`].
+pub const MAX_HEADER_LEN: usize = 4 * 1024 * 1024;
 
 #[derive(Error, Debug)]
 pub enum HeaderSerializeError {
+    #[cfg(feature = "std")]
     #[error("Could not serialize header: {0}")]
-    SerializationIoError(std::io::Error),
+    SerializationIoError(#[source] std::io::Error),
     #[error("Could not serialize header, `hdr.header_len` needs to be equal or greater than {1} bytes (got {0})")]
     HeaderLenFieldTooSmall(usize, usize),
+    #[error(
+        "Could not serialize header, `hdr.header_len` needs to be at most {1} bytes (got {0})"
+    )]
+    HeaderLenFieldTooLarge(usize, usize),
 }
 
 #[derive(Error, Debug)]
 pub enum HeaderDeserializeError {
+    #[cfg(feature = "std")]
     #[error("Could not deserialize header from bytes: {0}")]
-    DeserializationIoError(std::io::Error),
+    DeserializationIoError(#[source] std::io::Error),
     #[error("Does not include a valid magic header")]
     InvalidMagic,
-    #[error("Could not deserialize header, `hdr.header_len` is lower than {1} bytes (got {0})")]
+    #[error("Could not deserialize header, input is shorter than {1} bytes (got {0})")]
     InputHeaderTooSmall(usize, usize),
+    #[error("Could not deserialize header, `hdr.header_len` is lower than {1} bytes (got {0})")]
+    HeaderLenFieldTooSmall(usize, usize),
+    #[error("Could not deserialize header, `hdr.header_len` is greater than {1} bytes (got {0})")]
+    HeaderLenFieldTooLarge(usize, usize),
 }
 
+#[cfg(feature = "std")]
 impl From<std::io::Error> for HeaderDeserializeError {
-    fn from(err: Error) -> Self {
+    fn from(err: std::io::Error) -> Self {
         Self::DeserializationIoError(err)
     }
 }
 
+#[cfg(feature = "std")]
 impl From<std::io::Error> for HeaderSerializeError {
-    fn from(err: Error) -> Self {
+    fn from(err: std::io::Error) -> Self {
         Self::SerializationIoError(err)
     }
 }
 
 impl Header {
+    /// Create an empty header of `media_type`, with the default header length.
+    ///
+    /// The cipher fields have to be set, then the challenge signed with
+    /// [`crate::crypto::kugou::CipherModes::sign_challenge`].
+    pub fn new(media_type: MediaType) -> Self {
+        let mut hdr = Self {
+            header_len: DEFAULT_HEADER_LEN,
+            ..Default::default()
+        };
+        hdr.set_file_type(media_type);
+        hdr
+    }
+
+    /// Change the magic; the challenge has to be signed again.
+    pub fn set_file_type(&mut self, media_type: MediaType) {
+        self.magic = match media_type {
+            MediaType::KGM => KGM_HEADER_MAGIC,
+            MediaType::VPR => VPR_HEADER_MAGIC,
+        };
+    }
+
     pub fn get_file_type(&self) -> Option<MediaType> {
         if self.magic == KGM_HEADER_MAGIC {
             Some(MediaType::KGM)
@@ -88,38 +147,64 @@ impl Header {
         }
 
         let mut hdr = Self::default();
-        let mut reader = BufReader::new(data);
-        reader.read_exact(&mut hdr.magic)?;
-        hdr.header_len = reader.read_u32::<LE>()?;
-        hdr.crypto_version = reader.read_u32::<LE>()?;
-        hdr.key_slot = reader.read_u32::<LE>()?;
-        reader.read_exact(&mut hdr.encrypted_test_data)?;
-        reader.read_exact(&mut hdr.file_key)?;
-
-        match hdr.get_file_type() {
-            Some(_) => Ok(hdr),
-            None => Err(HeaderDeserializeError::InvalidMagic),
+        hdr.magic.copy_from_slice(&data[0x00..0x10]);
+        hdr.header_len = LE::read_u32(&data[0x10..0x14]);
+        hdr.crypto_version = LE::read_u32(&data[0x14..0x18]);
+        hdr.key_slot = LE::read_u32(&data[0x18..0x1C]);
+        hdr.encrypted_test_data.copy_from_slice(&data[0x1C..0x2C]);
+        hdr.file_key.copy_from_slice(&data[0x2C..0x3C]);
+
+        if hdr.get_file_type().is_none() {
+            Err(HeaderDeserializeError::InvalidMagic)?;
         }
+
+        // The audio data starts at `header_len`; it can't overlap the header itself.
+        let header_len = hdr.header_len as usize;
+        if header_len < MIN_HEADER_LEN {
+            Err(HeaderDeserializeError::HeaderLenFieldTooSmall(
+                header_len,
+                MIN_HEADER_LEN,
+            ))?;
+        }
+        if header_len > MAX_HEADER_LEN {
+            Err(HeaderDeserializeError::HeaderLenFieldTooLarge(
+                header_len,
+                MAX_HEADER_LEN,
+            ))?;
+        }
+
+        let extra_end = data.len().min(header_len);
+        hdr.extra = data[MIN_HEADER_LEN..extra_end].into();
+
+        Ok(hdr)
     }
 
     pub fn to_bytes(&self) -> Result<Vec<u8>, HeaderSerializeError> {
         let header_len = self.header_len as usize;
         if header_len < MIN_HEADER_LEN {
             Err(HeaderSerializeError::HeaderLenFieldTooSmall(
+                header_len,
                 MIN_HEADER_LEN,
+            ))?;
+        }
+        if header_len > MAX_HEADER_LEN {
+            Err(HeaderSerializeError::HeaderLenFieldTooLarge(
                 header_len,
+                MAX_HEADER_LEN,
             ))?;
         }
 
         let mut data = Vec::with_capacity(header_len);
 
-        data.write_all(&self.magic)?;
-        data.write_u32::<LE>(self.header_len)?;
-        data.write_u32::<LE>(self.crypto_version)?;
-        data.write_u32::<LE>(self.key_slot)?;
-        data.write_all(&self.encrypted_test_data)?;
-        data.write_all(&self.file_key)?;
+        data.extend_from_slice(&self.magic);
+        data.extend_from_slice(&self.header_len.to_le_bytes());
+        data.extend_from_slice(&self.crypto_version.to_le_bytes());
+        data.extend_from_slice(&self.key_slot.to_le_bytes());
+        data.extend_from_slice(&self.encrypted_test_data);
+        data.extend_from_slice(&self.file_key);
 
+        let extra_len = self.extra.len().min(header_len - MIN_HEADER_LEN);
+        data.extend_from_slice(&self.extra[..extra_len]);
         data.resize(header_len, 0);
         Ok(data)
     }
@@ -139,6 +224,7 @@ mod tests {
         assert_eq!(hdr.key_slot, 0);
         assert_eq!(hdr.encrypted_test_data, [0; 16]);
         assert_eq!(hdr.file_key, [0; 16]);
+        assert!(hdr.extra.is_empty());
     }
 
     #[test]
@@ -150,6 +236,7 @@ mod tests {
             key_slot: 3,
             encrypted_test_data: [4; 16],
             file_key: [5; 16],
+            extra: Box::from([6; 1024 - MIN_HEADER_LEN]),
         };
 
         let serialized_hdr = original_hdr.to_bytes().unwrap();
@@ -158,4 +245,98 @@ mod tests {
         let deserialized_hdr = Header::from_bytes(serialized_hdr).unwrap();
         assert_eq!(original_hdr, deserialized_hdr);
     }
+
+    #[test]
+    fn test_header_len_too_small() {
+        let mut data = Header {
+            magic: VPR_HEADER_MAGIC,
+            header_len: 1024,
+            ..Default::default()
+        }
+        .to_bytes()
+        .unwrap();
+        data[0x10..0x14].copy_from_slice(&0x20u32.to_le_bytes());
+
+        assert!(matches!(
+            Header::from_bytes(data),
+            Err(HeaderDeserializeError::HeaderLenFieldTooSmall(
+                0x20,
+                MIN_HEADER_LEN
+            ))
+        ));
+    }
+
+    #[test]
+    fn test_header_len_too_large() {
+        let mut data = Header::new(MediaType::KGM).to_bytes().unwrap();
+        data[0x10..0x14].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(matches!(
+            Header::from_bytes(data),
+            Err(HeaderDeserializeError::HeaderLenFieldTooLarge(
+                0xFFFF_FFFF,
+                MAX_HEADER_LEN
+            ))
+        ));
+
+        let hdr = Header {
+            header_len: MAX_HEADER_LEN as u32 + 1,
+            ..Header::new(MediaType::KGM)
+        };
+        assert!(matches!(
+            hdr.to_bytes(),
+            Err(HeaderSerializeError::HeaderLenFieldTooLarge(
+                _,
+                MAX_HEADER_LEN
+            ))
+        ));
+    }
+
+    #[test]
+    fn test_invalid_magic() {
+        let data = [0u8; MIN_HEADER_LEN];
+        assert!(matches!(
+            Header::from_bytes(data),
+            Err(HeaderDeserializeError::InvalidMagic)
+        ));
+    }
+
+    #[test]
+    fn test_extra_fields() {
+        let mut data = Header {
+            magic: KGM_HEADER_MAGIC,
+            header_len: 1024,
+            crypto_version: 5,
+            ..Default::default()
+        }
+        .to_bytes()
+        .unwrap();
+        data[0x40..0x50].copy_from_slice(b"0123456789abcdef");
+        data[0x3F0] = 0xFF;
+
+        let hdr = Header::from_bytes(&data).unwrap();
+        assert_eq!(hdr.extra.len(), 1024 - MIN_HEADER_LEN);
+        assert_eq!(
+            hdr.extra[0x40 - MIN_HEADER_LEN..][..16],
+            *b"0123456789abcdef"
+        );
+        assert_eq!(hdr.to_bytes().unwrap(), data);
+    }
+
+    #[test]
+    fn test_short_extra() {
+        let data = include_bytes!("../../../sample/kgm_header_v2.bin");
+        let hdr = Header::from_bytes(data).unwrap();
+        assert_eq!(hdr.extra.len(), data.len() - MIN_HEADER_LEN);
+
+        let serialized = hdr.to_bytes().unwrap();
+        assert_eq!(serialized.len(), 1024);
+        assert_eq!(&serialized[..data.len()], data);
+    }
+
+    #[test]
+    fn test_sample_round_trip() {
+        let data = &include_bytes!("../../../sample/test_kgm_v4.kgm")[..1024];
+        let hdr = Header::from_bytes(data).unwrap();
+        assert_eq!(hdr.to_bytes().unwrap(), data);
+    }
 }
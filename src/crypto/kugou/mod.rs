@@ -1,5 +1,9 @@
+mod cipher;
 mod header;
 mod modes;
 
-pub use header::{Header, HeaderDeserializeError, HeaderSerializeError, MediaType};
-pub use modes::{CipherError, CipherModes, Mode2, Mode3, Mode4, SLOT_KEYS};
+pub use cipher::{apply_vpr_mask, Kugou, VPR_MASK_DIFF};
+pub use header::{
+    Header, HeaderDeserializeError, HeaderSerializeError, MediaType, DEFAULT_HEADER_LEN,
+};
+pub use modes::{get_slot_key, CipherError, CipherModes, Mode2, Mode3, Mode4, SLOT_KEYS};
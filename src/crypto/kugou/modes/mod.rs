@@ -1,6 +1,5 @@
-use std::collections::HashMap;
+use alloc::vec::Vec;
 
-use lazy_static::lazy_static;
 use thiserror::Error;
 
 pub use mode2::Mode2;
@@ -14,13 +13,15 @@ mod mode2;
 mod mode3;
 mod mode4;
 
-lazy_static! {
-    /// Slot keys corresponds to the keys specified in the header.
-    pub static ref SLOT_KEYS: HashMap<u32, Box<[u8]>> = {
-        let mut m = HashMap::new();
-        m.insert(1, Box::from(*include_bytes!("../data/slot_01.bin")));
-        m
-    };
+/// Slot keys corresponds to the keys specified in the header.
+pub static SLOT_KEYS: &[(u32, &[u8])] = &[(1, include_bytes!("../data/slot_01.bin"))];
+
+/// Lookup a key from [`SLOT_KEYS`].
+pub fn get_slot_key(key_slot: u32) -> Option<&'static [u8]> {
+    SLOT_KEYS
+        .iter()
+        .find(|&&(slot, _)| slot == key_slot)
+        .map(|&(_, key)| key)
 }
 
 #[derive(Debug, Clone, PartialOrd, Ord, PartialEq, Eq)]
@@ -31,33 +32,117 @@ pub enum CipherModes {
 }
 
 impl CipherModes {
+    /// Init the cipher with the slot key from [`SLOT_KEYS`].
     pub fn new(hdr: &Header) -> Result<Self, CipherError> {
+        let slot_key =
+            get_slot_key(hdr.key_slot).ok_or(CipherError::SlotKeyMissing(hdr.key_slot))?;
+
+        Self::with_slot_key(hdr, slot_key)
+    }
+
+    /// Same as [`CipherModes::new`], but mode 4 key tables are computed on demand,
+    /// see [`Mode4::new_lazy`].
+    pub fn new_lazy(hdr: &Header) -> Result<Self, CipherError> {
+        let slot_key =
+            get_slot_key(hdr.key_slot).ok_or(CipherError::SlotKeyMissing(hdr.key_slot))?;
+
+        Self::with_slot_key_lazy(hdr, slot_key)
+    }
+
+    /// Init the cipher with a custom slot key, ignoring `hdr.key_slot`.
+    pub fn with_slot_key<T: AsRef<[u8]>>(hdr: &Header, slot_key: T) -> Result<Self, CipherError> {
+        let cipher = Self::build(hdr, slot_key.as_ref(), false)?;
+        cipher.verify_challenge(hdr)?;
+        Ok(cipher)
+    }
+
+    /// Same as [`CipherModes::with_slot_key`], but mode 4 key tables are computed on
+    /// demand, see [`Mode4::new_lazy`].
+    pub fn with_slot_key_lazy<T: AsRef<[u8]>>(
+        hdr: &Header,
+        slot_key: T,
+    ) -> Result<Self, CipherError> {
+        let cipher = Self::build(hdr, slot_key.as_ref(), true)?;
+        cipher.verify_challenge(hdr)?;
+        Ok(cipher)
+    }
+
+    /// Init the cipher with a custom slot key, without verifying the challenge.
+    ///
+    /// Used to create a new header, see [`CipherModes::sign_challenge`].
+    pub fn with_slot_key_unchecked<T: AsRef<[u8]>>(
+        hdr: &Header,
+        slot_key: T,
+    ) -> Result<Self, CipherError> {
+        Self::build(hdr, slot_key.as_ref(), false)
+    }
+
+    fn build(hdr: &Header, slot_key: &[u8], lazy: bool) -> Result<Self, CipherError> {
+        let cipher = match (hdr.crypto_version, lazy) {
+            (2, _) => CipherModes::Mode2(modes::Mode2::try_new(slot_key)?),
+            (3, _) => CipherModes::Mode3(modes::Mode3::try_new(slot_key, hdr.file_key)?),
+            (4, false) => CipherModes::Mode4(modes::Mode4::try_new(slot_key, hdr.file_key)?),
+            (4, true) => CipherModes::Mode4(modes::Mode4::try_new_lazy(slot_key, hdr.file_key)?),
+            (version, _) => Err(CipherError::UnsupportedCipherVersion(version))?,
+        };
+
+        Ok(cipher)
+    }
+
+    /// Maximum number of bytes the cipher can process, if limited.
+    pub fn max_len(&self) -> Option<usize> {
+        match self {
+            CipherModes::Mode4(m) => Some(m.max_len()),
+            _ => None,
+        }
+    }
+
+    /// Decrypt the buffer, or return an error if it goes beyond [`CipherModes::max_len`].
+    pub fn try_decipher_buffer<T: AsMut<[u8]> + ?Sized>(
+        &self,
+        offset: usize,
+        buffer: &mut T,
+    ) -> Result<(), CipherError> {
+        match self {
+            CipherModes::Mode4(m) => m.try_decipher_buffer(offset, buffer),
+            _ => {
+                self.decipher_buffer(offset, buffer);
+                Ok(())
+            }
+        }
+    }
+
+    /// Check if the cipher can decrypt the test data from the header.
+    pub fn verify_challenge(&self, hdr: &Header) -> Result<(), CipherError> {
         let challenge = hdr
             .get_challenge()
             .ok_or(CipherError::CouldNotGenerateChallenge)?;
-        let slot_key = SLOT_KEYS
-            .get(&hdr.key_slot)
-            .ok_or_else(|| CipherError::SlotKeyMissing(hdr.key_slot))?;
-
-        let cipher = match hdr.crypto_version {
-            2 => CipherModes::Mode2(modes::Mode2::new(slot_key)),
-            3 => CipherModes::Mode3(modes::Mode3::new(slot_key, hdr.file_key)),
-            4 => CipherModes::Mode4(modes::Mode4::new(slot_key, hdr.file_key)),
-            version => Err(CipherError::UnsupportedCipherVersion(version))?,
-        };
 
         let mut decrypted = hdr.encrypted_test_data;
-        cipher.decipher_buffer(0, &mut decrypted);
+        self.decipher_buffer(0, &mut decrypted);
         if challenge != decrypted {
             let challenge = challenge.into();
             let decrypted = decrypted.into();
             Err(CipherError::ChallengeValidationFail(challenge, decrypted))?;
         }
 
-        Ok(cipher)
+        Ok(())
+    }
+
+    /// Encrypt the challenge of the header's media type to `hdr.encrypted_test_data`.
+    pub fn sign_challenge(&self, hdr: &mut Header) -> Result<(), CipherError> {
+        let mut challenge = hdr
+            .get_challenge()
+            .ok_or(CipherError::CouldNotGenerateChallenge)?;
+
+        self.encipher_buffer(0, &mut challenge);
+        hdr.encrypted_test_data = challenge;
+        Ok(())
     }
 }
 
+/// Bytes beyond [`CipherModes::max_len`] are left as-is;
+/// use [`CipherModes::try_decipher_buffer`] to detect them.
 impl ByteOffsetDecipher for CipherModes {
     fn decipher_byte(&self, offset: usize, datum: u8) -> u8 {
         match self {
@@ -97,7 +182,7 @@ impl ByteOffsetEncipher for CipherModes {
 #[derive(Error, Debug)]
 pub enum CipherError {
     #[error("Parse header error: {0}")]
-    ParseHeaderFail(HeaderDeserializeError),
+    ParseHeaderFail(#[source] HeaderDeserializeError),
     #[error("Could not generate challenge - is file magic correct?")]
     CouldNotGenerateChallenge,
     #[error("Requested slot key does not exist: {0}")]
@@ -108,6 +193,12 @@ pub enum CipherError {
     ChallengeValidationFail(Vec<u8>, Vec<u8>),
     #[error("Not enough data, expect at least {0} bytes.")]
     NotEnoughData(usize),
+    #[error("Offset {0} is out of range, the cipher supports up to {1} bytes")]
+    OffsetOutOfRange(usize, usize),
+    #[error("Slot key must not be empty")]
+    EmptySlotKey,
+    #[error("Mode4 salt is too short: expected at least 2 bytes, got {0}")]
+    SaltTooShort(usize),
 }
 
 impl From<HeaderDeserializeError> for CipherError {
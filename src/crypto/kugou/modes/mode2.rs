@@ -1,4 +1,7 @@
+use alloc::boxed::Box;
+
 use crate::crypto::byte_offset_cipher::{ByteOffsetDecipher, ByteOffsetEncipher};
+use crate::crypto::kugou::CipherError;
 
 #[derive(Debug, Clone, PartialOrd, Ord, PartialEq, Eq)]
 pub struct Mode2 {
@@ -6,11 +9,23 @@ pub struct Mode2 {
 }
 
 impl Mode2 {
+    /// # Panics
+    ///
+    /// Deciphering panics if the slot key is empty; use [`Mode2::try_new`] for untrusted keys.
     pub fn new<T: AsRef<[u8]>>(slot_key: T) -> Self {
         Self {
             slot_key: Box::from(slot_key.as_ref()),
         }
     }
+
+    pub fn try_new<T: AsRef<[u8]>>(slot_key: T) -> Result<Self, CipherError> {
+        let slot_key = slot_key.as_ref();
+        if slot_key.is_empty() {
+            Err(CipherError::EmptySlotKey)?;
+        }
+
+        Ok(Self::new(slot_key))
+    }
 }
 
 impl ByteOffsetEncipher for Mode2 {
@@ -32,3 +47,25 @@ impl ByteOffsetDecipher for Mode2 {
         datum
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_try_new() {
+        assert!(matches!(
+            Mode2::try_new(b""),
+            Err(CipherError::EmptySlotKey)
+        ));
+
+        for len in 1..=64 {
+            let key = (0..len).map(|i| (i * 13 + len) as u8).collect::<Vec<_>>();
+            let cipher = Mode2::try_new(&key).unwrap();
+            let mut data = [0x55u8; 64];
+            cipher.encipher_buffer(0x1234, &mut data);
+            cipher.decipher_buffer(0x1234, &mut data);
+            assert_eq!(data, [0x55u8; 64]);
+        }
+    }
+}
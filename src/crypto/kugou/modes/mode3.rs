@@ -1,4 +1,7 @@
+use alloc::vec::Vec;
+
 use crate::crypto::byte_offset_cipher::{ByteOffsetDecipher, ByteOffsetEncipher};
+use crate::crypto::kugou::CipherError;
 use crate::utils::md5;
 
 #[derive(Debug, Clone, PartialOrd, Ord, PartialEq, Eq)]
@@ -21,6 +24,19 @@ impl Mode3 {
         }
     }
 
+    /// Same as [`Mode3::new`], but rejects empty slot keys.
+    pub fn try_new<T: AsRef<[u8]>, T2: AsRef<[u8]>>(
+        slot_key: T,
+        file_key: T2,
+    ) -> Result<Self, CipherError> {
+        let slot_key = slot_key.as_ref();
+        if slot_key.is_empty() {
+            Err(CipherError::EmptySlotKey)?;
+        }
+
+        Ok(Self::new(slot_key, file_key))
+    }
+
     fn hash_key<T: AsRef<[u8]>>(buffer: T) -> [u8; 16] {
         let digest = md5(buffer);
 
@@ -41,23 +57,6 @@ impl Mode3 {
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    #[test]
-    fn test_hash_key() {
-        let key = b"hello world";
-        let result = Mode3::hash_key(key);
-
-        assert_eq!(result.len(), 16);
-        assert_eq!(
-            result,
-            *b"\xCD\xC3\x8F\x5A\x22\xBB\x93\xCB\xEE\xD0\xE0\x1E\x3B\xBB\x5E\xB6"
-        );
-    }
-}
-
 impl ByteOffsetEncipher for Mode3 {
     fn encipher_byte(&self, offset: usize, datum: u8) -> u8 {
         let offset_checksum = Self::calc_offset_checksum(offset as u32);
@@ -88,3 +87,37 @@ impl ByteOffsetDecipher for Mode3 {
         datum
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hash_key() {
+        let key = b"hello world";
+        let result = Mode3::hash_key(key);
+
+        assert_eq!(result.len(), 16);
+        assert_eq!(
+            result,
+            *b"\xCD\xC3\x8F\x5A\x22\xBB\x93\xCB\xEE\xD0\xE0\x1E\x3B\xBB\x5E\xB6"
+        );
+    }
+
+    #[test]
+    fn test_try_new() {
+        assert!(matches!(
+            Mode3::try_new(b"", b"file key"),
+            Err(CipherError::EmptySlotKey)
+        ));
+
+        for len in 0..=64 {
+            let file_key = (0..len).map(|i| (i * 13 + len) as u8).collect::<Vec<_>>();
+            let cipher = Mode3::try_new(b"slot key", &file_key).unwrap();
+            let mut data = [0x55u8; 64];
+            cipher.encipher_buffer(0x1234, &mut data);
+            cipher.decipher_buffer(0x1234, &mut data);
+            assert_eq!(data, [0x55u8; 64]);
+        }
+    }
+}
@@ -1,14 +1,12 @@
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+
 use base64::{engine::general_purpose::STANDARD as Base64, Engine as _};
 
 use crate::crypto::byte_offset_cipher::{ByteOffsetDecipher, ByteOffsetEncipher};
+use crate::crypto::kugou::CipherError;
 use crate::utils::md5;
 
-#[derive(Debug, Clone, PartialOrd, Ord, PartialEq, Eq)]
-pub struct Mode4 {
-    slot_key_table: Box<[u8]>,
-    file_key_table: Box<[u8]>,
-}
-
 const V4_DIGEST_SIZE: usize = 31;
 const V4_DIGEST_INDEXES: [usize; V4_DIGEST_SIZE] = [
     0x05, 0x0e, 0x0d, 0x02, 0x0c, 0x0a, 0x0f, 0x0b, //
@@ -17,15 +15,214 @@ const V4_DIGEST_INDEXES: [usize; V4_DIGEST_SIZE] = [
     0x01, 0x0b, 0x08, 0x07, 0x09, 0x04, 0x01,
 ];
 
+/// Key table, either expanded up-front or computed on demand.
+#[derive(Debug, Clone, PartialOrd, Ord, PartialEq, Eq)]
+enum KeyTable {
+    Expanded(Box<[u8]>),
+    Lazy {
+        digest: [u8; V4_DIGEST_SIZE],
+        salt: Box<[u8]>,
+    },
+}
+
+impl KeyTable {
+    fn new<K: AsRef<[u8]>, S: AsRef<[u8]>>(key: K, salt: S, lazy: bool) -> Self {
+        let digest = Mode4::hash_key(key);
+        let salt = salt.as_ref();
+
+        match lazy {
+            true => Self::Lazy {
+                digest,
+                salt: salt.into(),
+            },
+            false => Self::Expanded(Self::expand(&digest, salt)),
+        }
+    }
+
+    fn expand(digest: &[u8; V4_DIGEST_SIZE], salt: &[u8]) -> Box<[u8]> {
+        let final_key_size = Self::table_len(salt);
+        let mut expanded_key = Vec::with_capacity(final_key_size);
+        for (i, &j) in digest.iter().enumerate().skip(1) {
+            for (k, &l) in salt.iter().enumerate().skip(1) {
+                expanded_key.extend(Self::entry(i, j, k, l));
+            }
+        }
+
+        expanded_key.into_boxed_slice()
+    }
+
+    fn table_len(salt: &[u8]) -> usize {
+        4 * (V4_DIGEST_SIZE - 1) * salt.len().saturating_sub(1)
+    }
+
+    /// 4 bytes of the table, derived from `digest[i] = j` and `salt[k] = l`.
+    fn entry(i: usize, j: u8, k: usize, l: u8) -> [u8; 4] {
+        let temp = (i as u32).wrapping_mul(j as u32);
+        let temp = temp.wrapping_mul(k as u32).wrapping_mul(l as u32);
+        let bytes = temp.to_le_bytes();
+
+        // (LittleEndian)   0x12345678
+        // Memory:  [78] [56] [34] [12]
+        //
+        //    SHR:   00   08   10   18
+        //       = 0x78 0x56 0x34 0x12
+        //  Index:    0    1    2    3
+        [
+            bytes[0], // temp >> 0x00
+            bytes[3], // temp >> 0x18
+            bytes[2], // temp >> 0x10
+            bytes[1], // temp >> 0x08
+        ]
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            Self::Expanded(table) => table.len(),
+            Self::Lazy { salt, .. } => Self::table_len(salt),
+        }
+    }
+
+    fn get(&self, index: usize) -> Option<u8> {
+        match self {
+            Self::Expanded(table) => table.get(index).copied(),
+            Self::Lazy { digest, salt } => {
+                if index >= self.len() {
+                    return None;
+                }
+
+                let entry_idx = index / 4;
+                let salt_len = salt.len() - 1;
+                let i = entry_idx / salt_len + 1;
+                let k = entry_idx % salt_len + 1;
+                Some(Self::entry(i, digest[i], k, salt[k])[index % 4])
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialOrd, Ord, PartialEq, Eq)]
+pub struct Mode4 {
+    slot_key_table: KeyTable,
+    file_key_table: KeyTable,
+}
+
 impl Mode4 {
     pub fn new<T: AsRef<[u8]>, T2: AsRef<[u8]>>(slot_key: T, file_key: T2) -> Self {
-        let slot_key = Base64.encode(hex::encode(md5(slot_key)));
         let slot_key_salt = include_bytes!("../data/mode4_slot_key_salt.bin");
         let file_key_salt = include_bytes!("../data/mode4_file_key_salt.bin");
+        Self::with_salts(slot_key, file_key, slot_key_salt, file_key_salt)
+    }
+
+    /// Same as [`Mode4::new`], but computes the key tables on demand instead of
+    /// allocating them; slower, but uses much less memory.
+    pub fn new_lazy<T: AsRef<[u8]>, T2: AsRef<[u8]>>(slot_key: T, file_key: T2) -> Self {
+        let slot_key_salt = include_bytes!("../data/mode4_slot_key_salt.bin");
+        let file_key_salt = include_bytes!("../data/mode4_file_key_salt.bin");
+        Self::build(slot_key, file_key, slot_key_salt, file_key_salt, true)
+    }
+
+    /// Same as [`Mode4::new`], but rejects empty slot keys.
+    pub fn try_new<T: AsRef<[u8]>, T2: AsRef<[u8]>>(
+        slot_key: T,
+        file_key: T2,
+    ) -> Result<Self, CipherError> {
+        let slot_key_salt = include_bytes!("../data/mode4_slot_key_salt.bin");
+        let file_key_salt = include_bytes!("../data/mode4_file_key_salt.bin");
+        Self::try_build(slot_key, file_key, slot_key_salt, file_key_salt, false)
+    }
+
+    /// Same as [`Mode4::new_lazy`], but rejects empty slot keys.
+    pub fn try_new_lazy<T: AsRef<[u8]>, T2: AsRef<[u8]>>(
+        slot_key: T,
+        file_key: T2,
+    ) -> Result<Self, CipherError> {
+        let slot_key_salt = include_bytes!("../data/mode4_slot_key_salt.bin");
+        let file_key_salt = include_bytes!("../data/mode4_file_key_salt.bin");
+        Self::try_build(slot_key, file_key, slot_key_salt, file_key_salt, true)
+    }
+
+    /// Same as [`Mode4::with_salts`], but rejects empty slot keys and salts
+    /// that would result in an empty key table.
+    pub fn try_with_salts<T1, T2, S1, S2>(
+        slot_key: T1,
+        file_key: T2,
+        slot_key_salt: S1,
+        file_key_salt: S2,
+    ) -> Result<Self, CipherError>
+    where
+        T1: AsRef<[u8]>,
+        T2: AsRef<[u8]>,
+        S1: AsRef<[u8]>,
+        S2: AsRef<[u8]>,
+    {
+        Self::try_build(slot_key, file_key, slot_key_salt, file_key_salt, false)
+    }
+
+    fn try_build<T1, T2, S1, S2>(
+        slot_key: T1,
+        file_key: T2,
+        slot_key_salt: S1,
+        file_key_salt: S2,
+        lazy: bool,
+    ) -> Result<Self, CipherError>
+    where
+        T1: AsRef<[u8]>,
+        T2: AsRef<[u8]>,
+        S1: AsRef<[u8]>,
+        S2: AsRef<[u8]>,
+    {
+        if slot_key.as_ref().is_empty() {
+            Err(CipherError::EmptySlotKey)?;
+        }
+        for salt in [slot_key_salt.as_ref(), file_key_salt.as_ref()] {
+            if salt.len() < 2 {
+                Err(CipherError::SaltTooShort(salt.len()))?;
+            }
+        }
+
+        Ok(Self::build(
+            slot_key,
+            file_key,
+            slot_key_salt,
+            file_key_salt,
+            lazy,
+        ))
+    }
+
+    /// Init the cipher with custom salts for the key table expansion.
+    pub fn with_salts<T1, T2, S1, S2>(
+        slot_key: T1,
+        file_key: T2,
+        slot_key_salt: S1,
+        file_key_salt: S2,
+    ) -> Self
+    where
+        T1: AsRef<[u8]>,
+        T2: AsRef<[u8]>,
+        S1: AsRef<[u8]>,
+        S2: AsRef<[u8]>,
+    {
+        Self::build(slot_key, file_key, slot_key_salt, file_key_salt, false)
+    }
+
+    fn build<T1, T2, S1, S2>(
+        slot_key: T1,
+        file_key: T2,
+        slot_key_salt: S1,
+        file_key_salt: S2,
+        lazy: bool,
+    ) -> Self
+    where
+        T1: AsRef<[u8]>,
+        T2: AsRef<[u8]>,
+        S1: AsRef<[u8]>,
+        S2: AsRef<[u8]>,
+    {
+        let slot_key = Base64.encode(hex::encode(md5(slot_key)));
 
         Self {
-            slot_key_table: Self::table_expansion(slot_key, slot_key_salt),
-            file_key_table: Self::table_expansion(file_key, file_key_salt),
+            slot_key_table: KeyTable::new(slot_key, slot_key_salt, lazy),
+            file_key_table: KeyTable::new(file_key, file_key_salt, lazy),
         }
     }
 
@@ -40,49 +237,57 @@ impl Mode4 {
         result
     }
 
-    fn table_expansion<K: AsRef<[u8]>, S: AsRef<[u8]>>(key: K, salt: S) -> Box<[u8]> {
-        let key = key.as_ref();
-        let salt = salt.as_ref();
-        let md5_final = Self::hash_key(key);
-
-        let final_key_size = 4 * (md5_final.len() - 1) * (key.len() - 1);
-        let mut expanded_key = Vec::with_capacity(final_key_size);
-        for (i, &j) in md5_final.iter().enumerate().skip(1) {
-            let temp = (i as u32).wrapping_mul(j as u32);
-
-            for (k, &l) in salt.iter().enumerate().skip(1) {
-                let temp = temp.wrapping_mul(k as u32).wrapping_mul(l as u32);
-                let bytes = temp.to_le_bytes();
-
-                // (LittleEndian)   0x12345678
-                // Memory:  [78] [56] [34] [12]
-                //
-                //    SHR:   00   08   10   18
-                //       = 0x78 0x56 0x34 0x12
-                //  Index:    0    1    2    3
-
-                expanded_key.push(bytes[0]); // temp >> 0x00
-                expanded_key.push(bytes[3]); // temp >> 0x18
-                expanded_key.push(bytes[2]); // temp >> 0x10
-                expanded_key.push(bytes[1]); // temp >> 0x08
-            }
-        }
-
-        expanded_key.into_boxed_slice()
+    /// Maximum number of bytes this cipher can process.
+    pub fn max_len(&self) -> usize {
+        self.slot_key_table
+            .len()
+            .saturating_mul(self.file_key_table.len())
     }
 
-    fn get_key(&self, offset: usize) -> (u8, u8, u8) {
+    /// Get the key for the given offset, or an error if it's beyond [`Mode4::max_len`].
+    pub fn get_key(&self, offset: usize) -> Result<(u8, u8, u8), CipherError> {
         let n = self.slot_key_table.len();
-        let slot_key = self.slot_key_table[offset % n];
-        let file_key = self.file_key_table[offset / n];
+        let keys = match n {
+            0 => None,
+            n => self
+                .slot_key_table
+                .get(offset % n)
+                .zip(self.file_key_table.get(offset / n)),
+        };
+
+        let (slot_key, file_key) =
+            keys.ok_or(CipherError::OffsetOutOfRange(offset, self.max_len()))?;
         let offset_checksum = offset.to_ne_bytes().iter().fold(0, |acc, x| acc ^ x);
-        (slot_key, file_key, offset_checksum)
+        Ok((slot_key, file_key, offset_checksum))
+    }
+
+    /// Decrypt the buffer, or return an error if it goes beyond [`Mode4::max_len`].
+    pub fn try_decipher_buffer<T: AsMut<[u8]> + ?Sized>(
+        &self,
+        offset: usize,
+        buffer: &mut T,
+    ) -> Result<(), CipherError> {
+        let buffer = buffer.as_mut();
+        if offset.saturating_add(buffer.len()) > self.max_len() {
+            Err(CipherError::OffsetOutOfRange(
+                offset.saturating_add(buffer.len()),
+                self.max_len(),
+            ))?;
+        }
+
+        self.decipher_buffer(offset, buffer);
+        Ok(())
     }
 }
 
+/// Bytes beyond [`Mode4::max_len`] are left as-is;
+/// use [`Mode4::try_decipher_buffer`] to detect them.
 impl ByteOffsetEncipher for Mode4 {
     fn encipher_byte(&self, offset: usize, datum: u8) -> u8 {
-        let (slot_key, file_key, offset_checksum) = self.get_key(offset);
+        let (slot_key, file_key, offset_checksum) = match self.get_key(offset) {
+            Ok(keys) => keys,
+            Err(_) => return datum,
+        };
 
         let mut datum = datum;
         datum ^= offset_checksum;
@@ -93,9 +298,14 @@ impl ByteOffsetEncipher for Mode4 {
     }
 }
 
+/// Bytes beyond [`Mode4::max_len`] are left as-is;
+/// use [`Mode4::try_decipher_buffer`] to detect them.
 impl ByteOffsetDecipher for Mode4 {
     fn decipher_byte(&self, offset: usize, datum: u8) -> u8 {
-        let (slot_key, file_key, offset_checksum) = self.get_key(offset);
+        let (slot_key, file_key, offset_checksum) = match self.get_key(offset) {
+            Ok(keys) => keys,
+            Err(_) => return datum,
+        };
 
         let mut datum = datum;
         datum ^= file_key;
@@ -105,3 +315,93 @@ impl ByteOffsetDecipher for Mode4 {
         datum
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lazy_table() {
+        let eager = Mode4::new(b"slot key", b"file key");
+        let lazy = Mode4::new_lazy(b"slot key", b"file key");
+        assert_eq!(eager.max_len(), lazy.max_len());
+
+        for table in [
+            (&eager.slot_key_table, &lazy.slot_key_table),
+            (&eager.file_key_table, &lazy.file_key_table),
+        ] {
+            assert_eq!(table.0.len(), table.1.len());
+            for i in 0..table.0.len() + 1 {
+                assert_eq!(table.0.get(i), table.1.get(i));
+            }
+        }
+
+        let mut data_eager = [0xAAu8; 100];
+        let mut data_lazy = data_eager;
+        eager.decipher_buffer(0x1234, &mut data_eager);
+        lazy.decipher_buffer(0x1234, &mut data_lazy);
+        assert_eq!(data_eager, data_lazy);
+    }
+
+    #[test]
+    fn test_out_of_range() {
+        // 2-byte salts: 120-byte tables, up to 120 * 120 bytes.
+        let cipher = Mode4::with_salts(b"slot key", b"file key", b"ab", b"cd");
+        assert_eq!(cipher.max_len(), 120 * 120);
+        assert!(cipher.get_key(120 * 120 - 1).is_ok());
+        assert!(matches!(
+            cipher.get_key(120 * 120),
+            Err(CipherError::OffsetOutOfRange(14400, 14400))
+        ));
+
+        let mut data = [0u8; 16];
+        assert!(cipher
+            .try_decipher_buffer(120 * 120 - 16, &mut data)
+            .is_ok());
+        assert!(matches!(
+            cipher.try_decipher_buffer(120 * 120 - 8, &mut data),
+            Err(CipherError::OffsetOutOfRange(14408, 14400))
+        ));
+
+        // No panic, the data is left as-is.
+        assert_eq!(cipher.decipher_byte(usize::MAX, 0x12), 0x12);
+    }
+
+    #[test]
+    fn test_empty_salt() {
+        let cipher = Mode4::with_salts(b"slot key", b"file key", b"", b"");
+        assert_eq!(cipher.max_len(), 0);
+        assert!(cipher.get_key(0).is_err());
+    }
+
+    #[test]
+    fn test_try_with_salts() {
+        assert!(matches!(
+            Mode4::try_new(b"", b"file key"),
+            Err(CipherError::EmptySlotKey)
+        ));
+        assert!(matches!(
+            Mode4::try_new_lazy(b"", b"file key"),
+            Err(CipherError::EmptySlotKey)
+        ));
+        assert_eq!(
+            Mode4::try_new_lazy(b"k", b"file key").unwrap(),
+            Mode4::new_lazy(b"k", b"file key")
+        );
+        assert!(matches!(
+            Mode4::try_with_salts(b"k", b"file key", b"s", b"salt"),
+            Err(CipherError::SaltTooShort(1))
+        ));
+
+        for len in 2..=32 {
+            let salt = (0..len).map(|i| (i * 13 + len) as u8).collect::<Vec<_>>();
+            let cipher = Mode4::try_with_salts(&salt[..1], &salt, &salt, &salt).unwrap();
+            assert!(cipher.max_len() > 0);
+
+            let mut data = [0x55u8; 64];
+            cipher.encipher_buffer(0, &mut data);
+            cipher.decipher_buffer(0, &mut data);
+            assert_eq!(data, [0x55u8; 64]);
+        }
+    }
+}
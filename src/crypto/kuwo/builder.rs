@@ -0,0 +1,239 @@
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+
+use crate::crypto::kuwo::header::KuwoHeader;
+use crate::crypto::kuwo::v1::KWMv1;
+use crate::crypto::kuwo::{InitCipherError, Kuwo};
+use crate::crypto::tencent::ekey;
+
+/// Lookup the ekey of a KWMv2 file, e.g. from the client's mmkv database.
+pub trait KuwoKeyStore {
+    /// Find the (still encrypted) ekey for the given header.
+    fn get_ekey(&self, hdr: &KuwoHeader) -> Option<Box<[u8]>>;
+}
+
+impl<F> KuwoKeyStore for F
+where
+    F: Fn(&KuwoHeader) -> Option<Box<[u8]>>,
+{
+    fn get_ekey(&self, hdr: &KuwoHeader) -> Option<Box<[u8]>> {
+        self(hdr)
+    }
+}
+
+/// Build a [`Kuwo`] cipher from the file header, resolving its key on the way.
+///
+/// The key is resolved in the following order: raw key, ekey, key store.
+///
+/// KWMv1 derives its key from the resource id, which can be overridden with
+/// [`KuwoBuilder::with_resource_id`] (e.g. when the header was zeroed), or
+/// searched with [`KuwoBuilder::with_verification`].
+///
+/// # Examples
+///
+/// ```
+/// use parakeet_crypto::crypto::kuwo::header::{KuwoHeader, MAGIC_1};
+/// use parakeet_crypto::crypto::kuwo::{Kuwo, KuwoBuilder};
+///
+/// let mut hdr = KuwoHeader::default();
+/// hdr.magic = MAGIC_1;
+/// hdr.version = 1;
+/// hdr.resource_id = 1234;
+///
+/// let (cipher, hdr) = KuwoBuilder::new(hdr.to_bytes()).build().unwrap();
+/// assert!(matches!(cipher, Kuwo::KWMv1(_)));
+/// assert_eq!(hdr.resource_id, 1234);
+/// ```
+pub struct KuwoBuilder<'a> {
+    header: Box<[u8]>,
+    key: Option<Box<[u8]>>,
+    ekey: Option<Box<[u8]>>,
+    key_store: Option<&'a dyn KuwoKeyStore>,
+    resource_id: Option<u32>,
+    verification: Option<(Box<[u8]>, Vec<u32>)>,
+}
+
+impl<'a> KuwoBuilder<'a> {
+    pub fn new<T: AsRef<[u8]>>(header: T) -> Self {
+        Self {
+            header: header.as_ref().into(),
+            key: None,
+            ekey: None,
+            key_store: None,
+            resource_id: None,
+            verification: None,
+        }
+    }
+
+    /// Use a decrypted key for KWMv2.
+    pub fn with_key<T: AsRef<[u8]>>(mut self, key: T) -> Self {
+        self.key = Some(key.as_ref().into());
+        self
+    }
+
+    /// Use an ekey for KWMv2.
+    pub fn with_ekey<T: AsRef<[u8]>>(mut self, ekey: T) -> Self {
+        self.ekey = Some(ekey.as_ref().into());
+        self
+    }
+
+    /// Lookup the ekey from a key store, if no key was provided.
+    pub fn with_key_store(mut self, key_store: &'a dyn KuwoKeyStore) -> Self {
+        self.key_store = Some(key_store);
+        self
+    }
+
+    /// Use this resource id instead of the one from the header.
+    pub fn with_resource_id(mut self, resource_id: u32) -> Self {
+        self.resource_id = Some(resource_id);
+        self
+    }
+
+    /// KWMv1: verify the resource id against the beginning of the encrypted audio
+    /// (right after the header), see [`KWMv1::verify`].
+    ///
+    /// The resource id (from the override, or the header) is tried first, followed by
+    /// `candidates`; the first one that yields a known audio format is kept.
+    pub fn with_verification<T, I>(mut self, encrypted_head: T, candidates: I) -> Self
+    where
+        T: AsRef<[u8]>,
+        I: IntoIterator<Item = u32>,
+    {
+        let candidates = candidates.into_iter().collect();
+        self.verification = Some((encrypted_head.as_ref().into(), candidates));
+        self
+    }
+
+    fn resolve_resource_id(&self, resource_id: u32) -> Result<u32, InitCipherError> {
+        match &self.verification {
+            None => Ok(resource_id),
+            Some((encrypted_head, candidates)) => {
+                let candidates = core::iter::once(resource_id).chain(candidates.iter().cloned());
+                KWMv1::find_resource_id(candidates, encrypted_head)
+                    .ok_or(InitCipherError::KWMv1VerificationFailed)
+            }
+        }
+    }
+
+    fn resolve_key(&self, hdr: &KuwoHeader) -> Result<Option<Box<[u8]>>, InitCipherError> {
+        if let Some(key) = &self.key {
+            return Ok(Some(key.clone()));
+        }
+
+        let ekey = match &self.ekey {
+            Some(ekey) => Some(ekey.clone()),
+            None => self.key_store.and_then(|store| store.get_ekey(hdr)),
+        };
+
+        match ekey {
+            Some(ekey) => Ok(Some(ekey::decrypt(ekey)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Build the cipher.
+    ///
+    /// The returned header carries the resource id that was used.
+    pub fn build(self) -> Result<(Kuwo, KuwoHeader), InitCipherError> {
+        let mut hdr = KuwoHeader::from_bytes(&self.header)?;
+        if let Some(resource_id) = self.resource_id {
+            hdr.resource_id = resource_id;
+        }
+        if hdr.version == 1 {
+            hdr.resource_id = self.resolve_resource_id(hdr.resource_id)?;
+        }
+
+        // KWMv1 derives its key from the header.
+        let key = match hdr.version {
+            1 => None,
+            _ => self.resolve_key(&hdr)?,
+        };
+
+        let cipher = Kuwo::from_header(&hdr, key)?;
+        Ok((cipher, hdr))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::crypto::byte_offset_cipher::ByteOffsetEncipher;
+    use crate::crypto::kuwo::header::MAGIC_1;
+
+    use super::*;
+
+    fn make_header(version: u32) -> Vec<u8> {
+        KuwoHeader {
+            magic: MAGIC_1,
+            version,
+            resource_id: 5177785,
+            format_name: *b"2000FLAC\0\0\0\0",
+            ..Default::default()
+        }
+        .to_bytes()
+    }
+
+    #[test]
+    fn test_kwm_v2_key_required() {
+        let result = KuwoBuilder::new(make_header(2)).build();
+        assert!(matches!(result, Err(InitCipherError::KWMv2KeyRequired)));
+    }
+
+    #[test]
+    fn test_kwm_v2_key_priority() {
+        let store = |_: &KuwoHeader| -> Option<Box<[u8]>> { Some(Box::from(*b"not an ekey")) };
+        let (cipher, _) = KuwoBuilder::new(make_header(2))
+            .with_key_store(&store)
+            .with_key(b"raw key")
+            .build()
+            .unwrap();
+        assert!(matches!(cipher, Kuwo::KWMv2(_)));
+    }
+
+    #[test]
+    fn test_kwm_v2_key_store() {
+        let store = |hdr: &KuwoHeader| -> Option<Box<[u8]>> {
+            assert_eq!(hdr.get_quality_id(), 2000);
+            Some(Box::from(*b"not an ekey"))
+        };
+        let result = KuwoBuilder::new(make_header(2))
+            .with_key_store(&store)
+            .build();
+        assert!(matches!(result, Err(InitCipherError::EKeyDecryptError(_))));
+    }
+
+    #[test]
+    fn test_kwm_v1_resource_id_override() {
+        let (_, hdr) = KuwoBuilder::new(make_header(1))
+            .with_resource_id(1234)
+            .build()
+            .unwrap();
+        assert_eq!(hdr.resource_id, 1234);
+    }
+
+    #[test]
+    fn test_kwm_v1_verification() {
+        const SAMPLE: &[u8] = include_bytes!("../../../sample/test_121529_32kbps.ogg");
+        let mut encrypted = SAMPLE[..64].to_vec();
+        KWMv1::from_resource_id(4321).encipher_buffer(0, &mut encrypted);
+
+        let (_, hdr) = KuwoBuilder::new(make_header(1))
+            .with_verification(&encrypted, [1234, 4321])
+            .build()
+            .unwrap();
+        assert_eq!(hdr.resource_id, 4321);
+
+        let result = KuwoBuilder::new(make_header(1))
+            .with_verification(&encrypted, [1234])
+            .build();
+        assert!(matches!(
+            result,
+            Err(InitCipherError::KWMv1VerificationFailed)
+        ));
+    }
+
+    #[test]
+    fn test_kwm_v2_empty_key() {
+        let result = KuwoBuilder::new(make_header(2)).with_key(b"").build();
+        assert!(matches!(result, Err(InitCipherError::InvalidKey(_))));
+    }
+}
@@ -1,6 +1,8 @@
-use std::io::{BufRead, BufReader, Error, Read};
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec::Vec;
 
-use byteorder::{ReadBytesExt, LE};
+use byteorder::{ByteOrder, LE};
 use thiserror::Error;
 
 pub const MAGIC_1: [u8; 16] = *b"yeelion-kuwo-tme";
@@ -9,19 +11,45 @@ pub const MAGIC_2: [u8; 16] = *b"yeelion-kuwo\0\0\0\0";
 pub const HEADER_PARSE_REQUIRED_LEN: usize = 0x3C;
 pub const HEADER_FIXED_LEN: usize = 0x400;
 
-#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Default)]
+/// Kuwo file header.
+///
+/// | Offset | Size    | Field          |
+/// |--------|---------|----------------|
+/// | `0x00` | 16      | `magic`        |
+/// | `0x10` | 4       | `version`      |
+/// | `0x14` | 4       | `unknown_1`    |
+/// | `0x18` | 4       | `resource_id`  |
+/// | `0x1C` | 20      | `unknown_2`    |
+/// | `0x30` | 12      | `format_name`  |
+/// | `0x3C` | `0x3C4` | `reserved`     |
+#[derive(Debug, Clone, Eq, PartialEq, Ord, PartialOrd, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct KuwoHeader {
     /// Either `MAGIC_1` or `MAGIC_2`
+    #[cfg_attr(feature = "serde", serde(with = "crate::utils::serde_hex"))]
     pub magic: [u8; 16],
     /// 1: Legacy KWM format
     /// 2: QMCv2 format (mflac/mgg)
     pub version: u32,
 
+    /// unknown, kept as-is.
+    pub unknown_1: u32,
+
     /// Numeric ID of the resource.
     pub resource_id: u32,
 
+    /// unknown, kept as-is.
+    #[cfg_attr(feature = "serde", serde(with = "crate::utils::serde_hex"))]
+    pub unknown_2: [u8; 0x14],
+
     /// Format name, e.g. b"2000FLAC" or b"20900kmflac" (padded with b'\0')
+    #[cfg_attr(feature = "serde", serde(with = "crate::utils::serde_hex"))]
     pub format_name: [u8; 12],
+
+    /// Rest of the header (up to `HEADER_FIXED_LEN`), kept as-is.
+    /// Empty if only `HEADER_PARSE_REQUIRED_LEN` bytes were given to the parser.
+    #[cfg_attr(feature = "serde", serde(with = "crate::utils::serde_hex"))]
+    pub reserved: Box<[u8]>,
 }
 
 #[derive(Debug, Error)]
@@ -29,9 +57,6 @@ pub enum HeaderParseError {
     #[error("Need more bytes to parse: expected {0} bytes")]
     NeedMoreBytes(usize),
 
-    #[error("I/O error: {0}")]
-    IoError(std::io::Error),
-
     #[error("File header does not contain a valid magic header")]
     InvalidMagic,
 
@@ -39,35 +64,58 @@ pub enum HeaderParseError {
     UnsupportedVersion(u32),
 }
 
-impl From<std::io::Error> for HeaderParseError {
-    fn from(error: Error) -> Self {
-        Self::IoError(error)
-    }
-}
-
 impl KuwoHeader {
+    /// Parse the header.
+    ///
+    /// At least `HEADER_PARSE_REQUIRED_LEN` bytes are required; `HEADER_FIXED_LEN` bytes
+    /// should be given if the header is going to be serialized again.
+    /// Only versions 1 (KWMv1) and 2 (KWMv2) are accepted.
     pub fn from_bytes<T: AsRef<[u8]>>(bytes: T) -> Result<Self, HeaderParseError> {
         let bytes = bytes.as_ref();
         if bytes.len() < HEADER_PARSE_REQUIRED_LEN {
             Err(HeaderParseError::NeedMoreBytes(HEADER_PARSE_REQUIRED_LEN))?;
         }
 
-        let mut reader = BufReader::new(bytes);
         let mut result = Self::default();
 
-        reader.read_exact(&mut result.magic)?;
-        result.version = reader.read_u32::<LE>()?;
-        reader.consume(0x04);
-        result.resource_id = reader.read_u32::<LE>()?;
-        reader.consume(0x14);
-        reader.read_exact(&mut result.format_name)?;
+        result.magic.copy_from_slice(&bytes[0x00..0x10]);
+        result.version = LE::read_u32(&bytes[0x10..0x14]);
+        result.unknown_1 = LE::read_u32(&bytes[0x14..0x18]);
+        result.resource_id = LE::read_u32(&bytes[0x18..0x1C]);
+        result.unknown_2.copy_from_slice(&bytes[0x1C..0x30]);
+        result.format_name.copy_from_slice(&bytes[0x30..0x3C]);
         if result.magic != MAGIC_1 && result.magic != MAGIC_2 {
             Err(HeaderParseError::InvalidMagic)?;
         }
+        if !matches!(result.version, 1 | 2) {
+            Err(HeaderParseError::UnsupportedVersion(result.version))?;
+        }
+
+        let reserved_end = bytes.len().min(HEADER_FIXED_LEN);
+        result.reserved = bytes[HEADER_PARSE_REQUIRED_LEN..reserved_end].into();
 
         Ok(result)
     }
 
+    /// Serialize the header to `HEADER_FIXED_LEN` bytes.
+    /// `reserved` is padded with zeros, or truncated to fit.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(HEADER_FIXED_LEN);
+
+        data.extend_from_slice(&self.magic);
+        data.extend_from_slice(&self.version.to_le_bytes());
+        data.extend_from_slice(&self.unknown_1.to_le_bytes());
+        data.extend_from_slice(&self.resource_id.to_le_bytes());
+        data.extend_from_slice(&self.unknown_2);
+        data.extend_from_slice(&self.format_name);
+
+        let reserved_len = self.reserved.len().min(HEADER_FIXED_LEN - data.len());
+        data.extend_from_slice(&self.reserved[..reserved_len]);
+        data.resize(HEADER_FIXED_LEN, 0);
+
+        data
+    }
+
     /// Get the quality id.
     ///
     /// This id can be used to lookup from mmkv database.
@@ -89,4 +137,102 @@ impl KuwoHeader {
             .take_while(|c| c.is_ascii_digit())
             .fold(0, |sum, &n| sum * 10 + u32::from(n - b'0'))
     }
+
+    /// Get the bitrate (in kbps), as shown by the client.
+    ///
+    /// This is the quality id for standard formats; AI up-scaled formats (`mflac`)
+    /// use their own quality ids and do not carry a bitrate.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use parakeet_crypto::crypto::kuwo::header::KuwoHeader;
+    ///
+    /// let mut hdr = KuwoHeader::default();
+    /// hdr.format_name = *b"320kmp3\0\0\0\0\0";
+    /// assert_eq!(hdr.get_bitrate(), Some(320));
+    /// hdr.format_name = *b"20900kmflac\0";
+    /// assert_eq!(hdr.get_bitrate(), None);
+    /// ```
+    pub fn get_bitrate(&self) -> Option<u32> {
+        match self.get_format_suffix() {
+            suffix if suffix.ends_with("mflac") => None,
+            _ => Some(self.get_quality_id()).filter(|&bitrate| bitrate != 0),
+        }
+    }
+
+    /// Get the format name without its quality id, e.g. `"FLAC"` or `"kmflac"`.
+    pub fn get_format_suffix(&self) -> String {
+        let name = self
+            .format_name
+            .iter()
+            .skip_while(|c| c.is_ascii_digit())
+            .take_while(|&&c| c != 0)
+            .cloned()
+            .collect::<Vec<_>>();
+        String::from_utf8_lossy(&name).into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let mut data = vec![0u8; HEADER_FIXED_LEN];
+        data[..16].copy_from_slice(&MAGIC_1);
+        for (i, v) in data.iter_mut().enumerate().skip(16) {
+            *v = i as u8;
+        }
+        assert!(matches!(
+            KuwoHeader::from_bytes(&data),
+            Err(HeaderParseError::UnsupportedVersion(0x13121110))
+        ));
+
+        data[0x10..0x14].copy_from_slice(&2u32.to_le_bytes());
+        let hdr = KuwoHeader::from_bytes(&data).unwrap();
+        assert_eq!(hdr.version, 2);
+        assert_eq!(hdr.unknown_1, 0x17161514);
+        assert_eq!(hdr.resource_id, 0x1B1A1918);
+        assert_eq!(hdr.unknown_2[0], 0x1C);
+        assert_eq!(hdr.format_name[0], 0x30);
+        assert_eq!(
+            hdr.reserved.len(),
+            HEADER_FIXED_LEN - HEADER_PARSE_REQUIRED_LEN
+        );
+        assert_eq!(hdr.to_bytes(), data);
+    }
+
+    #[test]
+    fn test_short_header() {
+        let mut data = vec![0u8; HEADER_PARSE_REQUIRED_LEN];
+        data[..16].copy_from_slice(&MAGIC_2);
+        data[0x10] = 1;
+        data[0x30..0x38].copy_from_slice(b"2000FLAC");
+
+        let hdr = KuwoHeader::from_bytes(&data).unwrap();
+        assert!(hdr.reserved.is_empty());
+        assert_eq!(hdr.get_format_suffix(), "FLAC");
+
+        let serialized = hdr.to_bytes();
+        assert_eq!(serialized.len(), HEADER_FIXED_LEN);
+        assert_eq!(&serialized[..HEADER_PARSE_REQUIRED_LEN], data);
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn test_serialize() {
+        let mut data = vec![0u8; HEADER_PARSE_REQUIRED_LEN];
+        data[..16].copy_from_slice(&MAGIC_1);
+        data[0x10] = 1;
+        data[0x30..0x38].copy_from_slice(b"2000FLAC");
+
+        let hdr = KuwoHeader::from_bytes(&data).unwrap();
+        let actual = serde_json::to_value(hdr).unwrap();
+        assert_eq!(actual["magic"], "7965656c696f6e2d6b75776f2d746d65");
+        assert_eq!(actual["format_name"], "32303030464c414300000000");
+        assert_eq!(actual["reserved"], "");
+        assert_eq!(actual["resource_id"], 0);
+    }
 }
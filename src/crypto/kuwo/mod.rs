@@ -2,7 +2,12 @@ use thiserror::Error;
 
 use crate::crypto::byte_offset_cipher::{ByteOffsetDecipher, ByteOffsetEncipher};
 use crate::crypto::kuwo::header::HeaderParseError;
+use crate::crypto::tencent;
+use crate::crypto::tencent::ekey::KeyDecryptError;
 
+pub use builder::{KuwoBuilder, KuwoKeyStore};
+
+mod builder;
 pub mod header;
 pub mod v1;
 pub mod v2;
@@ -16,11 +21,20 @@ pub enum Kuwo {
 #[derive(Debug, Error)]
 pub enum InitCipherError {
     #[error("Failed to parse header: {0}")]
-    HeaderParseError(HeaderParseError),
+    HeaderParseError(#[source] HeaderParseError),
 
     #[error("KWMv2 require a decrypted ekey")]
     KWMv2KeyRequired,
 
+    #[error("Invalid KWMv2 key: {0}")]
+    InvalidKey(#[source] tencent::InitCipherError),
+
+    #[error("Failed to decrypt ekey: {0}")]
+    EKeyDecryptError(#[source] KeyDecryptError),
+
+    #[error("None of the candidate resource ids yield a known audio format")]
+    KWMv1VerificationFailed,
+
     #[error("Header contains unsupported version: {0}")]
     UnsupportedVersion(u32),
 }
@@ -31,6 +45,18 @@ impl From<HeaderParseError> for InitCipherError {
     }
 }
 
+impl From<tencent::InitCipherError> for InitCipherError {
+    fn from(error: tencent::InitCipherError) -> Self {
+        Self::InvalidKey(error)
+    }
+}
+
+impl From<KeyDecryptError> for InitCipherError {
+    fn from(error: KeyDecryptError) -> Self {
+        Self::EKeyDecryptError(error)
+    }
+}
+
 impl Kuwo {
     pub fn from_header<K>(hdr: &header::KuwoHeader, key: Option<K>) -> Result<Self, InitCipherError>
     where
@@ -40,16 +66,15 @@ impl Kuwo {
             1 => Self::KWMv1(v1::KWMv1::from_resource_id(hdr.resource_id)),
             2 => match key {
                 None => Err(InitCipherError::KWMv2KeyRequired)?,
-                Some(key) => Self::KWMv2(v2::KWMv2::from_key(key)),
+                Some(key) => Self::KWMv2(v2::KWMv2::try_new(key)?),
             },
             version => Err(InitCipherError::UnsupportedVersion(version))?,
         };
         Ok(cipher)
     }
 
-    pub fn from_header_bytes<T, K>(hdr: &[u8], key: Option<K>) -> Result<Self, InitCipherError>
+    pub fn from_header_bytes<K>(hdr: &[u8], key: Option<K>) -> Result<Self, InitCipherError>
     where
-        T: AsRef<[u8]>,
         K: AsRef<[u8]>,
     {
         let hdr = header::KuwoHeader::from_bytes(hdr)?;
@@ -1,8 +1,16 @@
+use alloc::string::ToString;
+
 use crate::crypto::byte_offset_cipher::{ByteOffsetDecipher, ByteOffsetEncipher};
+use crate::utils::audio::{
+    detect_audio_type, is_mp3_frame_pair, is_mp3_frame_sync, MP3_FRAME_PAIR_LEN,
+};
 
 pub const KEY_SIZE: usize = 0x20;
 const SCRAMBLE_KEY: [u8; KEY_SIZE] = *include_bytes!("v1_key.bin");
 
+/// Number of bytes used by [`KWMv1::verify`].
+pub const VERIFY_LEN: usize = MP3_FRAME_PAIR_LEN;
+
 pub type ResourceKey = [u8; KEY_SIZE];
 
 #[derive(Debug, Copy, Clone, Default, Ord, PartialOrd, Eq, PartialEq)]
@@ -23,6 +31,32 @@ impl KWMv1 {
 
         Self { key }
     }
+
+    /// Check if the beginning of the file decrypts to a known audio format.
+    ///
+    /// `encrypted_head` should be the first [`VERIFY_LEN`] bytes after the header (or the
+    /// whole audio, if shorter). A bare MP3 frame sync is only accepted when followed by a
+    /// second frame, see [`is_mp3_frame_pair`].
+    pub fn verify(&self, encrypted_head: &[u8]) -> bool {
+        let len = encrypted_head.len().min(VERIFY_LEN);
+        let mut buffer = encrypted_head[..len].to_vec();
+        self.decipher_buffer(0, &mut buffer);
+        match detect_audio_type(&buffer) {
+            Some(_) if is_mp3_frame_sync(&buffer) => is_mp3_frame_pair(&buffer),
+            audio_type => audio_type.is_some(),
+        }
+    }
+
+    /// Try each of the candidate resource ids, and return the first one that
+    /// decrypts `encrypted_head` to a known audio format.
+    pub fn find_resource_id<I>(candidates: I, encrypted_head: &[u8]) -> Option<u32>
+    where
+        I: IntoIterator<Item = u32>,
+    {
+        candidates
+            .into_iter()
+            .find(|&rid| Self::from_resource_id(rid).verify(encrypted_head))
+    }
 }
 
 impl ByteOffsetDecipher for KWMv1 {
@@ -36,3 +70,39 @@ impl ByteOffsetEncipher for KWMv1 {
         self.decipher_byte(offset, datum)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE: &[u8] = include_bytes!("../../../sample/test_121529_32kbps.ogg");
+
+    #[test]
+    fn test_find_resource_id() {
+        let mut encrypted = SAMPLE[..64].to_vec();
+        KWMv1::from_resource_id(5177785).encipher_buffer(0, &mut encrypted);
+
+        assert!(!KWMv1::from_resource_id(0).verify(&encrypted));
+        assert_eq!(
+            KWMv1::find_resource_id([0, 1234, 5177785, 5177786], &encrypted),
+            Some(5177785)
+        );
+        assert_eq!(KWMv1::find_resource_id([0, 1234], &encrypted), None);
+    }
+
+    #[test]
+    fn test_verify_mp3() {
+        let mut audio = vec![0u8; VERIFY_LEN];
+        audio[..4].copy_from_slice(b"\xFF\xFB\x90\x64");
+        let cipher = KWMv1::from_resource_id(5177785);
+        let encrypt = |audio: &[u8]| {
+            let mut encrypted = audio.to_vec();
+            cipher.encipher_buffer(0, &mut encrypted);
+            encrypted
+        };
+
+        assert!(!cipher.verify(&encrypt(&audio)));
+        audio[417..421].copy_from_slice(b"\xFF\xFB\x90\x64");
+        assert!(cipher.verify(&encrypt(&audio)));
+    }
+}
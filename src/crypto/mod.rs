@@ -1,7 +1,12 @@
 pub mod byte_offset_cipher;
 
+#[cfg(feature = "kugou")]
 pub mod kugou;
+#[cfg(feature = "kuwo")]
 pub mod kuwo;
+#[cfg(feature = "tencent")]
 pub mod tencent;
+#[cfg(feature = "ximalaya-android")]
 pub mod ximalaya_android;
+#[cfg(feature = "ximalaya-pc")]
 pub mod ximalaya_pc;
@@ -1,8 +1,11 @@
-use std::ops::Mul;
+use alloc::boxed::Box;
+use alloc::vec::Vec;
 
 use base64::{engine::general_purpose::STANDARD as Base64, Engine as _};
 use thiserror::Error;
 
+use super::tc_tea;
+
 pub const MAX_EKEY_LEN: usize = 0x500;
 pub const EKEY_V2_PREFIX: &[u8; 24] = b"UVFNdXNpYyBFbmNWMixLZXk6";
 
@@ -22,17 +25,25 @@ pub enum KeyDecryptError {
     Base64Decoding,
 }
 
-fn make_simple_key<const N: usize>() -> [u8; N] {
-    let mut result = [0u8; N];
+#[derive(Debug, Clone, PartialEq, Error)]
+pub enum KeyEncryptError {
+    /// Key is too short to be used as an ekey.
+    #[error("Key is too short for encryption: expected at least {0} bytes")]
+    KeyTooShort(usize),
+}
 
-    for (i, v) in result.iter_mut().enumerate() {
-        let i = i as f32;
-        let value = 106.0 + i * 0.1;
-        let value = value.tan().abs().mul(100.0);
-        *v = value as u8;
-    }
+/// Length of the plaintext header of an ekey v1, also used to derive its tea key.
+const EKEY_V1_HEADER_LEN: usize = 8;
+
+/// `|tan(106 + i * 0.1)| * 100`, see `test_simple_key`.
+const SIMPLE_KEY: [u8; 8] = [0x69, 0x56, 0x46, 0x38, 0x2b, 0x20, 0x15, 0x0b];
 
-    result
+fn derive_v1_tea_key(header: &[u8]) -> Vec<u8> {
+    SIMPLE_KEY
+        .iter()
+        .zip(header)
+        .flat_map(|(&simple_part, &header_part)| [simple_part, header_part])
+        .collect()
 }
 
 fn decrypt_v1(ekey: &[u8]) -> Result<Box<[u8]>, KeyDecryptError> {
@@ -41,16 +52,12 @@ fn decrypt_v1(ekey: &[u8]) -> Result<Box<[u8]>, KeyDecryptError> {
     }
 
     let ekey = base64_decode(ekey)?;
-    let (header, cipher) = ekey.split_at(8);
-
-    let simple_key = make_simple_key::<8>();
-    let tea_key = simple_key
-        .iter()
-        .zip(header)
-        .flat_map(|(&simple_part, &header_part)| [simple_part, header_part])
-        .collect::<Vec<_>>();
-
-    let plaintext = tc_tea::decrypt(cipher, tea_key).ok_or(KeyDecryptError::FailDecryptV1)?;
+    if ekey.len() < EKEY_V1_HEADER_LEN {
+        return Err(KeyDecryptError::EKeyTooShort);
+    }
+    let (header, cipher) = ekey.split_at(EKEY_V1_HEADER_LEN);
+    let plaintext =
+        tc_tea::decrypt(cipher, derive_v1_tea_key(header)).ok_or(KeyDecryptError::FailDecryptV1)?;
     Ok([header, &plaintext].concat().into())
 }
 
@@ -82,3 +89,85 @@ pub fn decrypt<T: AsRef<[u8]>>(ekey: T) -> Result<Box<[u8]>, KeyDecryptError> {
         None => decrypt_v1(ekey),
     }
 }
+
+/// Encrypt a key to an ekey (v1), as stored in file tails.
+pub fn encrypt<T: AsRef<[u8]>>(key: T) -> Result<Box<[u8]>, KeyEncryptError> {
+    let key = key.as_ref();
+    if key.len() < EKEY_V1_HEADER_LEN {
+        Err(KeyEncryptError::KeyTooShort(EKEY_V1_HEADER_LEN))?;
+    }
+
+    let (header, plaintext) = key.split_at(EKEY_V1_HEADER_LEN);
+    let cipher = tc_tea::encrypt(plaintext, derive_v1_tea_key(header))
+        .expect("derived tea key should be 16 bytes");
+    let ekey = Base64.encode([header, &cipher].concat());
+    Ok(ekey.into_bytes().into())
+}
+
+/// Encrypt a key to an ekey v2 (prefixed with [`EKEY_V2_PREFIX`]).
+pub fn encrypt_v2<T: AsRef<[u8]>>(key: T) -> Result<Box<[u8]>, KeyEncryptError> {
+    let (key1, key2) = include_bytes!("ekey.bin").split_at(16);
+    let ekey = encrypt(key)?;
+    let ekey = tc_tea::encrypt(ekey, key2).expect("ekey.bin should hold valid tea keys");
+    let ekey = tc_tea::encrypt(ekey, key1).expect("ekey.bin should hold valid tea keys");
+
+    let mut result = EKEY_V2_PREFIX.to_vec();
+    result.extend_from_slice(Base64.encode(ekey).as_bytes());
+    Ok(result.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_simple_key() {
+        let mut expected = [0u8; 8];
+        for (i, v) in expected.iter_mut().enumerate() {
+            let value = 106.0 + i as f32 * 0.1;
+            *v = (value.tan().abs() * 100.0) as u8;
+        }
+        assert_eq!(SIMPLE_KEY, expected);
+    }
+
+    #[test]
+    fn test_encrypt_round_trip() {
+        let key = (0..256).map(|i| b'A' + (i % 26) as u8).collect::<Vec<_>>();
+
+        let ekey = encrypt(&key).unwrap();
+        assert!(!ekey.starts_with(EKEY_V2_PREFIX));
+        assert_eq!(&ekey[..8], Base64.encode(&key[..6]).as_bytes());
+        assert_eq!(decrypt(&ekey).unwrap(), key.clone().into());
+
+        let ekey = encrypt_v2(&key).unwrap();
+        assert!(ekey.starts_with(EKEY_V2_PREFIX));
+        assert_eq!(decrypt(&ekey).unwrap(), key.into());
+
+        assert_eq!(encrypt(b"1234567"), Err(KeyEncryptError::KeyTooShort(8)));
+    }
+
+    #[test]
+    fn test_decrypt_short() {
+        // 12 characters of base64, but only 7 bytes.
+        assert_eq!(decrypt(b"AAAAAAAAAA=="), Err(KeyDecryptError::EKeyTooShort));
+    }
+
+    #[test]
+    fn test_decrypt_random() {
+        use crate::utils::fuzz::Lcg;
+
+        let (key1, key2) = include_bytes!("ekey.bin").split_at(16);
+        let mut rng = Lcg::new(0x0EC0_FFEE);
+        for _ in 0..2000 {
+            let data = rng.input(64);
+            let _ = decrypt(&data);
+            let _ = decrypt(Base64.encode(&data));
+
+            // A valid ekey v1 layer, with random content inside.
+            let v1 = Base64.encode(&data);
+            let ekey = tc_tea::encrypt(v1, key2).unwrap();
+            let ekey = tc_tea::encrypt(ekey, key1).unwrap();
+            let _ = decrypt([&EKEY_V2_PREFIX[..], Base64.encode(ekey).as_bytes()].concat());
+        }
+    }
+}
@@ -1,10 +1,12 @@
 use map::map_l;
-pub use qmc1::{decrypt_qmc1, encrypt_qmc1, QMCv1};
-pub use qmc2::QMCv2;
+pub use qmc1::{decrypt_qmc1, encrypt_qmc1, QMCv1, QMC1_TABLE_LEN};
+pub use qmc2::{InitCipherError, Mode, QMCv2, DETECT_MODE_LEN};
 pub use qmc2_map::QMCv2Map;
 pub use qmc2_rc4::QMCv2RC4;
 pub use tail::metadata;
-pub use tail::parse_tail;
+pub use tail::{parse_tail, parse_tail_verbose, TailParseAttempt, TailParseReport, TailParser};
+#[cfg(feature = "std")]
+pub use tail::{parse_tail_from_reader, parse_tail_verbose_from_reader, TailReadError};
 
 mod map;
 mod qmc1;
@@ -15,3 +17,5 @@ pub mod ekey;
 mod qmc2;
 mod rc4;
 mod tail;
+mod tc_tea;
+pub mod variants;
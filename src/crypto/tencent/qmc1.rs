@@ -1,13 +1,43 @@
 use crate::crypto::byte_offset_cipher::{ByteOffsetDecipher, ByteOffsetEncipher};
+use crate::interfaces::DecryptorError;
 
-#[derive(Debug, Eq, PartialEq, Copy, Clone, Default)]
-pub struct QMCv1;
+pub const QMC1_TABLE_LEN: usize = 128;
 
-const QMC1_TABLE: &[u8; 128] = include_bytes!("./qmc1.bin");
+const QMC1_TABLE: &[u8; QMC1_TABLE_LEN] = include_bytes!("./qmc1.bin");
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub struct QMCv1 {
+    table: [u8; QMC1_TABLE_LEN],
+}
+
+impl Default for QMCv1 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl QMCv1 {
+    /// Init the cipher with the table used by the official clients.
+    pub fn new() -> Self {
+        Self { table: *QMC1_TABLE }
+    }
+
+    /// Init the cipher with a custom 128-byte table.
+    pub fn with_table<T: AsRef<[u8]>>(table: T) -> Result<Self, DecryptorError> {
+        let table = table.as_ref();
+        if table.len() != QMC1_TABLE_LEN {
+            Err(DecryptorError::QMCv1InitFailed(table.len()))?;
+        }
+
+        let mut result = Self::new();
+        result.table.copy_from_slice(table);
+        Ok(result)
+    }
+}
 
 impl ByteOffsetDecipher for QMCv1 {
     fn decipher_byte(&self, offset: usize, datum: u8) -> u8 {
-        datum ^ super::map_l(QMC1_TABLE, offset)
+        datum ^ super::map_l(&self.table, offset)
     }
 }
 
@@ -18,9 +48,32 @@ impl ByteOffsetEncipher for QMCv1 {
 }
 
 pub fn decrypt_qmc1<T: AsMut<[u8]>>(offset: usize, buffer: &mut T) {
-    QMCv1.decipher_buffer(offset, buffer);
+    QMCv1::new().decipher_buffer(offset, buffer);
 }
 
 pub fn encrypt_qmc1<T: AsMut<[u8]>>(offset: usize, buffer: &mut T) {
-    QMCv1.encipher_buffer(offset, buffer);
+    QMCv1::new().encipher_buffer(offset, buffer);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_with_table() {
+        let table = (0..QMC1_TABLE_LEN).map(|i| i as u8).collect::<Vec<_>>();
+        let cipher = QMCv1::with_table(&table).unwrap();
+
+        let mut data = [0u8; 4];
+        cipher.decipher_buffer(0x7E, &mut data);
+        assert_eq!(data, [0x7E, 0x7F, 0x00, 0x01]);
+    }
+
+    #[test]
+    fn test_with_table_invalid_len() {
+        assert!(matches!(
+            QMCv1::with_table([0u8; 64]),
+            Err(DecryptorError::QMCv1InitFailed(64))
+        ));
+    }
 }
@@ -1,5 +1,41 @@
+use thiserror::Error;
+
 use crate::crypto::byte_offset_cipher::{ByteOffsetDecipher, ByteOffsetEncipher};
 use crate::crypto::tencent::{QMCv2Map, QMCv2RC4};
+use crate::utils::audio::{
+    detect_audio_type, is_mp3_frame_pair, is_mp3_frame_sync, MP3_FRAME_PAIR_LEN,
+};
+
+/// Keys longer than this are assumed to be RC4 keys by [`QMCv2::from_key`].
+pub const MAP_KEY_MAX_LEN: usize = 300;
+
+/// Number of bytes decrypted by [`QMCv2::detect_mode`].
+pub const DETECT_MODE_LEN: usize = MP3_FRAME_PAIR_LEN;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Mode {
+    Map,
+    RC4,
+}
+
+impl Mode {
+    /// Guess the cipher mode from the length of the key, as done by the official clients.
+    pub fn from_key_len(len: usize) -> Self {
+        if len > MAP_KEY_MAX_LEN {
+            Mode::RC4
+        } else {
+            Mode::Map
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum InitCipherError {
+    #[error("invalid key length for QMCv2 {0:?} cipher: got {1} bytes")]
+    InvalidKeyLength(Mode, usize),
+    #[error("the key embedded in the tail differs from the cipher key")]
+    TailKeyMismatch,
+}
 
 #[derive(Debug, Clone, Ord, PartialOrd, Eq, PartialEq)]
 pub enum QMCv2 {
@@ -9,12 +45,63 @@ pub enum QMCv2 {
 
 /// A wrapper for QMCv2 decryption support
 impl QMCv2 {
+    /// Init the cipher, with its mode guessed from the key length.
+    /// Use [`QMCv2::with_mode`] if the mode is already known.
     pub fn from_key<T: AsRef<[u8]>>(key: T) -> Self {
         let key = key.as_ref();
-        if key.len() > 300 {
-            QMCv2::RC4(QMCv2RC4::new(key))
-        } else {
-            QMCv2::Map(QMCv2Map::new(key))
+        match Mode::from_key_len(key.len()) {
+            Mode::RC4 => QMCv2::RC4(QMCv2RC4::new(key)),
+            Mode::Map => QMCv2::Map(QMCv2Map::new(key)),
+        }
+    }
+
+    /// Same as [`QMCv2::from_key`], but rejects empty keys.
+    pub fn try_new<T: AsRef<[u8]>>(key: T) -> Result<Self, InitCipherError> {
+        let key = key.as_ref();
+        Self::with_mode(Mode::from_key_len(key.len()), key)
+    }
+
+    /// Init the cipher with an explicit mode.
+    pub fn with_mode<T: AsRef<[u8]>>(mode: Mode, key: T) -> Result<Self, InitCipherError> {
+        let key = key.as_ref();
+        let cipher = match mode {
+            Mode::Map => QMCv2::Map(QMCv2Map::try_new(key)?),
+            Mode::RC4 => QMCv2::RC4(QMCv2RC4::try_new(key)?),
+        };
+        Ok(cipher)
+    }
+
+    /// Detect the cipher mode by decrypting the beginning of the file with both modes,
+    /// and checking if the result looks like a known audio format.
+    ///
+    /// `encrypted_head` should be the first [`DETECT_MODE_LEN`] bytes of the encrypted file
+    /// (or the whole audio, if shorter). A bare MP3 frame sync is only accepted when
+    /// followed by a second frame, see [`is_mp3_frame_pair`].
+    pub fn detect_mode<T: AsRef<[u8]>>(key: T, encrypted_head: &[u8]) -> Option<Mode> {
+        let key = key.as_ref();
+        let guessed = Mode::from_key_len(key.len());
+        let other = match guessed {
+            Mode::Map => Mode::RC4,
+            Mode::RC4 => Mode::Map,
+        };
+
+        let len = encrypted_head.len().min(DETECT_MODE_LEN);
+        [guessed, other].into_iter().find(|&mode| {
+            QMCv2::with_mode(mode, key).is_ok_and(|cipher| {
+                let mut buffer = encrypted_head[..len].to_vec();
+                cipher.decipher_buffer(0, &mut buffer);
+                match detect_audio_type(&buffer) {
+                    Some(_) if is_mp3_frame_sync(&buffer) => is_mp3_frame_pair(&buffer),
+                    audio_type => audio_type.is_some(),
+                }
+            })
+        })
+    }
+
+    pub fn get_mode(&self) -> Mode {
+        match self {
+            Self::Map(_) => Mode::Map,
+            Self::RC4(_) => Mode::RC4,
         }
     }
 
@@ -51,3 +138,69 @@ impl ByteOffsetEncipher for QMCv2 {
         self.decipher_buffer(offset, buffer)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE: &[u8] = include_bytes!("../../../sample/test_121529_32kbps.ogg");
+
+    fn make_key(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 7 + 3) as u8).collect()
+    }
+
+    #[test]
+    fn test_with_mode_empty_key() {
+        assert_eq!(
+            QMCv2::with_mode(Mode::RC4, b""),
+            Err(InitCipherError::InvalidKeyLength(Mode::RC4, 0))
+        );
+    }
+
+    #[test]
+    fn test_try_new() {
+        assert_eq!(
+            QMCv2::try_new(b""),
+            Err(InitCipherError::InvalidKeyLength(Mode::Map, 0))
+        );
+        assert_eq!(QMCv2::try_new(make_key(256)).unwrap().get_mode(), Mode::Map);
+        assert_eq!(QMCv2::try_new(make_key(512)).unwrap().get_mode(), Mode::RC4);
+    }
+
+    #[test]
+    fn test_with_mode_overrides_heuristic() {
+        // A short RC4 key, which `from_key` would have treated as a map key.
+        let key = make_key(256);
+        let cipher = QMCv2::with_mode(Mode::RC4, &key).unwrap();
+        assert_eq!(cipher.get_mode(), Mode::RC4);
+        assert_eq!(QMCv2::from_key(&key).get_mode(), Mode::Map);
+    }
+
+    #[test]
+    fn test_detect_mode() {
+        for (mode, key) in [(Mode::RC4, make_key(256)), (Mode::Map, make_key(512))] {
+            let mut encrypted = SAMPLE[..64].to_vec();
+            QMCv2::with_mode(mode, &key)
+                .unwrap()
+                .encipher_buffer(0, &mut encrypted);
+            assert_eq!(QMCv2::detect_mode(&key, &encrypted), Some(mode));
+        }
+    }
+
+    #[test]
+    fn test_detect_mode_mp3() {
+        let key = make_key(128);
+        let cipher = QMCv2::with_mode(Mode::Map, &key).unwrap();
+        let encrypt = |audio: &[u8]| {
+            let mut encrypted = audio.to_vec();
+            cipher.encipher_buffer(0, &mut encrypted);
+            encrypted
+        };
+
+        let mut audio = vec![0u8; DETECT_MODE_LEN];
+        audio[..4].copy_from_slice(b"\xFF\xFB\x90\x64");
+        assert_eq!(QMCv2::detect_mode(&key, &encrypt(&audio)), None);
+        audio[417..421].copy_from_slice(b"\xFF\xFB\x90\x64");
+        assert_eq!(QMCv2::detect_mode(&key, &encrypt(&audio)), Some(Mode::Map));
+    }
+}
@@ -1,4 +1,7 @@
+use alloc::vec;
+
 use crate::crypto::byte_offset_cipher::{ByteOffsetDecipher, ByteOffsetEncipher};
+use crate::crypto::tencent::{InitCipherError, Mode};
 
 #[derive(Debug, Clone, Ord, PartialOrd, Eq, PartialEq)]
 pub struct QMCv2Map {
@@ -27,10 +30,19 @@ fn qmc2_key_to_qmc1(key_blob: &[u8]) -> [u8; 128] {
 }
 
 impl QMCv2Map {
+    /// Note: an empty key results in an all-zero key; use [`QMCv2Map::try_new`] to reject it.
     pub fn new(file_key: &[u8]) -> Self {
         let key = qmc2_key_to_qmc1(file_key);
         Self { key }
     }
+
+    pub fn try_new(file_key: &[u8]) -> Result<Self, InitCipherError> {
+        if file_key.is_empty() {
+            Err(InitCipherError::InvalidKeyLength(Mode::Map, 0))?;
+        }
+
+        Ok(Self::new(file_key))
+    }
 }
 
 impl ByteOffsetDecipher for QMCv2Map {
@@ -44,3 +56,25 @@ impl ByteOffsetEncipher for QMCv2Map {
         self.decipher_byte(offset, datum)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_try_new() {
+        assert_eq!(
+            QMCv2Map::try_new(b""),
+            Err(InitCipherError::InvalidKeyLength(Mode::Map, 0))
+        );
+
+        for len in 1..=512 {
+            let key = (0..len).map(|i| (i * 7 + len) as u8).collect::<Vec<_>>();
+            let cipher = QMCv2Map::try_new(&key).unwrap();
+            let mut data = [0u8; 64];
+            for offset in [0, 0x7F, 0x7FFF, 0x8000, 0xFFFF_FFFF] {
+                cipher.decipher_buffer(offset, &mut data);
+            }
+        }
+    }
+}
@@ -1,8 +1,10 @@
-use std::cmp::min;
+use alloc::boxed::Box;
+use core::cmp::min;
 
 use crate::crypto::byte_offset_cipher::{ByteOffsetDecipher, ByteOffsetEncipher};
 
 use super::rc4::RC4;
+use super::InitCipherError;
 
 const INITIAL_SEGMENT_SIZE: usize = 0x80;
 const OTHER_SEGMENT_SIZE: usize = 0x1400;
@@ -30,6 +32,9 @@ fn calc_key_hash(key: &[u8]) -> u32 {
 }
 
 impl QMCv2RC4 {
+    /// # Panics
+    ///
+    /// Panics if the key is empty; use [`QMCv2RC4::try_new`] for untrusted keys.
     pub fn new(key: &[u8]) -> Self {
         Self {
             key: key.into(),
@@ -38,6 +43,14 @@ impl QMCv2RC4 {
         }
     }
 
+    pub fn try_new(key: &[u8]) -> Result<Self, InitCipherError> {
+        Ok(Self {
+            key: key.into(),
+            key_hash: calc_key_hash(key),
+            key_stream: Box::from(RC4::try_new(key)?.get_key_stream()),
+        })
+    }
+
     #[inline]
     fn get_segment_key(&self, id: usize, seed: u8) -> usize {
         // Rust will panic on division by zero.
@@ -52,12 +65,10 @@ impl QMCv2RC4 {
     }
 
     fn encode_first_segment(&self, offset: usize, buffer: &mut [u8]) {
-        let mut i = offset;
         let key_len = self.key.len();
 
-        for item in buffer.iter_mut() {
+        for (i, item) in (offset..).zip(buffer.iter_mut()) {
             *item ^= self.key[self.get_segment_key(i, self.key[i % key_len]) % key_len];
-            i += 1;
         }
     }
 
@@ -125,3 +136,29 @@ impl ByteOffsetEncipher for QMCv2RC4 {
         self.decipher_buffer(offset, buffer)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::tencent::Mode;
+    use crate::utils::fuzz::Lcg;
+
+    #[test]
+    fn test_try_new() {
+        assert_eq!(
+            QMCv2RC4::try_new(b""),
+            Err(InitCipherError::InvalidKeyLength(Mode::RC4, 0))
+        );
+
+        let mut rng = Lcg::new(0x12345678);
+        for len in (1..=1024).step_by(13) {
+            let key = (0..len).map(|_| rng.next_u8()).collect::<Vec<_>>();
+            let cipher = QMCv2RC4::try_new(&key).unwrap();
+
+            let mut data = [0u8; 0x1500];
+            for offset in [0, 0x7F, 0x80, 0x13FF, 0x1400, 0x12345] {
+                cipher.decipher_buffer(offset, &mut data);
+            }
+        }
+    }
+}
@@ -1,3 +1,7 @@
+use alloc::boxed::Box;
+
+use crate::crypto::tencent::{InitCipherError, Mode};
+
 #[derive(Debug, Clone)]
 pub struct RC4 {
     state: Box<[u8]>,
@@ -22,6 +26,9 @@ fn init_state(key: &[u8]) -> Box<[u8]> {
 }
 
 impl RC4 {
+    /// # Panics
+    ///
+    /// Panics if the key is empty; use [`RC4::try_new`] for untrusted keys.
     pub fn new<K: AsRef<[u8]>>(key: K) -> Self {
         Self {
             state: init_state(key.as_ref()),
@@ -30,6 +37,15 @@ impl RC4 {
         }
     }
 
+    pub fn try_new<K: AsRef<[u8]>>(key: K) -> Result<Self, InitCipherError> {
+        let key = key.as_ref();
+        if key.is_empty() {
+            Err(InitCipherError::InvalidKeyLength(Mode::RC4, 0))?;
+        }
+
+        Ok(Self::new(key))
+    }
+
     fn at(&self, idx: usize) -> usize {
         self.state[idx].into()
     }
@@ -58,7 +74,7 @@ impl RC4 {
 
 #[cfg(test)]
 mod tests {
-    use super::RC4;
+    use super::*;
 
     #[test]
     fn test_rc4() {
@@ -71,4 +87,18 @@ mod tests {
 
         assert_eq!(&data, b"\x68\x75\x6b\x64\x64\x24\x7f\x60\x7c\x7d\x60")
     }
+
+    #[test]
+    fn test_rc4_try_new() {
+        assert!(matches!(
+            RC4::try_new(b""),
+            Err(InitCipherError::InvalidKeyLength(Mode::RC4, 0))
+        ));
+
+        for len in 1..=256 {
+            let key = (0..len).map(|i| (i * 31 + len) as u8).collect::<Vec<_>>();
+            let mut rc4 = RC4::try_new(&key).unwrap();
+            rc4.get_key_stream::<512>();
+        }
+    }
 }
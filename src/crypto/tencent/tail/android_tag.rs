@@ -0,0 +1,106 @@
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::str::FromStr;
+
+use byteorder::{ByteOrder, BE};
+
+use crate::crypto::tencent::tail::metadata::TailParseError;
+use crate::utils::validate::ValidatorTrait;
+
+/// Oldest tag version understood by the Android tag parsers.
+/// Newer versions are accepted, as they only append fields.
+pub const MIN_ANDROID_TAG_VERSION: u32 = 2;
+
+/// CSV payload of an Android tail: `<payload>` `<payload_len: u32 BE>` `<magic: 4 bytes>`.
+///
+/// Fields are addressed by their position; any field after the known ones are kept in
+/// [`AndroidTag::extra_fields`].
+pub struct AndroidTag<'a> {
+    pub tail_len: usize,
+    fields: Vec<&'a str>,
+}
+
+impl<'a> AndroidTag<'a> {
+    pub fn parse(
+        raw: &'a [u8],
+        magic: &'static str,
+        known_fields: usize,
+    ) -> Result<Self, TailParseError> {
+        if raw.len() < 8 {
+            Err(TailParseError::NeedMoreBytes(8))?;
+        }
+        if !raw.ends_with(magic.as_bytes()) {
+            Err(TailParseError::MagicMismatch(magic))?;
+        }
+        let (payload, tail_magic) = raw.split_at(raw.len() - 8);
+        let payload_len = BE::read_u32(tail_magic) as usize;
+        let tail_len = payload_len.checked_add(8).ok_or_else(|| {
+            TailParseError::MalformedField("payload_len", format!("{payload_len}"))
+        })?;
+        if raw.len() < tail_len {
+            Err(TailParseError::NeedMoreBytes(tail_len))?;
+        }
+
+        let payload = &payload[payload.len() - payload_len..];
+        let payload = core::str::from_utf8(payload).map_err(|_| {
+            TailParseError::MalformedField("payload", String::from_utf8_lossy(payload).into())
+        })?;
+
+        let fields = payload.split(',').collect::<Vec<_>>();
+        if fields.len() < known_fields {
+            Err(TailParseError::CsvFieldCountMismatch(
+                known_fields,
+                fields.len(),
+            ))?;
+        }
+
+        Ok(Self { tail_len, fields })
+    }
+
+    pub fn field(&self, idx: usize) -> &'a str {
+        self.fields[idx]
+    }
+
+    pub fn numeric_field<T: FromStr>(
+        &self,
+        idx: usize,
+        name: &'static str,
+    ) -> Result<T, TailParseError> {
+        let value = self.field(idx);
+        match value.parse::<T>() {
+            Ok(parsed) if value.is_digits() => Ok(parsed),
+            _ => Err(TailParseError::MalformedField(name, value.into())),
+        }
+    }
+
+    pub fn tag_version(&self, idx: usize) -> Result<u32, TailParseError> {
+        let version = self.numeric_field(idx, "tag_version")?;
+        if version < MIN_ANDROID_TAG_VERSION {
+            Err(TailParseError::UnsupportedTagVersion(version))?;
+        }
+        Ok(version)
+    }
+
+    pub fn extra_fields(&self, known_fields: usize) -> Vec<String> {
+        self.fields[known_fields..]
+            .iter()
+            .map(|&field| field.into())
+            .collect()
+    }
+}
+
+/// Serialize CSV fields to an Android tail, ending with `magic`.
+pub fn to_bytes<S: AsRef<str>>(fields: &[S], magic: &'static str) -> Vec<u8> {
+    let payload = fields
+        .iter()
+        .map(|field| field.as_ref())
+        .collect::<Vec<_>>()
+        .join(",");
+
+    let mut result = payload.into_bytes();
+    let payload_len = result.len() as u32;
+    result.extend_from_slice(&payload_len.to_be_bytes());
+    result.extend_from_slice(magic.as_bytes());
+    result
+}
@@ -1,68 +1,99 @@
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use crate::crypto::tencent::ekey::{KeyDecryptError, KeyEncryptError};
 use thiserror::Error;
-use crate::crypto::tencent::ekey::KeyDecryptError;
 
 /// Tail metadata extracted from "v1" and "v2" QMPC, up to v19.51
 /// "v2" introduced an extra key scrambler. The `key` field in this struct will have
 /// the unscrambled ekey.
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct PcLegacyMetadata {
     /// Size of the payload to trim off the end of the file.
     pub tail_len: usize,
     /// Embedded ekey.
+    #[cfg_attr(feature = "serde", serde(with = "crate::utils::serde_hex"))]
     pub key: Box<[u8]>,
 }
 
 /// Tail metadata extracted from "v3" QMPC, first introduced in QMPC v19.57
-/// The raw metadata contains `media_id` and `media_filename` in UTF16-LE encoding,
-/// and it has been downgraded to ASCII (which is UTF-8 compatible).
+/// The raw metadata contains `media_id` and `media_filename` in UTF16-LE encoding.
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct PcMusicExMetadata {
     /// Size of the payload to trim off the end of the file.
     pub tail_len: usize,
-    /// Should always be `1`.
+    /// `1` for the initial version; newer versions append extra fields.
     pub tag_version: u32,
     /// Resource identifier (`.mid`)
     pub mid: String,
     /// The actual file name used for `ekey` lookup (`.file.media_mid` + extension).
     pub media_filename: String,
+
+    /// unknown, possibly the numeric song id.
+    pub unknown_0: u32,
+    /// unknown
+    pub unknown_1: u32,
+    /// unknown
+    pub unknown_2: u32,
+    /// unknown; uninitialized memory?
+    pub unknown_3: u32,
+    /// Payload bytes after the fields known in v1, kept as-is.
+    #[cfg_attr(feature = "serde", serde(with = "crate::utils::serde_hex"))]
+    pub extra: Box<[u8]>,
 }
 
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct AndroidQTagMetadata {
     /// Size of the payload to trim off the end of the file.
     pub tail_len: usize,
     /// Embedded ekey.
+    #[cfg_attr(feature = "serde", serde(with = "crate::utils::serde_hex"))]
     pub key: Box<[u8]>,
-    /// Tag version associated to the metadata. Should be `2`.
+    /// Tag version associated to the metadata. `2` or later.
     pub tag_version: u32,
     /// The old, numeric id of the resource (`.id`).
     pub resource_id: u64,
+    /// Unknown fields following the known ones, introduced in newer tag versions.
+    pub extra_fields: Vec<String>,
 }
 
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct AndroidSTagMetadata {
     /// Size of the payload to trim off the end of the file.
     pub tail_len: usize,
-    /// Should always be `2`.
+    /// `2` or later.
     pub tag_version: u32,
     /// Resource identifier (aka. `file.media_mid`).
     pub media_mid: String,
     /// Numeric id.
     pub media_numeric_id: u64,
+    /// Unknown fields following the known ones, introduced in newer tag versions.
+    pub extra_fields: Vec<String>,
 }
 
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "serde", serde(tag = "type"))]
 pub enum TailParseResult {
     /// Tail parsed from legacy "v1" and "v2" encoded file, used in QMPC up to 19.51.
+    #[cfg_attr(feature = "serde", serde(rename = "pc_legacy"))]
     PcLegacy(PcLegacyMetadata),
     /// Tail parsed from "v3" encoded file, used since QMPC v19.57.
     /// The metadata had magic `"musicex\x00"` in the end.
+    #[cfg_attr(feature = "serde", serde(rename = "pc_musicex"))]
     PcMusicEx(PcMusicExMetadata),
     /// Tail parsed with "QTag" in the end.
     /// The `key` is embedded to the metadata.
+    #[cfg_attr(feature = "serde", serde(rename = "android_qtag"))]
     AndroidQTag(AndroidQTagMetadata),
     /// Tail parsed with "QTag" in the end.
     /// One should look at their app internal database and lookup the filename.
+    #[cfg_attr(feature = "serde", serde(rename = "android_stag"))]
     AndroidSTag(AndroidSTagMetadata),
 }
 
@@ -75,6 +106,16 @@ impl TailParseResult {
         }
     }
 
+    /// Serialize the metadata back to a tail; keys are encrypted as ekey (v1).
+    pub fn to_bytes(&self) -> Result<Vec<u8>, KeyEncryptError> {
+        match self {
+            TailParseResult::PcLegacy(m) => m.to_bytes(),
+            TailParseResult::PcMusicEx(m) => Ok(m.to_bytes()),
+            TailParseResult::AndroidQTag(m) => m.to_bytes(),
+            TailParseResult::AndroidSTag(m) => Ok(m.to_bytes()),
+        }
+    }
+
     pub fn get_tail_len(&self) -> usize {
         match self {
             TailParseResult::PcLegacy(m) => m.tail_len,
@@ -91,9 +132,33 @@ pub enum TailParseError {
     #[error("no valid tail found")]
     InvalidTail,
 
+    /// The tail does not end with the magic expected by this parser.
+    #[error("tail magic mismatch, expecting {0:?}")]
+    MagicMismatch(&'static str),
+
+    /// The CSV payload does not have enough fields.
+    #[error("CSV payload: expecting at least {0} fields, got {1}")]
+    CsvFieldCountMismatch(usize, usize),
+
+    /// The ekey is longer than what the clients would produce.
+    #[error("ekey is too long: {0} bytes (max {1} bytes)")]
+    EKeyTooLong(usize, usize),
+
+    /// The ekey contains characters outside of base64 charset.
+    #[error("ekey contains non-base64 characters")]
+    EKeyNotBase64,
+
+    /// Found a tag with an unsupported version.
+    #[error("unsupported tag version {0}")]
+    UnsupportedTagVersion(u32),
+
+    /// A field of the tag is malformed. The first parameter is the field name.
+    #[error("malformed field {0}: {1:?}")]
+    MalformedField(&'static str, String),
+
     /// Error when decoding the ekey.
     #[error("failed to decrypt ekey from tail: {0}")]
-    EKeyDecryptionFailure(KeyDecryptError),
+    EKeyDecryptionFailure(#[source] KeyDecryptError),
 
     /// Found a musicex tag but unsupported version
     #[error("MusicEx tail: unsupported tag version {0}")]
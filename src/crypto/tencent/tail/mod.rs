@@ -1,8 +1,19 @@
 pub use parse::parse as parse_tail;
+pub use parse::parse_verbose as parse_tail_verbose;
+pub use parse::{TailParseAttempt, TailParseReport, TailParser};
+#[cfg(feature = "std")]
+pub use parse_reader::parse_from_reader as parse_tail_from_reader;
+#[cfg(feature = "std")]
+pub use parse_reader::parse_verbose_from_reader as parse_tail_verbose_from_reader;
+#[cfg(feature = "std")]
+pub use parse_reader::TailReadError;
 
+mod android_tag;
 pub mod metadata;
 mod parse;
 mod parse_android_qtag;
 mod parse_android_stag;
 mod parse_pc_v1;
 mod parse_pc_v2;
+#[cfg(feature = "std")]
+mod parse_reader;
@@ -1,3 +1,5 @@
+use alloc::vec::Vec;
+
 use crate::crypto::tencent::tail::metadata::{TailParseError, TailParseResult};
 use crate::crypto::tencent::tail::parse_android_qtag::parse_android_qtag;
 use crate::crypto::tencent::tail::parse_android_stag::parse_android_stag;
@@ -19,5 +21,173 @@ pub fn parse(raw: &[u8]) -> Result<TailParseResult, TailParseError> {
     parse_helper!(parse_android_stag, raw);
 
     // Fallback to the behaviour of V1 parser, the most ambiguous of them all.
-    parse_pc_v1(raw)
+    // Its format errors are not meaningful on their own, as the tail could be anything.
+    match parse_pc_v1(raw) {
+        Err(TailParseError::NeedMoreBytes(n)) => Err(TailParseError::NeedMoreBytes(n)),
+        Err(TailParseError::EKeyDecryptionFailure(e)) => {
+            Err(TailParseError::EKeyDecryptionFailure(e))
+        }
+        Err(_) => Err(TailParseError::InvalidTail),
+        result => result,
+    }
+}
+
+/// Tail parsers, in the order they are attempted by [`parse`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TailParser {
+    PcMusicEx,
+    AndroidQTag,
+    AndroidSTag,
+    PcLegacy,
+}
+
+impl TailParser {
+    pub const ALL: [TailParser; 4] = [
+        TailParser::PcMusicEx,
+        TailParser::AndroidQTag,
+        TailParser::AndroidSTag,
+        TailParser::PcLegacy,
+    ];
+
+    pub fn parse(&self, raw: &[u8]) -> Result<TailParseResult, TailParseError> {
+        match self {
+            TailParser::PcMusicEx => parse_pc_v2(raw),
+            TailParser::AndroidQTag => parse_android_qtag(raw),
+            TailParser::AndroidSTag => parse_android_stag(raw),
+            TailParser::PcLegacy => parse_pc_v1(raw),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TailParseAttempt {
+    pub parser: TailParser,
+    pub result: Result<TailParseResult, TailParseError>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TailParseReport {
+    /// One attempt per parser, in the order of [`TailParser::ALL`].
+    pub attempts: Vec<TailParseAttempt>,
+}
+
+impl TailParseReport {
+    /// The first successfully parsed tail, if any.
+    pub fn accepted(&self) -> Option<&TailParseResult> {
+        self.attempts
+            .iter()
+            .find_map(|attempt| attempt.result.as_ref().ok())
+    }
+
+    /// The largest tail size requested by any of the parsers.
+    pub fn need_more_bytes(&self) -> Option<usize> {
+        self.attempts
+            .iter()
+            .filter_map(|attempt| match attempt.result {
+                Err(TailParseError::NeedMoreBytes(n)) => Some(n),
+                _ => None,
+            })
+            .max()
+    }
+}
+
+/// Run every parser against the tail and report why each of them accepted or rejected it.
+pub fn parse_verbose(raw: &[u8]) -> TailParseReport {
+    let attempts = TailParser::ALL
+        .iter()
+        .map(|&parser| TailParseAttempt {
+            parser,
+            result: parser.parse(raw),
+        })
+        .collect();
+
+    TailParseReport { attempts }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_verbose_report() {
+        let footer = include_bytes!("__fixtures__/ekey_android_stag.bin");
+        let report = parse_verbose(footer);
+
+        let errors = report
+            .attempts
+            .iter()
+            .map(|attempt| (attempt.parser, attempt.result.as_ref().err().cloned()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            errors,
+            vec![
+                (
+                    TailParser::PcMusicEx,
+                    Some(TailParseError::MagicMismatch("musicex\0"))
+                ),
+                (
+                    TailParser::AndroidQTag,
+                    Some(TailParseError::MagicMismatch("QTag"))
+                ),
+                (TailParser::AndroidSTag, None),
+                (
+                    TailParser::PcLegacy,
+                    Some(TailParseError::EKeyTooLong(0x67615453, 0x500))
+                )
+            ]
+        );
+        assert!(matches!(
+            report.accepted(),
+            Some(TailParseResult::AndroidSTag(_))
+        ));
+    }
+
+    #[test]
+    fn test_verbose_tag_version() {
+        let mut footer = include_bytes!("__fixtures__/ekey_android_stag.bin").to_vec();
+        let pos = footer.windows(3).position(|w| w == b",2,").unwrap();
+        footer[pos + 1] = b'1';
+
+        let report = parse_verbose(&footer);
+        assert_eq!(
+            report.attempts[2].result,
+            Err(TailParseError::UnsupportedTagVersion(1))
+        );
+        assert_eq!(report.accepted(), None);
+        assert_eq!(parse(&footer), Err(TailParseError::InvalidTail));
+    }
+
+    #[test]
+    fn test_parse_random() {
+        use crate::utils::fuzz::Lcg;
+        use base64::{engine::general_purpose::STANDARD as Base64, Engine as _};
+
+        let mut rng = Lcg::new(0x7A11);
+        for _ in 0..5000 {
+            let payload = rng.input(256);
+            let len = match rng.below(3) {
+                0 => payload.len() as u32,
+                1 => rng.below(payload.len() + 16) as u32,
+                _ => rng.next_u32() << rng.below(16),
+            };
+            let tails = [
+                [&payload[..], &len.to_be_bytes(), b"QTag"].concat(),
+                [&payload[..], &len.to_be_bytes(), b"STag"].concat(),
+                [
+                    &payload[..],
+                    &len.to_le_bytes(),
+                    &[1, 0, 0, 0],
+                    b"musicex\0",
+                ]
+                .concat(),
+                [Base64.encode(&payload).as_bytes(), &len.to_le_bytes()].concat(),
+                payload.clone(),
+            ];
+            for tail in tails {
+                let _ = parse(&tail);
+                let report = parse_verbose(&tail);
+                let _ = (report.accepted(), report.need_more_bytes());
+            }
+        }
+    }
 }
@@ -1,50 +1,55 @@
-use byteorder::ByteOrder;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
 
-use crate::crypto::tencent::ekey::decrypt;
-use crate::crypto::tencent::tail::metadata::TailParseError::NeedMoreBytes;
+use crate::crypto::tencent::ekey::{decrypt, encrypt, KeyEncryptError};
+use crate::crypto::tencent::tail::android_tag::{self, AndroidTag};
 use crate::crypto::tencent::tail::metadata::{
     AndroidQTagMetadata, TailParseError, TailParseResult,
 };
 use crate::utils::validate::ValidatorTrait;
 
-pub fn parse_android_qtag(raw: &[u8]) -> Result<TailParseResult, TailParseError> {
-    if raw.len() < 8 {
-        Err(NeedMoreBytes(8))?;
-    }
-    if !raw.ends_with(b"QTag") {
-        Err(TailParseError::InvalidTail)?;
-    }
-    let (payload, tail_magic) = raw.split_at(raw.len() - 8);
-    let payload_len = byteorder::BE::read_u32(tail_magic) as usize;
-    let tail_len = payload_len + 8;
+// CSV: ekey,resource_id,version[,extra...]
+const FIELD_EKEY: usize = 0;
+const FIELD_RESOURCE_ID: usize = 1;
+const FIELD_TAG_VERSION: usize = 2;
+const KNOWN_FIELDS: usize = 3;
 
-    // CSV: ekey,resource_id,version
-    let payload_str = String::from_utf8_lossy(&payload[payload.len() - payload_len..]);
-    let parts = payload_str.split(',').collect::<Vec<_>>();
-
-    if parts.len() != 3 {
-        Err(TailParseError::InvalidTail)?;
-    }
+pub fn parse_android_qtag(raw: &[u8]) -> Result<TailParseResult, TailParseError> {
+    let tag = AndroidTag::parse(raw, "QTag", KNOWN_FIELDS)?;
 
-    let (ekey, id, version) = (parts[0], parts[1], parts[2]);
-    if !ekey.is_base64() || version != "2" {
-        Err(TailParseError::InvalidTail)?;
+    let ekey = tag.field(FIELD_EKEY);
+    if !ekey.is_base64() {
+        Err(TailParseError::EKeyNotBase64)?;
     }
-
-    let id = match id.parse::<u64>() {
-        Ok(id) => id,
-        Err(_) => Err(TailParseError::InvalidTail)?,
-    };
+    let resource_id = tag.numeric_field(FIELD_RESOURCE_ID, "resource_id")?;
+    let tag_version = tag.tag_version(FIELD_TAG_VERSION)?;
 
     let key = decrypt(ekey).map_err(TailParseError::EKeyDecryptionFailure)?;
     Ok(TailParseResult::AndroidQTag(AndroidQTagMetadata {
-        tail_len,
+        tail_len: tag.tail_len,
         key,
-        tag_version: 2,
-        resource_id: id,
+        tag_version,
+        resource_id,
+        extra_fields: tag.extra_fields(KNOWN_FIELDS),
     }))
 }
 
+impl AndroidQTagMetadata {
+    /// Serialize the metadata back to a tail, with the key encrypted as an ekey (v1).
+    ///
+    /// `tail_len` is recalculated from the size of the fields.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, KeyEncryptError> {
+        let ekey = encrypt(&self.key)?;
+        let mut fields = Vec::with_capacity(KNOWN_FIELDS + self.extra_fields.len());
+        fields.push(String::from_utf8_lossy(&ekey).into_owned());
+        fields.push(self.resource_id.to_string());
+        fields.push(self.tag_version.to_string());
+        fields.extend(self.extra_fields.iter().cloned());
+
+        Ok(android_tag::to_bytes(&fields, "QTag"))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::crypto::tencent::parse_tail;
@@ -60,7 +65,30 @@ mod tests {
             tail_len: 0x02D4,
             resource_id: 326454301,
             tag_version: 2,
+            extra_fields: vec![],
         }));
         assert_eq!(actual, expected, "failed to parse enc_v2_map sample");
     }
+
+    #[test]
+    fn test_android_qtag_round_trip() {
+        let metadata = AndroidQTagMetadata {
+            key: Box::from(*include_bytes!("__fixtures__/ekey_android_qtag_result.bin")),
+            tail_len: 0,
+            resource_id: 326454301,
+            tag_version: 2,
+            extra_fields: vec!["extra".into()],
+        };
+
+        let tail = metadata.to_bytes().unwrap();
+        assert!(tail.ends_with(b"QTag"));
+        let expected = AndroidQTagMetadata {
+            tail_len: tail.len(),
+            ..metadata
+        };
+        assert_eq!(
+            parse_tail(&tail),
+            Ok(TailParseResult::AndroidQTag(expected))
+        );
+    }
 }
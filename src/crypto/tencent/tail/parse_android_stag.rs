@@ -1,48 +1,54 @@
-use byteorder::ByteOrder;
+use alloc::string::ToString;
+use alloc::vec::Vec;
 
-use crate::crypto::tencent::tail::metadata::TailParseError::NeedMoreBytes;
+use crate::crypto::tencent::tail::android_tag::{self, AndroidTag};
 use crate::crypto::tencent::tail::metadata::{
     AndroidSTagMetadata, TailParseError, TailParseResult,
 };
-use crate::utils::validate::ValidatorTrait;
 
-pub fn parse_android_stag(raw: &[u8]) -> Result<TailParseResult, TailParseError> {
-    if raw.len() < 8 {
-        Err(NeedMoreBytes(8))?;
-    }
-    if !raw.ends_with(b"STag") {
-        Err(TailParseError::InvalidTail)?;
-    }
-    let (payload, tail_magic) = raw.split_at(raw.len() - 8);
-    let payload_len = byteorder::BE::read_u32(tail_magic) as usize;
-    let tail_len = payload_len + 8;
-
-    // CSV: resource_id,version,file_media_mid
-    let payload_str = String::from_utf8_lossy(&payload[payload.len() - payload_len..]);
-    let parts = payload_str.split(',').collect::<Vec<_>>();
+// CSV: resource_id,version,file_media_mid[,extra...]
+const FIELD_RESOURCE_ID: usize = 0;
+const FIELD_TAG_VERSION: usize = 1;
+const FIELD_MEDIA_MID: usize = 2;
+const KNOWN_FIELDS: usize = 3;
 
-    if parts.len() != 3 {
-        Err(TailParseError::InvalidTail)?;
-    }
+pub fn parse_android_stag(raw: &[u8]) -> Result<TailParseResult, TailParseError> {
+    let tag = AndroidTag::parse(raw, "STag", KNOWN_FIELDS)?;
 
-    let (id, version, media_mid) = (parts[0], parts[1], parts[2]);
-    if !id.is_digits() || version != "2" {
-        Err(TailParseError::InvalidTail)?;
+    let media_numeric_id = tag.numeric_field(FIELD_RESOURCE_ID, "resource_id")?;
+    let tag_version = tag.tag_version(FIELD_TAG_VERSION)?;
+    let media_mid = tag.field(FIELD_MEDIA_MID);
+    if media_mid.is_empty() {
+        Err(TailParseError::MalformedField(
+            "media_mid",
+            media_mid.into(),
+        ))?;
     }
 
-    let id = match id.parse::<u64>() {
-        Ok(id) => id,
-        Err(_) => Err(TailParseError::InvalidTail)?,
-    };
-
     Ok(TailParseResult::AndroidSTag(AndroidSTagMetadata {
-        tail_len,
-        tag_version: 2,
+        tail_len: tag.tail_len,
+        tag_version,
         media_mid: media_mid.into(),
-        media_numeric_id: id,
+        media_numeric_id,
+        extra_fields: tag.extra_fields(KNOWN_FIELDS),
     }))
 }
 
+impl AndroidSTagMetadata {
+    /// Serialize the metadata back to a tail.
+    ///
+    /// `tail_len` is recalculated from the size of the fields.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut fields = Vec::with_capacity(KNOWN_FIELDS + self.extra_fields.len());
+        fields.push(self.media_numeric_id.to_string());
+        fields.push(self.tag_version.to_string());
+        fields.push(self.media_mid.clone());
+        fields.extend(self.extra_fields.iter().cloned());
+
+        android_tag::to_bytes(&fields, "STag")
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::crypto::tencent::parse_tail;
@@ -58,7 +64,75 @@ mod tests {
             tag_version: 2,
             media_mid: "001y7CaR29k6YP".into(),
             media_numeric_id: 5177785,
+            extra_fields: vec![],
         }));
         assert_eq!(actual, expected, "failed to parse enc_v2_map sample");
     }
+
+    #[test]
+    fn test_android_stag_round_trip() {
+        let footer = *include_bytes!("__fixtures__/ekey_android_stag.bin");
+        let metadata = parse_tail(&footer).unwrap();
+        let tail = metadata.to_bytes().unwrap();
+        assert_eq!(tail, &footer[footer.len() - metadata.get_tail_len()..]);
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn test_android_stag_serialize() {
+        let footer = *include_bytes!("__fixtures__/ekey_android_stag.bin");
+        let actual = serde_json::to_value(parse_tail(&footer).unwrap()).unwrap();
+        let expected = serde_json::json!({
+            "type": "android_stag",
+            "tail_len": 0x20,
+            "tag_version": 2,
+            "media_mid": "001y7CaR29k6YP",
+            "media_numeric_id": 5177785,
+            "extra_fields": [],
+        });
+        assert_eq!(actual, expected);
+    }
+
+    fn make_stag(payload: &str) -> Vec<u8> {
+        let mut tail = payload.as_bytes().to_vec();
+        tail.extend((payload.len() as u32).to_be_bytes());
+        tail.extend(b"STag");
+        tail
+    }
+
+    #[test]
+    fn test_android_stag_extra_fields() {
+        let footer = make_stag("5177785,3,001y7CaR29k6YP,,extra,field");
+        let expected = Ok(TailParseResult::AndroidSTag(AndroidSTagMetadata {
+            tail_len: footer.len(),
+            tag_version: 3,
+            media_mid: "001y7CaR29k6YP".into(),
+            media_numeric_id: 5177785,
+            extra_fields: vec!["".into(), "extra".into(), "field".into()],
+        }));
+        assert_eq!(parse_android_stag(&footer), expected);
+    }
+
+    #[test]
+    fn test_android_stag_malformed_fields() {
+        assert_eq!(
+            parse_android_stag(&make_stag("+5177785,2,001y7CaR29k6YP")),
+            Err(TailParseError::MalformedField(
+                "resource_id",
+                "+5177785".into()
+            ))
+        );
+        assert_eq!(
+            parse_android_stag(&make_stag("5177785,v2,001y7CaR29k6YP")),
+            Err(TailParseError::MalformedField("tag_version", "v2".into()))
+        );
+        assert_eq!(
+            parse_android_stag(&make_stag("5177785,1,001y7CaR29k6YP")),
+            Err(TailParseError::UnsupportedTagVersion(1))
+        );
+        assert_eq!(
+            parse_android_stag(&make_stag("5177785,2")),
+            Err(TailParseError::CsvFieldCountMismatch(3, 2))
+        );
+    }
 }
@@ -1,9 +1,11 @@
+use alloc::vec::Vec;
+
 use byteorder::ByteOrder;
 
 use crate::utils::validate::is_base64_str;
 
 use super::metadata::{PcLegacyMetadata, TailParseError, TailParseResult};
-use crate::crypto::tencent::ekey::{decrypt, MAX_EKEY_LEN};
+use crate::crypto::tencent::ekey::{decrypt, encrypt, KeyEncryptError, MAX_EKEY_LEN};
 
 pub fn parse_pc_v1(raw: &[u8]) -> Result<TailParseResult, TailParseError> {
     if raw.len() < 8 {
@@ -11,12 +13,12 @@ pub fn parse_pc_v1(raw: &[u8]) -> Result<TailParseResult, TailParseError> {
     }
 
     let key_len = byteorder::LE::read_u32(&raw[raw.len() - 4..]) as usize;
-    let tail_len = key_len + 4;
 
     // If the key is too long, probably not an ekey.
     if key_len > MAX_EKEY_LEN {
-        return Err(TailParseError::InvalidTail);
+        return Err(TailParseError::EKeyTooLong(key_len, MAX_EKEY_LEN));
     }
+    let tail_len = key_len + 4;
 
     // Check if we have enough bytes
     if raw.len() < tail_len {
@@ -32,7 +34,7 @@ pub fn parse_pc_v1(raw: &[u8]) -> Result<TailParseResult, TailParseError> {
     // Validate ekey
     if !is_base64_str(&ekey) {
         // Check if the ekey contains invalid characters.
-        return Err(TailParseError::InvalidTail);
+        return Err(TailParseError::EKeyNotBase64);
     }
 
     let key = decrypt(ekey).map_err(TailParseError::EKeyDecryptionFailure)?;
@@ -42,6 +44,18 @@ pub fn parse_pc_v1(raw: &[u8]) -> Result<TailParseResult, TailParseError> {
     }))
 }
 
+impl PcLegacyMetadata {
+    /// Serialize the metadata back to a tail, with the key encrypted as an ekey (v1).
+    ///
+    /// `tail_len` is recalculated from the size of the ekey.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, KeyEncryptError> {
+        let mut result = encrypt(&self.key)?.into_vec();
+        let key_len = result.len() as u32;
+        result.extend_from_slice(&key_len.to_le_bytes());
+        Ok(result)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -67,4 +81,19 @@ mod tests {
         }));
         assert_eq!(actual, expected, "failed to parse enc_v1_rc4 sample");
     }
+
+    #[test]
+    fn test_legacy_round_trip() {
+        let metadata = PcLegacyMetadata {
+            key: Box::from(*include_bytes!("__fixtures__/ekey_pc_enc_v1_result.bin")),
+            tail_len: 0,
+        };
+
+        let tail = metadata.to_bytes().unwrap();
+        let expected = PcLegacyMetadata {
+            tail_len: tail.len(),
+            ..metadata
+        };
+        assert_eq!(parse_pc_v1(&tail), Ok(TailParseResult::PcLegacy(expected)));
+    }
 }
@@ -1,79 +1,189 @@
-use bincode::Options;
-use byteorder::ByteOrder;
-use serde::{Deserialize, Serialize};
-use serde_big_array::BigArray;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use byteorder::{ByteOrder, LE};
 
 use super::metadata::{PcMusicExMetadata, TailParseError, TailParseResult};
 
+/// `tag_size` + `tag_version` + `"musicex\x00"`
+const MUSICEX_TRAILER_LEN: usize = 4 + 4 + 8;
+const MUSICEX_MAGIC: &[u8; 8] = b"musicex\x00";
+
+const MID_LEN: usize = 30;
+const MEDIA_FILENAME_LEN: usize = 50;
+
+/// Size of the fields known since v1: 4 x u32, `mid` and `media_filename`.
+const MUSICEX_V1_PAYLOAD_LEN: usize = 4 * 4 + (MID_LEN + MEDIA_FILENAME_LEN) * 2;
+/// Smallest tag size (including the trailer) accepted.
+const MUSICEX_MIN_TAG_LEN: usize = MUSICEX_V1_PAYLOAD_LEN + MUSICEX_TRAILER_LEN;
+
 pub fn parse_pc_v2(raw: &[u8]) -> Result<TailParseResult, TailParseError> {
-    if raw.len() < 16 {
-        return Err(TailParseError::NeedMoreBytes(16));
+    if raw.len() < MUSICEX_TRAILER_LEN {
+        return Err(TailParseError::NeedMoreBytes(MUSICEX_TRAILER_LEN));
     }
 
-    let (tail, tail_identifier) = raw.split_at(raw.len() - 12);
-    if !tail_identifier.ends_with(b"musicex\x00") {
-        return Err(TailParseError::InvalidTail);
+    let trailer = &raw[raw.len() - MUSICEX_TRAILER_LEN..];
+    if !trailer.ends_with(MUSICEX_MAGIC) {
+        return Err(TailParseError::MagicMismatch("musicex\0"));
     }
 
-    match byteorder::LE::read_u32(&tail_identifier[..4]) {
-        1 => parse_musicex_v1(tail),
-        version => Err(TailParseError::UnsupportedMusicExVersion(version)),
+    let tag_len = LE::read_u32(&trailer[0..4]) as usize;
+    let tag_version = LE::read_u32(&trailer[4..8]);
+    if tag_version == 0 {
+        return Err(TailParseError::UnsupportedMusicExVersion(tag_version));
+    }
+    if tag_len < MUSICEX_MIN_TAG_LEN {
+        return Err(TailParseError::UnsupportedMusicExPayloadSize(tag_len));
+    }
+    if raw.len() < tag_len {
+        return Err(TailParseError::NeedMoreBytes(tag_len));
     }
+
+    let payload = &raw[raw.len() - tag_len..raw.len() - MUSICEX_TRAILER_LEN];
+    parse_musicex_payload(payload, tag_version, tag_len).map(TailParseResult::PcMusicEx)
 }
 
-/// Convert UTF-16 LE string (within ASCII char range) to UTF-8
-fn from_ascii_utf16(data: &[u16]) -> String {
+/// Decode a NUL-terminated UTF-16 LE string.
+fn from_utf16_le(data: &[u8]) -> Result<String, TailParseError> {
     let data = data
-        .iter()
-        .take_while(|&&wide| wide != 0)
-        .map(|&wide| wide as u8)
+        .chunks_exact(2)
+        .map(LE::read_u16)
+        .take_while(|&wide| wide != 0)
         .collect::<Vec<_>>();
-    String::from_utf8_lossy(&data).to_string()
+    String::from_utf16(&data).map_err(|_| TailParseError::CouldNotDeserializeMusicExPayload)
+}
+
+/// Encode a string to a fixed-size UTF-16 LE buffer, padded with NUL.
+fn to_utf16_le<const N: usize>(value: &str) -> [u16; N] {
+    let mut result = [0u16; N];
+    for (dst, src) in result.iter_mut().zip(value.encode_utf16()) {
+        *dst = src;
+    }
+    result
 }
 
-#[derive(Serialize, Deserialize, PartialEq, Debug)]
-#[repr(C, packed)]
-struct MusicExV1 {
-    /// unused & unknown
-    unknown_0: u32,
-    /// unused & unknown
-    unknown_1: u32,
-    /// unused & unknown
-    unknown_2: u32,
-
-    /// Media ID
-    mid: [u16; 30],
-    /// Media file name
-    #[serde(with = "BigArray")]
-    media_filename: [u16; 50],
-
-    /// unused; uninitialized memory?
-    unknown_3: u32,
+/// Parse the payload of the tag, without the trailer.
+///
+/// Layout of the payload (v1):
+///
+/// | Offset | Size  | Field              |
+/// |--------|-------|--------------------|
+/// | `0x00` | 4     | `unknown_0`        |
+/// | `0x04` | 4     | `unknown_1`        |
+/// | `0x08` | 4     | `unknown_2`        |
+/// | `0x0C` | 60    | `mid`              |
+/// | `0x48` | 100   | `media_filename`   |
+/// | `0xAC` | 4     | `unknown_3`        |
+///
+/// Newer versions append more fields, which are kept in `extra`.
+fn parse_musicex_payload(
+    payload: &[u8],
+    tag_version: u32,
+    tag_len: usize,
+) -> Result<PcMusicExMetadata, TailParseError> {
+    let (mid, rest) = payload[0x0C..].split_at(MID_LEN * 2);
+    let (media_filename, rest) = rest.split_at(MEDIA_FILENAME_LEN * 2);
+    let (unknown_3, extra) = rest.split_at(4);
+
+    Ok(PcMusicExMetadata {
+        tail_len: tag_len,
+        tag_version,
+        mid: from_utf16_le(mid)?,
+        media_filename: from_utf16_le(media_filename)?,
+        unknown_0: LE::read_u32(&payload[0x00..]),
+        unknown_1: LE::read_u32(&payload[0x04..]),
+        unknown_2: LE::read_u32(&payload[0x08..]),
+        unknown_3: LE::read_u32(unknown_3),
+        extra: extra.into(),
+    })
 }
 
-fn parse_musicex_v1(tail: &[u8]) -> Result<TailParseResult, TailParseError> {
-    let (payload, len) = tail.split_at(tail.len() - 4);
-    let payload_len = byteorder::LE::read_u32(len) as usize;
-    if payload_len != 0xC0 {
-        return Err(TailParseError::UnsupportedMusicExPayloadSize(payload_len));
+impl PcMusicExMetadata {
+    /// Serialize the metadata back to a tail.
+    ///
+    /// `tail_len` is recalculated from the size of the fields.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let tag_len = MUSICEX_MIN_TAG_LEN + self.extra.len();
+
+        let mut result = vec![0u8; tag_len];
+        LE::write_u32(&mut result[0x00..], self.unknown_0);
+        LE::write_u32(&mut result[0x04..], self.unknown_1);
+        LE::write_u32(&mut result[0x08..], self.unknown_2);
+        LE::write_u16_into(
+            &to_utf16_le::<MID_LEN>(&self.mid),
+            &mut result[0x0C..0x0C + MID_LEN * 2],
+        );
+        LE::write_u16_into(
+            &to_utf16_le::<MEDIA_FILENAME_LEN>(&self.media_filename),
+            &mut result[0x48..0x48 + MEDIA_FILENAME_LEN * 2],
+        );
+        LE::write_u32(&mut result[0xAC..], self.unknown_3);
+
+        let (extra, trailer) = result[MUSICEX_V1_PAYLOAD_LEN..].split_at_mut(self.extra.len());
+        extra.copy_from_slice(&self.extra);
+        LE::write_u32(&mut trailer[0..4], tag_len as u32);
+        LE::write_u32(&mut trailer[4..8], self.tag_version);
+        trailer[8..].copy_from_slice(MUSICEX_MAGIC);
+
+        result
     }
-    let payload = &payload[payload.len() - payload_len..];
+}
 
-    let decoded = bincode::options()
-        .with_little_endian()
-        .deserialize::<MusicExV1>(payload)
-        .map_err(|_| TailParseError::CouldNotDeserializeMusicExPayload)?;
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    let mid = decoded.mid;
-    let mid = from_ascii_utf16(&mid);
+    fn make_metadata(tag_version: u32, extra: &[u8]) -> PcMusicExMetadata {
+        PcMusicExMetadata {
+            tail_len: MUSICEX_MIN_TAG_LEN + extra.len(),
+            tag_version,
+            mid: "001y7CaR29k6YP".into(),
+            media_filename: "F0M000歌曲名.mflac".into(),
+            unknown_0: 5177785,
+            unknown_1: 1,
+            unknown_2: 2,
+            unknown_3: 0xDEADBEEF,
+            extra: extra.into(),
+        }
+    }
+
+    #[test]
+    fn test_musicex_v1_round_trip() {
+        let metadata = make_metadata(1, &[]);
+        let tail = metadata.to_bytes();
+        assert_eq!(tail.len(), 0xC0);
 
-    let media_filename = decoded.media_filename;
-    let media_filename = from_ascii_utf16(&media_filename);
+        let mut file = b"[audio data]".to_vec();
+        file.extend(&tail);
+        assert_eq!(parse_pc_v2(&file), Ok(TailParseResult::PcMusicEx(metadata)));
+    }
 
-    Ok(TailParseResult::PcMusicEx(PcMusicExMetadata {
-        tail_len: payload_len + 0x0C,
-        tag_version: 1,
-        mid,
-        media_filename,
-    }))
+    #[test]
+    fn test_musicex_larger_payload() {
+        let metadata = make_metadata(2, b"new fields");
+        let tail = metadata.to_bytes();
+        assert_eq!(tail.len(), 0xC0 + 10);
+        assert_eq!(parse_pc_v2(&tail), Ok(TailParseResult::PcMusicEx(metadata)));
+    }
+
+    #[test]
+    fn test_musicex_need_more_bytes() {
+        let tail = make_metadata(1, &[]).to_bytes();
+        assert_eq!(
+            parse_pc_v2(&tail[0x10..]),
+            Err(TailParseError::NeedMoreBytes(0xC0))
+        );
+    }
+
+    #[test]
+    fn test_musicex_payload_too_small() {
+        let mut tail = make_metadata(1, &[]).to_bytes();
+        let trailer_offset = tail.len() - MUSICEX_TRAILER_LEN;
+        LE::write_u32(&mut tail[trailer_offset..], 0x20);
+        assert_eq!(
+            parse_pc_v2(&tail),
+            Err(TailParseError::UnsupportedMusicExPayloadSize(0x20))
+        );
+    }
 }
@@ -0,0 +1,154 @@
+use std::io::{Read, Seek, SeekFrom};
+
+use thiserror::Error;
+
+use crate::crypto::tencent::tail::metadata::{TailParseError, TailParseResult};
+use crate::crypto::tencent::tail::parse::{parse, parse_verbose, TailParseReport};
+
+/// Size of the first read window. Large enough for most tails.
+pub const INITIAL_TAIL_WINDOW_LEN: usize = 1024;
+
+#[derive(Debug, Error)]
+pub enum TailReadError {
+    #[error("failed to read tail: {0}")]
+    IoError(#[source] std::io::Error),
+    #[error("{0}")]
+    ParseError(#[source] TailParseError),
+}
+
+impl From<std::io::Error> for TailReadError {
+    fn from(error: std::io::Error) -> Self {
+        Self::IoError(error)
+    }
+}
+
+impl From<TailParseError> for TailReadError {
+    fn from(error: TailParseError) -> Self {
+        Self::ParseError(error)
+    }
+}
+
+/// Parse the tail from the end of a seekable stream.
+///
+/// The read window starts at [`INITIAL_TAIL_WINDOW_LEN`] and grows whenever a parser
+/// reports [`TailParseError::NeedMoreBytes`].
+///
+/// Returns the parsed tail, and the absolute offset where the encrypted payload ends.
+/// The stream position is unspecified after this call.
+pub fn parse_from_reader<R>(reader: &mut R) -> Result<(TailParseResult, u64), TailReadError>
+where
+    R: Read + Seek + ?Sized,
+{
+    let (result, file_len) = read_growing_window(reader, |buffer| {
+        let result = parse(buffer);
+        let need_more_bytes = match result {
+            Err(TailParseError::NeedMoreBytes(n)) => Some(n),
+            _ => None,
+        };
+        (result, need_more_bytes)
+    })?;
+
+    let result = result?;
+    let tail_len = result.get_tail_len() as u64;
+    if tail_len > file_len {
+        Err(TailParseError::NeedMoreBytes(tail_len as usize))?;
+    }
+    Ok((result, file_len - tail_len))
+}
+
+/// Same as [`parse_from_reader`], but report the result of each parser
+/// (see [`parse_verbose`]).
+///
+/// The stream position is unspecified after this call.
+pub fn parse_verbose_from_reader<R>(reader: &mut R) -> Result<TailParseReport, TailReadError>
+where
+    R: Read + Seek + ?Sized,
+{
+    let (report, _) = read_growing_window(reader, |buffer| {
+        let report = parse_verbose(buffer);
+        let need_more_bytes = report.need_more_bytes();
+        (report, need_more_bytes)
+    })?;
+    Ok(report)
+}
+
+/// Read a window from the end of the stream and `parse` it, growing the window for as long
+/// as `parse` asks for more bytes that the stream has.
+///
+/// Returns the last result of `parse`, and the stream length.
+fn read_growing_window<R, T, F>(reader: &mut R, mut parse: F) -> std::io::Result<(T, u64)>
+where
+    R: Read + Seek + ?Sized,
+    F: FnMut(&[u8]) -> (T, Option<usize>),
+{
+    let file_len = reader.seek(SeekFrom::End(0))?;
+    let mut window_len = INITIAL_TAIL_WINDOW_LEN as u64;
+
+    loop {
+        let read_len = window_len.min(file_len);
+        let mut buffer = vec![0u8; read_len as usize];
+        reader.seek(SeekFrom::Start(file_len - read_len))?;
+        reader.read_exact(&mut buffer)?;
+
+        match parse(&buffer) {
+            // Only retry when there are more bytes to read.
+            (_, Some(n)) if (n as u64) > read_len && (n as u64) <= file_len => {
+                window_len = n as u64;
+            }
+            (result, _) => return Ok((result, file_len)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    #[test]
+    fn test_grow_window_for_long_ekey() {
+        let ekey = include_bytes!("__fixtures__/ekey_pc_enc_v1.bin");
+        let key_len = u32::from_le_bytes(ekey[ekey.len() - 4..].try_into().unwrap()) as usize;
+
+        // Pad ekey to its max length, so the tail no longer fits the initial window.
+        let ekey_body = &ekey[ekey.len() - 4 - key_len..ekey.len() - 4];
+        let mut padded_ekey = ekey_body.to_vec();
+        padded_ekey.resize(0x500, 0);
+
+        let mut file = vec![0xAAu8; 4096];
+        file.extend(&padded_ekey);
+        file.extend(0x500u32.to_le_bytes());
+
+        let (tail, payload_end) = parse_from_reader(&mut Cursor::new(file)).unwrap();
+        assert_eq!(tail.get_tail_len(), 0x504);
+        assert_eq!(payload_end, 4096);
+    }
+
+    #[test]
+    fn test_verbose_grow_window() {
+        let ekey = include_bytes!("__fixtures__/ekey_pc_enc_v1.bin");
+        let key_len = u32::from_le_bytes(ekey[ekey.len() - 4..].try_into().unwrap()) as usize;
+        let mut file = vec![0xAAu8; 4096];
+        file.extend(&ekey[ekey.len() - 4 - key_len..ekey.len() - 4]);
+        file.resize(4096 + 0x500, 0);
+        file.extend(0x500u32.to_le_bytes());
+
+        let report = parse_verbose_from_reader(&mut Cursor::new(file)).unwrap();
+        assert_eq!(report.accepted().unwrap().get_tail_len(), 0x504);
+    }
+
+    #[test]
+    fn test_file_too_small() {
+        let mut file = vec![0u8; 12];
+        file.extend(0x500u32.to_le_bytes());
+
+        let result = parse_from_reader(&mut Cursor::new(file));
+        assert!(matches!(
+            result,
+            Err(TailReadError::ParseError(TailParseError::NeedMoreBytes(
+                0x504
+            )))
+        ));
+    }
+}
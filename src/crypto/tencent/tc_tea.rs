@@ -0,0 +1,196 @@
+//! Tencent's variant of TEA (`tc_tea`), CBC mode.
+//!
+//! Same as the `tc_tea` crate, which requires `std`; except that [`encrypt`] fills the
+//! padding and salt with zeros, so its output is deterministic.
+
+use alloc::boxed::Box;
+use alloc::vec;
+
+use byteorder::{ByteOrder, BE};
+
+const ROUNDS: u32 = 16;
+const DELTA: u32 = 0x9e3779b9;
+
+const SALT_LEN: usize = 2;
+const ZERO_LEN: usize = 7;
+const FIXED_PADDING_LEN: usize = 1 + SALT_LEN + ZERO_LEN;
+
+fn parse_key(key: &[u8]) -> Option<[u32; 4]> {
+    if key.len() < 16 {
+        return None;
+    }
+
+    let mut k = [0u32; 4];
+    BE::read_u32_into(&key[..16], &mut k);
+    Some(k)
+}
+
+#[inline]
+fn single_round(value: u32, sum: u32, key1: u32, key2: u32) -> u32 {
+    value.wrapping_shl(4).wrapping_add(key1)
+        ^ sum.wrapping_add(value)
+        ^ value.wrapping_shr(5).wrapping_add(key2)
+}
+
+fn ecb_decrypt(block: &mut [u8], k: &[u32; 4]) {
+    let mut y = BE::read_u32(&block[0..4]);
+    let mut z = BE::read_u32(&block[4..8]);
+    let mut sum = DELTA.wrapping_mul(ROUNDS);
+
+    for _ in 0..ROUNDS {
+        z = z.wrapping_sub(single_round(y, sum, k[2], k[3]));
+        y = y.wrapping_sub(single_round(z, sum, k[0], k[1]));
+        sum = sum.wrapping_sub(DELTA);
+    }
+
+    BE::write_u32(&mut block[0..4], y);
+    BE::write_u32(&mut block[4..8], z);
+}
+
+fn ecb_encrypt(block: &mut [u8], k: &[u32; 4]) {
+    let mut y = BE::read_u32(&block[0..4]);
+    let mut z = BE::read_u32(&block[4..8]);
+    let mut sum = 0u32;
+
+    for _ in 0..ROUNDS {
+        sum = sum.wrapping_add(DELTA);
+        y = y.wrapping_add(single_round(z, sum, k[0], k[1]));
+        z = z.wrapping_add(single_round(y, sum, k[2], k[3]));
+    }
+
+    BE::write_u32(&mut block[0..4], y);
+    BE::write_u32(&mut block[4..8], z);
+}
+
+/// Encrypt `plaintext`; `None` if the key is invalid.
+pub fn encrypt<T: AsRef<[u8]>, K: AsRef<[u8]>>(plaintext: T, key: K) -> Option<Box<[u8]>> {
+    let plaintext = plaintext.as_ref();
+    let key = parse_key(key.as_ref())?;
+
+    // `[pad_len:3 bits][padding][salt][plaintext][zeros]`, aligned to 8 bytes.
+    let pad_len = (8 - (plaintext.len() + FIXED_PADDING_LEN) % 8) % 8;
+    let start = 1 + pad_len + SALT_LEN;
+    let mut encrypted = vec![0u8; start + plaintext.len() + ZERO_LEN];
+    encrypted[0] = pad_len as u8;
+    encrypted[start..start + plaintext.len()].copy_from_slice(plaintext);
+
+    encrypt_blocks(&mut encrypted, &key);
+    Some(encrypted.into())
+}
+
+/// Encrypt the padded `data` in place.
+fn encrypt_blocks(data: &mut [u8], key: &[u32; 4]) {
+    // Each block is XOR-ed with the previous cipher text block before encryption,
+    // and with the previous (XOR-ed) plain text block after.
+    let mut prev_cipher = [0u8; 8];
+    let mut prev_plain = [0u8; 8];
+    for block in data.chunks_exact_mut(8) {
+        block
+            .iter_mut()
+            .zip(prev_cipher)
+            .for_each(|(datum, prev)| *datum ^= prev);
+        let plain: [u8; 8] = (*block).try_into().unwrap();
+        ecb_encrypt(block, key);
+        block
+            .iter_mut()
+            .zip(prev_plain)
+            .for_each(|(datum, prev)| *datum ^= prev);
+        prev_cipher.copy_from_slice(block);
+        prev_plain = plain;
+    }
+}
+
+/// Decrypt `encrypted`; `None` if the key or the padding is invalid.
+pub fn decrypt<T: AsRef<[u8]>, K: AsRef<[u8]>>(encrypted: T, key: K) -> Option<Box<[u8]>> {
+    let encrypted = encrypted.as_ref();
+    let key = parse_key(key.as_ref())?;
+    let len = encrypted.len();
+    if len < FIXED_PADDING_LEN || len % 8 != 0 {
+        return None;
+    }
+
+    let mut decrypted = encrypted.to_vec();
+    ecb_decrypt(&mut decrypted[0..8], &key);
+    for i in (8..len).step_by(8) {
+        for j in i..i + 8 {
+            decrypted[j] ^= decrypted[j - 8];
+        }
+        ecb_decrypt(&mut decrypted[i..i + 8], &key);
+    }
+
+    // XOR with the previous cipher text block.
+    decrypted[8..]
+        .iter_mut()
+        .zip(encrypted)
+        .for_each(|(datum, &prev)| *datum ^= prev);
+
+    // `[pad_len:3 bits][padding][salt][plaintext][zeros]`
+    let start = 1 + usize::from(decrypted[0] & 0b111) + SALT_LEN;
+    let end = len - ZERO_LEN;
+    if start > end || decrypted[end..].iter().any(|&datum| datum != 0) {
+        return None;
+    }
+
+    Some(decrypted[start..end].into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &[u8; 16] = b"12345678ABCDEFGH";
+
+    #[test]
+    fn test_decrypt() {
+        let encrypted = [
+            0x91, 0x09, 0x51, 0x62, 0xe3, 0xf5, 0xb6, 0xdc, //
+            0x6b, 0x41, 0x4b, 0x50, 0xd1, 0xa5, 0xb8, 0x4e, //
+            0xc5, 0x0d, 0x0c, 0x1b, 0x11, 0x96, 0xfd, 0x3c, //
+        ];
+        assert_eq!(
+            decrypt(encrypted, KEY).as_deref(),
+            Some(&[1u8, 2, 3, 4, 5, 6, 7, 8][..])
+        );
+
+        let mut bad_padding = encrypted;
+        bad_padding[23] ^= 0xff;
+        assert_eq!(decrypt(bad_padding, KEY), None);
+
+        assert_eq!(decrypt(encrypted, &KEY[..15]), None);
+        assert_eq!(decrypt(&encrypted[..20], KEY), None);
+    }
+
+    #[test]
+    fn test_decrypt_padding_too_long() {
+        // 7 bytes of padding do not fit in 2 blocks.
+        let mut data = [0u8; 16];
+        data[0] = 0b111;
+        encrypt_blocks(&mut data, &parse_key(KEY).unwrap());
+        assert_eq!(decrypt(data, KEY), None);
+    }
+
+    #[test]
+    fn test_encrypt() {
+        for len in 0..48 {
+            let plaintext: Vec<u8> = (0..len).map(|i| (i * 7) as u8).collect();
+            let encrypted = encrypt(&plaintext, KEY).unwrap();
+            assert_eq!(encrypted.len() % 8, 0);
+            assert_eq!(
+                tc_tea::decrypt(&encrypted, KEY).as_deref(),
+                Some(&plaintext[..])
+            );
+            assert_eq!(decrypt(&encrypted, KEY).as_deref(), Some(&plaintext[..]));
+        }
+
+        assert_eq!(encrypt([1, 2, 3], &KEY[..15]), None);
+    }
+
+    #[test]
+    fn test_same_as_tc_tea_crate() {
+        for len in 0..48 {
+            let plaintext: Vec<u8> = (0..len).map(|i| (i * 7) as u8).collect();
+            let encrypted = tc_tea::encrypt(&plaintext, KEY).unwrap();
+            assert_eq!(decrypt(encrypted, KEY).as_deref(), Some(&plaintext[..]));
+        }
+    }
+}
@@ -0,0 +1,86 @@
+use crate::utils::audio::AudioType;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CipherVersion {
+    /// Static map cipher, see [`QMCv1`](crate::crypto::tencent::QMCv1).
+    QMCv1,
+    /// Keyed map or RC4 cipher, see [`QMCv2`](crate::crypto::tencent::QMCv2).
+    QMCv2,
+    /// Extensions shared by QMCv1 files and QMCv2 files (with the key in the tail):
+    /// check for a tail first, and fall back to QMCv1.
+    Ambiguous,
+}
+
+/// A known encrypted file extension, and the audio container it decrypts to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileVariant {
+    /// Lowercase file extension, without the leading dot.
+    pub extension: &'static str,
+    pub cipher: CipherVersion,
+    /// `None` if the container is not known, detect it after decryption.
+    pub audio_type: Option<AudioType>,
+}
+
+macro_rules! variant {
+    ($ext:literal, $cipher:ident) => {
+        FileVariant {
+            extension: $ext,
+            cipher: CipherVersion::$cipher,
+            audio_type: None,
+        }
+    };
+    ($ext:literal, $cipher:ident, $audio_type:ident) => {
+        FileVariant {
+            extension: $ext,
+            cipher: CipherVersion::$cipher,
+            audio_type: Some(AudioType::$audio_type),
+        }
+    };
+}
+
+pub const FILE_VARIANTS: &[FileVariant] = &[
+    variant!("qmc0", Ambiguous, Mp3),
+    variant!("qmc2", Ambiguous, Ogg),
+    variant!("qmc3", Ambiguous, Mp3),
+    variant!("qmc4", Ambiguous),
+    variant!("qmc6", Ambiguous),
+    variant!("qmc8", Ambiguous),
+    variant!("qmcflac", Ambiguous, Flac),
+    variant!("qmcogg", Ambiguous, Ogg),
+    variant!("tkm", QMCv1, M4a),
+    variant!("bkcmp3", Ambiguous, Mp3),
+    variant!("bkcm4a", Ambiguous, M4a),
+    variant!("bkcflac", Ambiguous, Flac),
+    variant!("bkcwav", Ambiguous, Wav),
+    variant!("bkcape", Ambiguous, Ape),
+    variant!("bkcogg", Ambiguous, Ogg),
+    variant!("bkcwma", Ambiguous, Wma),
+    variant!("mflac", QMCv2, Flac),
+    variant!("mflac0", QMCv2, Flac),
+    variant!("mgg", QMCv2, Ogg),
+    variant!("mgg0", QMCv2, Ogg),
+    variant!("mgg1", QMCv2, Ogg),
+    variant!("mggl", QMCv2, Ogg),
+    variant!("mmp4", QMCv2, M4a),
+];
+
+/// Lookup a file variant by its extension (case-insensitive, leading dot is optional).
+///
+/// # Examples
+///
+/// ```
+/// use parakeet_crypto::crypto::tencent::variants::{find_variant, CipherVersion};
+/// use parakeet_crypto::utils::audio::AudioType;
+///
+/// let variant = find_variant(".qmcFLAC").unwrap();
+/// assert_eq!(variant.cipher, CipherVersion::Ambiguous);
+/// assert_eq!(variant.audio_type, Some(AudioType::Flac));
+/// assert_eq!(find_variant("mp3"), None);
+/// ```
+pub fn find_variant<S: AsRef<str>>(extension: S) -> Option<&'static FileVariant> {
+    let extension = extension.as_ref();
+    let extension = extension.strip_prefix('.').unwrap_or(extension);
+    FILE_VARIANTS
+        .iter()
+        .find(|v| v.extension.eq_ignore_ascii_case(extension))
+}
@@ -1,4 +1,35 @@
 use crate::crypto::ximalaya_android::keys::{ContentKey, ScrambleTable, SCRAMBLED_HEADER_LEN};
+use crate::crypto::ximalaya_android::Error;
+
+fn validate_scramble_table(scramble_table: &ScrambleTable) -> Result<(), Error> {
+    match scramble_table
+        .iter()
+        .find(|&&idx| idx >= SCRAMBLED_HEADER_LEN)
+    {
+        Some(&idx) => Err(Error::ScrambleIndexOutOfRange(idx)),
+        None => Ok(()),
+    }
+}
+
+/// Same as [`decrypt_header`], but rejects scramble tables with out-of-range indexes.
+pub fn try_decrypt_header(
+    header: &[u8; SCRAMBLED_HEADER_LEN],
+    content_key: &ContentKey,
+    scramble_table: &ScrambleTable,
+) -> Result<[u8; SCRAMBLED_HEADER_LEN], Error> {
+    validate_scramble_table(scramble_table)?;
+    Ok(decrypt_header(header, content_key, scramble_table))
+}
+
+/// Same as [`encrypt_header`], but rejects scramble tables with out-of-range indexes.
+pub fn try_encrypt_header(
+    header: [u8; SCRAMBLED_HEADER_LEN],
+    content_key: &ContentKey,
+    scramble_table: &ScrambleTable,
+) -> Result<[u8; SCRAMBLED_HEADER_LEN], Error> {
+    validate_scramble_table(scramble_table)?;
+    Ok(encrypt_header(header, content_key, scramble_table))
+}
 
 pub fn decrypt_header(
     header: &[u8; SCRAMBLED_HEADER_LEN],
@@ -29,3 +60,26 @@ pub fn encrypt_header(
 
     encrypted
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::ximalaya_android::keys::{get_key, Type};
+
+    #[test]
+    fn test_try_decrypt_header() {
+        let (content_key, scramble_table) = get_key(Type::X2M);
+        let header = [0x55u8; SCRAMBLED_HEADER_LEN];
+
+        let encrypted = try_encrypt_header(header, content_key, scramble_table).unwrap();
+        let decrypted = try_decrypt_header(&encrypted, content_key, scramble_table).unwrap();
+        assert_eq!(decrypted, header);
+
+        let mut bad_table = *scramble_table;
+        bad_table[123] = SCRAMBLED_HEADER_LEN;
+        assert_eq!(
+            try_decrypt_header(&header, content_key, &bad_table),
+            Err(Error::ScrambleIndexOutOfRange(SCRAMBLED_HEADER_LEN))
+        );
+    }
+}
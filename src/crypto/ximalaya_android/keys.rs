@@ -1,8 +1,9 @@
-use lazy_static::lazy_static;
+use crate::crypto::ximalaya_android::Error;
 
 pub const SCRAMBLED_HEADER_LEN: usize = 0x400;
 
 #[derive(Debug, PartialEq, Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub enum Type {
     X2M,
     X3M,
@@ -11,6 +12,26 @@ pub enum Type {
 pub type ContentKey = [u8; 32];
 pub type ScrambleTable = [usize; SCRAMBLED_HEADER_LEN];
 
+/// Same as [`gen_scramble_table`], but rejects parameters that could make the
+/// sequence diverge, instead of panicking.
+///
+/// `initial` should be within `(0, 1)` and `multiplier` within `(0, 4]`.
+pub fn try_gen_scramble_table<const N: usize>(
+    initial: f64,
+    multiplier: f64,
+) -> Result<[usize; N], Error> {
+    let initial_valid = initial > 0.0 && initial < 1.0;
+    let multiplier_valid = multiplier > 0.0 && multiplier <= 4.0;
+    if !initial_valid || !multiplier_valid {
+        Err(Error::InvalidScrambleParameters(initial, multiplier))?;
+    }
+
+    Ok(gen_scramble_table(initial, multiplier))
+}
+
+/// # Panics
+///
+/// Panics if the parameters make the sequence diverge; see [`try_gen_scramble_table`].
 pub fn gen_scramble_table<const N: usize>(initial: f64, multiplier: f64) -> [usize; N] {
     let mut scramble_key = [0; N];
     let mut values = [0f64; N];
@@ -39,23 +60,28 @@ pub fn gen_scramble_table<const N: usize>(initial: f64, multiplier: f64) -> [usi
     scramble_key
 }
 
-// X2M for Ximalaya Android (Legacy format)
-lazy_static! {
-    pub static ref X2M_CONTENT_KEY: ContentKey = {
-        let mut result = [0u8; 32];
-        result
-            .chunks_exact_mut(4)
-            .for_each(|chunk| chunk.copy_from_slice(b"xmly"));
-        result
-    };
-    pub static ref X2M_SCRAMBLE_TABLE: ScrambleTable = gen_scramble_table(0.615243, 3.837465);
+/// Load a pre-computed scramble table (`u16` LE).
+const fn load_scramble_table(data: &[u8; SCRAMBLED_HEADER_LEN * 2]) -> ScrambleTable {
+    let mut result = [0; SCRAMBLED_HEADER_LEN];
+    let mut i = 0;
+    while i < SCRAMBLED_HEADER_LEN {
+        result[i] = u16::from_le_bytes([data[i * 2], data[i * 2 + 1]]) as usize;
+        i += 1;
+    }
+    result
 }
 
+// X2M for Ximalaya Android (Legacy format)
+pub const X2M_CONTENT_KEY: ContentKey = *b"xmlyxmlyxmlyxmlyxmlyxmlyxmlyxmly";
+/// `gen_scramble_table(0.615243, 3.837465)`
+pub static X2M_SCRAMBLE_TABLE: ScrambleTable =
+    load_scramble_table(include_bytes!("data/x2m_scramble_table.bin"));
+
 // X3M for Ximalaya Android
-lazy_static! {
-    pub static ref X3M_CONTENT_KEY: ContentKey = *b"3989d111aad5613940f4fc44b639b292";
-    pub static ref X3M_SCRAMBLE_TABLE: ScrambleTable = gen_scramble_table(0.726354, 3.948576);
-}
+pub const X3M_CONTENT_KEY: ContentKey = *b"3989d111aad5613940f4fc44b639b292";
+/// `gen_scramble_table(0.726354, 3.948576)`
+pub static X3M_SCRAMBLE_TABLE: ScrambleTable =
+    load_scramble_table(include_bytes!("data/x3m_scramble_table.bin"));
 
 pub fn get_key(key_type: Type) -> (&'static ContentKey, &'static ScrambleTable) {
     match key_type {
@@ -67,10 +93,44 @@ pub fn get_key(key_type: Type) -> (&'static ContentKey, &'static ScrambleTable)
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::utils::fuzz::Lcg;
+
+    #[test]
+    fn test_precomputed_scramble_tables() {
+        assert_eq!(X2M_SCRAMBLE_TABLE, gen_scramble_table(0.615243, 3.837465));
+        assert_eq!(X3M_SCRAMBLE_TABLE, gen_scramble_table(0.726354, 3.948576));
+    }
 
     #[test]
     fn test_scramble_key() {
         let expected = [1, 3, 2, 4, 0];
         assert_eq!(expected, gen_scramble_table(0.334455, 3.998877));
     }
+
+    #[test]
+    fn test_try_gen_scramble_table() {
+        assert_eq!(
+            try_gen_scramble_table::<5>(0.334455, 3.998877),
+            Ok([1, 3, 2, 4, 0])
+        );
+
+        for (initial, multiplier) in [
+            (0.0, 3.9),
+            (1.0, 3.9),
+            (-0.5, 3.9),
+            (0.5, 4.5),
+            (0.5, 0.0),
+            (f64::NAN, 3.9),
+            (0.5, f64::INFINITY),
+        ] {
+            assert!(try_gen_scramble_table::<16>(initial, multiplier).is_err());
+        }
+
+        let mut rng = Lcg::new(0x2468ACE);
+        for _ in 0..64 {
+            let initial = (rng.below(9999) + 1) as f64 / 10000.0;
+            let multiplier = (rng.below(4000) + 1) as f64 / 1000.0;
+            let _ = try_gen_scramble_table::<64>(initial, multiplier);
+        }
+    }
 }
@@ -1,3 +1,14 @@
+use thiserror::Error;
+
 mod cipher;
 pub mod keys;
-pub use cipher::{decrypt_header, encrypt_header};
+pub use cipher::{decrypt_header, encrypt_header, try_decrypt_header, try_encrypt_header};
+
+#[derive(Debug, Error, PartialEq)]
+pub enum Error {
+    #[error("Scramble table index out of range: {0}")]
+    ScrambleIndexOutOfRange(usize),
+
+    #[error("Invalid scramble table parameters: initial={0}, multiplier={1}")]
+    InvalidScrambleParameters(f64, f64),
+}
@@ -1,3 +1,5 @@
+use alloc::vec::Vec;
+
 use crate::crypto::ximalaya_pc::{Error, Header};
 use aes::cipher::block_padding::Pkcs7;
 use aes::cipher::{BlockDecryptMut, KeyIvInit};
@@ -1,6 +1,12 @@
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec::Vec;
+
 use base64::{engine::general_purpose::STANDARD as Base64, Engine as _};
 use byteorder::{ByteOrder, BE};
-use std::str::FromStr;
+use core::str::FromStr;
+
+use crate::utils::media_extras::{Cover, MediaExtras};
 
 fn parse_safe_sync_u32(v: u32) -> u32 {
     let a = v & 0x00_00_00_7f;
@@ -19,17 +25,90 @@ fn from_utf16_le(data: &[u8]) -> Vec<u8> {
         .collect()
 }
 
+/// Split an ID3v2 string terminated according to its `encoding`; returns the string
+/// and the rest of the data.
+fn split_id3_string(encoding: u8, data: &[u8]) -> (&[u8], &[u8]) {
+    let end = match encoding {
+        1 | 2 => data
+            .chunks_exact(2)
+            .position(|c| c == [0, 0])
+            .map(|i| (i * 2, i * 2 + 2)),
+        _ => data.iter().position(|&c| c == 0).map(|i| (i, i + 1)),
+    };
+    match end {
+        Some((end, rest)) => (&data[..end], &data[rest..]),
+        None => (data, &[]),
+    }
+}
+
+/// Decode an ID3v2 string: ISO-8859-1 (`0`), UTF-16 with BOM (`1`), UTF-16BE (`2`)
+/// or UTF-8 (`3`).
+fn decode_id3_string(encoding: u8, data: &[u8]) -> String {
+    match encoding {
+        1 | 2 => {
+            let (little_endian, data) = match data {
+                [0xFF, 0xFE, rest @ ..] => (true, rest),
+                [0xFE, 0xFF, rest @ ..] => (false, rest),
+                _ => (false, data),
+            };
+            let units = data.chunks_exact(2).map(|c| match little_endian {
+                true => u16::from_le_bytes([c[0], c[1]]),
+                false => u16::from_be_bytes([c[0], c[1]]),
+            });
+            char::decode_utf16(units)
+                .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+                .collect()
+        }
+        3 => String::from_utf8_lossy(data).into(),
+        _ => data.iter().map(|&c| char::from(c)).collect(),
+    }
+}
+
+/// Text of a `T***` frame, `None` if empty.
+fn parse_text_frame(frame: &[u8]) -> Option<String> {
+    let (&encoding, data) = frame.split_first()?;
+    let (text, _) = split_id3_string(encoding, data);
+    Some(decode_id3_string(encoding, text)).filter(|text| !text.is_empty())
+}
+
+/// Picture type and picture of an `APIC` frame.
+fn parse_picture_frame(frame: &[u8]) -> Option<(u8, Cover)> {
+    let (&encoding, data) = frame.split_first()?;
+    let (mime, data) = split_id3_string(0, data);
+    let (&picture_type, data) = data.split_first()?;
+    let (_description, data) = split_id3_string(encoding, data);
+    if data.is_empty() {
+        return None;
+    }
+    let mime = decode_id3_string(0, mime);
+    Some((picture_type, Cover::new(Some(&mime), data)))
+}
+
+/// `APIC` picture type of the front cover.
+const PICTURE_TYPE_FRONT_COVER: u8 = 3;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct Header {
     pub data_start_offset: usize,
     pub encrypted_header_len: usize,
+    #[cfg_attr(feature = "serde", serde(with = "crate::utils::serde_hex"))]
     pub stage_1_iv: [u8; 16],
     /// aes-192, key length = 24-bytes (first 16 byte is also re-used as its iv)
+    #[cfg_attr(feature = "serde", serde(with = "crate::utils::serde_hex"))]
     pub stage_2_key: [u8; 24],
+    #[cfg_attr(feature = "serde", serde(with = "crate::utils::serde_hex"))]
     pub stolen_header_bytes: Box<[u8]>,
+    /// Title, artist, album and cover from the `TIT2`, `TPE1`, `TALB` and `APIC` frames.
+    pub extras: MediaExtras,
 }
 
 const MAGIC_ID3: [u8; 3] = *b"ID3";
 
+/// Largest `TSIZ` (encrypted part) accepted, well above the ones seen in the wild.
+/// The encrypted part is read in memory, so this bounds the allocation.
+pub const MAX_ENCRYPTED_HEADER_LEN: usize = 16 * 1024 * 1024;
+
 impl Header {
     pub fn from_bytes<T: AsRef<[u8]>>(data: T) -> Result<Self, super::Error> {
         let data = data.as_ref();
@@ -53,6 +132,7 @@ impl Header {
             stage_1_iv: [0u8; 16],
             stage_2_key: [0u8; 24],
             stolen_header_bytes: Box::new([]),
+            extras: MediaExtras::default(),
         };
 
         while offset < data_start_offset {
@@ -75,7 +155,8 @@ impl Header {
             // src: https://web.archive.org/web/2020/https://id3.org/id3v2.3.0#ID3v2_frame_overview
             // > If ISO-8859-1 is used this byte should be $00, if Unicode is used it should be $01.
             // > Unicode strings must begin with the Unicode BOM ($FF FE or $FE FF) to identify the byte order.
-            let tag_data = &data[offset + 3..offset + tag_size];
+            let frame_data = &data[offset..offset + tag_size];
+            let tag_data = frame_data.get(3..).unwrap_or_default();
             offset += tag_size;
 
             match tag_name {
@@ -84,7 +165,11 @@ impl Header {
                     let tag_len_str = String::from_utf8_lossy(tag_len_str.as_slice());
                     let header_len = u32::from_str(&tag_len_str)
                         .map_err(super::Error::DeserializeHeaderValueInt)?;
-                    result.encrypted_header_len = header_len as usize;
+                    let header_len = header_len as usize;
+                    if header_len > MAX_ENCRYPTED_HEADER_LEN {
+                        Err(super::Error::InvalidData(offset))?;
+                    }
+                    result.encrypted_header_len = header_len;
                 }
                 b"TSRC" | b"TENC" => {
                     let tag_data = from_utf16_le(tag_data);
@@ -106,12 +191,24 @@ impl Header {
                     let mut tag_data = from_utf16_le(tag_data);
                     let mut key = *b"123456781234567812345678";
                     if tag_data.len() > 24 {
-                        tag_data.drain(..24 - tag_data.len());
+                        tag_data.drain(..tag_data.len() - 24);
                     }
                     let left = key.len() - tag_data.len();
                     key[left..].copy_from_slice(&tag_data);
                     result.stage_2_key = key;
                 }
+                b"TIT2" => result.extras.title = parse_text_frame(frame_data),
+                b"TPE1" => result.extras.artist = parse_text_frame(frame_data),
+                b"TALB" => result.extras.album = parse_text_frame(frame_data),
+                b"APIC" => {
+                    // Prefer the front cover over other pictures.
+                    if let Some((picture_type, cover)) = parse_picture_frame(frame_data) {
+                        if result.extras.cover.is_none() || picture_type == PICTURE_TYPE_FRONT_COVER
+                        {
+                            result.extras.cover = Some(cover);
+                        }
+                    }
+                }
                 _ => {
                     // ignored
                 }
@@ -121,3 +218,118 @@ impl Header {
         Ok(result)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+
+    use super::*;
+
+    fn frame(name: &[u8; 4], data: &[u8]) -> Vec<u8> {
+        let mut frame = name.to_vec();
+        frame.extend((data.len() as u32).to_be_bytes());
+        frame.extend([0, 0]);
+        frame.extend(data);
+        frame
+    }
+
+    fn utf16_frame(name: &[u8; 4], value: &str) -> Vec<u8> {
+        let mut data = vec![0x01, 0xff, 0xfe];
+        data.extend(value.encode_utf16().flat_map(|c| c.to_le_bytes()));
+        frame(name, &data)
+    }
+
+    #[test]
+    fn test_extras() {
+        let back_cover = frame(b"APIC", b"\0image/png\0\x04\0\x89PNG");
+        let front_cover = frame(
+            b"APIC",
+            b"\x01\0\x03\xff\xfec\0o\0v\0e\0r\0\0\0\xFF\xD8\xFF\xE0",
+        );
+        let frames = [
+            utf16_frame(b"TIT2", "标题"),
+            utf16_frame(b"TPE1", "artist"),
+            frame(b"TALB", b"\x03album\0"),
+            back_cover,
+            front_cover,
+            utf16_frame(b"TSIZ", "1024"),
+        ]
+        .concat();
+
+        let hdr = Header::from_bytes(id3_file(&frames)).unwrap();
+        assert_eq!(hdr.encrypted_header_len, 1024);
+        assert_eq!(hdr.extras.title.as_deref(), Some("标题"));
+        assert_eq!(hdr.extras.artist.as_deref(), Some("artist"));
+        assert_eq!(hdr.extras.album.as_deref(), Some("album"));
+
+        let cover = hdr.extras.cover.unwrap();
+        assert_eq!(cover.mime, "image/jpeg", "mime guessed from the data");
+        assert_eq!(&cover.data[..], b"\xFF\xD8\xFF\xE0");
+    }
+
+    #[test]
+    fn test_encrypted_header_too_large() {
+        let max_len = MAX_ENCRYPTED_HEADER_LEN.to_string();
+        let hdr = Header::from_bytes(id3_file(&utf16_frame(b"TSIZ", &max_len))).unwrap();
+        assert_eq!(hdr.encrypted_header_len, MAX_ENCRYPTED_HEADER_LEN);
+
+        let too_large = (MAX_ENCRYPTED_HEADER_LEN + 1).to_string();
+        assert!(matches!(
+            Header::from_bytes(id3_file(&utf16_frame(b"TSIZ", &too_large))),
+            Err(crate::crypto::ximalaya_pc::Error::InvalidData(_))
+        ));
+    }
+
+    fn id3_file(frames: &[u8]) -> Vec<u8> {
+        let mut file = b"ID3\x03\x00\x00".to_vec();
+        file.extend(syncsafe_u32(frames.len() as u32).to_be_bytes());
+        file.extend(frames);
+        file
+    }
+
+    #[test]
+    fn test_long_track() {
+        let file = id3_file(&utf16_frame(
+            b"TRCK",
+            "0123456789abcdefghijklmnopqrstuvwxyz",
+        ));
+        let hdr = Header::from_bytes(file).unwrap();
+        assert_eq!(&hdr.stage_2_key, b"cdefghijklmnopqrstuvwxyz");
+    }
+
+    #[test]
+    fn test_random_frames() {
+        use crate::utils::fuzz::Lcg;
+
+        const NAMES: [&[u8; 4]; 8] = [
+            b"TSIZ", b"TSRC", b"TENC", b"TSSE", b"TRCK", b"TIT2", b"APIC", b"XXXX",
+        ];
+        let mut rng = Lcg::new(0x58_4D);
+        for _ in 0..5000 {
+            let frames = (0..rng.below(4))
+                .flat_map(|_| {
+                    let name = NAMES[rng.below(NAMES.len())];
+                    match rng.below(2) {
+                        0 => frame(name, &rng.input(64)),
+                        _ => utf16_frame(name, &String::from_utf8_lossy(&rng.input(48))),
+                    }
+                })
+                .collect::<Vec<_>>();
+            let mut file = id3_file(&frames);
+            // Truncated or corrupted headers.
+            match rng.below(4) {
+                0 => file.truncate(rng.below(file.len() + 1)),
+                1 => {
+                    let pos = rng.below(file.len());
+                    file[pos] = rng.next_u8();
+                }
+                _ => {}
+            }
+            let _ = Header::from_bytes(&file);
+        }
+    }
+
+    fn syncsafe_u32(value: u32) -> u32 {
+        (value & 0x7f) | (value & 0x3f80) << 1 | (value & 0x1fc000) << 2
+    }
+}
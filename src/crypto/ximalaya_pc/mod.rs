@@ -1,13 +1,13 @@
 use aes::cipher::block_padding::UnpadError;
+use core::num::ParseIntError;
 use hex::FromHexError;
-use std::num::ParseIntError;
 use thiserror::Error;
 
 mod cipher;
 mod header;
 
 pub use cipher::decipher_part_2;
-pub use header::Header;
+pub use header::{Header, MAX_ENCRYPTED_HEADER_LEN};
 
 #[derive(Debug, Error)]
 pub enum Error {
@@ -21,13 +21,13 @@ pub enum Error {
     UnexpectedHeaderEof(usize),
 
     #[error("Could not deserialize an integer: {0}")]
-    DeserializeHeaderValueInt(ParseIntError),
+    DeserializeHeaderValueInt(#[source] ParseIntError),
 
     #[error("Could not deserialize a hex str to vec: {0}")]
-    DeserializeHeaderValueHex(FromHexError),
+    DeserializeHeaderValueHex(#[cfg_attr(feature = "std", source)] FromHexError),
 
     #[error("Could not deserialize a base64 str to vec: {0}")]
-    DeserializeHeaderValueBase64(base64::DecodeError),
+    DeserializeHeaderValueBase64(#[cfg_attr(feature = "std", source)] base64::DecodeError),
 
     #[error("Failed to parse at offset: {0}")]
     InvalidData(usize),
@@ -36,11 +36,11 @@ pub enum Error {
     Stage1PadError(UnpadError),
 
     #[error("Failed to decrypt data (stage 1, b64 decode)")]
-    Stage1CipherDecodeError(base64::DecodeError),
+    Stage1CipherDecodeError(#[cfg_attr(feature = "std", source)] base64::DecodeError),
 
     #[error("Failed to decrypt data (stage 2, pkcs#7 padding error): {0}")]
     Stage2PadError(UnpadError),
 
     #[error("Failed to decrypt data (stage 2, b64 decode)")]
-    Stage2CipherDecodeError(base64::DecodeError),
+    Stage2CipherDecodeError(#[cfg_attr(feature = "std", source)] base64::DecodeError),
 }
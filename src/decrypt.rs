@@ -0,0 +1,465 @@
+//! Decrypt whole files, once their format is known (see [`crate::detect`]).
+//!
+//! [`decrypt`] reads the encrypted file from `src`, and writes the decrypted audio to `dst`.
+//! `progress` is called after each block of audio, with the number of audio bytes processed
+//! so far.
+
+use std::fs::{self, File};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use thiserror::Error;
+
+#[cfg(any(feature = "tencent", feature = "kugou", feature = "kuwo"))]
+use crate::crypto::byte_offset_cipher::ByteOffsetDecipher;
+use crate::crypto::byte_offset_cipher::StreamProgress;
+#[cfg(feature = "kugou")]
+use crate::crypto::kugou;
+#[cfg(feature = "kuwo")]
+use crate::crypto::kuwo::{
+    header::{KuwoHeader, HEADER_FIXED_LEN},
+    KuwoBuilder,
+};
+#[cfg(feature = "tencent")]
+use crate::crypto::tencent::{self, ekey, metadata::TailParseResult, QMCv1, QMCv2};
+#[cfg(feature = "ximalaya-android")]
+use crate::crypto::ximalaya_android::{
+    self,
+    keys::{Type as XimalayaType, SCRAMBLED_HEADER_LEN},
+};
+#[cfg(feature = "ximalaya-pc")]
+use crate::crypto::ximalaya_pc;
+use crate::detect::{self as detect_format, Format};
+#[cfg(any(feature = "kugou", feature = "ximalaya-pc"))]
+use crate::detect::{read_at, DETECTION_HEADER_LEN};
+use crate::Error;
+
+#[cfg(any(
+    feature = "tencent",
+    feature = "kugou",
+    feature = "kuwo",
+    feature = "ximalaya-android",
+    feature = "ximalaya-pc"
+))]
+const DECRYPTION_BUFFER_SIZE: usize = 2 * 1024 * 1024;
+
+/// Lookup an ekey by name, for files that do not include their key.
+///
+/// QMCv2 files are looked up by media file name, `mid` or file name; KWMv2 files by
+/// their mmkv key (`sec_ekey#<rid>-<quality>`).
+pub type KeyStore<'a> = dyn Fn(&str) -> Option<Box<[u8]>> + 'a;
+
+#[derive(Debug, Error)]
+pub enum DecryptError {
+    #[error(transparent)]
+    Parakeet(Error),
+    #[error("unsupported file format (or the file is not encrypted)")]
+    UnsupportedFormat,
+    #[error("key is required, not found in the key store (tried: {0:?})")]
+    KeyRequired(Vec<String>),
+    #[error("output is the input file")]
+    OutputIsInput,
+}
+
+impl<E: Into<Error>> From<E> for DecryptError {
+    fn from(error: E) -> Self {
+        Self::Parakeet(error.into())
+    }
+}
+
+#[cfg(any(feature = "tencent", feature = "kugou", feature = "kuwo"))]
+fn decrypt_audio<C, R, W, G>(
+    cipher: C,
+    src: &mut R,
+    dst: &mut W,
+    max_read: Option<usize>,
+    progress: G,
+) -> Result<usize, DecryptError>
+where
+    C: ByteOffsetDecipher,
+    R: Read + ?Sized,
+    W: Write + ?Sized,
+    G: FnMut(StreamProgress),
+{
+    let mut buffer = vec![0u8; DECRYPTION_BUFFER_SIZE];
+    let mut dst_write_error = Ok(());
+    let bytes_written = cipher.decipher_stream_with_progress(
+        &mut buffer,
+        0,
+        src,
+        max_read,
+        |block| {
+            dst_write_error = dst.write_all(block);
+            dst_write_error.as_ref().into()
+        },
+        progress,
+    )?;
+    dst_write_error?;
+    Ok(bytes_written)
+}
+
+/// Copy the rest of `src`, which is not encrypted.
+#[cfg(any(feature = "ximalaya-android", feature = "ximalaya-pc"))]
+fn copy_audio<R, W, G>(
+    src: &mut R,
+    dst: &mut W,
+    mut processed: usize,
+    mut progress: G,
+) -> Result<usize, DecryptError>
+where
+    R: Read + ?Sized,
+    W: Write + ?Sized,
+    G: FnMut(StreamProgress),
+{
+    let mut buffer = vec![0u8; DECRYPTION_BUFFER_SIZE];
+    loop {
+        progress(StreamProgress {
+            processed,
+            total: None,
+        });
+        let read_len = src.read(&mut buffer)?;
+        if read_len == 0 {
+            break;
+        }
+        dst.write_all(&buffer[..read_len])?;
+        processed += read_len;
+    }
+    Ok(processed)
+}
+
+/// Names to lookup in the key store when the tail does not include a key.
+#[cfg(feature = "tencent")]
+fn qmc2_key_names(tail: &TailParseResult, file_name: Option<&str>) -> Vec<String> {
+    let mut names = match tail {
+        TailParseResult::PcMusicEx(m) => vec![m.media_filename.clone(), m.mid.clone()],
+        TailParseResult::AndroidSTag(m) => vec![m.media_mid.clone()],
+        _ => vec![],
+    };
+    names.extend(file_name.map(String::from));
+    names.retain(|name| !name.is_empty());
+    names
+}
+
+#[cfg(feature = "tencent")]
+fn decrypt_qmc2<R, W, G>(
+    src: &mut R,
+    dst: &mut W,
+    file_name: Option<&str>,
+    key_store: Option<&KeyStore>,
+    progress: G,
+) -> Result<usize, DecryptError>
+where
+    R: Read + Seek + ?Sized,
+    W: Write + ?Sized,
+    G: FnMut(StreamProgress),
+{
+    let (tail, payload_len) = tencent::parse_tail_from_reader(src)?;
+    let key = match tail.get_key() {
+        Some(key) => Box::from(key),
+        None => {
+            let names = qmc2_key_names(&tail, file_name);
+            let ekey = key_store.and_then(|key_store| names.iter().find_map(|n| key_store(n)));
+            ekey::decrypt(ekey.ok_or(DecryptError::KeyRequired(names))?)?
+        }
+    };
+
+    let cipher = QMCv2::try_new(key)?;
+    src.seek(SeekFrom::Start(0))?;
+    decrypt_audio(cipher, src, dst, Some(payload_len as usize), progress)
+}
+
+#[cfg(feature = "kugou")]
+fn decrypt_kugou<R, W, G>(src: &mut R, dst: &mut W, progress: G) -> Result<usize, DecryptError>
+where
+    R: Read + Seek + ?Sized,
+    W: Write + ?Sized,
+    G: FnMut(StreamProgress),
+{
+    let hdr = kugou::Header::from_bytes(read_at(src, 0, DETECTION_HEADER_LEN)?)?;
+    let cipher = kugou::Kugou::new(&hdr)?;
+
+    let file_size = src.seek(SeekFrom::End(0))?;
+    let payload_len = file_size.saturating_sub(hdr.header_len.into()) as usize;
+    cipher.check_len(payload_len)?;
+
+    src.seek(SeekFrom::Start(hdr.header_len.into()))?;
+    decrypt_audio(cipher, src, dst, Some(payload_len), progress)
+}
+
+/// Name of the KWMv2 ekey, as stored in the client's mmkv database.
+#[cfg(feature = "kuwo")]
+pub fn kuwo_key_name(hdr: &KuwoHeader) -> String {
+    format!("sec_ekey#{}-{}", hdr.resource_id, hdr.get_quality_id())
+}
+
+#[cfg(feature = "kuwo")]
+fn decrypt_kuwo<R, W, G>(
+    src: &mut R,
+    dst: &mut W,
+    key_store: Option<&KeyStore>,
+    progress: G,
+) -> Result<usize, DecryptError>
+where
+    R: Read + Seek + ?Sized,
+    W: Write + ?Sized,
+    G: FnMut(StreamProgress),
+{
+    let mut header = vec![0u8; HEADER_FIXED_LEN];
+    src.read_exact(&mut header)?;
+    let kuwo_key_store = |hdr: &KuwoHeader| key_store.and_then(|ks| ks(&kuwo_key_name(hdr)));
+
+    let mut builder = KuwoBuilder::new(header);
+    if key_store.is_some() {
+        builder = builder.with_key_store(&kuwo_key_store);
+    }
+    let (cipher, _) = builder.build()?;
+    decrypt_audio(cipher, src, dst, None, progress)
+}
+
+#[cfg(feature = "ximalaya-android")]
+fn decrypt_ximalaya_android<R, W, G>(
+    key_type: XimalayaType,
+    src: &mut R,
+    dst: &mut W,
+    progress: G,
+) -> Result<usize, DecryptError>
+where
+    R: Read + ?Sized,
+    W: Write + ?Sized,
+    G: FnMut(StreamProgress),
+{
+    let mut hdr = [0u8; SCRAMBLED_HEADER_LEN];
+    src.read_exact(&mut hdr)?;
+
+    let (content_key, scramble_table) = ximalaya_android::keys::get_key(key_type);
+    let hdr = ximalaya_android::decrypt_header(&hdr, content_key, scramble_table);
+    dst.write_all(&hdr)?;
+    copy_audio(src, dst, hdr.len(), progress)
+}
+
+#[cfg(feature = "ximalaya-pc")]
+fn decrypt_ximalaya_pc<R, W, G>(
+    src: &mut R,
+    dst: &mut W,
+    progress: G,
+) -> Result<usize, DecryptError>
+where
+    R: Read + Seek + ?Sized,
+    W: Write + ?Sized,
+    G: FnMut(StreamProgress),
+{
+    let head = read_at(src, 0, DETECTION_HEADER_LEN)?;
+    let hdr = match ximalaya_pc::Header::from_bytes(&head) {
+        Err(ximalaya_pc::Error::InputTooSmall(n, _)) => {
+            ximalaya_pc::Header::from_bytes(read_at(src, 0, n)?)?
+        }
+        result => result?,
+    };
+
+    let mut part_2 = vec![0u8; hdr.encrypted_header_len];
+    src.seek(SeekFrom::Start(hdr.data_start_offset as u64))?;
+    src.read_exact(&mut part_2)?;
+    let part_2 = ximalaya_pc::decipher_part_2(&hdr, &part_2)?;
+
+    dst.write_all(&hdr.stolen_header_bytes)?;
+    dst.write_all(&part_2)?;
+    let processed = hdr.stolen_header_bytes.len() + part_2.len();
+    copy_audio(src, dst, processed, progress)
+}
+
+/// Decrypt a file of the given `format` from `src` to `dst`.
+///
+/// `file_name` and `key_store` are used to lookup the key of files without one, see
+/// [`KeyStore`]. Returns the number of audio bytes written.
+// `file_name` is only used by QMCv2.
+#[cfg_attr(not(feature = "tencent"), allow(unused_variables))]
+#[cfg_attr(
+    not(any(
+        feature = "tencent",
+        feature = "kugou",
+        feature = "kuwo",
+        feature = "ximalaya-android",
+        feature = "ximalaya-pc"
+    )),
+    allow(unreachable_code)
+)]
+pub fn decrypt<R, W, G>(
+    format: Format,
+    src: &mut R,
+    dst: &mut W,
+    file_name: Option<&str>,
+    key_store: Option<&KeyStore>,
+    progress: G,
+) -> Result<usize, DecryptError>
+where
+    R: Read + Seek + ?Sized,
+    W: Write + ?Sized,
+    G: FnMut(StreamProgress),
+{
+    src.seek(SeekFrom::Start(0))?;
+    match format {
+        #[cfg(feature = "tencent")]
+        Format::QMCv1 => decrypt_audio(QMCv1::new(), src, dst, None, progress),
+        #[cfg(feature = "tencent")]
+        Format::QMCv2 => decrypt_qmc2(src, dst, file_name, key_store, progress),
+        #[cfg(feature = "kugou")]
+        Format::Kugou => decrypt_kugou(src, dst, progress),
+        #[cfg(feature = "kuwo")]
+        Format::Kuwo => decrypt_kuwo(src, dst, key_store, progress),
+        #[cfg(feature = "ximalaya-android")]
+        Format::XimalayaAndroid(key_type) => decrypt_ximalaya_android(key_type, src, dst, progress),
+        #[cfg(feature = "ximalaya-pc")]
+        Format::XimalayaPc => decrypt_ximalaya_pc(src, dst, progress),
+    }
+}
+
+/// Detect the format of `path_in` (see [`crate::detect::detect`]), and decrypt it to
+/// `path_out`.
+///
+/// `path_out` must not be `path_in`, and is removed if the decryption fails.
+pub fn decrypt_file<G>(
+    path_in: &Path,
+    path_out: &Path,
+    key_store: Option<&KeyStore>,
+    progress: G,
+) -> Result<Format, DecryptError>
+where
+    G: FnMut(StreamProgress),
+{
+    let mut src = File::open(path_in)?;
+    let extension = path_in.extension().map(|ext| ext.to_string_lossy());
+    let format = detect_format::detect(&mut src, extension.as_deref())?
+        .ok_or(DecryptError::UnsupportedFormat)?;
+
+    // A missing output can't be the input.
+    if let (Ok(input), Ok(output)) = (path_in.canonicalize(), path_out.canonicalize()) {
+        if input == output {
+            Err(DecryptError::OutputIsInput)?;
+        }
+    }
+    let mut dst = File::create(path_out)?;
+
+    let file_name = path_in.file_name().map(|name| name.to_string_lossy());
+    let result = decrypt(
+        format,
+        &mut src,
+        &mut dst,
+        file_name.as_deref(),
+        key_store,
+        progress,
+    )
+    .and_then(|_| Ok(dst.flush()?));
+    if result.is_err() {
+        drop(dst);
+        let _ = fs::remove_file(path_out);
+    }
+    result.map(|_| format)
+}
+
+#[cfg(all(test, any(feature = "tencent", feature = "ximalaya-android")))]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    const SAMPLE: &[u8] = include_bytes!("../sample/test_121529_32kbps.ogg");
+
+    fn decrypt_bytes(
+        format: Format,
+        file: &[u8],
+        key_store: Option<&KeyStore>,
+    ) -> Result<Vec<u8>, DecryptError> {
+        let mut dst = vec![];
+        decrypt(
+            format,
+            &mut Cursor::new(file),
+            &mut dst,
+            Some("song.mflac"),
+            key_store,
+            |_| {},
+        )?;
+        Ok(dst)
+    }
+
+    #[test]
+    #[cfg(feature = "tencent")]
+    fn test_qmc2_key_store() {
+        use crate::crypto::byte_offset_cipher::ByteOffsetEncipher;
+
+        let ekey = b"not an ekey".to_vec();
+        let tail = include_bytes!("crypto/tencent/tail/__fixtures__/ekey_android_stag.bin");
+        let mut file = SAMPLE.to_vec();
+        QMCv1::new().encipher_buffer(0, &mut file);
+        assert_eq!(decrypt_bytes(Format::QMCv1, &file, None).unwrap(), SAMPLE);
+
+        file.extend_from_slice(tail);
+        let error = decrypt_bytes(Format::QMCv2, &file, None).unwrap_err();
+        assert!(matches!(
+            error,
+            DecryptError::KeyRequired(names) if names == ["001y7CaR29k6YP", "song.mflac"]
+        ));
+
+        // The key store is asked for the media mid first.
+        let asked = std::cell::RefCell::new(vec![]);
+        let key_store = |name: &str| {
+            asked.borrow_mut().push(name.to_string());
+            Some(ekey.clone().into())
+        };
+        assert!(decrypt_bytes(Format::QMCv2, &file, Some(&key_store as &KeyStore)).is_err());
+        assert_eq!(asked.into_inner(), ["001y7CaR29k6YP"]);
+    }
+
+    #[test]
+    #[cfg(feature = "ximalaya-android")]
+    fn test_ximalaya_android() {
+        let mut file = vec![];
+        crate::encrypt::encrypt_ximalaya_android(
+            &mut Cursor::new(SAMPLE),
+            &mut file,
+            XimalayaType::X2M,
+            |_| {},
+        )
+        .unwrap();
+
+        let format = Format::XimalayaAndroid(XimalayaType::X2M);
+        assert_eq!(decrypt_bytes(format, &file, None).unwrap(), SAMPLE);
+    }
+
+    #[test]
+    #[cfg(feature = "tencent")]
+    fn test_decrypt_file() {
+        use crate::crypto::byte_offset_cipher::ByteOffsetEncipher;
+
+        let dir = std::env::temp_dir().join(format!("parakeet-decrypt-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path_in = dir.join("song.qmcogg");
+        let path_out = dir.join("song.ogg");
+
+        let mut file = SAMPLE.to_vec();
+        QMCv1::new().encipher_buffer(0, &mut file);
+        fs::write(&path_in, &file).unwrap();
+        assert!(matches!(
+            decrypt_file(&path_in, &path_in, None, |_| {}),
+            Err(DecryptError::OutputIsInput)
+        ));
+        let format = decrypt_file(&path_in, &path_out, None, |_| {}).unwrap();
+        assert_eq!(format, Format::QMCv1);
+        assert_eq!(fs::read(&path_out).unwrap(), SAMPLE);
+
+        // The output is removed on failure.
+        let tail = include_bytes!("crypto/tencent/tail/__fixtures__/ekey_android_stag.bin");
+        file.extend_from_slice(tail);
+        fs::write(&path_in, &file).unwrap();
+        assert!(matches!(
+            decrypt_file(&path_in, &path_out, None, |_| {}),
+            Err(DecryptError::KeyRequired(_))
+        ));
+        assert!(!path_out.exists());
+
+        assert!(matches!(
+            decrypt_file(&dir.join("missing.mflac"), &path_out, None, |_| {}),
+            Err(DecryptError::Parakeet(Error::Io(_)))
+        ));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
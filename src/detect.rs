@@ -0,0 +1,234 @@
+//! Detect the format of an encrypted file.
+
+use std::io::{self, Read, Seek, SeekFrom};
+
+#[cfg(feature = "kugou")]
+use crate::crypto::kugou;
+#[cfg(feature = "kuwo")]
+use crate::crypto::kuwo::header::KuwoHeader;
+#[cfg(feature = "tencent")]
+use crate::crypto::tencent::{self, variants, variants::CipherVersion};
+#[cfg(feature = "ximalaya-android")]
+use crate::crypto::ximalaya_android::{
+    self, keys::Type as XimalayaType, keys::SCRAMBLED_HEADER_LEN,
+};
+#[cfg(feature = "ximalaya-pc")]
+use crate::crypto::ximalaya_pc;
+use crate::utils::audio::{detect_audio_type, is_mp3_frame_sync};
+
+/// Number of bytes read from the start of the file for detection.
+pub const DETECTION_HEADER_LEN: usize = 0x400;
+
+/// Supported file formats, named after the `parakeet_cli` subcommands.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    #[cfg(feature = "tencent")]
+    QMCv1,
+    #[cfg(feature = "tencent")]
+    QMCv2,
+    #[cfg(feature = "kugou")]
+    Kugou,
+    #[cfg(feature = "kuwo")]
+    Kuwo,
+    #[cfg(feature = "ximalaya-android")]
+    XimalayaAndroid(XimalayaType),
+    #[cfg(feature = "ximalaya-pc")]
+    XimalayaPc,
+}
+
+impl Format {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            #[cfg(feature = "tencent")]
+            Format::QMCv1 => "qmc1",
+            #[cfg(feature = "tencent")]
+            Format::QMCv2 => "qmc2",
+            #[cfg(feature = "kugou")]
+            Format::Kugou => "kugou",
+            #[cfg(feature = "kuwo")]
+            Format::Kuwo => "kuwo",
+            #[cfg(feature = "ximalaya-android")]
+            Format::XimalayaAndroid(_) => "ximalaya-android",
+            #[cfg(feature = "ximalaya-pc")]
+            Format::XimalayaPc => "ximalaya-pc",
+        }
+    }
+}
+
+pub(crate) fn read_at<R>(reader: &mut R, offset: u64, len: usize) -> io::Result<Vec<u8>>
+where
+    R: Read + Seek + ?Sized,
+{
+    let mut buffer = Vec::with_capacity(len);
+    reader.seek(SeekFrom::Start(offset))?;
+    reader.take(len as u64).read_to_end(&mut buffer)?;
+    Ok(buffer)
+}
+
+#[cfg(feature = "ximalaya-pc")]
+fn is_ximalaya_pc<R>(reader: &mut R, head: &[u8]) -> bool
+where
+    R: Read + Seek + ?Sized,
+{
+    let hdr = match ximalaya_pc::Header::from_bytes(head) {
+        Err(ximalaya_pc::Error::InputTooSmall(n, _)) => read_at(reader, 0, n)
+            .ok()
+            .and_then(|head| ximalaya_pc::Header::from_bytes(head).ok()),
+        result => result.ok(),
+    };
+
+    // A regular ID3 tag would not have the stolen bytes.
+    hdr.is_some_and(|hdr| hdr.encrypted_header_len > 0 && !hdr.stolen_header_bytes.is_empty())
+}
+
+#[cfg(feature = "ximalaya-android")]
+fn detect_ximalaya_android_type(head: &[u8]) -> Option<XimalayaType> {
+    let head: &[u8; SCRAMBLED_HEADER_LEN] = head.try_into().ok()?;
+    [XimalayaType::X2M, XimalayaType::X3M]
+        .into_iter()
+        .find(|&key_type| {
+            let (content_key, scramble_table) = ximalaya_android::keys::get_key(key_type);
+            let hdr = ximalaya_android::decrypt_header(head, content_key, scramble_table);
+            detect_audio_type(hdr).is_some()
+        })
+}
+
+/// Detect the format of an encrypted file.
+///
+/// The file content (header magic, tail) is checked first, followed by the file
+/// `extension` (without the leading dot), and finally by decrypting the first few
+/// bytes with each static key.
+///
+/// Returns `None` for unknown or unencrypted files.
+/// The stream position is unspecified after this call.
+#[cfg_attr(
+    not(any(feature = "tencent", feature = "ximalaya-android")),
+    allow(unused_variables)
+)]
+pub fn detect<R>(reader: &mut R, extension: Option<&str>) -> io::Result<Option<Format>>
+where
+    R: Read + Seek + ?Sized,
+{
+    let head = read_at(reader, 0, DETECTION_HEADER_LEN)?;
+
+    #[cfg(feature = "kugou")]
+    if kugou::Header::from_bytes(&head).is_ok() {
+        return Ok(Some(Format::Kugou));
+    }
+    #[cfg(feature = "kuwo")]
+    if KuwoHeader::from_bytes(&head).is_ok() {
+        return Ok(Some(Format::Kuwo));
+    }
+    #[cfg(feature = "ximalaya-pc")]
+    if is_ximalaya_pc(reader, &head) {
+        return Ok(Some(Format::XimalayaPc));
+    }
+    // A bare MP3 frame sync (11 bits) is too weak to rule out encrypted audio with a tail.
+    let is_audio = detect_audio_type(&head).is_some();
+    if is_audio && !is_mp3_frame_sync(&head) {
+        return Ok(None);
+    }
+    #[cfg(feature = "tencent")]
+    if tencent::parse_tail_from_reader(reader).is_ok() {
+        return Ok(Some(Format::QMCv2));
+    }
+    if is_audio {
+        return Ok(None);
+    }
+
+    let extension = extension.unwrap_or_default().to_lowercase();
+    #[cfg(feature = "tencent")]
+    if let Some(variant) = variants::find_variant(&extension) {
+        return Ok(Some(match variant.cipher {
+            // The tail was checked above.
+            CipherVersion::QMCv1 | CipherVersion::Ambiguous => Format::QMCv1,
+            CipherVersion::QMCv2 => Format::QMCv2,
+        }));
+    }
+    #[cfg(feature = "ximalaya-android")]
+    match extension.as_str() {
+        "x2m" => return Ok(Some(Format::XimalayaAndroid(XimalayaType::X2M))),
+        "x3m" => return Ok(Some(Format::XimalayaAndroid(XimalayaType::X3M))),
+        _ => {}
+    }
+
+    #[cfg(feature = "ximalaya-android")]
+    if let Some(key_type) = detect_ximalaya_android_type(&head) {
+        return Ok(Some(Format::XimalayaAndroid(key_type)));
+    }
+    #[cfg(feature = "tencent")]
+    {
+        use crate::crypto::byte_offset_cipher::ByteOffsetDecipher;
+
+        let mut head = head;
+        tencent::QMCv1::new().decipher_buffer(0, &mut head);
+        if detect_audio_type(head).is_some() {
+            return Ok(Some(Format::QMCv1));
+        }
+    }
+
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    const SAMPLE: &[u8] = include_bytes!("../sample/test_121529_32kbps.ogg");
+
+    fn detect_bytes(data: &[u8], extension: Option<&str>) -> Option<Format> {
+        detect(&mut Cursor::new(data), extension).unwrap()
+    }
+
+    #[test]
+    fn test_detect_plain_audio() {
+        assert_eq!(detect_bytes(SAMPLE, Some("mgg")), None);
+        assert_eq!(detect_bytes(b"", None), None);
+    }
+
+    #[test]
+    #[cfg(feature = "kugou")]
+    fn test_detect_kugou() {
+        let file = include_bytes!("../sample/test_kgm_v3.vpr");
+        assert_eq!(detect_bytes(file, None), Some(Format::Kugou));
+    }
+
+    #[test]
+    #[cfg(feature = "tencent")]
+    fn test_detect_qmc() {
+        use crate::crypto::byte_offset_cipher::ByteOffsetEncipher;
+
+        let mut file = SAMPLE.to_vec();
+        tencent::QMCv1::new().encipher_buffer(0, &mut file);
+        assert_eq!(detect_bytes(&file, None), Some(Format::QMCv1));
+        assert_eq!(detect_bytes(&file, Some("mflac")), Some(Format::QMCv2));
+        assert_eq!(detect_bytes(&file, Some("qmc4")), Some(Format::QMCv1));
+
+        let tail = include_bytes!("crypto/tencent/tail/__fixtures__/ekey_android_stag.bin");
+        file.extend_from_slice(tail);
+        assert_eq!(detect_bytes(&file, Some("qmcogg")), Some(Format::QMCv2));
+        assert_eq!(detect_bytes(&file, Some("bkcogg")), Some(Format::QMCv2));
+
+        // Encrypted audio that happens to start with an MP3 frame sync.
+        file[..2].copy_from_slice(&[0xFF, 0xFB]);
+        assert_eq!(detect_bytes(&file, None), Some(Format::QMCv2));
+        let len = file.len() - tail.len();
+        assert_eq!(detect_bytes(&file[..len], Some("mflac")), None);
+    }
+
+    #[test]
+    #[cfg(feature = "ximalaya-android")]
+    fn test_detect_ximalaya_android() {
+        let (content_key, scramble_table) = ximalaya_android::keys::get_key(XimalayaType::X3M);
+        let mut head = [0u8; SCRAMBLED_HEADER_LEN];
+        head.copy_from_slice(&SAMPLE[..SCRAMBLED_HEADER_LEN]);
+        let mut file = ximalaya_android::encrypt_header(head, content_key, scramble_table).to_vec();
+        file.extend_from_slice(&SAMPLE[SCRAMBLED_HEADER_LEN..]);
+
+        let expected = Some(Format::XimalayaAndroid(XimalayaType::X3M));
+        assert_eq!(detect_bytes(&file, None), expected);
+        assert_eq!(detect_bytes(&file, Some("X3M")), expected);
+    }
+}
@@ -0,0 +1,312 @@
+//! Encrypt audio to the formats used by the clients, e.g. to convert between formats.
+//!
+//! Each function reads the decrypted audio from `src`, and writes the whole encrypted file
+//! (header, audio and tail) to `dst`. `progress` is called after each block of audio, with
+//! the number of audio bytes processed so far.
+//!
+//! Ximalaya PC (`.xm`) files can't be created yet.
+
+use std::io::{Read, Write};
+
+#[cfg(any(feature = "tencent", feature = "kugou", feature = "kuwo"))]
+use crate::crypto::byte_offset_cipher::ByteOffsetEncipher;
+use crate::crypto::byte_offset_cipher::StreamProgress;
+#[cfg(feature = "kugou")]
+use crate::crypto::kugou;
+#[cfg(feature = "kuwo")]
+use crate::crypto::kuwo::{header::KuwoHeader, Kuwo};
+#[cfg(feature = "tencent")]
+use crate::crypto::tencent::{metadata::TailParseResult, InitCipherError, QMCv1, QMCv2};
+#[cfg(feature = "ximalaya-android")]
+use crate::crypto::ximalaya_android::{
+    self,
+    keys::{Type as XimalayaType, SCRAMBLED_HEADER_LEN},
+};
+use crate::Result;
+
+const ENCRYPTION_BUFFER_SIZE: usize = 2 * 1024 * 1024;
+
+#[cfg(any(feature = "tencent", feature = "kugou", feature = "kuwo"))]
+fn encrypt_audio<C, R, W, G>(cipher: &C, src: &mut R, dst: &mut W, progress: G) -> Result<usize>
+where
+    C: ByteOffsetEncipher,
+    R: Read + ?Sized,
+    W: Write + ?Sized,
+    G: FnMut(StreamProgress),
+{
+    let mut buffer = vec![0u8; ENCRYPTION_BUFFER_SIZE];
+    let mut dst_write_error = Ok(());
+    let bytes_written = cipher.encipher_stream_with_progress(
+        &mut buffer,
+        0,
+        src,
+        None,
+        |block| {
+            dst_write_error = dst.write_all(block);
+            dst_write_error.as_ref().into()
+        },
+        progress,
+    )?;
+    dst_write_error?;
+    Ok(bytes_written)
+}
+
+/// Encrypt to QMCv1 (e.g. `qmcflac`), with the default table.
+#[cfg(feature = "tencent")]
+pub fn encrypt_qmc1<R, W, G>(src: &mut R, dst: &mut W, progress: G) -> Result<usize>
+where
+    R: Read + ?Sized,
+    W: Write + ?Sized,
+    G: FnMut(StreamProgress),
+{
+    encrypt_audio(&QMCv1::new(), src, dst, progress)
+}
+
+/// Encrypt to QMCv2 (e.g. `mflac`) with a (decrypted) `key`, followed by `tail`.
+///
+/// Tails embedding a key (see [`TailParseResult::get_key`]) must hold the same `key`,
+/// otherwise [`InitCipherError::TailKeyMismatch`] is returned.
+#[cfg(feature = "tencent")]
+pub fn encrypt_qmc2<R, W, G>(
+    src: &mut R,
+    dst: &mut W,
+    key: &[u8],
+    tail: Option<&TailParseResult>,
+    progress: G,
+) -> Result<usize>
+where
+    R: Read + ?Sized,
+    W: Write + ?Sized,
+    G: FnMut(StreamProgress),
+{
+    let cipher = QMCv2::try_new(key)?;
+    if tail
+        .and_then(|tail| tail.get_key())
+        .is_some_and(|tail_key| tail_key != key)
+    {
+        Err(InitCipherError::TailKeyMismatch)?;
+    }
+    let tail = tail.map(|tail| tail.to_bytes()).transpose()?;
+
+    let bytes_written = encrypt_audio(&cipher, src, dst, progress)?;
+    if let Some(tail) = tail {
+        dst.write_all(&tail)?;
+    }
+    Ok(bytes_written)
+}
+
+/// Encrypt to KGM/VPR, depending on the magic of `hdr`.
+///
+/// The challenge of `hdr` is signed again with `cipher`, so a header from another file
+/// (or another media type) can be reused.
+#[cfg(feature = "kugou")]
+pub fn encrypt_kugou<R, W, G>(
+    src: &mut R,
+    dst: &mut W,
+    hdr: &kugou::Header,
+    cipher: kugou::CipherModes,
+    progress: G,
+) -> Result<usize>
+where
+    R: Read + ?Sized,
+    W: Write + ?Sized,
+    G: FnMut(StreamProgress),
+{
+    let mut hdr = hdr.clone();
+    cipher.sign_challenge(&mut hdr)?;
+    let cipher = kugou::Kugou::with_cipher(&hdr, cipher)?;
+
+    dst.write_all(&hdr.to_bytes()?)?;
+    let bytes_written = encrypt_audio(&cipher, src, dst, progress)?;
+    cipher.check_len(bytes_written)?;
+    Ok(bytes_written)
+}
+
+/// Encrypt to KWM; `key` is the decrypted key, required by KWMv2.
+#[cfg(feature = "kuwo")]
+pub fn encrypt_kuwo<R, W, G>(
+    src: &mut R,
+    dst: &mut W,
+    hdr: &KuwoHeader,
+    key: Option<&[u8]>,
+    progress: G,
+) -> Result<usize>
+where
+    R: Read + ?Sized,
+    W: Write + ?Sized,
+    G: FnMut(StreamProgress),
+{
+    let cipher = Kuwo::from_header(hdr, key)?;
+
+    dst.write_all(&hdr.to_bytes())?;
+    encrypt_audio(&cipher, src, dst, progress)
+}
+
+/// Encrypt to X2M/X3M. The audio must be at least `SCRAMBLED_HEADER_LEN` bytes long.
+#[cfg(feature = "ximalaya-android")]
+pub fn encrypt_ximalaya_android<R, W, G>(
+    src: &mut R,
+    dst: &mut W,
+    key_type: XimalayaType,
+    mut progress: G,
+) -> Result<usize>
+where
+    R: Read + ?Sized,
+    W: Write + ?Sized,
+    G: FnMut(StreamProgress),
+{
+    let mut hdr = [0u8; SCRAMBLED_HEADER_LEN];
+    src.read_exact(&mut hdr)?;
+    let (content_key, scramble_table) = ximalaya_android::keys::get_key(key_type);
+    dst.write_all(&ximalaya_android::encrypt_header(
+        hdr,
+        content_key,
+        scramble_table,
+    ))?;
+
+    // The rest of the audio is kept as-is.
+    let mut buffer = vec![0u8; ENCRYPTION_BUFFER_SIZE];
+    let mut processed = SCRAMBLED_HEADER_LEN;
+    loop {
+        progress(StreamProgress {
+            processed,
+            total: None,
+        });
+        let read_len = src.read(&mut buffer)?;
+        if read_len == 0 {
+            break;
+        }
+        dst.write_all(&buffer[..read_len])?;
+        processed += read_len;
+    }
+    Ok(processed)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    #[cfg(any(feature = "tencent", feature = "kuwo"))]
+    use crate::crypto::byte_offset_cipher::ByteOffsetDecipher;
+
+    const SAMPLE: &[u8] = include_bytes!("../sample/test_121529_32kbps.ogg");
+
+    fn encrypt_sample<F>(encrypt: F) -> Vec<u8>
+    where
+        F: FnOnce(&mut Cursor<&[u8]>, &mut Vec<u8>) -> Result<usize>,
+    {
+        let mut dst = vec![];
+        let bytes_written = encrypt(&mut Cursor::new(SAMPLE), &mut dst).unwrap();
+        assert_eq!(bytes_written, SAMPLE.len());
+        dst
+    }
+
+    #[test]
+    #[cfg(feature = "tencent")]
+    fn test_qmc2_with_tail() {
+        use crate::crypto::tencent::{self, metadata::AndroidQTagMetadata};
+
+        let key = (0..256).map(|i| b'a' + (i % 26) as u8).collect::<Vec<_>>();
+        let tail = TailParseResult::AndroidQTag(AndroidQTagMetadata {
+            tail_len: 0,
+            key: key.clone().into(),
+            tag_version: 2,
+            resource_id: 12345,
+            extra_fields: vec![],
+        });
+        let file = encrypt_sample(|src, dst| encrypt_qmc2(src, dst, &key, Some(&tail), |_| {}));
+
+        let tail = tencent::parse_tail(&file).unwrap();
+        assert_eq!(tail.get_key(), Some(&key[..]));
+        let mut audio = file[..file.len() - tail.get_tail_len()].to_vec();
+        QMCv2::try_new(&key).unwrap().decipher_buffer(0, &mut audio);
+        assert_eq!(audio, SAMPLE);
+
+        let other_key = &key[1..];
+        let error = encrypt_qmc2(
+            &mut &SAMPLE[..],
+            &mut vec![],
+            other_key,
+            Some(&tail),
+            |_| {},
+        );
+        assert!(matches!(
+            error,
+            Err(crate::Error::TencentInitCipher(
+                InitCipherError::TailKeyMismatch
+            ))
+        ));
+    }
+
+    #[test]
+    #[cfg(feature = "kugou")]
+    fn test_kgm_to_vpr() {
+        let kgm = include_bytes!("../sample/test_kgm_v3.kgm");
+        let mut hdr = kugou::Header::from_bytes(kgm).unwrap();
+        let cipher = kugou::CipherModes::with_slot_key(&hdr, b"09AZ").unwrap();
+
+        hdr.set_file_type(kugou::MediaType::VPR);
+        let file = encrypt_sample(|src, dst| encrypt_kugou(src, dst, &hdr, cipher, |_| {}));
+
+        // Only the magic and the challenge differ from the KGM header.
+        let vpr_hdr = kugou::Header::from_bytes(&file).unwrap();
+        assert_eq!(vpr_hdr.get_file_type(), Some(kugou::MediaType::VPR));
+        assert_eq!(file[0x10..0x1C], kgm[0x10..0x1C]);
+        assert_eq!(file[0x2C..1024], kgm[0x2C..1024]);
+        assert!(kugou::Kugou::with_slot_key(&vpr_hdr, b"09AZ").is_ok());
+
+        // Same keys as the KGM file: mode 3 is XOR-linear, so the audio only differs by
+        // `m ^ (m << 4)` for each byte `m` of the mask.
+        assert_eq!(file.len(), kgm.len());
+        let mask = kugou::VPR_MASK_DIFF;
+        for (i, (k, v)) in kgm[1024..].iter().zip(&file[1024..]).enumerate() {
+            let m = mask[i % mask.len()];
+            assert_eq!(k ^ v, m ^ (m << 4), "byte {i}");
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "kuwo")]
+    fn test_kuwo() {
+        use crate::crypto::kuwo::header::{HEADER_FIXED_LEN, MAGIC_1};
+
+        let hdr = KuwoHeader {
+            magic: MAGIC_1,
+            version: 1,
+            resource_id: 12345,
+            format_name: *b"2000FLAC\0\0\0\0",
+            ..Default::default()
+        };
+        let file = encrypt_sample(|src, dst| encrypt_kuwo(src, dst, &hdr, None, |_| {}));
+
+        let (cipher, _) = crate::crypto::kuwo::KuwoBuilder::new(&file[..HEADER_FIXED_LEN])
+            .build()
+            .unwrap();
+        let mut audio = file[HEADER_FIXED_LEN..].to_vec();
+        cipher.decipher_buffer(0, &mut audio);
+        assert_eq!(audio, SAMPLE);
+    }
+
+    #[test]
+    #[cfg(feature = "ximalaya-android")]
+    fn test_ximalaya_android() {
+        let file = encrypt_sample(|src, dst| {
+            encrypt_ximalaya_android(src, dst, XimalayaType::X3M, |_| {})
+        });
+
+        let (content_key, scramble_table) = ximalaya_android::keys::get_key(XimalayaType::X3M);
+        let hdr = ximalaya_android::decrypt_header(
+            file[..SCRAMBLED_HEADER_LEN].try_into().unwrap(),
+            content_key,
+            scramble_table,
+        );
+        assert_eq!(hdr, SAMPLE[..SCRAMBLED_HEADER_LEN]);
+        assert_eq!(file[SCRAMBLED_HEADER_LEN..], SAMPLE[SCRAMBLED_HEADER_LEN..]);
+
+        let mut src = Cursor::new(&SAMPLE[..10]);
+        assert!(
+            encrypt_ximalaya_android(&mut src, &mut vec![], XimalayaType::X2M, |_| {}).is_err()
+        );
+    }
+}
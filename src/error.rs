@@ -0,0 +1,193 @@
+use thiserror::Error;
+
+#[cfg(feature = "kugou")]
+use crate::crypto::kugou;
+#[cfg(feature = "kuwo")]
+use crate::crypto::kuwo;
+#[cfg(feature = "tencent")]
+use crate::crypto::tencent;
+#[cfg(feature = "ximalaya-android")]
+use crate::crypto::ximalaya_android;
+#[cfg(feature = "ximalaya-pc")]
+use crate::crypto::ximalaya_pc;
+use crate::interfaces::DecryptorError;
+
+/// Stable error codes, e.g. for FFI consumers.
+///
+/// Codes are grouped by module; existing values will not change.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(u32)]
+pub enum ErrorCode {
+    Io = 1,
+    Decryptor = 2,
+
+    TencentKeyDecrypt = 100,
+    TencentTailParse = 101,
+    TencentInitCipher = 102,
+    TencentKeyEncrypt = 103,
+
+    KugouHeaderDeserialize = 200,
+    KugouHeaderSerialize = 201,
+    KugouCipher = 202,
+
+    KuwoHeaderParse = 300,
+    KuwoInitCipher = 301,
+
+    XimalayaAndroid = 400,
+    XimalayaPc = 500,
+}
+
+impl ErrorCode {
+    pub const ALL: [ErrorCode; 13] = [
+        ErrorCode::Io,
+        ErrorCode::Decryptor,
+        ErrorCode::TencentKeyDecrypt,
+        ErrorCode::TencentTailParse,
+        ErrorCode::TencentInitCipher,
+        ErrorCode::TencentKeyEncrypt,
+        ErrorCode::KugouHeaderDeserialize,
+        ErrorCode::KugouHeaderSerialize,
+        ErrorCode::KugouCipher,
+        ErrorCode::KuwoHeaderParse,
+        ErrorCode::KuwoInitCipher,
+        ErrorCode::XimalayaAndroid,
+        ErrorCode::XimalayaPc,
+    ];
+}
+
+/// Crate-level error, wrapping the errors of each module.
+///
+/// Both the message and the [`core::error::Error::source`] are the ones of the module
+/// error; use [`Error::code`] to tell the modules apart.
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum Error {
+    #[cfg(feature = "std")]
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Decryptor(#[from] DecryptorError),
+
+    #[cfg(feature = "tencent")]
+    #[error(transparent)]
+    TencentKeyDecrypt(#[from] tencent::ekey::KeyDecryptError),
+    #[cfg(feature = "tencent")]
+    #[error(transparent)]
+    TencentTailParse(#[from] tencent::metadata::TailParseError),
+    #[cfg(feature = "tencent")]
+    #[error(transparent)]
+    TencentInitCipher(#[from] tencent::InitCipherError),
+    #[cfg(feature = "tencent")]
+    #[error(transparent)]
+    TencentKeyEncrypt(#[from] tencent::ekey::KeyEncryptError),
+
+    #[cfg(feature = "kugou")]
+    #[error(transparent)]
+    KugouHeaderDeserialize(#[from] kugou::HeaderDeserializeError),
+    #[cfg(feature = "kugou")]
+    #[error(transparent)]
+    KugouHeaderSerialize(#[from] kugou::HeaderSerializeError),
+    #[cfg(feature = "kugou")]
+    #[error(transparent)]
+    KugouCipher(#[from] kugou::CipherError),
+
+    #[cfg(feature = "kuwo")]
+    #[error(transparent)]
+    KuwoHeaderParse(#[from] kuwo::header::HeaderParseError),
+    #[cfg(feature = "kuwo")]
+    #[error(transparent)]
+    KuwoInitCipher(#[from] kuwo::InitCipherError),
+
+    #[cfg(feature = "ximalaya-android")]
+    #[error(transparent)]
+    XimalayaAndroid(#[from] ximalaya_android::Error),
+    #[cfg(feature = "ximalaya-pc")]
+    #[error(transparent)]
+    XimalayaPc(#[from] ximalaya_pc::Error),
+}
+
+#[cfg(all(feature = "std", feature = "tencent"))]
+impl From<tencent::TailReadError> for Error {
+    fn from(error: tencent::TailReadError) -> Self {
+        match error {
+            tencent::TailReadError::IoError(error) => Self::Io(error),
+            tencent::TailReadError::ParseError(error) => Self::TencentTailParse(error),
+        }
+    }
+}
+
+impl Error {
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            #[cfg(feature = "std")]
+            Error::Io(_) => ErrorCode::Io,
+            Error::Decryptor(_) => ErrorCode::Decryptor,
+            #[cfg(feature = "tencent")]
+            Error::TencentKeyDecrypt(_) => ErrorCode::TencentKeyDecrypt,
+            #[cfg(feature = "tencent")]
+            Error::TencentTailParse(_) => ErrorCode::TencentTailParse,
+            #[cfg(feature = "tencent")]
+            Error::TencentInitCipher(_) => ErrorCode::TencentInitCipher,
+            #[cfg(feature = "tencent")]
+            Error::TencentKeyEncrypt(_) => ErrorCode::TencentKeyEncrypt,
+            #[cfg(feature = "kugou")]
+            Error::KugouHeaderDeserialize(_) => ErrorCode::KugouHeaderDeserialize,
+            #[cfg(feature = "kugou")]
+            Error::KugouHeaderSerialize(_) => ErrorCode::KugouHeaderSerialize,
+            #[cfg(feature = "kugou")]
+            Error::KugouCipher(_) => ErrorCode::KugouCipher,
+            #[cfg(feature = "kuwo")]
+            Error::KuwoHeaderParse(_) => ErrorCode::KuwoHeaderParse,
+            #[cfg(feature = "kuwo")]
+            Error::KuwoInitCipher(_) => ErrorCode::KuwoInitCipher,
+            #[cfg(feature = "ximalaya-android")]
+            Error::XimalayaAndroid(_) => ErrorCode::XimalayaAndroid,
+            #[cfg(feature = "ximalaya-pc")]
+            Error::XimalayaPc(_) => ErrorCode::XimalayaPc,
+        }
+    }
+}
+
+pub type Result<T> = core::result::Result<T, Error>;
+
+#[cfg(all(test, feature = "tencent", any(feature = "std", feature = "kuwo")))]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[cfg(feature = "kuwo")]
+    fn test_source_chain() {
+        use std::error::Error as _;
+
+        let error: Error = kuwo::InitCipherError::from(tencent::InitCipherError::InvalidKeyLength(
+            tencent::Mode::Map,
+            0,
+        ))
+        .into();
+        assert_eq!(error.code(), ErrorCode::KuwoInitCipher);
+        assert_eq!(error.code() as u32, 301);
+
+        // The message is not repeated by the source.
+        let inner = kuwo::InitCipherError::from(tencent::InitCipherError::InvalidKeyLength(
+            tencent::Mode::Map,
+            0,
+        ));
+        assert_eq!(error.to_string(), inner.to_string());
+        let source = error.source().unwrap();
+        assert!(source.is::<tencent::InitCipherError>());
+        assert!(source.source().is_none());
+    }
+
+    #[test]
+    #[cfg(all(feature = "std", feature = "tencent"))]
+    fn test_tail_read_error() {
+        let error: Error =
+            tencent::TailReadError::ParseError(tencent::metadata::TailParseError::InvalidTail)
+                .into();
+        assert_eq!(error.code(), ErrorCode::TencentTailParse);
+
+        let io_error = std::io::Error::from(std::io::ErrorKind::UnexpectedEof);
+        let error: Error = tencent::TailReadError::IoError(io_error).into();
+        assert_eq!(error.code(), ErrorCode::Io);
+    }
+}
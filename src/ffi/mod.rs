@@ -0,0 +1,502 @@
+//! C ABI, see `include/parakeet_crypto.h`.
+//!
+//! All functions return `PARAKEET_OK` (`0`) on success, a negative value for
+//! errors raised by the FFI layer, or a positive [`ErrorCode`] for errors raised
+//! by the library. A panic is reported as `PARAKEET_ERR_PANIC`, instead of
+//! unwinding into the caller.
+//!
+//! # Safety
+//!
+//! Pointers must be either `NULL` or valid for the given length; handles must
+//! come from one of the `parakeet_*_new` functions, and not be used after
+//! [`parakeet_decryptor_free`].
+#![allow(clippy::missing_safety_doc)]
+
+use std::panic::{self, AssertUnwindSafe};
+use std::slice;
+
+use crate::crypto::byte_offset_cipher::ByteOffsetDecipher;
+use crate::crypto::kugou::{Header as KugouHeader, Kugou};
+use crate::crypto::kuwo::{Kuwo, KuwoBuilder};
+use crate::crypto::tencent::metadata::TailParseError;
+use crate::crypto::tencent::{ekey, parse_tail, QMCv1, QMCv2};
+use crate::{Error, ErrorCode};
+
+pub const PARAKEET_OK: i32 = 0;
+pub const PARAKEET_ERR_NULL_POINTER: i32 = -1;
+pub const PARAKEET_ERR_BUFFER_TOO_SMALL: i32 = -2;
+pub const PARAKEET_ERR_NEED_MORE_BYTES: i32 = -3;
+pub const PARAKEET_ERR_KEY_REQUIRED: i32 = -4;
+pub const PARAKEET_ERR_PANIC: i32 = -5;
+
+/// Opaque decryptor handle.
+pub enum ParakeetDecryptor {
+    QMCv1(QMCv1),
+    QMCv2(QMCv2),
+    Kugou(Kugou),
+    Kuwo(Kuwo),
+}
+
+impl ParakeetDecryptor {
+    fn decrypt(&self, offset: usize, buffer: &mut [u8]) -> i32 {
+        match self {
+            Self::QMCv1(cipher) => cipher.decipher_buffer(offset, buffer),
+            Self::QMCv2(cipher) => cipher.decipher_buffer(offset, buffer),
+            Self::Kugou(cipher) => {
+                if let Err(err) = cipher.try_decipher_buffer(offset, buffer) {
+                    return error_code(err);
+                }
+            }
+            Self::Kuwo(cipher) => cipher.decipher_buffer(offset, buffer),
+        }
+        PARAKEET_OK
+    }
+}
+
+/// Run the body of an exported function; a panic must not unwind into the caller.
+fn catch_panic<F: FnOnce() -> i32>(f: F) -> i32 {
+    panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or(PARAKEET_ERR_PANIC)
+}
+
+fn error_code<E: Into<Error>>(error: E) -> i32 {
+    error.into().code() as i32
+}
+
+/// Build a slice from a C pointer; `NULL` is accepted for empty slices.
+unsafe fn input_slice<'a>(ptr: *const u8, len: usize) -> Option<&'a [u8]> {
+    match (ptr.is_null(), len) {
+        (_, 0) => Some(&[]),
+        (true, _) => None,
+        (false, len) => Some(slice::from_raw_parts(ptr, len)),
+    }
+}
+
+/// Same as [`input_slice`], but `NULL` means "not provided".
+unsafe fn optional_slice<'a>(ptr: *const u8, len: usize) -> Option<&'a [u8]> {
+    match ptr.is_null() {
+        true => None,
+        false => Some(slice::from_raw_parts(ptr, len)),
+    }
+}
+
+/// Copy `data` to a caller-provided buffer, updating `*out_len` with the size of `data`.
+unsafe fn write_output(data: &[u8], out: *mut u8, out_len: *mut usize) -> i32 {
+    if out_len.is_null() {
+        return PARAKEET_ERR_NULL_POINTER;
+    }
+
+    let capacity = *out_len;
+    *out_len = data.len();
+    if out.is_null() || capacity < data.len() {
+        return PARAKEET_ERR_BUFFER_TOO_SMALL;
+    }
+
+    slice::from_raw_parts_mut(out, data.len()).copy_from_slice(data);
+    PARAKEET_OK
+}
+
+unsafe fn write_handle(decryptor: ParakeetDecryptor, out: *mut *mut ParakeetDecryptor) -> i32 {
+    *out = Box::into_raw(Box::new(decryptor));
+    PARAKEET_OK
+}
+
+/// Init a QMCv1 decryptor; `table` is optional (`NULL` for the default table).
+#[no_mangle]
+pub unsafe extern "C" fn parakeet_qmc1_new(
+    table: *const u8,
+    table_len: usize,
+    out: *mut *mut ParakeetDecryptor,
+) -> i32 {
+    catch_panic(|| {
+        if out.is_null() {
+            return PARAKEET_ERR_NULL_POINTER;
+        }
+
+        let cipher = match optional_slice(table, table_len) {
+            None => QMCv1::new(),
+            Some(table) => match QMCv1::with_table(table) {
+                Ok(cipher) => cipher,
+                Err(err) => return error_code(err),
+            },
+        };
+        write_handle(ParakeetDecryptor::QMCv1(cipher), out)
+    })
+}
+
+/// Init a QMCv2 decryptor from a decrypted key.
+#[no_mangle]
+pub unsafe extern "C" fn parakeet_qmc2_new(
+    key: *const u8,
+    key_len: usize,
+    out: *mut *mut ParakeetDecryptor,
+) -> i32 {
+    catch_panic(|| {
+        let key = match input_slice(key, key_len) {
+            Some(key) if !out.is_null() => key,
+            _ => return PARAKEET_ERR_NULL_POINTER,
+        };
+
+        match QMCv2::try_new(key) {
+            Ok(cipher) => write_handle(ParakeetDecryptor::QMCv2(cipher), out),
+            Err(err) => error_code(err),
+        }
+    })
+}
+
+/// Init a QMCv2 decryptor from the end of the file.
+///
+/// `*out_tail_len` receives the size of the tail, or the number of bytes
+/// required if `PARAKEET_ERR_NEED_MORE_BYTES` is returned.
+#[no_mangle]
+pub unsafe extern "C" fn parakeet_qmc2_new_from_tail(
+    tail: *const u8,
+    tail_len: usize,
+    out: *mut *mut ParakeetDecryptor,
+    out_tail_len: *mut usize,
+) -> i32 {
+    catch_panic(|| {
+        let tail = match input_slice(tail, tail_len) {
+            Some(tail) if !out.is_null() && !out_tail_len.is_null() => tail,
+            _ => return PARAKEET_ERR_NULL_POINTER,
+        };
+
+        let metadata = match parse_tail(tail) {
+            Ok(metadata) => metadata,
+            Err(TailParseError::NeedMoreBytes(len)) => {
+                *out_tail_len = len;
+                return PARAKEET_ERR_NEED_MORE_BYTES;
+            }
+            Err(err) => return error_code(err),
+        };
+        *out_tail_len = metadata.get_tail_len();
+
+        let key = match metadata.get_key() {
+            Some(key) => key,
+            None => return PARAKEET_ERR_KEY_REQUIRED,
+        };
+        match QMCv2::try_new(key) {
+            Ok(cipher) => write_handle(ParakeetDecryptor::QMCv2(cipher), out),
+            Err(err) => error_code(err),
+        }
+    })
+}
+
+/// Init a Kugou decryptor from the file header.
+///
+/// `slot_key` is optional (`NULL` to use the key selected by the header).
+/// `*out_header_len` receives the offset of the audio data.
+/// Mode 4 key tables are computed on demand, to keep the memory usage low.
+#[no_mangle]
+pub unsafe extern "C" fn parakeet_kugou_new(
+    header: *const u8,
+    header_len: usize,
+    slot_key: *const u8,
+    slot_key_len: usize,
+    out: *mut *mut ParakeetDecryptor,
+    out_header_len: *mut usize,
+) -> i32 {
+    catch_panic(|| {
+        let header = match input_slice(header, header_len) {
+            Some(header) if !out.is_null() && !out_header_len.is_null() => header,
+            _ => return PARAKEET_ERR_NULL_POINTER,
+        };
+
+        let hdr = match KugouHeader::from_bytes(header) {
+            Ok(hdr) => hdr,
+            Err(err) => return error_code(err),
+        };
+        let cipher = match optional_slice(slot_key, slot_key_len) {
+            None => Kugou::new_lazy(&hdr),
+            Some(slot_key) => Kugou::with_slot_key_lazy(&hdr, slot_key),
+        };
+
+        match cipher {
+            Ok(cipher) => {
+                *out_header_len = hdr.header_len as usize;
+                write_handle(ParakeetDecryptor::Kugou(cipher), out)
+            }
+            Err(err) => error_code(err),
+        }
+    })
+}
+
+/// Init a Kuwo decryptor from the file header (`0x400` bytes).
+///
+/// `ekey` is optional, and only required for KWMv2 files.
+#[no_mangle]
+pub unsafe extern "C" fn parakeet_kuwo_new(
+    header: *const u8,
+    header_len: usize,
+    ekey: *const u8,
+    ekey_len: usize,
+    out: *mut *mut ParakeetDecryptor,
+) -> i32 {
+    catch_panic(|| {
+        let header = match input_slice(header, header_len) {
+            Some(header) if !out.is_null() => header,
+            _ => return PARAKEET_ERR_NULL_POINTER,
+        };
+
+        let mut builder = KuwoBuilder::new(header);
+        if let Some(ekey) = optional_slice(ekey, ekey_len) {
+            builder = builder.with_ekey(ekey);
+        }
+
+        match builder.build() {
+            Ok((cipher, _)) => write_handle(ParakeetDecryptor::Kuwo(cipher), out),
+            Err(err) => error_code(err),
+        }
+    })
+}
+
+/// Decrypt `buf` in place; `offset` is relative to the start of the encrypted audio.
+///
+/// Kugou (mode 4) files can't be decrypted past a fixed length: `PARAKEET_ERR_KUGOU_CIPHER`
+/// is returned, and `buf` is left untouched.
+#[no_mangle]
+pub unsafe extern "C" fn parakeet_decrypt(
+    handle: *const ParakeetDecryptor,
+    offset: usize,
+    buf: *mut u8,
+    len: usize,
+) -> i32 {
+    catch_panic(|| {
+        if handle.is_null() || (buf.is_null() && len != 0) {
+            return PARAKEET_ERR_NULL_POINTER;
+        }
+
+        match len {
+            0 => PARAKEET_OK,
+            len => (*handle).decrypt(offset, slice::from_raw_parts_mut(buf, len)),
+        }
+    })
+}
+
+/// Free a decryptor handle; `NULL` is ignored.
+#[no_mangle]
+pub unsafe extern "C" fn parakeet_decryptor_free(handle: *mut ParakeetDecryptor) {
+    catch_panic(|| {
+        if !handle.is_null() {
+            drop(Box::from_raw(handle));
+        }
+        PARAKEET_OK
+    });
+}
+
+/// Decrypt a QMC ekey.
+///
+/// `*out_len` should hold the capacity of `out`, and receives the size of the key.
+#[no_mangle]
+pub unsafe extern "C" fn parakeet_ekey_decrypt(
+    ekey: *const u8,
+    ekey_len: usize,
+    out: *mut u8,
+    out_len: *mut usize,
+) -> i32 {
+    catch_panic(|| {
+        let ekey = match input_slice(ekey, ekey_len) {
+            Some(ekey) => ekey,
+            None => return PARAKEET_ERR_NULL_POINTER,
+        };
+
+        match ekey::decrypt(ekey) {
+            Ok(key) => write_output(&key, out, out_len),
+            Err(err) => error_code(err),
+        }
+    })
+}
+
+/// Parse a QMC tail.
+///
+/// `*out_tail_len` receives the size of the tail, or the number of bytes
+/// required if `PARAKEET_ERR_NEED_MORE_BYTES` is returned.
+///
+/// The embedded key is copied to `key` (optional); `*key_len` should hold
+/// its capacity, and receives the size of the key (`0` if the tail has no key).
+#[no_mangle]
+pub unsafe extern "C" fn parakeet_tail_parse(
+    tail: *const u8,
+    tail_len: usize,
+    out_tail_len: *mut usize,
+    key: *mut u8,
+    key_len: *mut usize,
+) -> i32 {
+    catch_panic(|| {
+        let tail = match input_slice(tail, tail_len) {
+            Some(tail) if !out_tail_len.is_null() => tail,
+            _ => return PARAKEET_ERR_NULL_POINTER,
+        };
+
+        let metadata = match parse_tail(tail) {
+            Ok(metadata) => metadata,
+            Err(TailParseError::NeedMoreBytes(len)) => {
+                *out_tail_len = len;
+                return PARAKEET_ERR_NEED_MORE_BYTES;
+            }
+            Err(err) => return error_code(err),
+        };
+        *out_tail_len = metadata.get_tail_len();
+
+        match (metadata.get_key(), key_len.is_null()) {
+            (_, true) => PARAKEET_OK,
+            (Some(embedded_key), false) => write_output(embedded_key, key, key_len),
+            (None, false) => {
+                *key_len = 0;
+                PARAKEET_OK
+            }
+        }
+    })
+}
+
+/// Static description of an error code.
+#[no_mangle]
+pub extern "C" fn parakeet_error_message(code: i32) -> *const std::ffi::c_char {
+    let message: &'static [u8] = match code {
+        PARAKEET_OK => b"ok\0",
+        PARAKEET_ERR_NULL_POINTER => b"null pointer\0",
+        PARAKEET_ERR_BUFFER_TOO_SMALL => b"output buffer too small\0",
+        PARAKEET_ERR_NEED_MORE_BYTES => b"need more bytes\0",
+        PARAKEET_ERR_KEY_REQUIRED => b"key required\0",
+        PARAKEET_ERR_PANIC => b"internal error (panic)\0",
+        code if code == ErrorCode::Io as i32 => b"i/o error\0",
+        code if code == ErrorCode::Decryptor as i32 => b"decryptor error\0",
+        code if code == ErrorCode::TencentKeyDecrypt as i32 => b"ekey decryption failed\0",
+        code if code == ErrorCode::TencentTailParse as i32 => b"tail parse error\0",
+        code if code == ErrorCode::TencentInitCipher as i32 => b"qmc cipher init failed\0",
+        code if code == ErrorCode::TencentKeyEncrypt as i32 => b"ekey encryption failed\0",
+        code if code == ErrorCode::KugouHeaderDeserialize as i32 => b"kugou header error\0",
+        code if code == ErrorCode::KugouHeaderSerialize as i32 => b"kugou header error\0",
+        code if code == ErrorCode::KugouCipher as i32 => b"kugou cipher error\0",
+        code if code == ErrorCode::KuwoHeaderParse as i32 => b"kuwo header error\0",
+        code if code == ErrorCode::KuwoInitCipher as i32 => b"kuwo cipher init failed\0",
+        code if code == ErrorCode::XimalayaAndroid as i32 => b"ximalaya (android) error\0",
+        code if code == ErrorCode::XimalayaPc as i32 => b"ximalaya (pc) error\0",
+        _ => b"unknown error\0",
+    };
+    message.as_ptr().cast()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ptr;
+
+    use super::*;
+
+    #[test]
+    fn test_kugou_decrypt() {
+        let file = include_bytes!("../../sample/test_kgm_v2.kgm");
+        let sample = include_bytes!("../../sample/test_121529_32kbps.ogg");
+        let slot_key = b"09AZ";
+
+        unsafe {
+            let mut handle = ptr::null_mut();
+            let mut header_len = 0usize;
+            let result = parakeet_kugou_new(
+                file.as_ptr(),
+                1024,
+                slot_key.as_ptr(),
+                slot_key.len(),
+                &mut handle,
+                &mut header_len,
+            );
+            assert_eq!(result, PARAKEET_OK);
+            assert_eq!(header_len, 1024);
+
+            let mut audio = file[header_len..].to_vec();
+            let (first, second) = audio.split_at_mut(100);
+            assert_eq!(
+                parakeet_decrypt(handle, 0, first.as_mut_ptr(), first.len()),
+                0
+            );
+            assert_eq!(
+                parakeet_decrypt(handle, 100, second.as_mut_ptr(), second.len()),
+                0
+            );
+            assert_eq!(audio, sample);
+
+            parakeet_decryptor_free(handle);
+        }
+    }
+
+    #[test]
+    fn test_ekey_buffer_too_small() {
+        let tail = include_bytes!("../crypto/tencent/tail/__fixtures__/ekey_pc_enc_v1.bin");
+        let key_len = u32::from_le_bytes(tail[tail.len() - 4..].try_into().unwrap()) as usize;
+        let ekey = &tail[tail.len() - 4 - key_len..tail.len() - 4];
+        let expected =
+            include_bytes!("../crypto/tencent/tail/__fixtures__/ekey_pc_enc_v1_result.bin");
+
+        unsafe {
+            let mut out_len = 0usize;
+            let result =
+                parakeet_ekey_decrypt(ekey.as_ptr(), ekey.len(), ptr::null_mut(), &mut out_len);
+            assert_eq!(result, PARAKEET_ERR_BUFFER_TOO_SMALL);
+            assert_eq!(out_len, expected.len());
+
+            let mut out = vec![0u8; out_len];
+            let result =
+                parakeet_ekey_decrypt(ekey.as_ptr(), ekey.len(), out.as_mut_ptr(), &mut out_len);
+            assert_eq!(result, PARAKEET_OK);
+            assert_eq!(&out[..], &expected[..]);
+        }
+    }
+
+    #[test]
+    fn test_tail_parse() {
+        let tail = include_bytes!("../crypto/tencent/tail/__fixtures__/ekey_pc_enc_v1.bin");
+        let expected =
+            include_bytes!("../crypto/tencent/tail/__fixtures__/ekey_pc_enc_v1_result.bin");
+
+        unsafe {
+            let mut tail_len = 0usize;
+            let mut key = [0u8; 1024];
+            let mut key_len = key.len();
+            let result = parakeet_tail_parse(
+                tail.as_ptr(),
+                tail.len(),
+                &mut tail_len,
+                key.as_mut_ptr(),
+                &mut key_len,
+            );
+            assert_eq!(result, PARAKEET_OK);
+            assert_eq!(tail_len, 0x2C0 + 4);
+            assert_eq!(&key[..key_len], expected);
+
+            let result = parakeet_tail_parse(
+                tail[tail.len() - 8..].as_ptr(),
+                8,
+                &mut tail_len,
+                ptr::null_mut(),
+                ptr::null_mut(),
+            );
+            assert_eq!(result, PARAKEET_ERR_NEED_MORE_BYTES);
+            assert!(tail_len > 8);
+        }
+    }
+
+    #[test]
+    fn test_catch_panic() {
+        assert_eq!(catch_panic(|| panic!("boom")), PARAKEET_ERR_PANIC);
+        assert_eq!(catch_panic(|| PARAKEET_OK), PARAKEET_OK);
+    }
+
+    #[test]
+    fn test_error_codes() {
+        unsafe {
+            let mut handle = ptr::null_mut();
+            assert_eq!(
+                parakeet_qmc2_new(ptr::null(), 0, &mut handle),
+                ErrorCode::TencentInitCipher as i32
+            );
+            assert_eq!(
+                parakeet_qmc2_new(ptr::null(), 4, &mut handle),
+                PARAKEET_ERR_NULL_POINTER
+            );
+            assert_eq!(
+                parakeet_kuwo_new([0u8; 4].as_ptr(), 4, ptr::null(), 0, &mut handle),
+                ErrorCode::KuwoInitCipher as i32
+            );
+            assert!(handle.is_null());
+        }
+    }
+}
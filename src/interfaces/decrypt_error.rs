@@ -1,5 +1,7 @@
+use alloc::boxed::Box;
+use alloc::string::String;
 use base64::DecodeError;
-use std::str::Utf8Error;
+use core::str::Utf8Error;
 use thiserror::Error;
 
 #[derive(Debug, Error)]
@@ -11,12 +13,13 @@ pub enum DecryptorError {
     #[error("input buffer size does not match output buffer size")]
     InputOutputBufferLenMismatch,
 
+    #[cfg(feature = "std")]
     #[error("io error, {0}")]
     IOError(#[from] std::io::Error),
     #[error("{0} not implement")]
     NotImplementedError(String),
-    #[error("QMC Static Cipher init failed - is key length correct?")]
-    QMCv1InitFailed,
+    #[error("QMC Static Cipher init failed - expected a 128-byte table, got {0} bytes")]
+    QMCv1InitFailed(usize),
     #[error("QMC parse error - footer magic number: {}", hex::encode(.0))]
     QMCInvalidFooter(Box<[u8]>),
     #[error("QMC init error - tail detection buffer too small")]
@@ -28,7 +31,7 @@ pub enum DecryptorError {
     #[error("string encode error, {0}")]
     StringEncodeError(#[from] Utf8Error),
     #[error("base64 decode error, {0}")]
-    Base64DecodeError(#[from] DecodeError),
+    Base64DecodeError(#[cfg_attr(feature = "std", from)] DecodeError),
     #[error("TEA key error (is your key correct?)")]
     TEADecryptError,
 
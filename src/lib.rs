@@ -1,9 +1,37 @@
-extern crate lazy_static;
-extern crate serde_big_array;
-extern crate serde_derive;
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
 
+extern crate alloc;
+
+mod error;
 pub mod interfaces;
 
+pub use error::{Error, ErrorCode, Result};
+
 pub mod utils;
 
 pub mod crypto;
+
+#[cfg(feature = "std")]
+pub mod decrypt;
+#[cfg(feature = "std")]
+pub mod detect;
+
+#[cfg(all(
+    feature = "std",
+    any(
+        feature = "tencent",
+        feature = "kugou",
+        feature = "kuwo",
+        feature = "ximalaya-android"
+    )
+))]
+pub mod encrypt;
+
+#[cfg(feature = "std")]
+pub mod tagging;
+
+#[cfg(feature = "ffi")]
+pub mod ffi;
+
+#[cfg(feature = "wasm")]
+pub mod wasm;
@@ -0,0 +1,210 @@
+use std::io::{self, Write};
+
+use byteorder::{ByteOrder, BE};
+
+use super::vorbis::{comment_tags, picture_block, VorbisComment, PICTURE_TYPE_FRONT_COVER};
+use super::{Metadata, Step};
+
+const FLAC_MAGIC_LEN: usize = 4;
+const BLOCK_HEADER_LEN: usize = 4;
+const BLOCK_LAST_FLAG: u8 = 0x80;
+const BLOCK_TYPE_VORBIS_COMMENT: u8 = 4;
+const BLOCK_TYPE_PICTURE: u8 = 6;
+const MAX_BLOCK_LEN: usize = 0xFF_FFFF;
+
+/// Rewrite the `VORBIS_COMMENT` metadata block, or add one after `STREAMINFO`.
+///
+/// The cover is written to a `PICTURE` block following it, replacing the front cover.
+pub struct FlacTagger {
+    metadata: Metadata,
+}
+
+impl FlacTagger {
+    pub fn new(metadata: Metadata) -> Self {
+        Self { metadata }
+    }
+
+    pub fn process<W: Write + ?Sized>(
+        &mut self,
+        buffer: &mut Vec<u8>,
+        out: &mut W,
+        eof: bool,
+    ) -> io::Result<Step> {
+        let incomplete = match eof {
+            true => Step::Done { tagged: false },
+            false => Step::NeedMore,
+        };
+
+        // `(type, body)` of each metadata block.
+        let mut blocks = vec![];
+        let mut pos = FLAC_MAGIC_LEN;
+        loop {
+            let Some(header) = buffer.get(pos..pos + BLOCK_HEADER_LEN) else {
+                return Ok(incomplete);
+            };
+            let body_start = pos + BLOCK_HEADER_LEN;
+            let body_end = body_start + BE::read_u24(&header[1..]) as usize;
+            let Some(body) = buffer.get(body_start..body_end) else {
+                return Ok(incomplete);
+            };
+            blocks.push((header[0] & !BLOCK_LAST_FLAG, body));
+            pos = body_end;
+            if header[0] & BLOCK_LAST_FLAG != 0 {
+                break;
+            }
+        }
+
+        let comment_idx = blocks
+            .iter()
+            .position(|&(block_type, _)| block_type == BLOCK_TYPE_VORBIS_COMMENT);
+        let mut comment = match comment_idx {
+            Some(idx) => match VorbisComment::from_bytes(blocks[idx].1) {
+                Some((comment, _)) => comment,
+                None => return Ok(Step::Done { tagged: false }),
+            },
+            None => VorbisComment::default(),
+        };
+        comment.set_tags(&comment_tags(&self.metadata));
+        let comment = comment.to_bytes();
+        let picture = self.metadata.extras.cover.as_ref().map(picture_block);
+        if comment.len() > MAX_BLOCK_LEN
+            || picture.as_ref().is_some_and(|p| p.len() > MAX_BLOCK_LEN)
+        {
+            return Ok(Step::Done { tagged: false });
+        }
+
+        match comment_idx {
+            Some(idx) => blocks[idx].1 = &comment,
+            // Right after `STREAMINFO`, which must be the first block.
+            None => blocks.insert(1.min(blocks.len()), (BLOCK_TYPE_VORBIS_COMMENT, &comment)),
+        }
+
+        if let Some(picture) = &picture {
+            let front_cover = PICTURE_TYPE_FRONT_COVER.to_be_bytes();
+            blocks.retain(|&(block_type, body)| {
+                block_type != BLOCK_TYPE_PICTURE || !body.starts_with(&front_cover)
+            });
+            let comment_idx = blocks
+                .iter()
+                .position(|&(block_type, _)| block_type == BLOCK_TYPE_VORBIS_COMMENT)
+                .unwrap_or_default();
+            blocks.insert(comment_idx + 1, (BLOCK_TYPE_PICTURE, picture));
+        }
+
+        let mut metadata = buffer[..FLAC_MAGIC_LEN].to_vec();
+        let last_idx = blocks.len() - 1;
+        for (i, &(block_type, body)) in blocks.iter().enumerate() {
+            let flag = if i == last_idx { BLOCK_LAST_FLAG } else { 0 };
+            metadata.push(block_type | flag);
+            metadata.extend_from_slice(&(body.len() as u32).to_be_bytes()[1..]);
+            metadata.extend_from_slice(body);
+        }
+
+        out.write_all(&metadata)?;
+        buffer.drain(..pos);
+        Ok(Step::Done { tagged: true })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tagging::tests::{sample_extras, test_tags, write_tagged, write_with_extras};
+
+    use super::*;
+
+    fn block(block_type: u8, body: &[u8]) -> Vec<u8> {
+        let mut result = vec![block_type];
+        result.extend_from_slice(&(body.len() as u32).to_be_bytes()[1..]);
+        result.extend_from_slice(body);
+        result
+    }
+
+    fn parse_comment(file: &[u8]) -> (VorbisComment, usize) {
+        // STREAMINFO is 0x22 bytes, followed by the comment.
+        assert_eq!(file[4 + 4 + 0x22] & 0x7F, BLOCK_TYPE_VORBIS_COMMENT);
+        let start = 4 + 4 + 0x22 + 4;
+        let (comment, len) = VorbisComment::from_bytes(&file[start..]).unwrap();
+        (comment, start + len)
+    }
+
+    #[test]
+    fn test_add_comment_block() {
+        let mut file = b"fLaC".to_vec();
+        file.extend(block(BLOCK_LAST_FLAG, &[0x11; 0x22]));
+        file.extend(b"\xFF\xF8 frames");
+
+        let (output, report) = write_tagged(&file, test_tags());
+        assert!(report.tagged);
+        assert_eq!(output[4], 0, "STREAMINFO is no longer the last block");
+
+        let (comment, end) = parse_comment(&output);
+        assert_eq!(
+            output[4 + 4 + 0x22],
+            BLOCK_LAST_FLAG | BLOCK_TYPE_VORBIS_COMMENT
+        );
+        assert_eq!(
+            comment.comments,
+            vec![
+                b"QQMUSIC_MID=001y7CaR29k6YP".to_vec(),
+                b"QQMUSIC_RESOURCE_ID=5177785".to_vec()
+            ]
+        );
+        assert_eq!(&output[end..], b"\xFF\xF8 frames");
+    }
+
+    #[test]
+    fn test_update_comment_block() {
+        let existing = VorbisComment {
+            vendor: b"reference libFLAC".to_vec(),
+            comments: vec![b"TITLE=song".to_vec(), b"QQMUSIC_MID=old".to_vec()],
+        };
+        let mut file = b"fLaC".to_vec();
+        file.extend(block(0, &[0x11; 0x22]));
+        file.extend(block(BLOCK_TYPE_VORBIS_COMMENT, &existing.to_bytes()));
+        file.extend(block(BLOCK_LAST_FLAG | 1, &[0; 16]));
+        file.extend(b"\xFF\xF8 frames");
+
+        let (output, report) = write_tagged(&file, test_tags());
+        assert!(report.tagged);
+
+        let (comment, end) = parse_comment(&output);
+        assert_eq!(comment.vendor, b"reference libFLAC");
+        assert_eq!(comment.comments.len(), 3);
+        assert_eq!(comment.comments[0], b"TITLE=song");
+        assert_eq!(comment.comments[1], b"QQMUSIC_MID=001y7CaR29k6YP");
+        assert_eq!(&output[end..end + 4], &[BLOCK_LAST_FLAG | 1, 0, 0, 16]);
+        assert!(output.ends_with(b"\xFF\xF8 frames"));
+    }
+
+    #[test]
+    fn test_extras() {
+        let old_cover = [PICTURE_TYPE_FRONT_COVER.to_be_bytes().as_slice(), b"old"].concat();
+        let back_cover = [4u32.to_be_bytes().as_slice(), b"back"].concat();
+        let mut file = b"fLaC".to_vec();
+        file.extend(block(0, &[0x11; 0x22]));
+        file.extend(block(BLOCK_TYPE_PICTURE, &old_cover));
+        file.extend(block(BLOCK_TYPE_PICTURE, &back_cover));
+        file.extend(block(BLOCK_LAST_FLAG | 1, &[0; 16]));
+        file.extend(b"\xFF\xF8 frames");
+
+        let extras = sample_extras();
+        let (output, report) = write_with_extras(&file, vec![], extras.clone());
+        assert!(report.tagged);
+
+        let (comment, end) = parse_comment(&output);
+        assert_eq!(
+            comment.comments,
+            vec!["TITLE=标题".as_bytes().to_vec(), b"ARTIST=artist".to_vec()]
+        );
+
+        let picture = picture_block(extras.cover.as_ref().unwrap());
+        let expected = [
+            block(BLOCK_TYPE_PICTURE, &picture),
+            block(BLOCK_TYPE_PICTURE, &back_cover),
+            block(BLOCK_LAST_FLAG | 1, &[0; 16]),
+            b"\xFF\xF8 frames".to_vec(),
+        ]
+        .concat();
+        assert_eq!(&output[end..], expected);
+    }
+}
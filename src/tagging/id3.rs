@@ -0,0 +1,515 @@
+use std::io::{self, Write};
+
+use byteorder::{ByteOrder, BE};
+
+use crate::utils::media_extras::Cover;
+
+use super::{Metadata, Step, Tag};
+
+const ID3_MAGIC: &[u8; 3] = b"ID3";
+const ID3_HEADER_LEN: usize = 10;
+const FLAG_UNSYNCHRONISATION: u8 = 0x80;
+const FLAG_EXTENDED_HEADER: u8 = 0x40;
+const FLAG_FOOTER: u8 = 0x10;
+const MAX_SYNCSAFE: usize = (1 << 28) - 1;
+
+const ENCODING_LATIN1: u8 = 0;
+const ENCODING_UTF16: u8 = 1;
+const ENCODING_UTF16BE: u8 = 2;
+const ENCODING_UTF8: u8 = 3;
+
+fn read_syncsafe(data: &[u8]) -> Option<usize> {
+    data.iter().try_fold(0usize, |value, &b| match b & 0x80 {
+        0 => Some((value << 7) | usize::from(b)),
+        _ => None,
+    })
+}
+
+fn syncsafe(value: usize) -> [u8; 4] {
+    [
+        (value >> 21) as u8 & 0x7F,
+        (value >> 14) as u8 & 0x7F,
+        (value >> 7) as u8 & 0x7F,
+        value as u8 & 0x7F,
+    ]
+}
+
+/// Insert a `0x00` after each `0xFF`, that is followed by `0x00` or `0b111xxxxx` (or the end).
+fn unsynchronise(data: &[u8]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(data.len());
+    for (i, &b) in data.iter().enumerate() {
+        result.push(b);
+        if b == 0xFF
+            && data
+                .get(i + 1)
+                .map_or(true, |&next| next == 0 || next >= 0xE0)
+        {
+            result.push(0);
+        }
+    }
+    result
+}
+
+/// Encode the text of a frame, followed by the terminator if `terminated`.
+fn encode_text(text: &str, encoding: u8, terminated: bool) -> Vec<u8> {
+    let mut result = vec![];
+    match encoding {
+        ENCODING_UTF16 => {
+            result.extend_from_slice(&[0xFF, 0xFE]);
+            text.encode_utf16()
+                .for_each(|c| result.extend_from_slice(&c.to_le_bytes()));
+            if terminated {
+                result.extend_from_slice(&[0, 0]);
+            }
+        }
+        _ => {
+            result.extend_from_slice(text.as_bytes());
+            if terminated {
+                result.push(0);
+            }
+        }
+    }
+    result
+}
+
+/// Read the description of a `TXXX` frame body.
+fn txxx_description(body: &[u8]) -> Option<String> {
+    let (&encoding, text) = body.split_first()?;
+    match encoding {
+        ENCODING_UTF16 | ENCODING_UTF16BE => {
+            let units = text
+                .chunks_exact(2)
+                .map(|c| [c[0], c[1]])
+                .take_while(|&c| c != [0, 0])
+                .collect::<Vec<_>>();
+            let units = match units.first() {
+                Some([0xFF, 0xFE]) => units[1..].iter().map(|&c| u16::from_le_bytes(c)).collect(),
+                Some([0xFE, 0xFF]) => units[1..].iter().map(|&c| u16::from_be_bytes(c)).collect(),
+                _ => units
+                    .iter()
+                    .map(|&c| u16::from_be_bytes(c))
+                    .collect::<Vec<_>>(),
+            };
+            String::from_utf16(&units).ok()
+        }
+        _ => {
+            let text = text.split(|&c| c == 0).next()?;
+            Some(text.iter().map(|&c| char::from(c)).collect())
+        }
+    }
+}
+
+/// `(ID3v2.2, ID3v2.3+)` frame ids of the title, artist and album.
+const TITLE_FRAME: (&[u8], &[u8]) = (b"TT2", b"TIT2");
+const ARTIST_FRAME: (&[u8], &[u8]) = (b"TP1", b"TPE1");
+const ALBUM_FRAME: (&[u8], &[u8]) = (b"TAL", b"TALB");
+const PICTURE_FRAME: (&[u8], &[u8]) = (b"PIC", b"APIC");
+const TXXX_FRAME: (&[u8], &[u8]) = (b"TXX", b"TXXX");
+
+const PICTURE_TYPE_FRONT_COVER: u8 = 3;
+const FRAME_FLAG_UNSYNCHRONISATION: u8 = 0x02;
+
+fn frame_id(version: u8, ids: (&'static [u8], &'static [u8])) -> &'static [u8] {
+    match version {
+        2 => ids.0,
+        _ => ids.1,
+    }
+}
+
+/// Write `TXXX` frames, and the extras as the standard frames (`TIT2`, `TPE1`, `TALB` and
+/// `APIC`), to the ID3v2 tag at the start of an MP3; a new ID3v2.4 tag is added if missing.
+pub struct Id3Tagger {
+    metadata: Metadata,
+}
+
+impl Id3Tagger {
+    pub fn new(metadata: Metadata) -> Self {
+        Self { metadata }
+    }
+
+    fn frame_header_len(version: u8) -> usize {
+        match version {
+            2 => 6,
+            _ => 10,
+        }
+    }
+
+    /// Build a frame; `unsynchronised` is only used by ID3v2.4, where it applies to
+    /// each frame instead of the whole tag.
+    fn make_frame(version: u8, id: &[u8], body: Vec<u8>, unsynchronised: bool) -> Vec<u8> {
+        let (body, flags) = match version == 4 && unsynchronised {
+            true => (unsynchronise(&body), [0, FRAME_FLAG_UNSYNCHRONISATION]),
+            false => (body, [0, 0]),
+        };
+
+        let mut frame = id.to_vec();
+        match version {
+            2 => frame.extend_from_slice(&(body.len() as u32).to_be_bytes()[1..]),
+            3 => frame.extend_from_slice(&(body.len() as u32).to_be_bytes()),
+            _ => frame.extend_from_slice(&syncsafe(body.len())),
+        }
+        if version != 2 {
+            frame.extend_from_slice(&flags);
+        }
+        frame.extend(body);
+        frame
+    }
+
+    fn text_encoding(version: u8, texts: &[&str]) -> u8 {
+        match version {
+            4 => ENCODING_UTF8,
+            _ if texts.iter().all(|text| text.is_ascii()) => ENCODING_LATIN1,
+            _ => ENCODING_UTF16,
+        }
+    }
+
+    /// Body of a `TXXX` frame for a tag.
+    fn txxx_body(version: u8, tag: &Tag) -> Vec<u8> {
+        let encoding = Self::text_encoding(version, &[&tag.key, &tag.value]);
+        let mut body = vec![encoding];
+        body.extend(encode_text(&tag.key, encoding, true));
+        body.extend(encode_text(&tag.value, encoding, false));
+        body
+    }
+
+    /// Body of a text frame, e.g. `TIT2`.
+    fn text_body(version: u8, text: &str) -> Vec<u8> {
+        let encoding = Self::text_encoding(version, &[text]);
+        let mut body = vec![encoding];
+        body.extend(encode_text(text, encoding, false));
+        body
+    }
+
+    /// Body of the `APIC` (or `PIC` in ID3v2.2) frame of the front cover.
+    fn picture_body(version: u8, cover: &Cover) -> Vec<u8> {
+        let mut body = vec![ENCODING_LATIN1];
+        match version {
+            2 if cover.extension() == "png" => body.extend_from_slice(b"PNG"),
+            2 => body.extend_from_slice(b"JPG"),
+            _ => body.extend(encode_text(&cover.mime, ENCODING_LATIN1, true)),
+        }
+        body.push(PICTURE_TYPE_FRONT_COVER);
+        body.push(0); // empty description
+        body.extend_from_slice(&cover.data);
+        body
+    }
+
+    /// Frames to add to the tag.
+    fn new_frames(&self, version: u8, unsynchronised: bool) -> Vec<u8> {
+        let extras = &self.metadata.extras;
+        let text_frames = [
+            (TITLE_FRAME, &extras.title),
+            (ARTIST_FRAME, &extras.artist),
+            (ALBUM_FRAME, &extras.album),
+        ];
+
+        let mut bodies = vec![];
+        for (ids, text) in text_frames {
+            if let Some(text) = text {
+                bodies.push((ids, Self::text_body(version, text)));
+            }
+        }
+        if let Some(cover) = &extras.cover {
+            bodies.push((PICTURE_FRAME, Self::picture_body(version, cover)));
+        }
+        for tag in &self.metadata.tags {
+            bodies.push((TXXX_FRAME, Self::txxx_body(version, tag)));
+        }
+
+        bodies
+            .into_iter()
+            .flat_map(|(ids, body)| {
+                Self::make_frame(version, frame_id(version, ids), body, unsynchronised)
+            })
+            .collect()
+    }
+
+    /// Check if an existing frame is replaced by one of ours.
+    fn is_replaced(&self, version: u8, id: &[u8], body: &[u8]) -> bool {
+        let extras = &self.metadata.extras;
+        let id_of = |ids| frame_id(version, ids);
+
+        if id == id_of(TXXX_FRAME) {
+            txxx_description(body)
+                .is_some_and(|desc| self.metadata.tags.iter().any(|tag| tag.key == desc))
+        } else if id == id_of(PICTURE_FRAME) {
+            // `[encoding][mime or format][type]...`
+            let picture_type = match version {
+                2 => body.get(4),
+                _ => body
+                    .iter()
+                    .skip(1)
+                    .position(|&c| c == 0)
+                    .and_then(|end| body.get(end + 2)),
+            };
+            extras.cover.is_some() && picture_type == Some(&PICTURE_TYPE_FRONT_COVER)
+        } else {
+            (id == id_of(TITLE_FRAME) && extras.title.is_some())
+                || (id == id_of(ARTIST_FRAME) && extras.artist.is_some())
+                || (id == id_of(ALBUM_FRAME) && extras.album.is_some())
+        }
+    }
+
+    /// Split the frames into `(frames, padding)`, dropping the frames replaced by ours.
+    fn filter_frames(&self, version: u8, frames: &[u8]) -> Option<(Vec<u8>, usize)> {
+        let header_len = Self::frame_header_len(version);
+        let id_len = frame_id(version, TXXX_FRAME).len();
+
+        let mut result = vec![];
+        let mut pos = 0;
+        while let Some(header) = frames.get(pos..pos + header_len) {
+            if header[0] == 0 {
+                break;
+            }
+            let size = match version {
+                2 => BE::read_u24(&header[3..]) as usize,
+                3 => BE::read_u32(&header[4..]) as usize,
+                _ => read_syncsafe(&header[4..8])?,
+            };
+            let frame = frames.get(pos..pos + header_len + size)?;
+            if !self.is_replaced(version, &header[..id_len], &frame[header_len..]) {
+                result.extend_from_slice(frame);
+            }
+            pos += frame.len();
+        }
+
+        Some((result, frames.len() - pos))
+    }
+
+    /// Rewrite the tag in `data`, returns `None` if unsupported.
+    fn rewrite_tag(&self, data: &[u8]) -> Option<Vec<u8>> {
+        let version = data[3];
+        let flags = data[5];
+        let size = read_syncsafe(&data[6..10])?;
+        if !(2..=4).contains(&version) || flags & FLAG_EXTENDED_HEADER != 0 {
+            return None;
+        }
+
+        let body = &data[ID3_HEADER_LEN..ID3_HEADER_LEN + size];
+        let unsynchronised = flags & FLAG_UNSYNCHRONISATION != 0;
+        let new_frames = self.new_frames(version, unsynchronised);
+
+        // Before ID3v2.4, the unsynchronisation applies to the whole tag:
+        // the frames can't be walked as-is, ours are added in front of them.
+        let new_body = match (version, unsynchronised) {
+            (2 | 3, true) => [unsynchronise(&new_frames).as_slice(), body].concat(),
+            _ => {
+                let (frames, padding_len) = self.filter_frames(version, body)?;
+                let mut new_body = frames;
+                new_body.extend(new_frames);
+                new_body.resize(new_body.len() + padding_len, 0);
+                new_body
+            }
+        };
+        if new_body.len() > MAX_SYNCSAFE {
+            return None;
+        }
+
+        let mut result = data[..6].to_vec();
+        result.extend_from_slice(&syncsafe(new_body.len()));
+        result.extend(new_body);
+        if version == 4 && flags & FLAG_FOOTER != 0 {
+            let footer = [b"3DI".as_slice(), &result[3..ID3_HEADER_LEN]].concat();
+            result.extend(footer);
+        }
+        Some(result)
+    }
+
+    fn new_tag(&self) -> Option<Vec<u8>> {
+        let frames = self.new_frames(4, false);
+        if frames.len() > MAX_SYNCSAFE {
+            return None;
+        }
+
+        let mut result = ID3_MAGIC.to_vec();
+        result.extend_from_slice(&[4, 0, 0]);
+        result.extend_from_slice(&syncsafe(frames.len()));
+        result.extend(frames);
+        Some(result)
+    }
+
+    pub fn process<W: Write + ?Sized>(
+        &mut self,
+        buffer: &mut Vec<u8>,
+        out: &mut W,
+        eof: bool,
+    ) -> io::Result<Step> {
+        let incomplete = match eof {
+            true => Step::Done { tagged: false },
+            false => Step::NeedMore,
+        };
+
+        if buffer.len() < ID3_HEADER_LEN {
+            return Ok(incomplete);
+        }
+        if !buffer.starts_with(ID3_MAGIC) {
+            return match self.new_tag() {
+                Some(tag) => {
+                    out.write_all(&tag)?;
+                    Ok(Step::Done { tagged: true })
+                }
+                None => Ok(Step::Done { tagged: false }),
+            };
+        }
+
+        let Some(size) = read_syncsafe(&buffer[6..10]) else {
+            return Ok(Step::Done { tagged: false });
+        };
+        let footer_len = match buffer[3] == 4 && buffer[5] & FLAG_FOOTER != 0 {
+            true => ID3_HEADER_LEN,
+            false => 0,
+        };
+        let tag_len = ID3_HEADER_LEN + size + footer_len;
+        if buffer.len() < tag_len {
+            return Ok(incomplete);
+        }
+
+        match self.rewrite_tag(&buffer[..tag_len]) {
+            Some(tag) => {
+                out.write_all(&tag)?;
+                buffer.drain(..tag_len);
+                Ok(Step::Done { tagged: true })
+            }
+            None => Ok(Step::Done { tagged: false }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tagging::tests::{sample_extras, test_tags, write_tagged, write_with_extras};
+
+    use super::*;
+
+    const MP3_FRAME: &[u8] = b"\xFF\xFB\x90\x64 frame data";
+
+    fn id3_tag(version: u8, flags: u8, frames: &[u8], padding: usize) -> Vec<u8> {
+        let mut result = vec![b'I', b'D', b'3', version, 0, flags];
+        result.extend_from_slice(&syncsafe(frames.len() + padding));
+        result.extend_from_slice(frames);
+        result.resize(result.len() + padding, 0);
+        result
+    }
+
+    #[test]
+    fn test_new_tag() {
+        let (output, report) = write_tagged(MP3_FRAME, test_tags());
+        assert!(report.tagged);
+
+        let frames = [
+            b"TXXX\0\0\0\x1B\0\0\x03QQMUSIC_MID\x00001y7CaR29k6YP".as_slice(),
+            b"TXXX\0\0\0\x1C\0\0\x03QQMUSIC_RESOURCE_ID\x005177785",
+        ]
+        .concat();
+        assert_eq!(
+            output,
+            [id3_tag(4, 0, &frames, 0), MP3_FRAME.to_vec()].concat()
+        );
+    }
+
+    #[test]
+    fn test_update_v23() {
+        let title = b"TIT2\0\0\0\x05\0\0\0song";
+        let old = b"TXXX\0\0\0\x10\0\0\0QQMUSIC_MID\0old";
+        let frames = [title.as_slice(), old].concat();
+        let input = [id3_tag(3, 0, &frames, 32), MP3_FRAME.to_vec()].concat();
+
+        let tags = vec![Tag::new("QQMUSIC_MID", "新")];
+        let (output, report) = write_tagged(&input, tags);
+        assert!(report.tagged);
+
+        let new =
+            b"TXXX\0\0\0\x1F\0\0\x01\xFF\xFEQ\0Q\0M\0U\0S\0I\0C\0_\0M\0I\0D\0\0\0\xFF\xFE\xB0\x65";
+        let expected = id3_tag(3, 0, &[title.as_slice(), new.as_slice()].concat(), 32);
+        assert_eq!(output, [expected, MP3_FRAME.to_vec()].concat());
+    }
+
+    #[test]
+    fn test_update_v22_unsynchronised() {
+        let frames = b"TT2\0\0\x05\0song";
+        let input = [
+            id3_tag(2, FLAG_UNSYNCHRONISATION, frames, 0),
+            MP3_FRAME.to_vec(),
+        ]
+        .concat();
+
+        let tags = vec![Tag::new("KEY", "\u{FFFF}")];
+        let (output, report) = write_tagged(&input, tags);
+        assert!(report.tagged);
+
+        // The BOM and `U+FFFF` are unsynchronised.
+        let new = b"TXX\0\0\x0F\x01\xFF\0\xFEK\0E\0Y\0\0\0\xFF\0\xFE\xFF\0\xFF\0";
+        let expected = id3_tag(
+            2,
+            FLAG_UNSYNCHRONISATION,
+            &[new.as_slice(), frames].concat(),
+            0,
+        );
+        assert_eq!(output, [expected, MP3_FRAME.to_vec()].concat());
+    }
+
+    #[test]
+    fn test_extended_header_is_kept() {
+        let input = [
+            id3_tag(4, FLAG_EXTENDED_HEADER, &[0; 16], 0),
+            MP3_FRAME.to_vec(),
+        ]
+        .concat();
+        let (output, report) = write_tagged(&input, test_tags());
+        assert!(!report.tagged);
+        assert_eq!(output, input);
+    }
+
+    fn frame_v23(id: &[u8; 4], body: &[u8]) -> Vec<u8> {
+        Id3Tagger::make_frame(3, id, body.to_vec(), false)
+    }
+
+    #[test]
+    fn test_extras_v23() {
+        let album = frame_v23(b"TALB", b"\0album");
+        let frames = [
+            frame_v23(b"TIT2", b"\0old"),
+            album.clone(),
+            frame_v23(b"APIC", b"\0image/jpeg\0\x03\0old cover"),
+            frame_v23(b"APIC", b"\0image/jpeg\0\x04\0back cover"),
+        ]
+        .concat();
+        let input = [id3_tag(3, 0, &frames, 8), MP3_FRAME.to_vec()].concat();
+
+        let (output, report) = write_with_extras(&input, vec![], sample_extras());
+        assert!(report.tagged);
+
+        let expected = [
+            album,
+            frame_v23(b"APIC", b"\0image/jpeg\0\x04\0back cover"),
+            frame_v23(b"TIT2", b"\x01\xFF\xFE\x07\x68\x98\x98"),
+            frame_v23(b"TPE1", b"\0artist"),
+            frame_v23(b"APIC", b"\0image/png\0\x03\0\x89PNG\r\n\x1a\n cover"),
+        ]
+        .concat();
+        assert_eq!(
+            output,
+            [id3_tag(3, 0, &expected, 8), MP3_FRAME.to_vec()].concat()
+        );
+    }
+
+    #[test]
+    fn test_extras_v24_unsynchronised() {
+        let input = [
+            id3_tag(4, FLAG_UNSYNCHRONISATION, &[], 0),
+            MP3_FRAME.to_vec(),
+        ]
+        .concat();
+        let mut extras = sample_extras();
+        extras.cover = Some(Cover::new(Some("image/jpeg"), *b"\xFF\xD8\xFF\xE0"));
+        extras.title = None;
+        extras.artist = None;
+
+        let (output, report) = write_with_extras(&input, vec![], extras);
+        assert!(report.tagged);
+
+        let apic = b"APIC\0\0\0\x13\0\x02\0image/jpeg\0\x03\0\xFF\xD8\xFF\0\xE0";
+        let expected = id3_tag(4, FLAG_UNSYNCHRONISATION, apic, 0);
+        assert_eq!(output, [expected, MP3_FRAME.to_vec()].concat());
+    }
+}
@@ -0,0 +1,294 @@
+//! Write custom tags to the container of a decrypted stream, e.g. to keep the provenance
+//! information found in the encrypted file (see [`provenance`]), along with the
+//! [`MediaExtras`] carried by some formats.
+//!
+//! [`TagWriter`] wraps the output: the container is detected from the first bytes, and only
+//! its tag area is rewritten while the audio is streamed through, so there's no second pass.
+//!
+//! | Container | Tags                                           |
+//! |-----------|------------------------------------------------|
+//! | FLAC      | Vorbis comments                                |
+//! | Ogg       | Vorbis comments (Vorbis and Opus streams)      |
+//! | MP3       | ID3v2 `TXXX` frames                            |
+//! | M4A       | iTunes freeform atoms (`----:com.apple.iTunes`) |
+//!
+//! The extras are written to the standard fields of the container: `TITLE` / `ARTIST` /
+//! `ALBUM` and `PICTURE` (`METADATA_BLOCK_PICTURE` in Ogg) for Vorbis comments;
+//! `TIT2` / `TPE1` / `TALB` and `APIC` for ID3v2; `©nam` / `©ART` / `©alb` and `covr` for M4A.
+//!
+//! Other containers, or streams that can't be parsed, are written as-is.
+
+use std::io::{self, Write};
+
+use crate::utils::audio::{detect_audio_type, AudioType, AUDIO_DETECTION_LEN};
+use crate::utils::media_extras::MediaExtras;
+
+mod flac;
+mod id3;
+mod mp4;
+mod ogg;
+pub mod provenance;
+mod vorbis;
+
+/// Maximum number of bytes buffered before giving up, e.g. for a huge FLAC picture block.
+pub const MAX_BUFFER_LEN: usize = 64 * 1024 * 1024;
+
+/// A custom text tag, e.g. `QQMUSIC_MID=001y7CaR29k6YP`.
+///
+/// Existing tags with the same key are replaced.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tag {
+    pub key: String,
+    pub value: String,
+}
+
+impl Tag {
+    pub fn new<K: Into<String>, V: Into<String>>(key: K, value: V) -> Self {
+        Self {
+            key: key.into(),
+            value: value.into(),
+        }
+    }
+}
+
+/// Everything written by the taggers.
+#[derive(Debug, Clone, Default)]
+struct Metadata {
+    tags: Vec<Tag>,
+    extras: MediaExtras,
+}
+
+impl Metadata {
+    fn is_empty(&self) -> bool {
+        self.tags.is_empty() && self.extras.is_empty()
+    }
+}
+
+/// Result of [`TagWriter::finish`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TagReport {
+    /// Detected container, `None` if unknown (or there was nothing to write).
+    pub audio_type: Option<AudioType>,
+    /// `true` if the tags (and extras) were written.
+    pub tagged: bool,
+}
+
+/// What a container tagger needs next.
+enum Step {
+    /// Everything that could be processed was consumed from the buffer; feed more data.
+    NeedMore,
+    /// Stop processing, the rest of the buffer and the stream is copied as-is.
+    Done { tagged: bool },
+}
+
+enum Tagger {
+    Flac(flac::FlacTagger),
+    Mp3(id3::Id3Tagger),
+    Ogg(ogg::OggTagger),
+    M4a(mp4::Mp4Tagger),
+}
+
+impl Tagger {
+    fn new(audio_type: AudioType, metadata: &Metadata) -> Option<Self> {
+        let metadata = metadata.clone();
+        match audio_type {
+            AudioType::Flac => Some(Self::Flac(flac::FlacTagger::new(metadata))),
+            AudioType::Mp3 => Some(Self::Mp3(id3::Id3Tagger::new(metadata))),
+            AudioType::Ogg => Some(Self::Ogg(ogg::OggTagger::new(metadata))),
+            AudioType::M4a => Some(Self::M4a(mp4::Mp4Tagger::new(metadata))),
+            _ => None,
+        }
+    }
+
+    /// Consume what can be processed from the front of `buffer`, and write the result
+    /// to `out`. `eof` is set when no more data will come; `Step::Done` must be returned.
+    fn process<W: Write + ?Sized>(
+        &mut self,
+        buffer: &mut Vec<u8>,
+        out: &mut W,
+        eof: bool,
+    ) -> io::Result<Step> {
+        match self {
+            Tagger::Flac(t) => t.process(buffer, out, eof),
+            Tagger::Mp3(t) => t.process(buffer, out, eof),
+            Tagger::Ogg(t) => t.process(buffer, out, eof),
+            Tagger::M4a(t) => t.process(buffer, out, eof),
+        }
+    }
+}
+
+enum State {
+    Detect,
+    Process(Tagger),
+    Passthrough,
+}
+
+/// Writer adding tags to the container written through it.
+///
+/// [`TagWriter::finish`] must be called once everything is written.
+pub struct TagWriter<W: Write> {
+    inner: W,
+    metadata: Metadata,
+    buffer: Vec<u8>,
+    state: State,
+    report: TagReport,
+}
+
+impl<W: Write> TagWriter<W> {
+    pub fn new(inner: W, tags: Vec<Tag>) -> Self {
+        Self {
+            inner,
+            metadata: Metadata {
+                tags,
+                extras: MediaExtras::default(),
+            },
+            buffer: vec![],
+            state: State::Detect,
+            report: TagReport {
+                audio_type: None,
+                tagged: false,
+            },
+        }
+    }
+
+    /// Also write the extras, e.g. the cover found in the encrypted file.
+    pub fn with_extras(mut self, extras: MediaExtras) -> Self {
+        self.metadata.extras = extras;
+        self
+    }
+
+    /// `true` if there's nothing to write: the stream is copied as-is.
+    pub fn is_empty(&self) -> bool {
+        self.metadata.is_empty()
+    }
+
+    fn process(&mut self, eof: bool) -> io::Result<()> {
+        loop {
+            match &mut self.state {
+                State::Detect if self.metadata.is_empty() => self.state = State::Passthrough,
+                State::Detect => {
+                    if self.buffer.len() < AUDIO_DETECTION_LEN && !eof {
+                        return Ok(());
+                    }
+                    self.report.audio_type = detect_audio_type(&self.buffer);
+                    self.state = match self.report.audio_type {
+                        Some(audio_type) => Tagger::new(audio_type, &self.metadata)
+                            .map_or(State::Passthrough, State::Process),
+                        None => State::Passthrough,
+                    };
+                }
+                State::Process(tagger) => {
+                    match tagger.process(&mut self.buffer, &mut self.inner, eof)? {
+                        Step::NeedMore if !eof && self.buffer.len() <= MAX_BUFFER_LEN => {
+                            return Ok(())
+                        }
+                        Step::NeedMore => self.state = State::Passthrough,
+                        Step::Done { tagged } => {
+                            self.report.tagged = tagged;
+                            self.state = State::Passthrough;
+                        }
+                    }
+                }
+                State::Passthrough => {
+                    self.inner.write_all(&self.buffer)?;
+                    self.buffer.clear();
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    /// Write what's left in the buffer, and return the inner writer.
+    pub fn finish(mut self) -> io::Result<(W, TagReport)> {
+        self.process(true)?;
+        self.inner.flush()?;
+        Ok((self.inner, self.report))
+    }
+}
+
+impl<W: Write> Write for TagWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self.state {
+            State::Passthrough => self.inner.write(buf),
+            _ => {
+                self.buffer.extend_from_slice(buf);
+                self.process(false)?;
+                Ok(buf.len())
+            }
+        }
+    }
+
+    /// Flush the inner writer; data held back to rewrite the tags is only written once
+    /// they are processed, or by [`TagWriter::finish`].
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use crate::utils::media_extras::Cover;
+
+    use super::*;
+
+    pub fn test_tags() -> Vec<Tag> {
+        vec![
+            Tag::new("QQMUSIC_MID", "001y7CaR29k6YP"),
+            Tag::new("QQMUSIC_RESOURCE_ID", "5177785"),
+        ]
+    }
+
+    pub fn sample_extras() -> MediaExtras {
+        MediaExtras {
+            title: Some("标题".into()),
+            artist: Some("artist".into()),
+            album: None,
+            cover: Some(Cover::new(None, *b"\x89PNG\r\n\x1a\n cover")),
+        }
+    }
+
+    /// Write `data` in small chunks, to exercise the buffering.
+    pub fn write_tagged(data: &[u8], tags: Vec<Tag>) -> (Vec<u8>, TagReport) {
+        write_with_extras(data, tags, MediaExtras::default())
+    }
+
+    pub fn write_with_extras(
+        data: &[u8],
+        tags: Vec<Tag>,
+        extras: MediaExtras,
+    ) -> (Vec<u8>, TagReport) {
+        let mut writer = TagWriter::new(vec![], tags).with_extras(extras);
+        for chunk in data.chunks(7) {
+            writer.write_all(chunk).unwrap();
+        }
+        writer.finish().unwrap()
+    }
+
+    #[test]
+    fn test_passthrough() {
+        let data = b"RIFF\x24\0\0\0WAVEfmt some audio data";
+        let (output, report) = write_tagged(data, test_tags());
+        assert_eq!(output, data);
+        assert_eq!(report.audio_type, Some(AudioType::Wav));
+        assert!(!report.tagged);
+
+        let (output, report) = write_tagged(b"fLaC", vec![]);
+        assert_eq!(output, b"fLaC");
+        assert_eq!(report.audio_type, None);
+
+        // Extras only.
+        let (output, report) = write_with_extras(b"fLaC", vec![], sample_extras());
+        assert_eq!(output, b"fLaC");
+        assert_eq!(report.audio_type, Some(AudioType::Flac));
+    }
+
+    #[test]
+    fn test_malformed_is_written_as_is() {
+        // Truncated FLAC metadata block.
+        let data = b"fLaC\x80\0\0\x22\0\0\0\0";
+        let (output, report) = write_tagged(data, test_tags());
+        assert_eq!(output, data);
+        assert_eq!(report.audio_type, Some(AudioType::Flac));
+        assert!(!report.tagged);
+    }
+}
@@ -0,0 +1,501 @@
+use std::io::{self, Write};
+use std::ops::Range;
+
+use byteorder::{ByteOrder, BE};
+
+use crate::utils::media_extras::Cover;
+
+use super::{Metadata, Step, Tag};
+
+const BOX_HEADER_LEN: usize = 8;
+const BOX_LARGE_HEADER_LEN: usize = 16;
+const FULL_BOX_HEADER_LEN: usize = 4;
+
+const FREEFORM_MEAN: &[u8] = b"com.apple.iTunes";
+const DATA_TYPE_UTF8: u32 = 1;
+const DATA_TYPE_JPEG: u32 = 13;
+const DATA_TYPE_PNG: u32 = 14;
+const DATA_TYPE_BMP: u32 = 27;
+
+const ITEM_TITLE: &[u8; 4] = b"\xA9nam";
+const ITEM_ARTIST: &[u8; 4] = b"\xA9ART";
+const ITEM_ALBUM: &[u8; 4] = b"\xA9alb";
+const ITEM_COVER: &[u8; 4] = b"covr";
+const ITEM_FREEFORM: &[u8; 4] = b"----";
+
+/// Boxes containing the chunk offset tables, from `moov`.
+const STBL_PATH: &[&[u8; 4]] = &[b"trak", b"mdia", b"minf", b"stbl"];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct BoxInfo {
+    box_type: [u8; 4],
+    /// Start of the box (its header).
+    start: usize,
+    /// Start of the box content.
+    content: usize,
+    end: usize,
+}
+
+/// Read the header of the box at `pos`: `(type, header length, box length)`;
+/// a box length of `None` extends to the end of the file.
+fn box_header(data: &[u8], pos: usize) -> Option<([u8; 4], usize, Option<u64>)> {
+    let header = data.get(pos..pos + BOX_HEADER_LEN)?;
+    let box_type = header[4..8].try_into().unwrap();
+    match BE::read_u32(header) {
+        0 => Some((box_type, BOX_HEADER_LEN, None)),
+        1 => {
+            let size = BE::read_u64(data.get(pos + 8..pos + BOX_LARGE_HEADER_LEN)?);
+            Some((box_type, BOX_LARGE_HEADER_LEN, Some(size)))
+        }
+        size => Some((box_type, BOX_HEADER_LEN, Some(u64::from(size)))),
+    }
+}
+
+/// List the boxes in `range`; `None` if they don't fit.
+fn children(data: &[u8], range: Range<usize>) -> Option<Vec<BoxInfo>> {
+    let mut result = vec![];
+    let mut pos = range.start;
+    while pos < range.end {
+        let (box_type, header_len, size) = box_header(&data[..range.end], pos)?;
+        let end = match size {
+            Some(size) => pos.checked_add(usize::try_from(size).ok()?)?,
+            None => range.end,
+        };
+        if end > range.end || end < pos + header_len {
+            return None;
+        }
+        result.push(BoxInfo {
+            box_type,
+            start: pos,
+            content: pos + header_len,
+            end,
+        });
+        pos = end;
+    }
+    Some(result)
+}
+
+fn find(boxes: &[BoxInfo], box_type: &[u8; 4]) -> Option<BoxInfo> {
+    boxes.iter().find(|b| &b.box_type == box_type).cloned()
+}
+
+fn make_box(box_type: &[u8; 4], content: &[u8]) -> Vec<u8> {
+    let mut result = ((BOX_HEADER_LEN + content.len()) as u32)
+        .to_be_bytes()
+        .to_vec();
+    result.extend_from_slice(box_type);
+    result.extend_from_slice(content);
+    result
+}
+
+fn data_box(data_type: u32, value: &[u8]) -> Vec<u8> {
+    let mut data = data_type.to_be_bytes().to_vec();
+    data.extend_from_slice(&[0; 4]); // locale
+    data.extend_from_slice(value);
+    make_box(b"data", &data)
+}
+
+/// `----` item of `ilst`, with its `mean`, `name` and `data` boxes.
+fn freeform_item(tag: &Tag) -> Vec<u8> {
+    let mean = make_box(b"mean", &[&[0; 4], FREEFORM_MEAN].concat());
+    let name = make_box(b"name", &[&[0; 4], tag.key.as_bytes()].concat());
+    let data = data_box(DATA_TYPE_UTF8, tag.value.as_bytes());
+    make_box(ITEM_FREEFORM, &[mean, name, data].concat())
+}
+
+fn cover_item(cover: &Cover) -> Vec<u8> {
+    let data_type = match cover.extension() {
+        "png" => DATA_TYPE_PNG,
+        "bmp" => DATA_TYPE_BMP,
+        _ => DATA_TYPE_JPEG,
+    };
+    make_box(ITEM_COVER, &data_box(data_type, &cover.data))
+}
+
+/// Name of a `----` item.
+fn freeform_name<'a>(data: &'a [u8], item: &BoxInfo) -> Option<&'a [u8]> {
+    let name = find(&children(data, item.content..item.end)?, b"name")?;
+    data.get(name.content + FULL_BOX_HEADER_LEN..name.end)
+}
+
+/// `meta` box (a full box) with the `hdlr` expected by iTunes, and the given `ilst`.
+fn meta_box(ilst: &[u8]) -> Vec<u8> {
+    let mut hdlr = vec![0; 8]; // version, flags & pre_defined
+    hdlr.extend_from_slice(b"mdirappl");
+    hdlr.extend_from_slice(&[0; 9]); // reserved & empty name
+    let hdlr = make_box(b"hdlr", &hdlr);
+    make_box(b"meta", &[&[0; 4], hdlr.as_slice(), ilst].concat())
+}
+
+/// Add `----:com.apple.iTunes` items, and the extras (`©nam`, `©ART`, `©alb` and `covr`),
+/// to `moov/udta/meta/ilst`, which is created if missing.
+pub struct Mp4Tagger {
+    metadata: Metadata,
+    /// Bytes left to copy of the current box, before the next top level box.
+    remaining: u64,
+    /// Position in the input.
+    offset: u64,
+}
+
+impl Mp4Tagger {
+    pub fn new(metadata: Metadata) -> Self {
+        Self {
+            metadata,
+            remaining: 0,
+            offset: 0,
+        }
+    }
+
+    /// Items to add to `ilst`, with their types.
+    fn new_items(&self) -> Vec<([u8; 4], Vec<u8>)> {
+        let extras = &self.metadata.extras;
+        let text_items = [
+            (ITEM_TITLE, &extras.title),
+            (ITEM_ARTIST, &extras.artist),
+            (ITEM_ALBUM, &extras.album),
+        ];
+
+        let mut items = vec![];
+        for (item_type, text) in text_items {
+            if let Some(text) = text {
+                items.push((
+                    *item_type,
+                    make_box(item_type, &data_box(DATA_TYPE_UTF8, text.as_bytes())),
+                ));
+            }
+        }
+        if let Some(cover) = &extras.cover {
+            items.push((*ITEM_COVER, cover_item(cover)));
+        }
+        for tag in &self.metadata.tags {
+            items.push((*ITEM_FREEFORM, freeform_item(tag)));
+        }
+        items
+    }
+
+    /// Check if an existing item is replaced by one of ours.
+    fn is_replaced(&self, moov: &[u8], item: &BoxInfo, new_items: &[([u8; 4], Vec<u8>)]) -> bool {
+        match &item.box_type {
+            ITEM_FREEFORM => freeform_name(moov, item).is_some_and(|name| {
+                self.metadata
+                    .tags
+                    .iter()
+                    .any(|tag| tag.key.as_bytes() == name)
+            }),
+            item_type => new_items.iter().any(|(new_type, _)| new_type == item_type),
+        }
+    }
+
+    /// Rewrite `moov`, located at `moov_offset` in the file; `None` if unsupported.
+    fn rewrite_moov(&self, moov: &[u8], moov_offset: u64) -> Option<Vec<u8>> {
+        let new_items = self.new_items();
+        let new_data = new_items
+            .iter()
+            .flat_map(|(_, item)| item)
+            .copied()
+            .collect::<Vec<_>>();
+
+        // Boxes to update the size of, and the range to replace.
+        let mut ancestors = vec![0];
+        let root = children(moov, BOX_HEADER_LEN..moov.len())?;
+        let (range, replacement) = match find(&root, b"udta") {
+            None => (
+                moov.len()..moov.len(),
+                make_box(b"udta", &meta_box(&make_box(b"ilst", &new_data))),
+            ),
+            Some(udta) => {
+                ancestors.push(udta.start);
+                match find(&children(moov, udta.content..udta.end)?, b"meta") {
+                    None => (udta.end..udta.end, meta_box(&make_box(b"ilst", &new_data))),
+                    Some(meta) => {
+                        ancestors.push(meta.start);
+                        let meta_content = meta.content + FULL_BOX_HEADER_LEN;
+                        match find(&children(moov, meta_content..meta.end)?, b"ilst") {
+                            None => (meta.end..meta.end, make_box(b"ilst", &new_data)),
+                            Some(ilst) => {
+                                ancestors.push(ilst.start);
+                                let mut items = vec![];
+                                for item in children(moov, ilst.content..ilst.end)? {
+                                    if !self.is_replaced(moov, &item, &new_items) {
+                                        items.extend_from_slice(&moov[item.start..item.end]);
+                                    }
+                                }
+                                items.extend(new_data);
+                                (ilst.content..ilst.end, items)
+                            }
+                        }
+                    }
+                }
+            }
+        };
+
+        let delta = replacement.len() as i64 - range.len() as i64;
+        let mut result = moov[..range.start].to_vec();
+        result.extend(replacement);
+        result.extend_from_slice(&moov[range.end..]);
+
+        for start in ancestors {
+            match BE::read_u32(&result[start..]) {
+                1 => {
+                    let size = BE::read_u64(&result[start + 8..]) as i64 + delta;
+                    BE::write_u64(&mut result[start + 8..], size as u64);
+                }
+                size => {
+                    let size = u32::try_from(i64::from(size) + delta).ok()?;
+                    BE::write_u32(&mut result[start..], size);
+                }
+            }
+        }
+
+        // Chunks stored after `moov` are moved.
+        let moov_end = moov_offset + moov.len() as u64;
+        Self::shift_chunk_offsets(&mut result, moov_end, delta)?;
+        Some(result)
+    }
+
+    /// Add `delta` to the chunk offsets (`stco` / `co64`) that are after `threshold`.
+    fn shift_chunk_offsets(moov: &mut [u8], threshold: u64, delta: i64) -> Option<()> {
+        // Children of each `stbl`.
+        let mut boxes = children(moov, BOX_HEADER_LEN..moov.len())?;
+        for box_type in STBL_PATH {
+            let mut next = vec![];
+            for parent in boxes.iter().filter(|b| &b.box_type == *box_type) {
+                next.extend(children(moov, parent.content..parent.end)?);
+            }
+            boxes = next;
+        }
+
+        for table in boxes {
+            let entry_len = match &table.box_type {
+                b"stco" => 4,
+                b"co64" => 8,
+                _ => continue,
+            };
+            let count_pos = table.content + FULL_BOX_HEADER_LEN;
+            let count = BE::read_u32(moov.get(count_pos..count_pos + 4)?) as usize;
+            let entries = moov
+                .get_mut(count_pos + 4..)?
+                .get_mut(..count * entry_len)?;
+            for entry in entries.chunks_exact_mut(entry_len) {
+                let offset = match entry_len {
+                    4 => u64::from(BE::read_u32(entry)),
+                    _ => BE::read_u64(entry),
+                };
+                if offset < threshold {
+                    continue;
+                }
+                let offset = u64::try_from(offset as i64 + delta).ok()?;
+                match entry_len {
+                    4 => BE::write_u32(entry, u32::try_from(offset).ok()?),
+                    _ => BE::write_u64(entry, offset),
+                }
+            }
+        }
+        Some(())
+    }
+
+    pub fn process<W: Write + ?Sized>(
+        &mut self,
+        buffer: &mut Vec<u8>,
+        out: &mut W,
+        eof: bool,
+    ) -> io::Result<Step> {
+        loop {
+            if self.remaining > 0 {
+                let len = self.remaining.min(buffer.len() as u64) as usize;
+                out.write_all(&buffer[..len])?;
+                buffer.drain(..len);
+                self.remaining -= len as u64;
+                self.offset += len as u64;
+                if self.remaining > 0 || buffer.is_empty() {
+                    return Ok(match eof {
+                        true => Step::Done { tagged: false },
+                        false => Step::NeedMore,
+                    });
+                }
+            }
+
+            let Some((box_type, header_len, size)) = box_header(buffer, 0) else {
+                return Ok(match eof {
+                    true => Step::Done { tagged: false },
+                    false => Step::NeedMore,
+                });
+            };
+            let Some(size) = size.filter(|&size| size >= header_len as u64) else {
+                // Last box, or invalid size.
+                return Ok(Step::Done { tagged: false });
+            };
+
+            if &box_type != b"moov" {
+                self.remaining = size;
+                continue;
+            }
+
+            let Some(moov) = usize::try_from(size)
+                .ok()
+                .and_then(|size| buffer.get(..size))
+            else {
+                return Ok(match eof {
+                    true => Step::Done { tagged: false },
+                    false => Step::NeedMore,
+                });
+            };
+            return match self.rewrite_moov(moov, self.offset) {
+                Some(moov) => {
+                    out.write_all(&moov)?;
+                    buffer.drain(..size as usize);
+                    Ok(Step::Done { tagged: true })
+                }
+                None => Ok(Step::Done { tagged: false }),
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tagging::tests::{sample_extras, test_tags, write_tagged, write_with_extras};
+
+    use super::*;
+
+    fn full_box(box_type: &[u8; 4], content: &[u8]) -> Vec<u8> {
+        make_box(box_type, &[&[0; 4], content].concat())
+    }
+
+    fn moov(stco: &[u32], udta: Option<Vec<u8>>) -> Vec<u8> {
+        let mut table = (stco.len() as u32).to_be_bytes().to_vec();
+        stco.iter()
+            .for_each(|o| table.extend_from_slice(&o.to_be_bytes()));
+        let stbl = make_box(b"stbl", &full_box(b"stco", &table));
+        let trak = make_box(b"trak", &make_box(b"mdia", &make_box(b"minf", &stbl)));
+        make_box(b"moov", &[trak, udta.unwrap_or_default()].concat())
+    }
+
+    /// `(name, value)` of a freeform item.
+    type FreeformItem = (Vec<u8>, Vec<u8>);
+
+    /// Read the `stco` entries and the freeform items from the output.
+    fn parse_moov(file: &[u8]) -> (Vec<u32>, Vec<FreeformItem>) {
+        let top = children(file, 0..file.len()).unwrap();
+        let moov = find(&top, b"moov").unwrap();
+
+        let mut path = moov.content..moov.end;
+        for box_type in STBL_PATH.iter().chain(&[b"stco"]) {
+            let b = find(&children(file, path).unwrap(), box_type).unwrap();
+            path = b.content..b.end;
+        }
+        let stco = file[path.start + 8..path.end]
+            .chunks(4)
+            .map(BE::read_u32)
+            .collect();
+
+        let udta = find(&children(file, moov.content..moov.end).unwrap(), b"udta").unwrap();
+        let meta = find(&children(file, udta.content..udta.end).unwrap(), b"meta").unwrap();
+        let ilst = find(
+            &children(file, meta.content + 4..meta.end).unwrap(),
+            b"ilst",
+        )
+        .unwrap();
+        let items = children(file, ilst.content..ilst.end)
+            .unwrap()
+            .into_iter()
+            .filter(|item| &item.box_type == b"----")
+            .map(|item| {
+                let boxes = children(file, item.content..item.end).unwrap();
+                let data = find(&boxes, b"data").unwrap();
+                (
+                    freeform_name(file, &item).unwrap().to_vec(),
+                    file[data.content + 8..data.end].to_vec(),
+                )
+            })
+            .collect();
+        (stco, items)
+    }
+
+    #[test]
+    fn test_moov_before_mdat() {
+        let ftyp = make_box(b"ftyp", b"M4A \0\0\0\0");
+        let mdat = make_box(b"mdat", &[0x55; 64]);
+        let moov_len = moov(&[0, 0], None).len();
+        let chunks = [
+            (ftyp.len() + moov_len + 8) as u32,
+            (ftyp.len() + moov_len + 40) as u32,
+        ];
+        let input = [ftyp.clone(), moov(&chunks, None), mdat.clone()].concat();
+
+        let (output, report) = write_tagged(&input, test_tags());
+        assert!(report.tagged);
+        assert!(output.ends_with(&mdat));
+
+        let delta = (output.len() - input.len()) as u32;
+        let (stco, items) = parse_moov(&output);
+        assert_eq!(stco, vec![chunks[0] + delta, chunks[1] + delta]);
+        assert_eq!(
+            items,
+            vec![
+                (b"QQMUSIC_MID".to_vec(), b"001y7CaR29k6YP".to_vec()),
+                (b"QQMUSIC_RESOURCE_ID".to_vec(), b"5177785".to_vec()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_update_ilst_after_mdat() {
+        let ftyp = make_box(b"ftyp", b"M4A \0\0\0\0");
+        let mdat = make_box(b"mdat", &[0x55; 64]);
+        let title = make_box(b"\xA9nam", &make_box(b"data", b"\0\0\0\x01\0\0\0\0song"));
+        let old = freeform_item(&Tag::new("QQMUSIC_MID", "old"));
+        let ilst = make_box(b"ilst", &[title.clone(), old].concat());
+        let udta = make_box(b"udta", &meta_box(&ilst));
+        let chunks = [ftyp.len() as u32 + 8];
+        let input = [ftyp, mdat, moov(&chunks, Some(udta))].concat();
+
+        let (output, report) = write_tagged(&input, test_tags());
+        assert!(report.tagged);
+
+        let (stco, items) = parse_moov(&output);
+        assert_eq!(stco, chunks);
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0].1, b"001y7CaR29k6YP");
+        let pos = output.windows(title.len()).position(|w| w == title);
+        assert!(pos.is_some(), "other items are kept");
+    }
+
+    #[test]
+    fn test_no_moov() {
+        let input = [
+            make_box(b"ftyp", b"M4A \0\0\0\0"),
+            make_box(b"mdat", &[0x55; 64]),
+        ]
+        .concat();
+        let (output, report) = write_tagged(&input, test_tags());
+        assert!(!report.tagged);
+        assert_eq!(output, input);
+    }
+
+    #[test]
+    fn test_extras() {
+        let ftyp = make_box(b"ftyp", b"M4A \0\0\0\0");
+        let mdat = make_box(b"mdat", &[0x55; 64]);
+        let title = make_box(ITEM_TITLE, &data_box(DATA_TYPE_UTF8, b"old"));
+        let album = make_box(ITEM_ALBUM, &data_box(DATA_TYPE_UTF8, b"album"));
+        let ilst = make_box(b"ilst", &[title.clone(), album.clone()].concat());
+        let udta = make_box(b"udta", &meta_box(&ilst));
+        let chunks = [ftyp.len() as u32 + 8];
+        let input = [ftyp, mdat, moov(&chunks, Some(udta))].concat();
+
+        let (output, report) = write_with_extras(&input, vec![], sample_extras());
+        assert!(report.tagged);
+
+        let contains = |item: &[u8]| output.windows(item.len()).any(|w| w == item);
+        let new_title = make_box(ITEM_TITLE, &data_box(DATA_TYPE_UTF8, "标题".as_bytes()));
+        let cover = make_box(
+            ITEM_COVER,
+            &data_box(DATA_TYPE_PNG, b"\x89PNG\r\n\x1a\n cover"),
+        );
+        assert!(!contains(&title), "old title is replaced");
+        assert!(contains(&album), "other items are kept");
+        assert!(contains(&new_title));
+        assert!(contains(&cover));
+    }
+}
@@ -0,0 +1,441 @@
+use std::io::{self, Write};
+
+use base64::{engine::general_purpose::STANDARD as Base64, Engine as _};
+use byteorder::{ByteOrder, LE};
+
+use super::vorbis::{comment_tags, picture_block, VorbisComment, METADATA_BLOCK_PICTURE};
+use super::{Metadata, Step, Tag};
+
+const PAGE_MAGIC: &[u8; 4] = b"OggS";
+const PAGE_HEADER_LEN: usize = 27;
+const MAX_SEGMENTS: usize = 255;
+const FLAG_CONTINUED: u8 = 0x01;
+const GRANULE_NO_PACKET_END: u64 = u64::MAX;
+
+const VORBIS_IDENTIFICATION: &[u8] = b"\x01vorbis";
+const VORBIS_COMMENT: &[u8] = b"\x03vorbis";
+const OPUS_HEAD: &[u8] = b"OpusHead";
+const OPUS_TAGS: &[u8] = b"OpusTags";
+
+const fn crc_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = (i as u32) << 24;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = match crc & 0x8000_0000 {
+                0 => crc << 1,
+                _ => (crc << 1) ^ 0x04C1_1DB7,
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+static CRC_TABLE: [u32; 256] = crc_table();
+
+fn crc32(data: &[u8]) -> u32 {
+    data.iter().fold(0, |crc, &b| {
+        (crc << 8) ^ CRC_TABLE[(((crc >> 24) as u8) ^ b) as usize]
+    })
+}
+
+/// Set the checksum of a page.
+fn update_crc(page: &mut [u8]) {
+    page[22..26].fill(0);
+    let crc = crc32(page);
+    LE::write_u32(&mut page[22..26], crc);
+}
+
+/// Length of the page at the start of `data`:
+/// `None` if more data is needed, `Some(Err(()))` if it's not a page.
+fn page_len(data: &[u8]) -> Option<Result<usize, ()>> {
+    if data.len() < PAGE_HEADER_LEN {
+        return None;
+    }
+    if !data.starts_with(PAGE_MAGIC) || data[4] != 0 {
+        return Some(Err(()));
+    }
+    let segments = data.get(PAGE_HEADER_LEN..PAGE_HEADER_LEN + data[26] as usize)?;
+    let len =
+        PAGE_HEADER_LEN + segments.len() + segments.iter().map(|&n| n as usize).sum::<usize>();
+    match data.len() >= len {
+        true => Some(Ok(len)),
+        false => None,
+    }
+}
+
+fn page_serial(page: &[u8]) -> u32 {
+    LE::read_u32(&page[14..18])
+}
+
+fn page_sequence(page: &[u8]) -> u32 {
+    LE::read_u32(&page[18..22])
+}
+
+fn page_body(page: &[u8]) -> &[u8] {
+    &page[PAGE_HEADER_LEN + page[26] as usize..]
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Codec {
+    Vorbis,
+    Opus,
+}
+
+impl Codec {
+    fn detect(packet: &[u8]) -> Option<Self> {
+        match packet {
+            p if p.starts_with(VORBIS_IDENTIFICATION) => Some(Self::Vorbis),
+            p if p.starts_with(OPUS_HEAD) => Some(Self::Opus),
+            _ => None,
+        }
+    }
+
+    /// Number of header packets, including the identification header.
+    fn header_packets(&self) -> usize {
+        match self {
+            Codec::Vorbis => 3,
+            Codec::Opus => 2,
+        }
+    }
+
+    fn comment_magic(&self) -> &'static [u8] {
+        match self {
+            Codec::Vorbis => VORBIS_COMMENT,
+            Codec::Opus => OPUS_TAGS,
+        }
+    }
+}
+
+enum State {
+    /// Waiting for the first page.
+    Start,
+    /// Collecting the pages of the header packets following the identification header.
+    Headers { codec: Codec, serial: u32 },
+    /// Headers rewritten; the sequence number of the following pages are shifted.
+    Renumber { serial: u32, delta: u32 },
+}
+
+/// Rewrite the comment header of an Ogg Vorbis or Opus stream.
+///
+/// The header packets are paginated again; the sequence numbers of the following pages
+/// of the stream are shifted if the number of pages changed.
+pub struct OggTagger {
+    /// Comments to write, including the cover.
+    tags: Vec<Tag>,
+    state: State,
+    /// Raw header pages, after the first page.
+    pages: Vec<Vec<u8>>,
+    /// Complete header packets, after the identification header.
+    packets: Vec<Vec<u8>>,
+    partial_packet: Vec<u8>,
+}
+
+impl OggTagger {
+    pub fn new(metadata: Metadata) -> Self {
+        let mut tags = comment_tags(&metadata);
+        if let Some(cover) = &metadata.extras.cover {
+            let picture = Base64.encode(picture_block(cover));
+            tags.push(Tag::new(METADATA_BLOCK_PICTURE, picture));
+        }
+
+        Self {
+            tags,
+            state: State::Start,
+            pages: vec![],
+            packets: vec![],
+            partial_packet: vec![],
+        }
+    }
+
+    /// Build the comment packet with our tags.
+    fn comment_packet(&self, codec: Codec, packet: &[u8]) -> Option<Vec<u8>> {
+        let magic = codec.comment_magic();
+        let data = packet.strip_prefix(magic)?;
+        let (mut comment, len) = VorbisComment::from_bytes(data)?;
+        comment.set_tags(&self.tags);
+
+        let mut result = magic.to_vec();
+        result.extend(comment.to_bytes());
+        // Vorbis framing bit, or Opus extra data.
+        result.extend_from_slice(&data[len..]);
+        Some(result)
+    }
+
+    /// Split the packets into pages, numbered from `sequence`.
+    fn paginate(packets: &[Vec<u8>], serial: u32, sequence: u32) -> Vec<Vec<u8>> {
+        // `(lacing value, end of packet)` of each segment.
+        let segments = packets.iter().flat_map(|packet| {
+            let n = packet.len() / 255;
+            (0..=n).map(move |i| match i == n {
+                true => ((packet.len() % 255) as u8, true),
+                false => (255, false),
+            })
+        });
+        let segments = segments.collect::<Vec<_>>();
+        let mut body = packets.concat().into_iter();
+
+        let mut pages = vec![];
+        let mut continued = false;
+        for (i, chunk) in segments.chunks(MAX_SEGMENTS).enumerate() {
+            let body_len = chunk.iter().map(|&(n, _)| n as usize).sum::<usize>();
+            let granule = match chunk.iter().any(|&(_, end)| end) {
+                true => 0,
+                false => GRANULE_NO_PACKET_END,
+            };
+
+            let mut page = PAGE_MAGIC.to_vec();
+            page.push(0);
+            page.push(if continued { FLAG_CONTINUED } else { 0 });
+            page.extend_from_slice(&granule.to_le_bytes());
+            page.extend_from_slice(&serial.to_le_bytes());
+            page.extend_from_slice(&(sequence + i as u32).to_le_bytes());
+            page.extend_from_slice(&[0; 4]);
+            page.push(chunk.len() as u8);
+            page.extend(chunk.iter().map(|&(n, _)| n));
+            page.extend(body.by_ref().take(body_len));
+            update_crc(&mut page);
+            pages.push(page);
+
+            continued = !chunk.last().map_or(true, |&(_, end)| end);
+        }
+        pages
+    }
+
+    /// Rewrite the header pages once all header packets are collected.
+    fn rewrite_headers<W: Write + ?Sized>(
+        &mut self,
+        out: &mut W,
+        codec: Codec,
+        serial: u32,
+    ) -> io::Result<Step> {
+        let Some(comment) = self.comment_packet(codec, &self.packets[0]) else {
+            out.write_all(&self.pages.concat())?;
+            return Ok(Step::Done { tagged: false });
+        };
+        self.packets[0] = comment;
+
+        let sequence = page_sequence(&self.pages[0]);
+        let pages = Self::paginate(&self.packets, serial, sequence);
+        out.write_all(&pages.concat())?;
+
+        let delta = (pages.len() as u32).wrapping_sub(self.pages.len() as u32);
+        self.pages.clear();
+        self.packets.clear();
+        match delta {
+            0 => Ok(Step::Done { tagged: true }),
+            _ => {
+                self.state = State::Renumber { serial, delta };
+                Ok(Step::NeedMore)
+            }
+        }
+    }
+
+    pub fn process<W: Write + ?Sized>(
+        &mut self,
+        buffer: &mut Vec<u8>,
+        out: &mut W,
+        eof: bool,
+    ) -> io::Result<Step> {
+        loop {
+            let len = match page_len(buffer) {
+                Some(Ok(len)) => len,
+                Some(Err(())) => return self.abort(out),
+                None if eof => return self.abort(out),
+                None => return Ok(Step::NeedMore),
+            };
+            let mut page = buffer.drain(..len).collect::<Vec<_>>();
+
+            match self.state {
+                State::Start => {
+                    out.write_all(&page)?;
+                    let serial = page_serial(&page);
+                    // The identification header is alone in the first page.
+                    match Codec::detect(page_body(&page)) {
+                        Some(codec) => self.state = State::Headers { codec, serial },
+                        None => return Ok(Step::Done { tagged: false }),
+                    }
+                }
+                State::Headers { codec, serial } => {
+                    if page_serial(&page) != serial {
+                        // Multiplexed streams are not supported.
+                        self.pages.push(page);
+                        return self.abort(out);
+                    }
+
+                    let segments =
+                        page[PAGE_HEADER_LEN..PAGE_HEADER_LEN + page[26] as usize].to_vec();
+                    let mut body = page_body(&page);
+                    for &n in &segments {
+                        let (data, rest) = body.split_at(n as usize);
+                        self.partial_packet.extend_from_slice(data);
+                        body = rest;
+                        if n < 255 {
+                            self.packets.push(std::mem::take(&mut self.partial_packet));
+                        }
+                    }
+                    self.pages.push(page);
+
+                    let collected = self.packets.len() + 1 >= codec.header_packets();
+                    if collected && self.partial_packet.is_empty() {
+                        if let Step::Done { tagged } = self.rewrite_headers(out, codec, serial)? {
+                            return Ok(Step::Done { tagged });
+                        }
+                    }
+                }
+                State::Renumber { serial, delta } => {
+                    if page_serial(&page) == serial {
+                        let sequence = page_sequence(&page).wrapping_add(delta);
+                        LE::write_u32(&mut page[18..22], sequence);
+                        update_crc(&mut page);
+                    }
+                    out.write_all(&page)?;
+                }
+            }
+        }
+    }
+
+    /// Stop processing, and write the pages held back as-is.
+    fn abort<W: Write + ?Sized>(&mut self, out: &mut W) -> io::Result<Step> {
+        out.write_all(&self.pages.concat())?;
+        self.pages.clear();
+        let tagged = matches!(self.state, State::Renumber { .. });
+        Ok(Step::Done { tagged })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+
+    use crate::tagging::tests::{sample_extras, test_tags, write_tagged, write_with_extras};
+
+    use super::*;
+
+    /// Split a stream into its pages.
+    fn pages(mut data: &[u8]) -> Vec<&[u8]> {
+        let mut result = vec![];
+        while !data.is_empty() {
+            let len = page_len(data).unwrap().unwrap();
+            result.push(&data[..len]);
+            data = &data[len..];
+        }
+        result
+    }
+
+    /// Read the packets of a stream, ignoring page boundaries.
+    fn packets(data: &[u8]) -> Vec<Vec<u8>> {
+        let mut result = vec![];
+        let mut packet = vec![];
+        for page in pages(data) {
+            let mut body = page_body(page);
+            for &n in &page[PAGE_HEADER_LEN..PAGE_HEADER_LEN + page[26] as usize] {
+                let (data, rest) = body.split_at(n as usize);
+                packet.extend_from_slice(data);
+                body = rest;
+                if n < 255 {
+                    result.push(std::mem::take(&mut packet));
+                }
+            }
+        }
+        result
+    }
+
+    fn check_pages(data: &[u8]) {
+        for (i, page) in pages(data).into_iter().enumerate() {
+            let mut copy = page.to_vec();
+            update_crc(&mut copy);
+            assert_eq!(copy, page, "crc of page {i}");
+            assert_eq!(page_sequence(page), i as u32, "sequence of page {i}");
+        }
+    }
+
+    #[test]
+    fn test_vorbis_sample() {
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("sample/test_121529_32kbps.ogg");
+        let input = fs::read(path).unwrap();
+        check_pages(&input);
+
+        let (output, report) = write_tagged(&input, test_tags());
+        assert!(report.tagged);
+        check_pages(&output);
+
+        let input_packets = packets(&input);
+        let output_packets = packets(&output);
+        assert_eq!(input_packets.len(), output_packets.len());
+        assert_eq!(input_packets[0], output_packets[0]);
+        assert_eq!(input_packets[2..], output_packets[2..]);
+
+        let comment = output_packets[1].strip_prefix(VORBIS_COMMENT).unwrap();
+        let (comment, len) = VorbisComment::from_bytes(comment).unwrap();
+        assert!(comment
+            .comments
+            .contains(&b"QQMUSIC_RESOURCE_ID=5177785".to_vec()));
+        assert_eq!(output_packets[1].len(), VORBIS_COMMENT.len() + len + 1);
+    }
+
+    #[test]
+    fn test_renumber_pages() {
+        // A large comment spans more pages than the original.
+        let value = "x".repeat(255 * 300);
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("sample/test_121529_32kbps.ogg");
+        let input = fs::read(path).unwrap();
+
+        let (output, report) = write_tagged(&input, vec![Tag::new("LARGE", value)]);
+        assert!(report.tagged);
+        assert!(pages(&output).len() > pages(&input).len());
+        check_pages(&output);
+        assert_eq!(packets(&input)[2..], packets(&output)[2..]);
+    }
+
+    #[test]
+    fn test_opus() {
+        let head = b"OpusHead\x01\x02\x38\x01\x80\xBB\0\0\0\0\0".to_vec();
+        let tags = [OPUS_TAGS, &VorbisComment::default().to_bytes()].concat();
+        let audio = vec![0x55; 100];
+        let input = [
+            OggTagger::paginate(std::slice::from_ref(&head), 1, 0),
+            OggTagger::paginate(&[tags], 1, 1),
+            OggTagger::paginate(std::slice::from_ref(&audio), 1, 2),
+        ]
+        .concat()
+        .concat();
+
+        let (output, report) = write_tagged(&input, test_tags());
+        assert!(report.tagged);
+        check_pages(&output);
+
+        let output_packets = packets(&output);
+        assert_eq!(output_packets[0], head);
+        assert_eq!(output_packets[2], audio);
+        let (comment, _) = VorbisComment::from_bytes(&output_packets[1][8..]).unwrap();
+        assert_eq!(comment.comments.len(), 2);
+    }
+
+    #[test]
+    fn test_extras() {
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("sample/test_121529_32kbps.ogg");
+        let input = fs::read(path).unwrap();
+
+        let extras = sample_extras();
+        let (output, report) = write_with_extras(&input, vec![], extras.clone());
+        assert!(report.tagged);
+        check_pages(&output);
+
+        let output_packets = packets(&output);
+        let comment = output_packets[1].strip_prefix(VORBIS_COMMENT).unwrap();
+        let (comment, _) = VorbisComment::from_bytes(comment).unwrap();
+        assert!(comment.comments.contains(&"TITLE=标题".as_bytes().to_vec()));
+        assert!(comment.comments.contains(&b"ARTIST=artist".to_vec()));
+
+        let picture = Base64.encode(picture_block(extras.cover.as_ref().unwrap()));
+        let picture = format!("{METADATA_BLOCK_PICTURE}={picture}");
+        assert!(comment.comments.contains(&picture.into_bytes()));
+    }
+}
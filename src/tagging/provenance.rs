@@ -0,0 +1,135 @@
+//! Tags describing where a decrypted file came from, built from the metadata of the
+//! encrypted file (e.g. the QQ Music tail or the Kuwo header).
+
+#[cfg(feature = "kuwo")]
+use crate::crypto::kuwo::header::KuwoHeader;
+#[cfg(feature = "tencent")]
+use crate::crypto::tencent::metadata::TailParseResult;
+
+// `kuwo` depends on `tencent`.
+#[cfg(feature = "tencent")]
+use super::Tag;
+
+pub const QQMUSIC_MID: &str = "QQMUSIC_MID";
+pub const QQMUSIC_MEDIA_MID: &str = "QQMUSIC_MEDIA_MID";
+pub const QQMUSIC_RESOURCE_ID: &str = "QQMUSIC_RESOURCE_ID";
+pub const KUWO_RESOURCE_ID: &str = "KUWO_RESOURCE_ID";
+pub const KUWO_FORMAT_NAME: &str = "KUWO_FORMAT_NAME";
+
+/// Tags from the tail of a QQ Music file (`mid`, `media_mid` and `resource_id`).
+///
+/// Legacy PC tails only contain the key, and give no tags.
+#[cfg(feature = "tencent")]
+pub fn from_tencent_tail(tail: &TailParseResult) -> Vec<Tag> {
+    let mut tags = vec![];
+    match tail {
+        TailParseResult::PcLegacy(_) => {}
+        TailParseResult::PcMusicEx(m) => {
+            if !m.mid.is_empty() {
+                tags.push(Tag::new(QQMUSIC_MID, &m.mid));
+            }
+            // The file name is `media_mid` followed by the extension.
+            let media_mid = match m.media_filename.rsplit_once('.') {
+                Some((stem, _)) => stem,
+                None => &m.media_filename,
+            };
+            if !media_mid.is_empty() {
+                tags.push(Tag::new(QQMUSIC_MEDIA_MID, media_mid));
+            }
+        }
+        TailParseResult::AndroidQTag(m) => {
+            tags.push(Tag::new(QQMUSIC_RESOURCE_ID, m.resource_id.to_string()));
+        }
+        TailParseResult::AndroidSTag(m) => {
+            if !m.media_mid.is_empty() {
+                tags.push(Tag::new(QQMUSIC_MEDIA_MID, &m.media_mid));
+            }
+            tags.push(Tag::new(
+                QQMUSIC_RESOURCE_ID,
+                m.media_numeric_id.to_string(),
+            ));
+        }
+    }
+    tags
+}
+
+/// Tags from the header of a Kuwo file (`resource_id` and `format_name`).
+#[cfg(feature = "kuwo")]
+pub fn from_kuwo_header(hdr: &KuwoHeader) -> Vec<Tag> {
+    let format_name = hdr
+        .format_name
+        .split(|&c| c == 0)
+        .next()
+        .unwrap_or_default();
+    let mut tags = vec![Tag::new(KUWO_RESOURCE_ID, hdr.resource_id.to_string())];
+    if !format_name.is_empty() {
+        tags.push(Tag::new(
+            KUWO_FORMAT_NAME,
+            String::from_utf8_lossy(format_name),
+        ));
+    }
+    tags
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    #[cfg(feature = "tencent")]
+    #[test]
+    fn test_tencent_tail() {
+        use crate::crypto::tencent::metadata::{AndroidSTagMetadata, PcMusicExMetadata};
+
+        let tail = TailParseResult::PcMusicEx(PcMusicExMetadata {
+            tail_len: 0xC0,
+            tag_version: 1,
+            mid: "001y7CaR29k6YP".into(),
+            media_filename: "0011wjLv1bIkvv.mflac".into(),
+            unknown_0: 0,
+            unknown_1: 0,
+            unknown_2: 0,
+            unknown_3: 0,
+            extra: Box::from([]),
+        });
+        assert_eq!(
+            from_tencent_tail(&tail),
+            vec![
+                Tag::new(QQMUSIC_MID, "001y7CaR29k6YP"),
+                Tag::new(QQMUSIC_MEDIA_MID, "0011wjLv1bIkvv"),
+            ]
+        );
+
+        let tail = TailParseResult::AndroidSTag(AndroidSTagMetadata {
+            tail_len: 0x20,
+            tag_version: 2,
+            media_mid: "0011wjLv1bIkvv".into(),
+            media_numeric_id: 5177785,
+            extra_fields: vec![],
+        });
+        assert_eq!(
+            from_tencent_tail(&tail),
+            vec![
+                Tag::new(QQMUSIC_MEDIA_MID, "0011wjLv1bIkvv"),
+                Tag::new(QQMUSIC_RESOURCE_ID, "5177785"),
+            ]
+        );
+    }
+
+    #[cfg(feature = "kuwo")]
+    #[test]
+    fn test_kuwo_header() {
+        let hdr = KuwoHeader {
+            resource_id: 0x01020304,
+            format_name: *b"2000FLAC\0\0\0\0",
+            ..Default::default()
+        };
+        assert_eq!(
+            from_kuwo_header(&hdr),
+            vec![
+                Tag::new(KUWO_RESOURCE_ID, "16909060"),
+                Tag::new(KUWO_FORMAT_NAME, "2000FLAC"),
+            ]
+        );
+    }
+}
@@ -0,0 +1,134 @@
+use byteorder::{ByteOrder, LE};
+
+use crate::utils::media_extras::Cover;
+
+use super::{Metadata, Tag};
+
+/// Vendor string used when a new comment block is created.
+pub const DEFAULT_VENDOR: &str = "parakeet-crypto";
+
+/// Comment storing a picture block (base64) in Ogg.
+pub const METADATA_BLOCK_PICTURE: &str = "METADATA_BLOCK_PICTURE";
+pub const PICTURE_TYPE_FRONT_COVER: u32 = 3;
+
+/// Comments to write: the extras (`TITLE`, `ARTIST` and `ALBUM`), then the tags.
+pub fn comment_tags(metadata: &Metadata) -> Vec<Tag> {
+    let extras = &metadata.extras;
+    let fields = [
+        ("TITLE", &extras.title),
+        ("ARTIST", &extras.artist),
+        ("ALBUM", &extras.album),
+    ];
+
+    let mut tags = fields
+        .into_iter()
+        .filter_map(|(key, value)| value.as_ref().map(|value| Tag::new(key, value)))
+        .collect::<Vec<_>>();
+    tags.extend_from_slice(&metadata.tags);
+    tags
+}
+
+/// FLAC `PICTURE` metadata block of the front cover; Ogg stores it as a comment.
+pub fn picture_block(cover: &Cover) -> Vec<u8> {
+    let mut result = PICTURE_TYPE_FRONT_COVER.to_be_bytes().to_vec();
+    result.extend_from_slice(&(cover.mime.len() as u32).to_be_bytes());
+    result.extend_from_slice(cover.mime.as_bytes());
+    // Empty description; unknown width, height, color depth and number of colors.
+    result.extend_from_slice(&[0; 4 * 5]);
+    result.extend_from_slice(&(cover.data.len() as u32).to_be_bytes());
+    result.extend_from_slice(&cover.data);
+    result
+}
+
+/// Vorbis comments, as stored in FLAC metadata and Ogg Vorbis/Opus headers.
+///
+/// `[vendor_len: u32 LE][vendor]` `[count: u32 LE]` `([len: u32 LE][KEY=value])*`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VorbisComment {
+    pub vendor: Vec<u8>,
+    pub comments: Vec<Vec<u8>>,
+}
+
+impl Default for VorbisComment {
+    fn default() -> Self {
+        Self {
+            vendor: DEFAULT_VENDOR.into(),
+            comments: vec![],
+        }
+    }
+}
+
+fn read_chunk<'a>(data: &'a [u8], pos: &mut usize) -> Option<&'a [u8]> {
+    let len = LE::read_u32(data.get(*pos..*pos + 4)?) as usize;
+    let chunk = data.get(*pos + 4..)?.get(..len)?;
+    *pos += 4 + len;
+    Some(chunk)
+}
+
+impl VorbisComment {
+    /// Parse the comments; returns them with the number of bytes used.
+    pub fn from_bytes(data: &[u8]) -> Option<(Self, usize)> {
+        let mut pos = 0;
+        let vendor = read_chunk(data, &mut pos)?.to_vec();
+        let count = LE::read_u32(data.get(pos..pos + 4)?);
+        pos += 4;
+
+        let mut comments = vec![];
+        for _ in 0..count {
+            comments.push(read_chunk(data, &mut pos)?.to_vec());
+        }
+        Some((Self { vendor, comments }, pos))
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut result = vec![];
+        result.extend_from_slice(&(self.vendor.len() as u32).to_le_bytes());
+        result.extend_from_slice(&self.vendor);
+        result.extend_from_slice(&(self.comments.len() as u32).to_le_bytes());
+        for comment in &self.comments {
+            result.extend_from_slice(&(comment.len() as u32).to_le_bytes());
+            result.extend_from_slice(comment);
+        }
+        result
+    }
+
+    /// Add the tags, replacing the comments with the same (case-insensitive) key.
+    pub fn set_tags(&mut self, tags: &[Tag]) {
+        self.comments.retain(|comment| {
+            let key = comment.split(|&c| c == b'=').next().unwrap_or_default();
+            !tags
+                .iter()
+                .any(|tag| tag.key.as_bytes().eq_ignore_ascii_case(key))
+        });
+        for tag in tags {
+            self.comments
+                .push(format!("{}={}", tag.key, tag.value).into_bytes());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let mut comment = VorbisComment {
+            vendor: b"vendor".to_vec(),
+            comments: vec![b"TITLE=song".to_vec(), b"qqmusic_mid=old".to_vec()],
+        };
+        comment.set_tags(&[Tag::new("QQMUSIC_MID", "new")]);
+        assert_eq!(
+            comment.comments,
+            vec![b"TITLE=song".to_vec(), b"QQMUSIC_MID=new".to_vec()]
+        );
+
+        let mut data = comment.to_bytes();
+        data.push(1); // e.g. the framing bit of Ogg Vorbis
+        assert_eq!(
+            VorbisComment::from_bytes(&data),
+            Some((comment, data.len() - 1))
+        );
+        assert_eq!(VorbisComment::from_bytes(&data[..20]), None);
+    }
+}
@@ -0,0 +1,200 @@
+/// Audio container types that can be identified from the first few bytes
+/// of a decrypted stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AudioType {
+    Flac,
+    Ogg,
+    Mp3,
+    M4a,
+    Wav,
+    Ape,
+    Wma,
+    Dff,
+}
+
+/// Minimum number of bytes required by [`detect_audio_type`].
+pub const AUDIO_DETECTION_LEN: usize = 16;
+
+const MAGIC_ASF: [u8; 16] = *b"\x30\x26\xB2\x75\x8E\x66\xCF\x11\xA6\xD9\x00\xAA\x00\x62\xCE\x6C";
+
+impl AudioType {
+    /// Common file extension of the container, without the leading dot.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use parakeet_crypto::utils::audio::AudioType;
+    ///
+    /// assert_eq!(AudioType::Flac.extension(), "flac");
+    /// assert_eq!(AudioType::M4a.extension(), "m4a");
+    /// ```
+    pub fn extension(&self) -> &'static str {
+        match self {
+            AudioType::Flac => "flac",
+            AudioType::Ogg => "ogg",
+            AudioType::Mp3 => "mp3",
+            AudioType::M4a => "m4a",
+            AudioType::Wav => "wav",
+            AudioType::Ape => "ape",
+            AudioType::Wma => "wma",
+            AudioType::Dff => "dff",
+        }
+    }
+}
+
+pub(crate) fn is_mp3_frame_sync(header: &[u8]) -> bool {
+    // 11 bits of frame sync, followed by a valid (non-reserved) version and layer.
+    header.len() >= 2
+        && header[0] == 0xFF
+        && (header[1] & 0xE0) == 0xE0
+        && (header[1] & 0x18) != 0x08
+        && (header[1] & 0x06) != 0x00
+}
+
+/// Bit rates in kbps, by bit rate index: MPEG1 layer I/II/III, then MPEG2/2.5 layer I
+/// and layer II/III. Index 0 (free format) and 15 (bad) are not valid here.
+const MP3_BIT_RATES: [[u16; 15]; 5] = [
+    [
+        0, 32, 64, 96, 128, 160, 192, 224, 256, 288, 320, 352, 384, 416, 448,
+    ],
+    [
+        0, 32, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320, 384,
+    ],
+    [
+        0, 32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320,
+    ],
+    [
+        0, 32, 48, 56, 64, 80, 96, 112, 128, 144, 160, 176, 192, 224, 256,
+    ],
+    [0, 8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160],
+];
+
+/// Sample rates of MPEG1, by sample rate index; halved for MPEG2, quartered for MPEG2.5.
+const MP3_SAMPLE_RATES: [u32; 3] = [44100, 48000, 32000];
+
+/// Length of the MPEG audio frame starting at `header`, including its header.
+fn mp3_frame_len(header: &[u8]) -> Option<usize> {
+    if header.len() < 4 || !is_mp3_frame_sync(header) {
+        return None;
+    }
+
+    let version = (header[1] >> 3) & 0b11; // 0: MPEG2.5, 2: MPEG2, 3: MPEG1
+    let layer = 4 - ((header[1] >> 1) & 0b11); // 1: layer I, 2: layer II, 3: layer III
+    let bit_rate_index = usize::from(header[2] >> 4);
+    let sample_rate_index = usize::from((header[2] >> 2) & 0b11);
+    let padding = u32::from((header[2] >> 1) & 1);
+    if bit_rate_index == 0 || bit_rate_index == 15 || sample_rate_index == 3 {
+        return None;
+    }
+
+    let table = match (version, layer) {
+        (3, layer) => layer - 1,
+        (_, 1) => 3,
+        _ => 4,
+    };
+    let bit_rate = u32::from(MP3_BIT_RATES[usize::from(table)][bit_rate_index]) * 1000;
+    let sample_rate = match version {
+        3 => MP3_SAMPLE_RATES[sample_rate_index],
+        2 => MP3_SAMPLE_RATES[sample_rate_index] / 2,
+        _ => MP3_SAMPLE_RATES[sample_rate_index] / 4,
+    };
+
+    let len = match (version, layer) {
+        (_, 1) => (12 * bit_rate / sample_rate + padding) * 4,
+        (3, _) | (_, 2) => 144 * bit_rate / sample_rate + padding,
+        _ => 72 * bit_rate / sample_rate + padding,
+    };
+    Some(len as usize)
+}
+
+/// Longest MPEG audio frame (MPEG1 layer II, 384 kbps at 32 kHz, with padding), plus the
+/// header of the next frame.
+pub const MP3_FRAME_PAIR_LEN: usize = 1729 + 4;
+
+/// Check that `data` starts with an MPEG audio frame, directly followed by another frame
+/// of the same version, layer and sample rate.
+///
+/// A single frame sync (11 bits) is too weak to tell audio from random bytes; `data`
+/// should hold at least [`MP3_FRAME_PAIR_LEN`] bytes.
+pub fn is_mp3_frame_pair(data: &[u8]) -> bool {
+    let next = match mp3_frame_len(data) {
+        Some(len) => &data[len.min(data.len())..],
+        None => return false,
+    };
+    mp3_frame_len(next).is_some()
+        && (data[1] & 0xFE) == (next[1] & 0xFE)
+        && (data[2] & 0x0C) == (next[2] & 0x0C)
+}
+
+/// Detect the audio container from the beginning of a (decrypted) file.
+///
+/// # Examples
+///
+/// ```
+/// use parakeet_crypto::utils::audio::{detect_audio_type, AudioType};
+///
+/// assert_eq!(detect_audio_type(b"fLaC\0\0\0\x22"), Some(AudioType::Flac));
+/// assert_eq!(detect_audio_type(b"\0\0\0\x20ftypM4A "), Some(AudioType::M4a));
+/// assert_eq!(detect_audio_type(b"not an audio file"), None);
+/// ```
+pub fn detect_audio_type<T: AsRef<[u8]>>(header: T) -> Option<AudioType> {
+    let header = header.as_ref();
+
+    if header.starts_with(b"fLaC") {
+        Some(AudioType::Flac)
+    } else if header.starts_with(b"OggS") {
+        Some(AudioType::Ogg)
+    } else if header.starts_with(b"ID3") || is_mp3_frame_sync(header) {
+        Some(AudioType::Mp3)
+    } else if header.len() >= 8 && &header[4..8] == b"ftyp" {
+        Some(AudioType::M4a)
+    } else if header.starts_with(b"RIFF") {
+        Some(AudioType::Wav)
+    } else if header.starts_with(b"MAC ") {
+        Some(AudioType::Ape)
+    } else if header.starts_with(&MAGIC_ASF) {
+        Some(AudioType::Wma)
+    } else if header.starts_with(b"FRM8") {
+        Some(AudioType::Dff)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_sample_ogg() {
+        let sample = include_bytes!("../../sample/test_121529_32kbps.ogg");
+        assert_eq!(detect_audio_type(sample), Some(AudioType::Ogg));
+    }
+
+    #[test]
+    fn test_mp3_frame_pair() {
+        // MPEG1 layer III, 128 kbps, 44.1 kHz: 417 bytes, 418 with padding.
+        let mut data = vec![0u8; 1024];
+        data[..4].copy_from_slice(b"\xFF\xFB\x90\x64");
+        assert!(!is_mp3_frame_pair(&data));
+        data[417..421].copy_from_slice(b"\xFF\xFB\x92\x64");
+        assert!(is_mp3_frame_pair(&data));
+        assert!(!is_mp3_frame_pair(&data[..420]));
+
+        // Different sample rate.
+        data[417..421].copy_from_slice(b"\xFF\xFB\x94\x64");
+        assert!(!is_mp3_frame_pair(&data));
+
+        // MPEG2 layer III, 64 kbps, 22.05 kHz: 208 bytes.
+        data.fill(0);
+        data[..4].copy_from_slice(b"\xFF\xF3\x80\xC4");
+        data[208..212].copy_from_slice(b"\xFF\xF3\x80\xC4");
+        assert!(is_mp3_frame_pair(&data));
+    }
+
+    #[test]
+    fn test_detect_mp3_frame_sync() {
+        assert_eq!(detect_audio_type(b"\xFF\xFB\x90\x64"), Some(AudioType::Mp3));
+        assert_eq!(detect_audio_type(b"\xFF\xE8\x90\x64"), None);
+    }
+}
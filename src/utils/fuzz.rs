@@ -0,0 +1,40 @@
+//! Pseudo-random inputs for the parser and cipher tests.
+//!
+//! Deterministic, so a failing input can be reproduced from its seed.
+
+use alloc::vec::Vec;
+
+/// Linear congruential generator.
+pub struct Lcg(u32);
+
+impl Lcg {
+    pub fn new(seed: u32) -> Self {
+        Self(seed)
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        self.0 = self.0.wrapping_mul(1103515245).wrapping_add(12345);
+        self.0 >> 16
+    }
+
+    pub fn next_u8(&mut self) -> u8 {
+        self.next_u32() as u8
+    }
+
+    /// A value in `0..n`.
+    pub fn below(&mut self, n: usize) -> usize {
+        self.next_u32() as usize % n
+    }
+
+    /// Up to `max_len` random bytes, biased towards the interesting values `0x00` and `0xFF`.
+    pub fn input(&mut self, max_len: usize) -> Vec<u8> {
+        let len = self.below(max_len + 1);
+        (0..len)
+            .map(|_| match self.below(8) {
+                0 => 0x00,
+                1 => 0xFF,
+                _ => self.next_u8(),
+            })
+            .collect()
+    }
+}
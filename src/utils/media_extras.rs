@@ -0,0 +1,103 @@
+use alloc::boxed::Box;
+use alloc::string::String;
+
+/// Picture embedded in the encrypted file, e.g. the album cover.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Cover {
+    /// MIME type, e.g. `"image/jpeg"`.
+    pub mime: String,
+    /// Picture content; only its length is serialized.
+    #[cfg_attr(
+        feature = "serde",
+        serde(rename = "data_len", serialize_with = "serialize_len")
+    )]
+    pub data: Box<[u8]>,
+}
+
+#[cfg(feature = "serde")]
+fn serialize_len<S: serde::Serializer>(data: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_u64(data.len() as u64)
+}
+
+impl Cover {
+    /// Build a cover; the MIME type is guessed from the content when not given.
+    pub fn new<T: Into<Box<[u8]>>>(mime: Option<&str>, data: T) -> Self {
+        let data = data.into();
+        let mime = match mime {
+            Some(mime) if !mime.is_empty() => mime.into(),
+            _ => detect_image_mime(&data).unwrap_or("image/jpeg").into(),
+        };
+        Self { mime, data }
+    }
+
+    /// Common file extension of the picture, without the leading dot.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use parakeet_crypto::utils::media_extras::Cover;
+    ///
+    /// assert_eq!(Cover::new(None, *b"\x89PNG\r\n\x1a\n").extension(), "png");
+    /// assert_eq!(Cover::new(Some("image/jpg"), []).extension(), "jpg");
+    /// ```
+    pub fn extension(&self) -> &'static str {
+        match self.mime.to_ascii_lowercase().as_str() {
+            "image/png" => "png",
+            "image/gif" => "gif",
+            "image/webp" => "webp",
+            "image/bmp" => "bmp",
+            _ => "jpg",
+        }
+    }
+}
+
+/// Detect the MIME type of a picture from its first bytes.
+pub fn detect_image_mime(data: &[u8]) -> Option<&'static str> {
+    match data {
+        [0xFF, 0xD8, 0xFF, ..] => Some("image/jpeg"),
+        [0x89, b'P', b'N', b'G', ..] => Some("image/png"),
+        [b'G', b'I', b'F', b'8', ..] => Some("image/gif"),
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..] => Some("image/webp"),
+        [b'B', b'M', ..] => Some("image/bmp"),
+        _ => None,
+    }
+}
+
+/// Media information carried by some formats besides the audio: cover, title, artist
+/// and album. See `ximalaya_pc::Header::extras`.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct MediaExtras {
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub cover: Option<Cover>,
+}
+
+impl MediaExtras {
+    pub fn is_empty(&self) -> bool {
+        self.title.is_none()
+            && self.artist.is_none()
+            && self.album.is_none()
+            && self.cover.is_none()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cover_mime() {
+        let cover = Cover::new(None, *b"\xFF\xD8\xFF\xE0\0\x10JFIF");
+        assert_eq!(cover.mime, "image/jpeg");
+        assert_eq!(cover.extension(), "jpg");
+
+        let cover = Cover::new(Some(""), *b"RIFF\0\0\0\0WEBPVP8 ");
+        assert_eq!(cover.mime, "image/webp");
+        assert_eq!(cover.extension(), "webp");
+
+        assert!(MediaExtras::default().is_empty());
+    }
+}
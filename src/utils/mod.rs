@@ -1,5 +1,15 @@
+#[cfg(feature = "kugou")]
 pub use self::md5::md5;
 
+#[cfg(feature = "kugou")]
 mod md5;
 
+pub mod audio;
+// Only used by the tests of some vendors.
+#[cfg(test)]
+#[allow(dead_code)]
+pub(crate) mod fuzz;
+pub mod media_extras;
+#[cfg(feature = "serde")]
+pub mod serde_hex;
 pub mod validate;
@@ -0,0 +1,10 @@
+use serde::Serializer;
+
+/// Serialize bytes as a lowercase hex string, for `#[serde(with = "crate::utils::serde_hex")]`.
+pub fn serialize<T, S>(value: &T, serializer: S) -> Result<S::Ok, S::Error>
+where
+    T: AsRef<[u8]>,
+    S: Serializer,
+{
+    serializer.serialize_str(&hex::encode(value))
+}
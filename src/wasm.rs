@@ -0,0 +1,487 @@
+//! JS-friendly API, exported with `wasm-bindgen`.
+//!
+//! A [`Decryptor`] is created from the head (or the tail) of the file, then the
+//! file is processed in chunks, e.g. from a `ReadableStream`:
+//!
+//! 1. Emit [`Decryptor::prefix`];
+//! 2. Skip the first [`Decryptor::header_len`] bytes, and the last [`Decryptor::tail_len`] bytes;
+//! 3. Decrypt everything in between with [`Decryptor::decrypt_chunk`], where `offset`
+//!    starts at `0` after the header.
+//!
+//! Constructors fail with a [`DecryptError`]; when the given buffer is too short,
+//! [`DecryptError::needed_len`] is the number of bytes to retry with.
+
+use alloc::boxed::Box;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+use wasm_bindgen::prelude::*;
+
+use crate::crypto::byte_offset_cipher::ByteOffsetDecipher;
+use crate::crypto::kugou::{Header as KugouHeader, HeaderDeserializeError, Kugou};
+use crate::crypto::kuwo::header::{HeaderParseError, HEADER_FIXED_LEN};
+use crate::crypto::kuwo::{InitCipherError as KuwoInitCipherError, Kuwo, KuwoBuilder};
+use crate::crypto::tencent::metadata::TailParseError;
+use crate::crypto::tencent::{parse_tail, QMCv1, QMCv2};
+use crate::crypto::{ximalaya_android, ximalaya_pc};
+use crate::{Error, ErrorCode};
+
+#[wasm_bindgen]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecryptError {
+    code: ErrorCode,
+    message: String,
+    needed_len: Option<usize>,
+}
+
+#[wasm_bindgen]
+impl DecryptError {
+    /// See [`ErrorCode`].
+    #[wasm_bindgen(getter)]
+    pub fn code(&self) -> u32 {
+        self.code as u32
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn message(&self) -> String {
+        self.message.clone()
+    }
+
+    /// Set if the buffer given was too short.
+    #[wasm_bindgen(getter, js_name = neededLen)]
+    pub fn needed_len(&self) -> Option<usize> {
+        self.needed_len
+    }
+}
+
+impl DecryptError {
+    fn new(code: ErrorCode, message: &str) -> Self {
+        Self {
+            code,
+            message: message.into(),
+            needed_len: None,
+        }
+    }
+
+    fn need_more_bytes(code: ErrorCode, needed_len: usize) -> Self {
+        Self {
+            code,
+            message: alloc::format!("need at least {needed_len} bytes"),
+            needed_len: Some(needed_len),
+        }
+    }
+}
+
+impl<E: Into<Error>> From<E> for DecryptError {
+    fn from(error: E) -> Self {
+        let error = error.into();
+        let needed_len = match &error {
+            Error::TencentTailParse(TailParseError::NeedMoreBytes(len)) => Some(*len),
+            Error::KugouHeaderDeserialize(HeaderDeserializeError::InputHeaderTooSmall(_, len)) => {
+                Some(*len)
+            }
+            Error::KuwoHeaderParse(HeaderParseError::NeedMoreBytes(len)) => Some(*len),
+            Error::KuwoInitCipher(KuwoInitCipherError::HeaderParseError(
+                HeaderParseError::NeedMoreBytes(len),
+            )) => Some(*len),
+            Error::XimalayaPc(ximalaya_pc::Error::InputTooSmall(len, _)) => Some(*len),
+            _ => None,
+        };
+
+        Self {
+            code: error.code(),
+            message: error.to_string(),
+            needed_len,
+        }
+    }
+}
+
+enum Cipher {
+    /// The audio data is not encrypted (the encrypted part is in the prefix).
+    None,
+    QMCv1(QMCv1),
+    QMCv2(QMCv2),
+    Kugou(Kugou),
+    Kuwo(Kuwo),
+}
+
+#[wasm_bindgen]
+pub struct Decryptor {
+    cipher: Cipher,
+    prefix: Box<[u8]>,
+    header_len: usize,
+    tail_len: usize,
+}
+
+impl Decryptor {
+    fn new(cipher: Cipher, header_len: usize) -> Self {
+        Self {
+            cipher,
+            prefix: Box::new([]),
+            header_len,
+            tail_len: 0,
+        }
+    }
+}
+
+#[wasm_bindgen]
+impl Decryptor {
+    /// QMCv1 (`.qmcflac`, `.qmc0`...)
+    pub fn qmc1() -> Decryptor {
+        Self::new(Cipher::QMCv1(QMCv1::new()), 0)
+    }
+
+    /// QMCv2, with a decrypted key.
+    pub fn qmc2(key: &[u8]) -> Result<Decryptor, DecryptError> {
+        Ok(Self::new(Cipher::QMCv2(QMCv2::try_new(key)?), 0))
+    }
+
+    /// QMCv2, from the end of the file.
+    ///
+    /// `key` overrides the key from the tail, and is required if the tail does
+    /// not include one (e.g. Android "STag").
+    #[wasm_bindgen(js_name = qmc2FromTail)]
+    pub fn qmc2_from_tail(tail: &[u8], key: Option<Vec<u8>>) -> Result<Decryptor, DecryptError> {
+        let metadata = parse_tail(tail)?;
+        let key = match (key, metadata.get_key()) {
+            (Some(key), _) => key,
+            (None, Some(key)) => key.to_vec(),
+            (None, None) => Err(DecryptError::new(
+                ErrorCode::TencentInitCipher,
+                "the tail does not include a key",
+            ))?,
+        };
+
+        let mut result = Self::qmc2(&key)?;
+        result.tail_len = metadata.get_tail_len();
+        Ok(result)
+    }
+
+    /// Kugou (`.kgm`, `.vpr`); `slot_key` overrides the key selected by the header.
+    ///
+    /// Mode 4 key tables are computed on demand, to keep the memory usage low.
+    pub fn kugou(header: &[u8], slot_key: Option<Vec<u8>>) -> Result<Decryptor, DecryptError> {
+        let hdr = KugouHeader::from_bytes(header)?;
+        let cipher = match slot_key {
+            None => Kugou::new_lazy(&hdr)?,
+            Some(slot_key) => Kugou::with_slot_key_lazy(&hdr, slot_key)?,
+        };
+
+        Ok(Self::new(Cipher::Kugou(cipher), hdr.header_len as usize))
+    }
+
+    /// Kuwo (`.kwm`); `ekey` is required for KWMv2.
+    pub fn kuwo(header: &[u8], ekey: Option<Vec<u8>>) -> Result<Decryptor, DecryptError> {
+        let mut builder = KuwoBuilder::new(header);
+        if let Some(ekey) = ekey {
+            builder = builder.with_ekey(ekey);
+        }
+
+        let (cipher, _) = builder.build()?;
+        Ok(Self::new(Cipher::Kuwo(cipher), HEADER_FIXED_LEN))
+    }
+
+    /// Ximalaya Android (`.x2m`, `.x3m`), from the first `0x400` bytes.
+    #[wasm_bindgen(js_name = ximalayaAndroid)]
+    pub fn ximalaya_android(header: &[u8], x3m: bool) -> Result<Decryptor, DecryptError> {
+        use ximalaya_android::keys::{get_key, Type, SCRAMBLED_HEADER_LEN};
+
+        let header: &[u8; SCRAMBLED_HEADER_LEN] = header
+            .get(..SCRAMBLED_HEADER_LEN)
+            .and_then(|header| header.try_into().ok())
+            .ok_or_else(|| {
+                DecryptError::need_more_bytes(ErrorCode::XimalayaAndroid, SCRAMBLED_HEADER_LEN)
+            })?;
+
+        let (content_key, scramble_table) = get_key(if x3m { Type::X3M } else { Type::X2M });
+        let plain = ximalaya_android::decrypt_header(header, content_key, scramble_table);
+
+        let mut result = Self::new(Cipher::None, SCRAMBLED_HEADER_LEN);
+        result.prefix = Box::from(plain);
+        Ok(result)
+    }
+
+    /// Ximalaya PC (`.xm`), from the head of the file.
+    ///
+    /// The encrypted part is included in the header, see [`DecryptError::needed_len`].
+    #[wasm_bindgen(js_name = ximalayaPc)]
+    pub fn ximalaya_pc(header: &[u8]) -> Result<Decryptor, DecryptError> {
+        let hdr = ximalaya_pc::Header::from_bytes(header)?;
+        let header_len = hdr.data_start_offset + hdr.encrypted_header_len;
+        let part_2 = header
+            .get(hdr.data_start_offset..header_len)
+            .ok_or_else(|| DecryptError::need_more_bytes(ErrorCode::XimalayaPc, header_len))?;
+        let part_2 = ximalaya_pc::decipher_part_2(&hdr, part_2)?;
+
+        let mut result = Self::new(Cipher::None, header_len);
+        result.prefix = [&hdr.stolen_header_bytes[..], &part_2].concat().into();
+        Ok(result)
+    }
+
+    /// Decrypted data to emit before the rest of the audio.
+    #[wasm_bindgen(getter)]
+    pub fn prefix(&self) -> Vec<u8> {
+        self.prefix.to_vec()
+    }
+
+    /// Number of bytes to skip at the start of the file.
+    #[wasm_bindgen(getter, js_name = headerLen)]
+    pub fn header_len(&self) -> usize {
+        self.header_len
+    }
+
+    /// Number of bytes to drop at the end of the file.
+    #[wasm_bindgen(getter, js_name = tailLen)]
+    pub fn tail_len(&self) -> usize {
+        self.tail_len
+    }
+
+    /// Decrypt a chunk in place; `offset` is relative to the end of the header.
+    ///
+    /// Throws if the chunk goes past the length supported by the cipher (Kugou mode 4).
+    #[wasm_bindgen(js_name = decryptChunk)]
+    pub fn decrypt_chunk(&self, offset: usize, buffer: &mut [u8]) -> Result<(), DecryptError> {
+        match &self.cipher {
+            Cipher::Kugou(cipher) => cipher.try_decipher_buffer(offset, buffer)?,
+            _ => self.decipher_buffer(offset, buffer),
+        }
+        Ok(())
+    }
+}
+
+impl ByteOffsetDecipher for Decryptor {
+    fn decipher_byte(&self, offset: usize, datum: u8) -> u8 {
+        match &self.cipher {
+            Cipher::None => datum,
+            Cipher::QMCv1(cipher) => cipher.decipher_byte(offset, datum),
+            Cipher::QMCv2(cipher) => cipher.decipher_byte(offset, datum),
+            Cipher::Kugou(cipher) => cipher.decipher_byte(offset, datum),
+            Cipher::Kuwo(cipher) => cipher.decipher_byte(offset, datum),
+        }
+    }
+
+    fn decipher_buffer<T: AsMut<[u8]> + ?Sized>(&self, offset: usize, buffer: &mut T) {
+        match &self.cipher {
+            Cipher::None => {}
+            Cipher::QMCv1(cipher) => cipher.decipher_buffer(offset, buffer),
+            Cipher::QMCv2(cipher) => cipher.decipher_buffer(offset, buffer),
+            Cipher::Kugou(cipher) => cipher.decipher_buffer(offset, buffer),
+            Cipher::Kuwo(cipher) => cipher.decipher_buffer(offset, buffer),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::crypto::byte_offset_cipher::ByteOffsetEncipher;
+    use crate::crypto::kuwo::header::{KuwoHeader, MAGIC_1};
+
+    const PLAIN: &[u8] = include_bytes!("../sample/test_121529_32kbps.ogg");
+
+    /// Process the file in chunks of different sizes, as a browser would.
+    fn decrypt_file(decryptor: &Decryptor, file: &[u8]) -> Vec<u8> {
+        let mut result = decryptor.prefix();
+        let mut audio = file[decryptor.header_len()..file.len() - decryptor.tail_len()].to_vec();
+
+        let mut offset = 0;
+        for chunk_len in [1, 7, 4096, 333].into_iter().cycle() {
+            if offset == audio.len() {
+                break;
+            }
+            let chunk_len = chunk_len.min(audio.len() - offset);
+            decryptor
+                .decrypt_chunk(offset, &mut audio[offset..offset + chunk_len])
+                .unwrap();
+            offset += chunk_len;
+        }
+
+        result.extend(audio);
+        result
+    }
+
+    #[test]
+    fn test_qmc1() {
+        let mut file = PLAIN.to_vec();
+        QMCv1::new().encipher_buffer(0, &mut file);
+
+        let decryptor = Decryptor::qmc1();
+        assert_eq!(decrypt_file(&decryptor, &file), PLAIN);
+    }
+
+    #[test]
+    fn test_qmc2_from_tail() {
+        for (tail, key) in [
+            (
+                &include_bytes!("crypto/tencent/tail/__fixtures__/ekey_pc_enc_v1.bin")[..],
+                &include_bytes!("crypto/tencent/tail/__fixtures__/ekey_pc_enc_v1_result.bin")[..],
+            ),
+            (
+                &include_bytes!("crypto/tencent/tail/__fixtures__/ekey_pc_enc_v2.bin")[..],
+                &include_bytes!("crypto/tencent/tail/__fixtures__/ekey_pc_enc_v2_result.bin")[..],
+            ),
+        ] {
+            let mut file = PLAIN.to_vec();
+            QMCv2::try_new(key).unwrap().encipher_buffer(0, &mut file);
+            let tail_len = parse_tail(tail).unwrap().get_tail_len();
+            file.extend_from_slice(&tail[tail.len() - tail_len..]);
+
+            // Grow the tail window until the parser is satisfied.
+            let mut tail_window = 8;
+            let decryptor = loop {
+                match Decryptor::qmc2_from_tail(&file[file.len() - tail_window..], None) {
+                    Ok(decryptor) => break decryptor,
+                    Err(error) => {
+                        let needed_len = error.needed_len().unwrap();
+                        assert!(needed_len > tail_window);
+                        tail_window = needed_len;
+                    }
+                }
+            };
+            assert_eq!(decrypt_file(&decryptor, &file), PLAIN);
+        }
+
+        let error = Decryptor::qmc2_from_tail(
+            include_bytes!("crypto/tencent/tail/__fixtures__/ekey_android_stag.bin"),
+            None,
+        )
+        .err();
+        assert_eq!(error.unwrap().code(), ErrorCode::TencentInitCipher as u32);
+    }
+
+    #[test]
+    fn test_kugou() {
+        let file = include_bytes!("../sample/test_kgm_v3.vpr");
+
+        let error = Decryptor::kugou(&file[..16], Some(b"09AZ".to_vec())).err();
+        assert_eq!(error.unwrap().needed_len(), Some(60));
+
+        let decryptor = Decryptor::kugou(&file[..60], Some(b"09AZ".to_vec())).unwrap();
+        assert_eq!(decrypt_file(&decryptor, file), PLAIN);
+
+        let error = Decryptor::kugou(file, Some(b"nope".to_vec()))
+            .err()
+            .unwrap();
+        assert_eq!(error.code(), ErrorCode::KugouCipher as u32);
+        assert_eq!(error.needed_len(), None);
+    }
+
+    #[test]
+    fn test_kuwo() {
+        let header = KuwoHeader {
+            magic: MAGIC_1,
+            version: 1,
+            resource_id: 12345,
+            ..Default::default()
+        };
+        let mut file = header.to_bytes();
+        let mut audio = PLAIN.to_vec();
+        crate::crypto::kuwo::v1::KWMv1::from_resource_id(12345).encipher_buffer(0, &mut audio);
+        file.extend(audio);
+
+        let decryptor = Decryptor::kuwo(&file, None).unwrap();
+        assert_eq!(decrypt_file(&decryptor, &file), PLAIN);
+
+        let mut header = header;
+        header.version = 2;
+        let error = Decryptor::kuwo(&header.to_bytes(), None).err().unwrap();
+        assert_eq!(error.code(), ErrorCode::KuwoInitCipher as u32);
+    }
+
+    #[test]
+    fn test_ximalaya_android() {
+        use ximalaya_android::keys::{get_key, Type, SCRAMBLED_HEADER_LEN};
+
+        for (key_type, x3m) in [(Type::X2M, false), (Type::X3M, true)] {
+            let (content_key, scramble_table) = get_key(key_type);
+            let mut file = PLAIN.to_vec();
+            let header = file[..SCRAMBLED_HEADER_LEN].try_into().unwrap();
+            let header = ximalaya_android::encrypt_header(header, content_key, scramble_table);
+            file[..SCRAMBLED_HEADER_LEN].copy_from_slice(&header);
+
+            let error = Decryptor::ximalaya_android(&file[..100], x3m).err();
+            assert_eq!(error.unwrap().needed_len(), Some(SCRAMBLED_HEADER_LEN));
+
+            let decryptor = Decryptor::ximalaya_android(&file, x3m).unwrap();
+            assert_eq!(decrypt_file(&decryptor, &file), PLAIN);
+        }
+    }
+
+    #[test]
+    fn test_ximalaya_pc() {
+        let file = ximalaya_pc_test_file();
+
+        let error = Decryptor::ximalaya_pc(&file[..10]).err().unwrap();
+        let data_start = error.needed_len().unwrap();
+        let error = Decryptor::ximalaya_pc(&file[..data_start]).err().unwrap();
+        let header_len = error.needed_len().unwrap();
+        assert!(header_len > data_start);
+
+        let decryptor = Decryptor::ximalaya_pc(&file[..header_len]).unwrap();
+        assert_eq!(decryptor.header_len(), header_len);
+        assert_eq!(decrypt_file(&decryptor, &file), PLAIN);
+
+        let error = Decryptor::ximalaya_pc(PLAIN).err().unwrap();
+        assert_eq!(error.code(), ErrorCode::XimalayaPc as u32);
+    }
+
+    /// Build a Ximalaya PC file: `[ID3 tag][encrypted part][rest of the audio]`.
+    fn ximalaya_pc_test_file() -> Vec<u8> {
+        use aes::cipher::block_padding::Pkcs7;
+        use aes::cipher::{BlockEncryptMut, KeyIvInit};
+        use base64::{engine::general_purpose::STANDARD as Base64, Engine as _};
+
+        const STAGE_1_KEY: &[u8; 32] = include_bytes!("crypto/ximalaya_pc/data/stage_1.bin");
+        let stage_1_iv = [0x11u8; 16];
+        let stage_2_key = *b"1234567812345678parakeet";
+        let (stolen, rest) = PLAIN.split_at(16);
+        let (part_2, rest) = rest.split_at(1000);
+
+        fn encrypt<C: BlockEncryptMut>(cipher: C, data: String) -> Vec<u8> {
+            let mut buffer = data.into_bytes();
+            let len = buffer.len();
+            buffer.resize(len + 16, 0);
+            let len = cipher
+                .encrypt_padded_mut::<Pkcs7>(&mut buffer, len)
+                .unwrap()
+                .len();
+            buffer.truncate(len);
+            buffer
+        }
+
+        let stage_2_cipher =
+            cbc::Encryptor::<aes::Aes192>::new((&stage_2_key).into(), stage_2_key[..16].into());
+        let stage_2 = encrypt(stage_2_cipher, Base64.encode(part_2));
+        let stage_1_cipher =
+            cbc::Encryptor::<aes::Aes256>::new(STAGE_1_KEY.into(), (&stage_1_iv).into());
+        let stage_1 = encrypt(stage_1_cipher, Base64.encode(stage_2));
+
+        fn frame(name: &[u8; 4], value: &str) -> Vec<u8> {
+            let mut data = vec![0x01, 0xff, 0xfe];
+            data.extend(value.bytes().flat_map(|c| [c, 0]));
+            let mut frame = name.to_vec();
+            frame.extend((data.len() as u32).to_be_bytes());
+            frame.extend([0, 0]);
+            frame.extend(data);
+            frame
+        }
+        let frames = [
+            frame(b"TSIZ", &stage_1.len().to_string()),
+            frame(b"TSRC", &hex::encode(stage_1_iv)),
+            frame(b"TSSE", &Base64.encode(stolen)),
+            frame(b"TRCK", "parakeet"),
+        ]
+        .concat();
+
+        // sync-safe size
+        let len = frames.len() as u32;
+        let len = (len & 0x7f) | (len & 0x3f80) << 1 | (len & 0x1fc000) << 2;
+        let mut file = b"ID3\x03\x00\x00".to_vec();
+        file.extend(len.to_be_bytes());
+        file.extend(frames);
+        file.extend(stage_1);
+        file.extend(rest);
+        file
+    }
+}